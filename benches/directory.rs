@@ -0,0 +1,100 @@
+// Benchmark of the directory scan on a large tree, with a regression threshold
+
+use criterion::{criterion_group, Criterion};
+use shellai::utils::directory::scan_directory;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+use tempfile::{tempdir, TempDir};
+
+// Shape of the scanned tree: 1,000 files spread over 50 subdirectories
+const SUBDIRECTORIES: usize = 50;
+const FILES_PER_SUBDIRECTORY: usize = 20;
+
+// Deep enough to reach every file of the tree
+const MAX_DEPTH: usize = 3;
+
+// Slowest a scan of the tree may be before the benchmark fails
+const THRESHOLD: Duration = Duration::from_millis(100);
+
+// Scans timed for the threshold check, the fastest one is compared
+const THRESHOLD_RUNS: usize = 5;
+
+// Create the tree, with files of a few different kinds and sizes
+fn create_tree() -> TempDir {
+    let dir = tempdir().expect("Failed to create temp directory");
+    for i in 0..SUBDIRECTORIES {
+        let subdirectory = dir.path().join(format!("module_{:02}", i));
+        fs::create_dir(&subdirectory).expect("Failed to create subdirectory");
+        for j in 0..FILES_PER_SUBDIRECTORY {
+            let (name, contents) = match j % 4 {
+                0 => (format!("file_{:02}.rs", j), "fn main() {}\n".repeat(j + 1)),
+                1 => (format!("file_{:02}.md", j), "# Notes\n".repeat(j + 1)),
+                2 => (format!("file_{:02}.json", j), "{\"key\": 1}\n".to_string()),
+                _ => (format!("file_{:02}.bin", j), "\0\x01\x02".repeat(j + 1)),
+            };
+            fs::write(subdirectory.join(name), contents).expect("Failed to write file");
+        }
+    }
+    dir
+}
+
+fn scan(path: &Path) -> String {
+    scan_directory(path, MAX_DEPTH, 0).expect("Failed to scan directory")
+}
+
+fn bench_scan_directory(c: &mut Criterion) {
+    let tree = create_tree();
+    c.bench_function("scan_directory 1000 files in 50 subdirectories", |b| {
+        b.iter(|| scan(black_box(tree.path())))
+    });
+}
+
+criterion_group! {
+    name = benches;
+    // The default 5 seconds are too short for 100 samples of the scan
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = bench_scan_directory
+}
+
+// Fail if the scan got slower than `THRESHOLD`, so regressions don't go unnoticed
+fn check_threshold() {
+    let tree = create_tree();
+    let listing = scan(tree.path());
+    assert!(
+        listing.contains("module_49") && listing.contains("file_19.bin"),
+        "The scan should reach every file of the tree"
+    );
+
+    let fastest = (0..THRESHOLD_RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            black_box(scan(tree.path()));
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or_default();
+    if fastest > THRESHOLD {
+        eprintln!(
+            "scan_directory took {:?} on {} files, over the {:?} threshold",
+            fastest,
+            SUBDIRECTORIES * FILES_PER_SUBDIRECTORY,
+            THRESHOLD
+        );
+        process::exit(1);
+    }
+    println!(
+        "scan_directory took {:?} on {} files, within the {:?} threshold",
+        fastest,
+        SUBDIRECTORIES * FILES_PER_SUBDIRECTORY,
+        THRESHOLD
+    );
+}
+
+fn main() {
+    check_threshold();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
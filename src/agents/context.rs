@@ -0,0 +1,134 @@
+// Context window management for multi-turn conversations
+
+use crate::agents::openai::ChatMessage;
+
+// Tokens added by the API around every message for its role and separators
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Estimates how many tokens a piece of text uses
+///
+/// The built-in `HeuristicEstimator` is a rough approximation; a real tokenizer can be
+/// plugged in by implementing this trait.
+pub trait TokenEstimator {
+    fn estimate(&self, text: &str) -> usize;
+}
+
+/// Estimates one token per four characters, which is close enough for English text and code
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicEstimator;
+
+impl TokenEstimator for HeuristicEstimator {
+    fn estimate(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// Estimates the tokens used by a message, including the per-message overhead
+fn message_tokens(estimator: &impl TokenEstimator, content: &str) -> usize {
+    estimator.estimate(content) + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Works out how many of the oldest history messages must be left out of the next request
+///
+/// Messages are dropped in user/assistant pairs, oldest first, until the request fits the
+/// budget. The system prompt, the new prompt and the latest exchange are always kept, so the
+/// request can still exceed the budget if they alone are too large.
+///
+/// # Arguments
+///
+/// * `estimator` - How tokens are counted
+/// * `budget` - Maximum number of tokens the request should use
+/// * `system_prompt` - The system prompt sent with the request
+/// * `history` - Earlier messages of the conversation, oldest first
+/// * `prompt` - The new user message
+///
+/// # Returns
+///
+/// The number of messages to skip at the start of `history`
+pub fn messages_to_trim(
+    estimator: &impl TokenEstimator,
+    budget: usize,
+    system_prompt: &str,
+    history: &[ChatMessage],
+    prompt: &str,
+) -> usize {
+    let mut total = message_tokens(estimator, system_prompt)
+        + message_tokens(estimator, prompt)
+        + history
+            .iter()
+            .map(|message| message_tokens(estimator, &message.content))
+            .sum::<usize>();
+
+    let mut trimmed = 0;
+    while total > budget && history.len() - trimmed > 2 {
+        for message in &history[trimmed..trimmed + 2] {
+            total -= message_tokens(estimator, &message.content);
+        }
+        trimmed += 2;
+    }
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts every character as a token to keep the arithmetic readable
+    struct CharEstimator;
+
+    impl TokenEstimator for CharEstimator {
+        fn estimate(&self, text: &str) -> usize {
+            text.len()
+        }
+    }
+
+    fn exchange(question: &str, answer: &str) -> Vec<ChatMessage> {
+        vec![ChatMessage::user(question), ChatMessage::assistant(answer)]
+    }
+
+    #[test]
+    fn test_heuristic_estimator() {
+        assert_eq!(HeuristicEstimator.estimate(""), 0);
+        assert_eq!(HeuristicEstimator.estimate("abcd"), 1);
+        assert_eq!(HeuristicEstimator.estimate("abcde"), 2);
+    }
+
+    #[test]
+    fn test_nothing_trimmed_within_budget() {
+        let history = exchange("question", "answer");
+        assert_eq!(
+            messages_to_trim(&CharEstimator, 1_000, "system", &history, "prompt"),
+            0
+        );
+    }
+
+    #[test]
+    fn test_oldest_pairs_trimmed_first() {
+        let mut history = exchange(&"a".repeat(100), &"b".repeat(100));
+        history.extend(exchange(&"c".repeat(100), &"d".repeat(100)));
+        history.extend(exchange("e", "f"));
+
+        // Each message costs its length plus 4: system 10, prompt 10, history 208 + 208 + 10
+        assert_eq!(
+            messages_to_trim(&CharEstimator, 446, "system", &history, "prompt"),
+            0
+        );
+        assert_eq!(
+            messages_to_trim(&CharEstimator, 445, "system", &history, "prompt"),
+            2
+        );
+        assert_eq!(
+            messages_to_trim(&CharEstimator, 100, "system", &history, "prompt"),
+            4
+        );
+    }
+
+    #[test]
+    fn test_latest_exchange_always_kept() {
+        let history = exchange(&"a".repeat(500), &"b".repeat(500));
+        assert_eq!(
+            messages_to_trim(&CharEstimator, 10, "system", &history, "prompt"),
+            0
+        );
+    }
+}
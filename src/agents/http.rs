@@ -0,0 +1,169 @@
+// HTTP layer of the agents, replaceable so requests can be answered without a network
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Future returned by `HttpClient::post`
+pub type HttpFuture<'a> = Pin<Box<dyn Future<Output = anyhow::Result<HttpResponse>> + Send + 'a>>;
+
+/// Status and body of an HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub body: String,
+    /// How long the server asked to wait before trying again, from `Retry-After`
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpResponse {
+    /// Creates a response
+    pub fn new(status: StatusCode, body: &str) -> Self {
+        HttpResponse {
+            status,
+            body: body.to_string(),
+            retry_after: None,
+        }
+    }
+
+    /// Sets how long the server asked to wait before trying again
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+}
+
+/// Parses the value of a `Retry-After` header
+///
+/// # Arguments
+///
+/// * `value` - A number of seconds, or an HTTP date such as `Wed, 21 Oct 2015 07:28:00 GMT`
+/// * `now` - The current time, a date in the past meaning no wait
+///
+/// # Returns
+///
+/// How long to wait, or None if the value is neither
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Sends the requests of an agent
+pub trait HttpClient: Send + Sync {
+    /// Sends a POST request
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The full URL of the endpoint
+    /// * `headers` - The request headers
+    /// * `body` - The request body
+    ///
+    /// # Returns
+    ///
+    /// The response, whatever its status, or an error if no response was received
+    fn post(&self, url: &str, headers: HeaderMap, body: String) -> HttpFuture<'_>;
+}
+
+/// Sends requests over the network with `reqwest`
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+}
+
+impl ReqwestClient {
+    /// Creates a client sending requests through an existing `reqwest` client
+    pub fn new(client: reqwest::Client) -> Self {
+        ReqwestClient { client }
+    }
+}
+
+impl HttpClient for ReqwestClient {
+    fn post(&self, url: &str, headers: HeaderMap, body: String) -> HttpFuture<'_> {
+        let request = self.client.post(url).headers(headers).body(body);
+        Box::pin(async move {
+            let response = request.send().await?;
+            let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, Utc::now()));
+            Ok(HttpResponse {
+                status,
+                body: response.text().await?,
+                retry_after,
+            })
+        })
+    }
+}
+
+/// Answers requests with canned responses, in order, and records what was sent
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct CannedHttpClient {
+    responses: std::sync::Mutex<std::collections::VecDeque<HttpResponse>>,
+    requests: std::sync::Mutex<Vec<(String, String)>>,
+}
+
+#[cfg(test)]
+impl CannedHttpClient {
+    /// Creates a client giving each response once, then failing
+    pub fn new(responses: Vec<HttpResponse>) -> Self {
+        CannedHttpClient {
+            responses: std::sync::Mutex::new(responses.into()),
+            requests: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Gets the URL and body of each request sent so far
+    pub fn requests(&self) -> Vec<(String, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl HttpClient for CannedHttpClient {
+    fn post(&self, url: &str, _headers: HeaderMap, body: String) -> HttpFuture<'_> {
+        self.requests.lock().unwrap().push((url.to_string(), body));
+        let response = self.responses.lock().unwrap().pop_front();
+        Box::pin(async move { response.ok_or_else(|| anyhow::anyhow!("No canned response left")) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        // A date already past means trying again right away
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+}
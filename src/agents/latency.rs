@@ -0,0 +1,132 @@
+// Round-trip times of API requests, remembered per model
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Most round-trip times remembered for each model, older ones are forgotten first
+pub const LATENCY_HISTORY_LIMIT: usize = 20;
+
+// Whether timings are printed, see `configure_timings`
+static SHOW_TIMINGS: AtomicBool = AtomicBool::new(true);
+
+/// Turns the timing lines printed after answers and commands on or off for the rest of
+/// the process, with `show_timings` in `config.toml`
+pub fn configure_timings(enabled: bool) {
+    SHOW_TIMINGS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether timing lines such as `(model responded in 12.4s)` are printed
+pub fn timings_shown() -> bool {
+    SHOW_TIMINGS.load(Ordering::Relaxed)
+}
+
+/// The latest round-trip times of the requests sent to each model
+///
+/// Agents record every response they receive, including those of fallback models, so the
+/// history can tell how long the next request to a model is likely to take.
+#[derive(Debug, Default)]
+pub struct LatencyHistory {
+    samples: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+impl LatencyHistory {
+    /// Creates an empty history
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the round-trip time of a request to a model
+    pub fn record(&self, model: &str, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let model_samples = samples.entry(model.to_string()).or_default();
+        if model_samples.len() == LATENCY_HISTORY_LIMIT {
+            model_samples.pop_front();
+        }
+        model_samples.push_back(latency);
+    }
+
+    /// Gets the remembered round-trip times of a model, oldest first
+    pub fn samples(&self, model: &str) -> Vec<Duration> {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples
+            .get(model)
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Estimates how long the next request to a model will take
+    ///
+    /// # Returns
+    ///
+    /// The median of the remembered round-trip times, which a single slow request doesn't
+    /// skew, or None if no request to the model was recorded yet
+    pub fn typical(&self, model: &str) -> Option<Duration> {
+        let mut samples = self.samples(model);
+        samples.sort();
+        samples.get(samples.len() / 2).copied()
+    }
+}
+
+/// Formats a round-trip time as shown after a response, such as `[API: 342ms]`
+///
+/// # Returns
+///
+/// Milliseconds below a second, such as `342ms`, and seconds with one decimal from a
+/// second on, such as `2.4s`
+pub fn format_latency(latency: Duration) -> String {
+    let millis = latency.as_millis();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else {
+        format!("{:.1}s", latency.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&ms| Duration::from_millis(ms)).collect()
+    }
+
+    #[test]
+    fn test_history_is_kept_per_model() {
+        let history = LatencyHistory::new();
+        assert_eq!(history.typical("gpt-4"), None);
+
+        for ms in [300, 5000, 200] {
+            history.record("gpt-4", Duration::from_millis(ms));
+        }
+        history.record("gpt-4o-mini", Duration::from_millis(90));
+
+        assert_eq!(history.samples("gpt-4"), millis(&[300, 5000, 200]));
+        // One slow request doesn't change the estimate much
+        assert_eq!(history.typical("gpt-4"), Some(Duration::from_millis(300)));
+        assert_eq!(
+            history.typical("gpt-4o-mini"),
+            Some(Duration::from_millis(90))
+        );
+    }
+
+    #[test]
+    fn test_history_is_limited() {
+        let history = LatencyHistory::new();
+        for ms in 0..LATENCY_HISTORY_LIMIT as u64 + 5 {
+            history.record("gpt-4", Duration::from_millis(ms));
+        }
+        let samples = history.samples("gpt-4");
+        assert_eq!(samples.len(), LATENCY_HISTORY_LIMIT);
+        assert_eq!(samples[0], Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_format_latency() {
+        assert_eq!(format_latency(Duration::from_millis(342)), "342ms");
+        assert_eq!(format_latency(Duration::from_millis(999)), "999ms");
+        assert_eq!(format_latency(Duration::from_millis(1000)), "1.0s");
+        assert_eq!(format_latency(Duration::from_millis(2440)), "2.4s");
+    }
+}
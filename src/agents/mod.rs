@@ -1,4 +1,28 @@
 // Agents module
 
+// Export the context window management module
+pub mod context;
+
+// Export the HTTP layer module
+pub mod http;
+
+// Export the request latency history module
+pub mod latency;
+
+// Export the model descriptions module
+pub mod models;
+
 // Export the OpenAI agent module
 pub mod openai;
+
+// Export the API provider module
+pub mod provider;
+
+// Export the structured response module
+pub mod structured;
+
+// Export the tool calling module
+pub mod tools;
+
+// Export the request throttling module
+pub mod throttle;
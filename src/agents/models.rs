@@ -0,0 +1,318 @@
+// AI model descriptions shared by the agents and the model selector
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Prefixes of the o-series reasoning models
+const REASONING_MODEL_PREFIXES: &[&str] = &["o1", "o3", "o4"];
+
+// Reasoning models that don't support streamed responses
+const NON_STREAMING_MODELS: &[&str] = &["o1", "o1-pro"];
+
+// Context window and list price (USD per million prompt/completion tokens) of well-known models
+const KNOWN_MODELS: &[(&str, u32, f64, f64)] = &[
+    ("gpt-4", 8_192, 30.0, 60.0),
+    ("gpt-4-turbo", 128_000, 10.0, 30.0),
+    ("gpt-4o", 128_000, 2.5, 10.0),
+    ("gpt-4o-mini", 128_000, 0.15, 0.6),
+    ("gpt-3.5-turbo", 16_385, 0.5, 1.5),
+    ("o1", 200_000, 15.0, 60.0),
+    ("o1-mini", 128_000, 1.1, 4.4),
+    ("o3-mini", 200_000, 1.1, 4.4),
+];
+
+/// What a model accepts in a chat completion request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+    /// Whether the model is an o-series reasoning model
+    pub reasoning: bool,
+    /// Whether the `temperature` parameter is accepted
+    pub supports_temperature: bool,
+    /// Whether the system prompt must be sent with the `developer` role instead of `system`
+    pub uses_developer_role: bool,
+    /// Whether the length cap is called `max_completion_tokens` instead of `max_tokens`
+    pub uses_max_completion_tokens: bool,
+    /// Whether responses can be streamed
+    pub supports_streaming: bool,
+}
+
+impl ModelCapabilities {
+    /// Works out the capabilities of a model from its id
+    pub fn for_model(model_id: &str) -> Self {
+        // Fine-tuned models look like "ft:<base model>:<org>:..."
+        let base_model = model_id.strip_prefix("ft:").unwrap_or(model_id);
+        let base_model = base_model.split(':').next().unwrap_or(base_model);
+
+        let reasoning = REASONING_MODEL_PREFIXES
+            .iter()
+            .any(|prefix| base_model.starts_with(prefix));
+
+        if reasoning {
+            Self {
+                reasoning,
+                supports_temperature: false,
+                uses_developer_role: true,
+                uses_max_completion_tokens: true,
+                supports_streaming: !NON_STREAMING_MODELS.contains(&base_model),
+            }
+        } else {
+            Self {
+                reasoning,
+                supports_temperature: true,
+                uses_developer_role: false,
+                uses_max_completion_tokens: false,
+                supports_streaming: true,
+            }
+        }
+    }
+}
+
+/// How much effort a reasoning model spends thinking before it answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl FromStr for ReasoningEffort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "low" => Ok(ReasoningEffort::Low),
+            "medium" => Ok(ReasoningEffort::Medium),
+            "high" => Ok(ReasoningEffort::High),
+            other => Err(format!(
+                "Unknown reasoning effort '{}', expected low, medium or high",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReasoningEffort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// List price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Looks up the context window and pricing of a well-known model
+///
+/// Dated snapshots such as `gpt-4o-2024-08-06` share the entry of their base model.
+/// Fine-tuned models keep the context window of their base model but are priced differently,
+/// so no pricing is returned for them.
+fn known_model_details(model_id: &str) -> Option<(u32, Option<ModelPricing>)> {
+    let fine_tuned = model_id.starts_with("ft:");
+    let base_model = model_id.strip_prefix("ft:").unwrap_or(model_id);
+    let base_model = base_model.split(':').next().unwrap_or(base_model);
+
+    KNOWN_MODELS
+        .iter()
+        .filter(|(id, ..)| match base_model.strip_prefix(id) {
+            Some(rest) => {
+                rest.is_empty()
+                    || rest
+                        .strip_prefix('-')
+                        .is_some_and(|date| date.starts_with(|c: char| c.is_ascii_digit()))
+            }
+            None => false,
+        })
+        .max_by_key(|(id, ..)| id.len())
+        .map(|&(_, context_window, prompt, completion)| {
+            let pricing = (!fine_tuned).then_some(ModelPricing { prompt, completion });
+            (context_window, pricing)
+        })
+}
+
+/// Looks up the context window of a model, if it is a well-known one
+pub fn context_window(model_id: &str) -> Option<u32> {
+    known_model_details(model_id).map(|(context_window, _)| context_window)
+}
+
+/// An AI model that can be selected for the agent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AIModel {
+    pub name: String,
+    pub model_id: String,
+    pub description: String,
+    pub capabilities: ModelCapabilities,
+    /// Maximum number of tokens in a request and its response, if known
+    pub context_window: Option<u32>,
+    /// List price of the model, if known
+    pub pricing: Option<ModelPricing>,
+}
+
+impl AIModel {
+    /// Creates a model entry, working out its capabilities, context window and pricing from the id
+    pub fn new(name: &str, model_id: &str, description: &str) -> Self {
+        let (context_window, pricing) = match known_model_details(model_id) {
+            Some((context_window, pricing)) => (Some(context_window), pricing),
+            None => (None, None),
+        };
+
+        Self {
+            name: name.to_string(),
+            model_id: model_id.to_string(),
+            description: description.to_string(),
+            capabilities: ModelCapabilities::for_model(model_id),
+            context_window,
+            pricing,
+        }
+    }
+
+    /// Creates an entry for a model id typed in by the user
+    pub fn custom(model_id: &str) -> Self {
+        Self::new(model_id, model_id, "Custom model")
+    }
+}
+
+/// Builds the warning shown when a model id asked for isn't among the known models
+///
+/// Unknown ids are still used, since fine-tuned models (`ft:...`) and new releases aren't
+/// in any list; the API rejects ids that don't exist on the first request.
+///
+/// # Arguments
+///
+/// * `model_id` - The model id asked for, such as with `--model`
+/// * `known` - The built-in or fetched models of the provider
+///
+/// # Returns
+///
+/// The warning, or None if the model is known
+pub fn unknown_model_warning(model_id: &str, known: &[AIModel]) -> Option<String> {
+    if known.iter().any(|model| model.model_id == model_id) {
+        return None;
+    }
+    Some(format!(
+        "Unknown model '{}', using it anyway; it will be checked on the first request",
+        model_id
+    ))
+}
+
+/// Built-in list of AI models, used when the model list can't be fetched from the API
+pub fn default_models() -> Vec<AIModel> {
+    vec![
+        AIModel::new(
+            "GPT-4",
+            "gpt-4",
+            "Advanced model with strong reasoning capabilities",
+        ),
+        AIModel::new(
+            "GPT-3.5 Turbo",
+            "gpt-3.5-turbo",
+            "Fast and efficient for most tasks",
+        ),
+        AIModel::new(
+            "GPT-4o",
+            "gpt-4o",
+            "Latest model with improved capabilities",
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_model() {
+        let model = AIModel::custom("ft:gpt-4o:my-org:custom:abc");
+        assert_eq!(model.model_id, "ft:gpt-4o:my-org:custom:abc");
+        assert_eq!(model.name, "ft:gpt-4o:my-org:custom:abc");
+        assert!(!default_models().contains(&model));
+    }
+
+    #[test]
+    fn test_unknown_model_warning() {
+        assert_eq!(unknown_model_warning("gpt-4o", &default_models()), None);
+        let warning = unknown_model_warning("ft:gpt-4o-mini:acme::abc123", &default_models());
+        assert!(warning.unwrap().contains("'ft:gpt-4o-mini:acme::abc123'"));
+    }
+
+    #[test]
+    fn test_capabilities_for_standard_models() {
+        for model_id in ["gpt-4", "gpt-4o-mini", "ft:gpt-4o:my-org:custom:abc"] {
+            let capabilities = ModelCapabilities::for_model(model_id);
+            assert!(!capabilities.reasoning, "{}", model_id);
+            assert!(capabilities.supports_temperature, "{}", model_id);
+            assert!(!capabilities.uses_developer_role, "{}", model_id);
+            assert!(!capabilities.uses_max_completion_tokens, "{}", model_id);
+            assert!(capabilities.supports_streaming, "{}", model_id);
+        }
+    }
+
+    #[test]
+    fn test_capabilities_for_reasoning_models() {
+        for model_id in [
+            "o1",
+            "o1-mini",
+            "o3-mini",
+            "o4-mini",
+            "ft:o4-mini:my-org::abc",
+        ] {
+            let capabilities = ModelCapabilities::for_model(model_id);
+            assert!(capabilities.reasoning, "{}", model_id);
+            assert!(!capabilities.supports_temperature, "{}", model_id);
+            assert!(capabilities.uses_developer_role, "{}", model_id);
+            assert!(capabilities.uses_max_completion_tokens, "{}", model_id);
+        }
+
+        assert!(!ModelCapabilities::for_model("o1").supports_streaming);
+        assert!(ModelCapabilities::for_model("o3-mini").supports_streaming);
+    }
+
+    #[test]
+    fn test_known_model_details() {
+        let model = AIModel::new("GPT-4o", "gpt-4o", "");
+        assert_eq!(model.context_window, Some(128_000));
+        assert_eq!(
+            model.pricing,
+            Some(ModelPricing {
+                prompt: 2.5,
+                completion: 10.0
+            })
+        );
+
+        // Dated snapshots use their base model, other variants don't
+        assert_eq!(
+            AIModel::new("", "gpt-4-0613", "").context_window,
+            Some(8_192)
+        );
+        assert_eq!(
+            AIModel::new("", "gpt-4o-mini-2024-07-18", "").pricing,
+            AIModel::new("", "gpt-4o-mini", "").pricing
+        );
+        assert_eq!(
+            AIModel::new("", "gpt-4o-audio-preview", "").context_window,
+            None
+        );
+
+        // Fine-tuned models keep the context window but not the pricing
+        let fine_tuned = AIModel::custom("ft:gpt-4o:my-org:custom:abc");
+        assert_eq!(fine_tuned.context_window, Some(128_000));
+        assert_eq!(fine_tuned.pricing, None);
+    }
+
+    #[test]
+    fn test_reasoning_effort_parsing() {
+        assert_eq!("low".parse(), Ok(ReasoningEffort::Low));
+        assert_eq!(" HIGH ".parse(), Ok(ReasoningEffort::High));
+        assert!("extreme".parse::<ReasoningEffort>().is_err());
+        assert_eq!(ReasoningEffort::Medium.to_string(), "medium");
+    }
+}
@@ -0,0 +1,425 @@
+// API providers an agent can be created for
+
+use crate::agents::models::{default_models, AIModel};
+use crate::agents::openai::{check_api_key_format, OpenAIAgent};
+use crate::error::OPENAI_BILLING_URL;
+use crate::state::DEFAULT_MODEL;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Root of Anthropic's OpenAI-compatible API
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+/// Page where an Anthropic account is given credit
+const ANTHROPIC_BILLING_URL: &str = "https://console.anthropic.com/settings/billing";
+
+/// Address of a local Ollama server when `OLLAMA_HOST` isn't set
+const OLLAMA_DEFAULT_HOST: &str = "http://localhost:11434";
+
+/// Service answering the chat requests
+///
+/// Anthropic and Ollama are reached through their OpenAI-compatible endpoints, so every
+/// provider is driven by an `OpenAIAgent` and there is no agent type per provider. Those
+/// endpoints only accept part of the OpenAI API: parameters they don't support, such as
+/// `seed`, `response_format` or `tools`, are ignored or rejected, so seeded answers,
+/// structured answers (`/review`, `/security-check`) and tool calls may not work there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    OpenAI,
+    Anthropic,
+    Ollama,
+}
+
+impl Provider {
+    /// Every provider, in the order the model menu lists them
+    pub const ALL: [Provider; 3] = [Provider::OpenAI, Provider::Anthropic, Provider::Ollama];
+
+    /// Names accepted by `from_str`, as given to `--provider`
+    pub const NAMES: &'static [&'static str] = &["openai", "anthropic", "ollama"];
+
+    /// Gets the name accepted by `from_str`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "openai",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama",
+        }
+    }
+
+    /// Gets the environment variable holding the API key, None for Ollama which needs none
+    pub fn api_key_var(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAI => Some("OPENAI_API_KEY"),
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Provider::Ollama => None,
+        }
+    }
+
+    /// Gets the page where the account is given credit, None for Ollama which runs locally
+    pub fn billing_url(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAI => Some(OPENAI_BILLING_URL),
+            Provider::Anthropic => Some(ANTHROPIC_BILLING_URL),
+            Provider::Ollama => None,
+        }
+    }
+
+    /// Gets the model used when none is selected for this provider
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => DEFAULT_MODEL,
+            Provider::Anthropic => "claude-3-5-sonnet-latest",
+            Provider::Ollama => "llama3.1",
+        }
+    }
+
+    /// Built-in list of the provider's models, used when the list can't be fetched
+    pub fn models(&self) -> Vec<AIModel> {
+        match self {
+            Provider::OpenAI => default_models(),
+            Provider::Anthropic => vec![
+                AIModel::new(
+                    "Claude 3.5 Sonnet",
+                    "claude-3-5-sonnet-latest",
+                    "Strong at code and reasoning",
+                ),
+                AIModel::new(
+                    "Claude 3.5 Haiku",
+                    "claude-3-5-haiku-latest",
+                    "Fast and inexpensive",
+                ),
+                AIModel::new(
+                    "Claude 3 Opus",
+                    "claude-3-opus-latest",
+                    "Largest Claude 3 model",
+                ),
+            ],
+            Provider::Ollama => vec![
+                AIModel::new("Llama 3.1", "llama3.1", "General purpose local model"),
+                AIModel::new(
+                    "Qwen 2.5 Coder",
+                    "qwen2.5-coder",
+                    "Local model tuned for code",
+                ),
+                AIModel::new("Mistral", "mistral", "Small and fast local model"),
+            ],
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|provider| provider.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown provider '{}', use one of: {}",
+                    name,
+                    Self::NAMES.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Provider::OpenAI => "OpenAI",
+            Provider::Anthropic => "Anthropic",
+            Provider::Ollama => "Ollama",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Creates an agent for a provider, checking that its credentials are present
+///
+/// The agent is an `OpenAIAgent` pointed at the provider's OpenAI-compatible endpoint, see
+/// `Provider` for what that leaves out.
+///
+/// # Arguments
+///
+/// * `provider` - The provider answering the requests
+/// * `model` - The model to use
+/// * `api_key` - A key from the configuration, used instead of the OpenAI environment
+///   variable; it only applies to OpenAI
+/// * `api_key_path` - A file holding the key from the configuration, read when neither the
+///   key variable nor its `_FILE` variable, such as `OPENAI_API_KEY_FILE`, is set; it only
+///   applies to OpenAI
+/// * `env` - Looks up an environment variable, `std::env::var` outside of tests
+///
+/// # Returns
+///
+/// The agent, or an error naming the variable to set if the provider's key is missing
+pub fn create_provider_agent(
+    provider: Provider,
+    model: &str,
+    api_key: Option<&str>,
+    api_key_path: Option<&Path>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<OpenAIAgent, Box<dyn Error>> {
+    let configured_key = api_key.filter(|_| provider == Provider::OpenAI);
+    let configured_path = api_key_path.filter(|_| provider == Provider::OpenAI);
+    let api_key = match (configured_key, provider.api_key_var()) {
+        (Some(key), _) => key.to_string(),
+        (None, Some(var)) => {
+            // The variable wins over a file, so a key can be overridden for one run
+            let key_file = env(&format!("{}_FILE", var))
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| configured_path.map(Path::to_path_buf));
+            let key = match (env(var).filter(|key| !key.is_empty()), key_file) {
+                (Some(key), _) => key,
+                (None, Some(path)) => read_api_key_file(&path)?,
+                (None, None) => return Err(format!("{} environment variable not set", var).into()),
+            };
+            // Other compatible APIs have keys of their own format
+            if provider == Provider::OpenAI && env("OPENAI_BASE_URL").is_none() {
+                if let Err(problem) = check_api_key_format(&key) {
+                    eprintln!("Warning: {} {}", var, problem);
+                }
+            }
+            key
+        }
+        // Ollama ignores the key, but the header is always sent
+        (None, None) => "ollama".to_string(),
+    };
+
+    let agent = OpenAIAgent::with_api_key(model.to_string(), api_key).with_provider(provider);
+    Ok(match provider {
+        Provider::OpenAI => agent,
+        Provider::Anthropic => agent.with_base_url(ANTHROPIC_BASE_URL),
+        Provider::Ollama => {
+            let host = env("OLLAMA_HOST").unwrap_or_else(|| OLLAMA_DEFAULT_HOST.to_string());
+            let host = host.trim_end_matches('/');
+            if host.contains("://") {
+                agent.with_base_url(&format!("{}/v1", host))
+            } else {
+                agent.with_base_url(&format!("http://{}/v1", host))
+            }
+        }
+    })
+}
+
+/// Reads an API key from a file, such as a secret mounted by Docker or Kubernetes
+///
+/// # Returns
+///
+/// The contents of the file without the surrounding whitespace and trailing newline, or an
+/// error if the file can't be read or is empty
+pub fn read_api_key_file(path: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read API key file {}: {}", path.display(), e))?;
+    let key = contents.trim();
+    if key.is_empty() {
+        return Err(format!("API key file {} is empty", path.display()));
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::models::unknown_model_warning;
+    use tempfile::tempdir;
+
+    // Look up variables in a fixed list instead of the environment
+    fn env_with(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    #[test]
+    fn test_provider_names() {
+        for provider in Provider::ALL {
+            assert_eq!(provider.name().parse(), Ok(provider));
+        }
+        assert_eq!("Anthropic".parse(), Ok(Provider::Anthropic));
+        assert_eq!(
+            "gemini".parse::<Provider>(),
+            Err("Unknown provider 'gemini', use one of: openai, anthropic, ollama".to_string())
+        );
+        assert_eq!(Provider::default(), Provider::OpenAI);
+    }
+
+    #[test]
+    fn test_create_provider_agent() {
+        let env = env_with(&[
+            ("OPENAI_API_KEY", "sk-abcdefghijklmnopqrstuvwxyz"),
+            ("ANTHROPIC_API_KEY", "sk-ant-test"),
+        ]);
+
+        let agent = create_provider_agent(
+            Provider::Anthropic,
+            "claude-3-5-haiku-latest",
+            None,
+            None,
+            &env,
+        )
+        .expect("Anthropic key is set");
+        assert_eq!(agent.base_url(), ANTHROPIC_BASE_URL);
+        assert_eq!(agent.provider(), Provider::Anthropic);
+
+        let agent = create_provider_agent(Provider::Ollama, "llama3.1", None, None, &env)
+            .expect("Ollama needs no key");
+        assert_eq!(agent.base_url(), "http://localhost:11434/v1");
+        assert_eq!(agent.provider(), Provider::Ollama);
+
+        let agent = create_provider_agent(
+            Provider::Ollama,
+            "llama3.1",
+            None,
+            None,
+            env_with(&[("OLLAMA_HOST", "192.168.1.20:11434")]),
+        )
+        .unwrap();
+        assert_eq!(agent.base_url(), "http://192.168.1.20:11434/v1");
+
+        assert!(create_provider_agent(Provider::OpenAI, "gpt-4o", None, None, &env).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_model_is_still_used() {
+        let env = env_with(&[("OPENAI_API_KEY", "sk-abcdefghijklmnopqrstuvwxyz")]);
+        let model = "gtp-4o typo";
+        assert!(unknown_model_warning(model, &Provider::OpenAI.models()).is_some());
+        assert!(create_provider_agent(Provider::OpenAI, model, None, None, &env).is_ok());
+    }
+
+    #[test]
+    fn test_missing_credentials() {
+        let env = env_with(&[("OPENAI_API_KEY", "sk-abcdefghijklmnopqrstuvwxyz")]);
+        let error = create_provider_agent(
+            Provider::Anthropic,
+            "claude-3-5-haiku-latest",
+            None,
+            None,
+            &env,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ANTHROPIC_API_KEY environment variable not set"
+        );
+
+        // The configured key is an OpenAI key and doesn't stand in for other providers
+        let error = create_provider_agent(
+            Provider::Anthropic,
+            "claude",
+            Some("sk-key"),
+            None,
+            env_with(&[]),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("ANTHROPIC_API_KEY"));
+        assert!(create_provider_agent(
+            Provider::OpenAI,
+            "gpt-4o",
+            Some("sk-key"),
+            None,
+            env_with(&[])
+        )
+        .is_ok());
+        assert!(create_provider_agent(
+            Provider::OpenAI,
+            "gpt-4o",
+            None,
+            None,
+            env_with(&[("OPENAI_API_KEY", "")])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_api_key_from_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("openai_key");
+        fs::write(&path, "sk-from-file-abcdefghijklmnop\n").unwrap();
+        assert_eq!(
+            read_api_key_file(&path).unwrap(),
+            "sk-from-file-abcdefghijklmnop"
+        );
+
+        let agent = create_provider_agent(
+            Provider::OpenAI,
+            "gpt-4o",
+            None,
+            None,
+            env_with(&[("OPENAI_API_KEY_FILE", path.to_str().unwrap())]),
+        )
+        .unwrap();
+        assert_eq!(agent.api_key(), "sk-from-file-abcdefghijklmnop");
+
+        let agent =
+            create_provider_agent(Provider::OpenAI, "gpt-4o", None, Some(&path), env_with(&[]))
+                .unwrap();
+        assert_eq!(agent.api_key(), "sk-from-file-abcdefghijklmnop");
+
+        // The configured file only holds an OpenAI key
+        assert!(create_provider_agent(
+            Provider::Anthropic,
+            "claude",
+            None,
+            Some(&path),
+            env_with(&[])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_api_key_variable_wins_over_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("openai_key");
+        fs::write(&path, "sk-from-file-abcdefghijklmnop").unwrap();
+
+        let agent = create_provider_agent(
+            Provider::OpenAI,
+            "gpt-4o",
+            None,
+            Some(&path),
+            env_with(&[
+                ("OPENAI_API_KEY", "sk-from-env-abcdefghijklmnop"),
+                ("OPENAI_API_KEY_FILE", path.to_str().unwrap()),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(agent.api_key(), "sk-from-env-abcdefghijklmnop");
+    }
+
+    #[test]
+    fn test_unusable_api_key_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("openai_key");
+        fs::write(&path, " \n\n").unwrap();
+        let error =
+            create_provider_agent(Provider::OpenAI, "gpt-4o", None, Some(&path), env_with(&[]))
+                .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("API key file {} is empty", path.display())
+        );
+
+        let missing = dir.path().join("missing");
+        let error = read_api_key_file(&missing).unwrap_err();
+        assert!(error.starts_with(&format!(
+            "Failed to read API key file {}",
+            missing.display()
+        )));
+    }
+}
@@ -0,0 +1,286 @@
+// Structured JSON responses for scripting
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// A JSON schema the model's response must follow
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonSchema {
+    /// Name of the schema, letters, digits, underscores and dashes only
+    pub name: String,
+    /// The JSON Schema document itself
+    pub schema: serde_json::Value,
+    /// Whether the API should enforce the schema exactly
+    pub strict: bool,
+}
+
+/// The `response_format` parameter of a chat completion request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    JsonSchema { json_schema: JsonSchema },
+}
+
+impl From<&JsonSchema> for ResponseFormat {
+    fn from(schema: &JsonSchema) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: schema.clone(),
+        }
+    }
+}
+
+/// A suggested set of shell commands with an explanation, for use in scripts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShellSuggestion {
+    /// What the commands do
+    pub explanation: String,
+    /// The commands to run, in order
+    pub commands: Vec<String>,
+}
+
+impl ShellSuggestion {
+    /// Gets the built-in schema describing a `ShellSuggestion`
+    pub fn schema() -> JsonSchema {
+        JsonSchema {
+            name: "shell_suggestion".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "explanation": {
+                        "type": "string",
+                        "description": "What the commands do"
+                    },
+                    "commands": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Shell commands to run, in order"
+                    }
+                },
+                "required": ["explanation", "commands"],
+                "additionalProperties": false
+            }),
+            strict: true,
+        }
+    }
+}
+
+/// How serious a security issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// Every severity, from least to most serious, as named in the schema
+    pub const NAMES: [&'static str; 4] = ["low", "medium", "high", "critical"];
+
+    /// Gets the name used in the schema, such as "high"
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+}
+
+/// A vulnerable pattern found in a piece of code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityFinding {
+    /// The vulnerable pattern, quoting the code where possible
+    pub pattern: String,
+    /// How serious the issue is
+    pub severity: Severity,
+    /// Why the pattern is dangerous
+    pub explanation: String,
+    /// How to fix it
+    pub remediation: String,
+}
+
+/// The result of a security review of a piece of code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityReport {
+    /// Overall assessment of the code
+    pub summary: String,
+    /// The issues found, empty if the code looks safe
+    pub findings: Vec<SecurityFinding>,
+}
+
+impl SecurityReport {
+    /// Gets the built-in schema describing a `SecurityReport`
+    pub fn schema() -> JsonSchema {
+        JsonSchema {
+            name: "security_report".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "summary": {
+                        "type": "string",
+                        "description": "Overall assessment of the code"
+                    },
+                    "findings": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "pattern": {
+                                    "type": "string",
+                                    "description": "The vulnerable pattern, quoting the code"
+                                },
+                                "severity": {
+                                    "type": "string",
+                                    "enum": Severity::NAMES
+                                },
+                                "explanation": {
+                                    "type": "string",
+                                    "description": "Why the pattern is dangerous"
+                                },
+                                "remediation": {
+                                    "type": "string",
+                                    "description": "How to fix it"
+                                }
+                            },
+                            "required": ["pattern", "severity", "explanation", "remediation"],
+                            "additionalProperties": false
+                        }
+                    }
+                },
+                "required": ["summary", "findings"],
+                "additionalProperties": false
+            }),
+            strict: true,
+        }
+    }
+
+    /// Gets the severity of the most serious finding, or None if nothing was found
+    pub fn highest_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+}
+
+/// The result of a review of the changes made to a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewResult {
+    /// Bugs the changes introduce
+    pub bugs: Vec<String>,
+    /// Improvements to consider
+    pub suggestions: Vec<String>,
+    /// Quality of the changes, from 1 (poor) to 5 (excellent)
+    pub rating: u8,
+}
+
+impl ReviewResult {
+    /// Lowest and highest rating
+    pub const RATINGS: [u8; 5] = [1, 2, 3, 4, 5];
+
+    /// Gets the built-in schema describing a `ReviewResult`
+    pub fn schema() -> JsonSchema {
+        JsonSchema {
+            name: "review_result".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "bugs": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Bugs the changes introduce, empty if none"
+                    },
+                    "suggestions": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Improvements to consider"
+                    },
+                    "rating": {
+                        "type": "integer",
+                        "enum": Self::RATINGS,
+                        "description": "Quality of the changes, from 1 (poor) to 5 (excellent)"
+                    }
+                },
+                "required": ["bugs", "suggestions", "rating"],
+                "additionalProperties": false
+            }),
+            strict: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_format_serialization() {
+        let format = ResponseFormat::from(&ShellSuggestion::schema());
+        let value = serde_json::to_value(format).expect("Format should serialize");
+
+        assert_eq!(value["type"], "json_schema");
+        assert_eq!(value["json_schema"]["name"], "shell_suggestion");
+        assert_eq!(value["json_schema"]["strict"], true);
+        assert_eq!(value["json_schema"]["schema"]["required"][1], "commands");
+    }
+
+    #[test]
+    fn test_shell_suggestion_rejects_unknown_fields() {
+        let valid = r#"{"explanation": "Lists files", "commands": ["ls -la"]}"#;
+        let suggestion: ShellSuggestion = serde_json::from_str(valid).expect("Should parse");
+        assert_eq!(suggestion.commands, vec!["ls -la"]);
+
+        let extra = r#"{"explanation": "Lists files", "commands": [], "danger": true}"#;
+        assert!(serde_json::from_str::<ShellSuggestion>(extra).is_err());
+        let missing = r#"{"explanation": "Lists files"}"#;
+        assert!(serde_json::from_str::<ShellSuggestion>(missing).is_err());
+    }
+
+    #[test]
+    fn test_security_report_parsing() {
+        let json = r#"{
+            "summary": "Deletes files without checking the variable",
+            "findings": [
+                {"pattern": "rm -rf $DIR/", "severity": "critical", "explanation": "An empty DIR deletes /", "remediation": "Use ${DIR:?}"},
+                {"pattern": "curl | sh", "severity": "high", "explanation": "Runs unverified code", "remediation": "Download and check first"}
+            ]
+        }"#;
+        let report: SecurityReport = serde_json::from_str(json).expect("Should parse");
+        assert_eq!(report.findings.len(), 2);
+        assert_eq!(report.highest_severity(), Some(Severity::Critical));
+        assert_eq!(Severity::High.name(), "high");
+
+        let safe = r#"{"summary": "Only lists files", "findings": []}"#;
+        let report: SecurityReport = serde_json::from_str(safe).expect("Should parse");
+        assert_eq!(report.highest_severity(), None);
+
+        let unknown_severity = r#"{"summary": "", "findings": [{"pattern": "", "severity": "severe", "explanation": "", "remediation": ""}]}"#;
+        assert!(serde_json::from_str::<SecurityReport>(unknown_severity).is_err());
+    }
+
+    #[test]
+    fn test_parse_review_result() {
+        let json = r#"{"bugs": ["Off by one in the loop bound"], "suggestions": [], "rating": 2}"#;
+        let review: ReviewResult = serde_json::from_str(json).expect("Should parse");
+        assert_eq!(review.bugs, vec!["Off by one in the loop bound"]);
+        assert_eq!(review.rating, 2);
+
+        let schema = ReviewResult::schema();
+        assert_eq!(
+            schema.schema["properties"]["rating"]["enum"],
+            json!([1, 2, 3, 4, 5])
+        );
+        assert!(
+            serde_json::from_str::<ReviewResult>(r#"{"bugs": [], "suggestions": []}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_security_report_schema_lists_severities() {
+        let schema = SecurityReport::schema();
+        let severity = &schema.schema["properties"]["findings"]["items"]["properties"]["severity"];
+        assert_eq!(
+            severity["enum"],
+            json!(["low", "medium", "high", "critical"])
+        );
+    }
+}
@@ -0,0 +1,179 @@
+// Minimum interval between API requests, to smooth bursts before they hit rate limits
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest interval a throttle keeps between requests, longer ones are shortened to it
+pub const MAX_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Future returned by `Clock::sleep`
+pub type SleepFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Where a `RequestThrottle` reads the time and waits, replaceable so tests don't wait
+pub trait Clock: Send + Sync {
+    /// Gets the current time
+    fn now(&self) -> Instant;
+
+    /// Waits for `duration`
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_>;
+}
+
+/// The real time, waited for with tokio
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Keeps requests at least an interval apart
+///
+/// Each request takes the next free slot before waiting for it, so requests made at once
+/// go out one interval after another rather than together.
+pub struct RequestThrottle {
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    /// When the latest request was allowed to go, None before the first one
+    last: Mutex<Option<Instant>>,
+}
+
+impl RequestThrottle {
+    /// Creates a throttle spacing requests `interval` apart in real time
+    pub fn new(interval: Duration) -> Self {
+        Self::with_clock(interval, Arc::new(TokioClock))
+    }
+
+    /// Creates a throttle reading the time from `clock`
+    ///
+    /// The interval is at most `MAX_INTERVAL`, so the time of the next slot always fits in
+    /// an `Instant`.
+    pub fn with_clock(interval: Duration, clock: Arc<dyn Clock>) -> Self {
+        RequestThrottle {
+            interval: interval.min(MAX_INTERVAL),
+            clock,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Gets the minimum interval between requests
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Waits until a request may be sent, and counts it as sent
+    ///
+    /// # Returns
+    ///
+    /// How long it waited, zero if the previous request was long enough ago
+    pub async fn wait(&self) -> Duration {
+        let delay = {
+            let mut last = self.last.lock().unwrap();
+            let now = self.clock.now();
+            let delay = last
+                .and_then(|last| last.checked_add(self.interval))
+                .map_or(Duration::ZERO, |next| next.saturating_duration_since(now));
+            *last = Some(now + delay);
+            delay
+        };
+        if !delay.is_zero() {
+            self.clock.sleep(delay).await;
+        }
+        delay
+    }
+}
+
+impl std::fmt::Debug for RequestThrottle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestThrottle")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Time that only passes when slept through or advanced by hand, recording each sleep
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<Instant>,
+    sleeps: Mutex<Vec<Duration>>,
+}
+
+/// Starts at the current time
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> Self {
+        FakeClock {
+            now: Mutex::new(Instant::now()),
+            sleeps: Mutex::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl FakeClock {
+    /// Moves the time forward
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Gets how long each sleep so far lasted
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_> {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration);
+        Box::pin(async {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[tokio::test]
+    async fn test_second_request_waits_for_the_interval() {
+        let clock = Arc::new(FakeClock::default());
+        let throttle = RequestThrottle::with_clock(2 * SECOND, clock.clone());
+
+        assert_eq!(throttle.wait().await, Duration::ZERO);
+        assert_eq!(throttle.wait().await, 2 * SECOND);
+
+        // Only the rest of the interval is waited for
+        clock.advance(SECOND / 2);
+        assert_eq!(throttle.wait().await, SECOND * 3 / 2);
+
+        // Nothing to wait for once the interval has passed
+        clock.advance(5 * SECOND);
+        assert_eq!(throttle.wait().await, Duration::ZERO);
+        assert_eq!(clock.sleeps(), vec![2 * SECOND, SECOND * 3 / 2]);
+    }
+
+    #[tokio::test]
+    async fn test_huge_interval_is_clamped() {
+        let clock = Arc::new(FakeClock::default());
+        let throttle = RequestThrottle::with_clock(Duration::MAX, clock.clone());
+        assert_eq!(throttle.interval(), MAX_INTERVAL);
+
+        assert_eq!(throttle.wait().await, Duration::ZERO);
+        assert_eq!(throttle.wait().await, MAX_INTERVAL);
+    }
+}
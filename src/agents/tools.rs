@@ -0,0 +1,407 @@
+// Tools the model can call to look at the user's files
+
+use crate::utils::directory;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Future returned by `Tool::call`
+pub type ToolFuture<'a> = Pin<Box<dyn Future<Output = String> + Send + 'a>>;
+
+/// Bytes `read_file` returns when the model doesn't ask for a limit
+const DEFAULT_READ_LIMIT: usize = 16_000;
+
+/// Most bytes `read_file` returns, whatever limit the model asks for
+const MAX_READ_LIMIT: usize = 256_000;
+
+/// Depth of the tree `list_directory` returns
+const LIST_DIRECTORY_DEPTH: usize = 1;
+
+/// An action the model can ask ShellAI to perform while answering
+pub trait Tool: Send + Sync {
+    /// Name the model calls the tool by, letters, digits, underscores and dashes only
+    fn name(&self) -> &str;
+
+    /// What the tool does, shown to the model so it knows when to call it
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object the tool takes
+    fn parameters(&self) -> serde_json::Value;
+
+    /// Runs the tool
+    ///
+    /// # Arguments
+    ///
+    /// * `arguments` - The arguments object sent by the model
+    ///
+    /// # Returns
+    ///
+    /// The text sent back to the model, which should describe the problem if the tool failed
+    fn call(&self, arguments: serde_json::Value) -> ToolFuture<'_>;
+}
+
+/// A tool call requested by the model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+/// The function and arguments of a `ToolCall`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments object, encoded as a JSON string
+    pub arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// A tool as described in the `tools` parameter of a chat completion request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolDefinition {
+    Function { function: FunctionDefinition },
+}
+
+/// The function described by a `ToolDefinition`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// The tools registered with an agent
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.tools.iter().map(|tool| tool.name()))
+            .finish()
+    }
+}
+
+impl ToolRegistry {
+    /// Adds a tool, replacing any registered tool with the same name
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.retain(|existing| existing.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    /// Returns true if no tool is registered
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    /// Describes the registered tools for a request
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| ToolDefinition::Function {
+                function: FunctionDefinition {
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters(),
+                },
+            })
+            .collect()
+    }
+
+    /// Runs the tool the model asked for
+    ///
+    /// Unknown tools and arguments that aren't JSON are reported back to the model as text
+    /// so it can correct itself.
+    pub async fn call(&self, call: &ToolCall) -> String {
+        let Some(tool) = self
+            .tools
+            .iter()
+            .find(|tool| tool.name() == call.function.name)
+        else {
+            return format!("Error: unknown tool '{}'", call.function.name);
+        };
+
+        match serde_json::from_str(&call.function.arguments) {
+            Ok(arguments) => tool.call(arguments).await,
+            Err(e) => format!("Error: arguments aren't valid JSON: {}", e),
+        }
+    }
+}
+
+/// Resolves a path given by the model, refusing anything outside `root`
+fn resolve_within(root: &Path, path: &str) -> Result<PathBuf, String> {
+    let root = root.canonicalize().map_err(|e| format!("Error: {}", e))?;
+    let resolved = root
+        .join(path)
+        .canonicalize()
+        .map_err(|e| format!("Error: {}: {}", path, e))?;
+
+    if resolved.starts_with(&root) {
+        Ok(resolved)
+    } else {
+        Err(format!("Error: {} is outside the working directory", path))
+    }
+}
+
+/// Built-in tool reading the start of a file in the working directory
+#[derive(Debug, Clone)]
+pub struct ReadFileTool {
+    root: PathBuf,
+}
+
+impl ReadFileTool {
+    /// Creates the tool, giving access to files under `root`
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn read(&self, arguments: &serde_json::Value) -> String {
+        let Some(path) = arguments["path"].as_str() else {
+            return "Error: missing 'path' argument".to_string();
+        };
+        let limit = arguments["max_bytes"]
+            .as_u64()
+            .map_or(DEFAULT_READ_LIMIT, |limit| {
+                limit.min(MAX_READ_LIMIT as u64) as usize
+            });
+
+        let path = match resolve_within(&self.root, path) {
+            Ok(path) => path,
+            Err(message) => return message,
+        };
+
+        let mut bytes = Vec::new();
+        let result = fs::File::open(&path).and_then(|file| {
+            file.take((limit as u64).saturating_add(1))
+                .read_to_end(&mut bytes)
+        });
+        if let Err(e) = result {
+            return format!("Error: {}", e);
+        }
+
+        let truncated = bytes.len() > limit;
+        bytes.truncate(limit);
+        let mut content = String::from_utf8_lossy(&bytes).into_owned();
+        if truncated {
+            content.push_str(&format!("\n[truncated after {} bytes]", limit));
+        }
+        content
+    }
+}
+
+impl Tool for ReadFileTool {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Read a text file in the current working directory"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path of the file, relative to the working directory"
+                },
+                "max_bytes": {
+                    "type": "integer",
+                    "description": format!(
+                        "Largest number of bytes to return, at most {}",
+                        MAX_READ_LIMIT
+                    )
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> ToolFuture<'_> {
+        Box::pin(async move { self.read(&arguments) })
+    }
+}
+
+/// Built-in tool listing a directory in the working directory
+#[derive(Debug, Clone)]
+pub struct ListDirectoryTool {
+    root: PathBuf,
+}
+
+impl ListDirectoryTool {
+    /// Creates the tool, giving access to directories under `root`
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn list(&self, arguments: &serde_json::Value) -> String {
+        let path = arguments["path"].as_str().unwrap_or(".");
+        let path = match resolve_within(&self.root, path) {
+            Ok(path) => path,
+            Err(message) => return message,
+        };
+        if !path.is_dir() {
+            return format!("Error: {} is not a directory", path.display());
+        }
+
+        match directory::scan_directory(&path, LIST_DIRECTORY_DEPTH, 0) {
+            Ok(listing) if listing.is_empty() => "(empty directory)".to_string(),
+            Ok(listing) => listing,
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+}
+
+impl Tool for ListDirectoryTool {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "List the files and subdirectories of a directory in the current working directory"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path of the directory, relative to the working directory (default \".\")"
+                }
+            }
+        })
+    }
+
+    fn call(&self, arguments: serde_json::Value) -> ToolFuture<'_> {
+        Box::pin(async move { self.list(&arguments) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tool_call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            call_type: function_type(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tool_definitions() {
+        let mut registry = ToolRegistry::default();
+        assert!(registry.is_empty());
+        registry.register(Arc::new(ReadFileTool::new(PathBuf::from("."))));
+        registry.register(Arc::new(ReadFileTool::new(PathBuf::from("."))));
+
+        let definitions = serde_json::to_value(registry.definitions()).expect("Should serialize");
+        assert_eq!(definitions.as_array().map(Vec::len), Some(1));
+        assert_eq!(definitions[0]["type"], "function");
+        assert_eq!(definitions[0]["function"]["name"], "read_file");
+        assert_eq!(
+            definitions[0]["function"]["parameters"]["required"][0],
+            "path"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_file_tool() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        fs::write(temp_dir.path().join("notes.txt"), "hello world").expect("Failed to write");
+
+        let mut registry = ToolRegistry::default();
+        registry.register(Arc::new(ReadFileTool::new(temp_dir.path().to_path_buf())));
+
+        let output = registry
+            .call(&tool_call("read_file", r#"{"path": "notes.txt"}"#))
+            .await;
+        assert_eq!(output, "hello world");
+
+        let output = registry
+            .call(&tool_call(
+                "read_file",
+                r#"{"path": "notes.txt", "max_bytes": 5}"#,
+            ))
+            .await;
+        assert_eq!(output, "hello\n[truncated after 5 bytes]");
+
+        // Limits beyond the maximum are clamped rather than overflowing
+        let large = "a".repeat(MAX_READ_LIMIT + 10);
+        fs::write(temp_dir.path().join("large.txt"), &large).expect("Failed to write");
+        let output = registry
+            .call(&tool_call(
+                "read_file",
+                &format!(r#"{{"path": "large.txt", "max_bytes": {}}}"#, u64::MAX),
+            ))
+            .await;
+        assert_eq!(
+            output,
+            format!(
+                "{}\n[truncated after {} bytes]",
+                &large[..MAX_READ_LIMIT],
+                MAX_READ_LIMIT
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_file_tool_stays_in_root() {
+        let outside = tempdir().expect("Failed to create temp directory");
+        fs::write(outside.path().join("secret.txt"), "secret").expect("Failed to write");
+        let root = outside.path().join("project");
+        fs::create_dir(&root).expect("Failed to create directory");
+
+        let output = ReadFileTool::new(root)
+            .call(json!({ "path": "../secret.txt" }))
+            .await;
+        assert!(output.contains("outside the working directory"));
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_tool() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        fs::create_dir(temp_dir.path().join("src")).expect("Failed to create directory");
+        fs::write(temp_dir.path().join("src/main.rs"), "").expect("Failed to write");
+
+        let tool = ListDirectoryTool::new(temp_dir.path().to_path_buf());
+        let output = tool.call(json!({})).await;
+        assert!(output.contains("📁 src/"));
+        assert!(output.contains("📄 main.rs"));
+
+        let output = tool.call(json!({ "path": "src/main.rs" })).await;
+        assert!(output.contains("is not a directory"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_and_bad_arguments() {
+        let mut registry = ToolRegistry::default();
+        registry.register(Arc::new(ReadFileTool::new(PathBuf::from("."))));
+
+        let output = registry.call(&tool_call("delete_everything", "{}")).await;
+        assert_eq!(output, "Error: unknown tool 'delete_everything'");
+        let output = registry.call(&tool_call("read_file", "not json")).await;
+        assert!(output.starts_with("Error: arguments aren't valid JSON"));
+    }
+}
@@ -0,0 +1,219 @@
+// On-disk cache of model responses for ShellAI
+
+use crate::agents::openai::Completion;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A cached response together with the time it was stored
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Seconds since the Unix epoch
+    created: u64,
+    completion: Completion,
+}
+
+/// Stores responses on disk, keyed on everything that was sent to the model
+///
+/// Every operation is best effort: a cache that can't be read or written behaves like an
+/// empty cache and never fails the request.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+/// Converts a time into whole seconds since the Unix epoch
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+impl ResponseCache {
+    /// Creates a cache in `dir` whose entries expire after `ttl`, keeping at most `max_entries`
+    pub fn new(dir: PathBuf, ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            dir,
+            ttl,
+            max_entries,
+        }
+    }
+
+    /// Gets the default cache directory (`~/.cache/shellai` on Linux)
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("shellai"))
+    }
+
+    /// Builds the cache key for a request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The serialized request, including the model, system prompt, history and
+    ///   prompt
+    ///
+    /// # Returns
+    ///
+    /// The SHA-256 of the request as a hex string
+    pub fn key(request: &str) -> String {
+        format!("{:x}", Sha256::digest(request.as_bytes()))
+    }
+
+    // Path of the file holding an entry
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Looks up a response that hasn't expired yet
+    pub fn get(&self, key: &str) -> Option<Completion> {
+        self.get_at(key, SystemTime::now())
+    }
+
+    fn get_at(&self, key: &str, now: SystemTime) -> Option<Completion> {
+        let path = self.entry_path(key);
+        let entry: CacheEntry = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+
+        if unix_seconds(now).saturating_sub(entry.created) > self.ttl.as_secs() {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.completion)
+    }
+
+    /// Stores a response, evicting the oldest entries if the cache is full
+    pub fn put(&self, key: &str, completion: &Completion) {
+        let _ = self.put_at(key, completion, SystemTime::now());
+    }
+
+    fn put_at(&self, key: &str, completion: &Completion, now: SystemTime) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let entry = CacheEntry {
+            created: unix_seconds(now),
+            completion: completion.clone(),
+        };
+        fs::write(self.entry_path(key), serde_json::to_string(&entry)?)?;
+
+        self.evict()
+    }
+
+    // Remove the oldest entries until at most `max_entries` are left
+    fn evict(&self) -> io::Result<()> {
+        let mut entries: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                entries.push((entry_created(&path), path));
+            }
+        }
+
+        if entries.len() <= self.max_entries {
+            return Ok(());
+        }
+
+        entries.sort();
+        let excess = entries.len() - self.max_entries;
+        for (_, path) in entries.into_iter().take(excess) {
+            let _ = fs::remove_file(path);
+        }
+        Ok(())
+    }
+}
+
+// Creation time of an entry file, unreadable entries counting as the oldest
+fn entry_created(path: &Path) -> u64 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<CacheEntry>(&contents).ok())
+        .map_or(0, |entry| entry.created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn completion(content: &str) -> Completion {
+        Completion {
+            content: content.to_string(),
+            model: "gpt-4".to_string(),
+            usage: None,
+            trimmed_messages: 0,
+            fallback: false,
+            attempt: 1,
+            latency_ms: None,
+            cached: false,
+            system_fingerprint: None,
+        }
+    }
+
+    #[test]
+    fn test_key_depends_on_request() {
+        assert_eq!(ResponseCache::key("request"), ResponseCache::key("request"));
+        assert_ne!(ResponseCache::key("request"), ResponseCache::key("other"));
+        assert_eq!(ResponseCache::key("request").len(), 64);
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let cache = ResponseCache::new(temp_dir.path().join("cache"), HOUR, 10);
+
+        assert_eq!(cache.get("key"), None);
+        cache.put("key", &completion("answer"));
+        assert_eq!(cache.get("key"), Some(completion("answer")));
+    }
+
+    #[test]
+    fn test_expired_entries_are_ignored() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let cache = ResponseCache::new(temp_dir.path().to_path_buf(), HOUR, 10);
+        let now = SystemTime::now();
+
+        cache
+            .put_at("key", &completion("answer"), now)
+            .expect("Failed to store entry");
+        assert!(cache.get_at("key", now + HOUR / 2).is_some());
+        assert!(cache.get_at("key", now + HOUR * 2).is_none());
+        // Expired entries are removed
+        assert!(!cache.entry_path("key").exists());
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let cache = ResponseCache::new(temp_dir.path().to_path_buf(), HOUR, 2);
+        let now = SystemTime::now();
+
+        for (i, key) in ["first", "second", "third"].iter().enumerate() {
+            let stored_at = now + Duration::from_secs(i as u64);
+            cache
+                .put_at(key, &completion(key), stored_at)
+                .expect("Failed to store entry");
+        }
+
+        let later = now + Duration::from_secs(10);
+        assert!(cache.get_at("first", later).is_none());
+        assert!(cache.get_at("second", later).is_some());
+        assert!(cache.get_at("third", later).is_some());
+    }
+
+    #[test]
+    fn test_unusable_directory_is_ignored() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let file_path = temp_dir.path().join("not-a-directory");
+        fs::write(&file_path, "").expect("Failed to create file");
+
+        let cache = ResponseCache::new(file_path, HOUR, 10);
+        cache.put("key", &completion("answer"));
+        assert_eq!(cache.get("key"), None);
+    }
+}
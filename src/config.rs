@@ -0,0 +1,578 @@
+// Configuration file support for ShellAI
+
+use crate::agents::models::ReasoningEffort;
+use crate::agents::openai::DEFAULT_MAX_REQUEST_BYTES;
+use crate::agents::provider::Provider;
+use crate::ui::keys::KeyBindings;
+use crate::ui::theme::Theme;
+use crate::utils::directory::{ContextFormat, TreeStyle};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Table holding the named profiles in `config.toml`
+const PROFILES_KEY: &str = "profiles";
+
+/// Settings of the on-disk response cache (`[cache]` in `config.toml`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Whether repeated requests are answered from the cache
+    pub enabled: bool,
+    /// How long a cached response stays valid, in hours
+    pub ttl_hours: u64,
+    /// Number of responses kept before the oldest are evicted
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_hours: 24 * 7,
+            max_entries: 500,
+        }
+    }
+}
+
+// Default for `max_output_bytes`
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 8_000;
+
+// Default for `max_attachment_bytes`
+const DEFAULT_MAX_ATTACHMENT_BYTES: usize = 32_000;
+
+/// User configuration loaded from `config.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How the current directory is described in the system prompt
+    pub context_format: ContextFormat,
+    /// Thinking effort for reasoning models (the API default is used when unset)
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// API key to use instead of `OPENAI_API_KEY`
+    pub api_key: Option<String>,
+    /// File holding the API key, read when neither `OPENAI_API_KEY` nor
+    /// `OPENAI_API_KEY_FILE` is set
+    pub api_key_path: Option<PathBuf>,
+    /// Root of an OpenAI-compatible API to use instead of the OpenAI API, unless
+    /// `OPENAI_BASE_URL` is set
+    pub base_url: Option<String>,
+    /// API provider used at startup
+    pub provider: Provider,
+    /// Model selected at startup, if the provider in use is `provider`
+    pub model: Option<String>,
+    /// Instructions that replace the built-in system prompt
+    pub system_prompt: Option<String>,
+    /// File whose contents replace the built-in system prompt, taking precedence over
+    /// `system_prompt`
+    pub system_prompt_path: Option<PathBuf>,
+    /// Tokens a conversation request may use before older messages are trimmed
+    pub context_budget: Option<usize>,
+    /// Models to try, in order, when the selected model fails
+    pub fallback_models: Vec<String>,
+    /// Local Ollama model offered for the rest of the session when the account runs out of
+    /// quota
+    pub ollama_model: Option<String>,
+    /// Times a request is repeated on the same model after a transient error, such as a
+    /// rate limit or an overloaded server, before falling back
+    pub max_retries: u32,
+    /// On-disk response cache settings
+    pub cache: CacheConfig,
+    /// Environment variables set for executed code blocks, over the inherited ones
+    pub env: HashMap<String, String>,
+    /// Command prefixes, such as `ls` or `git status`, of code blocks that run without
+    /// asking; empty by default, and dangerous code always asks
+    pub auto_approve: Vec<String>,
+    /// SQLite database file SQL query blocks run on, an in-memory database if unset
+    pub sqlite_database: Option<PathBuf>,
+    /// Largest command output, in bytes per stream, sent back to the model after execution
+    pub max_output_bytes: usize,
+    /// Largest file, in bytes, attached to a prompt with an @path reference
+    pub max_attachment_bytes: usize,
+    /// Sampling seed sent with every request
+    pub seed: Option<u64>,
+    /// Upper bound on the number of tokens in each response
+    pub max_tokens: Option<u32>,
+    /// Whether the model may call the built-in tools to read files in the working directory
+    pub tools: bool,
+    /// Whether to suggest related commands after code runs successfully
+    pub suggest_related_commands: bool,
+    /// Whether to print a summary of what a longer session accomplished when leaving it
+    pub session_summary_on_exit: bool,
+    /// Whether to ring the terminal bell when background jobs finish, once the prompt is idle
+    pub job_bell: bool,
+    /// Whether to print how long each answer and each command run took
+    pub show_timings: bool,
+    /// Whether the system prompt describes the git repository of the working directory
+    pub git_context: bool,
+    /// Largest staged diff added to the git context, in kilobytes (the diff is left out if unset)
+    pub git_diff_kb: Option<usize>,
+    /// Whether the system prompt lists the locked versions of a Rust project's direct dependencies
+    pub include_dependency_versions: bool,
+    /// Globs a file must match to be shown in the directory scan (all files if empty)
+    pub include_globs: Vec<String>,
+    /// Globs of files and directories left out of the directory scan, winning over includes
+    pub exclude_globs: Vec<String>,
+    /// Whether the directory scan leaves out the files and directories git ignores
+    pub respect_gitignore: bool,
+    /// Whether the directory tree shows file sizes and directory subtotals
+    pub show_sizes: bool,
+    /// List files changed most in the git history of the last this many days first in the tree
+    pub change_frequency_days: Option<u32>,
+    /// How the directory tree is drawn: indented, or with `tree(1)`-style connectors
+    pub tree_style: TreeStyle,
+    /// Largest chat request sent to the API, in bytes, so oversized prompts fail early
+    pub max_request_bytes: usize,
+    /// Least time between two API requests, in seconds, waited out before sending too soon
+    pub min_request_interval: Option<f64>,
+    /// Keys of the prompt actions (`[keys]` in `config.toml`), checked for conflicts
+    pub keys: KeyBindings,
+    /// Colors of the output (`[theme]` in `config.toml`), from a preset and overrides
+    pub theme: Theme,
+    /// Name of the profile merged over the base configuration, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+}
+
+/// Expands a leading `~` in a path to the home directory
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Merges the values of `overlay` over `base`, recursing into tables present in both
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            context_format: ContextFormat::default(),
+            reasoning_effort: None,
+            api_key: None,
+            api_key_path: None,
+            base_url: None,
+            provider: Provider::default(),
+            model: None,
+            system_prompt: None,
+            system_prompt_path: None,
+            context_budget: None,
+            fallback_models: Vec::new(),
+            ollama_model: None,
+            max_retries: 2,
+            cache: CacheConfig::default(),
+            env: HashMap::new(),
+            auto_approve: Vec::new(),
+            sqlite_database: None,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            max_attachment_bytes: DEFAULT_MAX_ATTACHMENT_BYTES,
+            seed: None,
+            max_tokens: None,
+            tools: false,
+            suggest_related_commands: true,
+            session_summary_on_exit: false,
+            job_bell: true,
+            show_timings: true,
+            git_context: true,
+            git_diff_kb: None,
+            include_dependency_versions: false,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            respect_gitignore: false,
+            show_sizes: false,
+            change_frequency_days: None,
+            tree_style: TreeStyle::default(),
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            min_request_interval: None,
+            keys: KeyBindings::default(),
+            theme: Theme::default(),
+            profile: None,
+        }
+    }
+}
+
+impl Config {
+    /// Gets the default configuration file path (`~/.config/shellai/config.toml` on Linux)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("shellai").join("config.toml"))
+    }
+
+    /// Loads the configuration from the default path
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - Name of a `[profiles.<name>]` section to merge over the base configuration
+    ///
+    /// # Returns
+    ///
+    /// The parsed configuration, or the defaults if no configuration file exists and no
+    /// profile was requested
+    pub fn load(profile: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load_from(&path, profile),
+            _ => match profile {
+                Some(name) => Err(format!(
+                    "Profile '{}' requested but there is no configuration file",
+                    name
+                )
+                .into()),
+                None => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Loads the configuration from a specific file
+    pub fn load_from(path: &Path, profile: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_toml_with_profile(&contents, profile)
+    }
+
+    /// Gets the instructions that replace the built-in system prompt
+    ///
+    /// # Returns
+    ///
+    /// The contents of `system_prompt_path` if set, otherwise `system_prompt`, or `None` to
+    /// keep the built-in prompt. Fails if the file can't be read.
+    pub fn custom_system_prompt(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match &self.system_prompt_path {
+            Some(path) => {
+                let path = expand_home(path);
+                let contents = fs::read_to_string(&path).map_err(|e| {
+                    format!(
+                        "Failed to read system prompt file {}: {}",
+                        path.display(),
+                        e
+                    )
+                })?;
+                Ok(Some(contents.trim().to_string()))
+            }
+            None => Ok(self.system_prompt.clone()),
+        }
+    }
+
+    /// Gets `api_key_path` with a leading `~` expanded to the home directory
+    pub fn api_key_file(&self) -> Option<PathBuf> {
+        self.api_key_path.as_deref().map(expand_home)
+    }
+
+    /// Gets `sqlite_database` with a leading `~` expanded to the home directory
+    pub fn sqlite_database_path(&self) -> Option<PathBuf> {
+        self.sqlite_database.as_deref().map(expand_home)
+    }
+
+    /// Parses the configuration from TOML text
+    pub fn from_toml(contents: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_toml_with_profile(contents, None)
+    }
+
+    /// Parses the configuration from TOML text and merges a named profile over it
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The TOML text
+    /// * `profile` - Name of a `[profiles.<name>]` section, whose values take precedence
+    ///
+    /// # Returns
+    ///
+    /// The merged configuration, or an error if the profile doesn't exist
+    pub fn from_toml_with_profile(
+        contents: &str,
+        profile: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        let mut profiles = match table.remove(PROFILES_KEY) {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => return Err("'profiles' must be a table of named profiles".into()),
+            None => toml::Table::new(),
+        };
+
+        if let Some(name) = profile {
+            match profiles.remove(name) {
+                Some(toml::Value::Table(overlay)) => merge_tables(&mut table, overlay),
+                Some(_) => return Err(format!("Profile '{}' must be a table", name).into()),
+                None => {
+                    let mut names: Vec<&String> = profiles.keys().collect();
+                    names.sort();
+                    let available = if names.is_empty() {
+                        "none defined".to_string()
+                    } else {
+                        names
+                            .iter()
+                            .map(|name| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    };
+                    return Err(format!(
+                        "Profile '{}' not found in the configuration (available: {})",
+                        name, available
+                    )
+                    .into());
+                }
+            }
+        }
+
+        let mut config: Self = table.try_into()?;
+        config.profile = profile.map(str::to_string);
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::keys::KeyAction;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = Config::from_toml("").expect("Failed to parse empty config");
+        assert_eq!(config.context_format, ContextFormat::Tree);
+        assert!(config.fallback_models.is_empty());
+        assert_eq!(config.ollama_model, None);
+        assert!(config.auto_approve.is_empty());
+        assert_eq!(config.api_key_path, None);
+        assert_eq!(config.sqlite_database, None);
+        assert_eq!(config.max_retries, 2);
+        assert_eq!(config.max_output_bytes, DEFAULT_MAX_OUTPUT_BYTES);
+        assert_eq!(config.max_attachment_bytes, DEFAULT_MAX_ATTACHMENT_BYTES);
+        assert_eq!(config.max_tokens, None);
+        assert!(!config.tools);
+        assert!(config.git_context);
+        assert!(config.suggest_related_commands);
+        assert!(!config.session_summary_on_exit);
+        assert!(config.job_bell);
+        assert!(config.show_timings);
+        assert_eq!(config.git_diff_kb, None);
+        assert!(!config.include_dependency_versions);
+        assert!(config.include_globs.is_empty());
+        assert!(config.exclude_globs.is_empty());
+        assert!(!config.respect_gitignore);
+        assert!(!config.show_sizes);
+        assert_eq!(config.change_frequency_days, None);
+        assert_eq!(config.tree_style, TreeStyle::Indented);
+        assert_eq!(config.max_request_bytes, DEFAULT_MAX_REQUEST_BYTES);
+        assert_eq!(config.min_request_interval, None);
+        assert_eq!(config.keys, KeyBindings::default());
+        assert_eq!(config.theme, Theme::DARK);
+        assert_eq!(config.provider, Provider::OpenAI);
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let config = Config::from_toml("max_tokens = 800").expect("Failed to parse config");
+        assert_eq!(config.max_tokens, Some(800));
+    }
+
+    #[test]
+    fn test_cache_config() {
+        assert!(!Config::default().cache.enabled);
+
+        let config = Config::from_toml("[cache]\nenabled = true\nttl_hours = 1")
+            .expect("Failed to parse config");
+        assert_eq!(
+            config.cache,
+            CacheConfig {
+                enabled: true,
+                ttl_hours: 1,
+                ..CacheConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        assert!(Config::default().env.is_empty());
+
+        let config = Config::from_toml("[env]\nPROJECT_ROOT = \"/srv/app\"\nRUST_LOG = \"info\"")
+            .expect("Failed to parse config");
+        assert_eq!(config.env.len(), 2);
+        assert_eq!(config.env["PROJECT_ROOT"], "/srv/app");
+    }
+
+    #[test]
+    fn test_theme() {
+        let config = Config::from_toml("[theme]\npreset = \"light\"\nprompt = \"blue\"")
+            .expect("Failed to parse config");
+        assert_eq!(config.theme.prompt, colored::Color::Blue);
+        assert_eq!(config.theme.code, Theme::LIGHT.code);
+        assert!(Config::from_toml("[theme]\nprompt = \"teal\"").is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        let config = Config::from_toml(
+            "[keys]\nsubmit = \"ctrl+d\"\nnewline = [\"enter\", \"shift+enter\"]",
+        )
+        .expect("Failed to parse config");
+        assert_eq!(config.keys.describe(KeyAction::Submit), "Ctrl+D");
+        assert_eq!(
+            config.keys.describe(KeyAction::Newline),
+            "Enter/Shift+Enter"
+        );
+        assert_eq!(config.keys.describe(KeyAction::Help), "Ctrl+H");
+
+        let error = Config::from_toml("[keys]\nhelp = \"ctrl+s\"").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Ctrl+S is bound to both 'submit' and 'help'"));
+        assert!(Config::from_toml("[keys]\nsend = \"ctrl+d\"").is_err());
+        assert!(Config::from_toml("[keys]\nsubmit = \"ctrl+nope\"").is_err());
+    }
+
+    #[test]
+    fn test_fallback_models() {
+        let config = Config::from_toml("fallback_models = [\"gpt-4o\", \"gpt-4o-mini\"]")
+            .expect("Failed to parse config");
+        assert_eq!(config.fallback_models, vec!["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[test]
+    fn test_context_format() {
+        let config =
+            Config::from_toml("context_format = \"combined\"").expect("Failed to parse config");
+        assert_eq!(config.context_format, ContextFormat::Combined);
+
+        assert!(Config::from_toml("context_format = \"unknown\"").is_err());
+
+        let config = Config::from_toml("tree_style = \"lines\"").expect("Failed to parse config");
+        assert_eq!(config.tree_style, TreeStyle::Lines);
+    }
+
+    #[test]
+    fn test_provider() {
+        let config = Config::from_toml("provider = \"ollama\"\nmodel = \"mistral\"")
+            .expect("Failed to parse config");
+        assert_eq!(config.provider, Provider::Ollama);
+        assert!(Config::from_toml("provider = \"gemini\"").is_err());
+    }
+
+    #[test]
+    fn test_reasoning_effort() {
+        let config =
+            Config::from_toml("reasoning_effort = \"high\"").expect("Failed to parse config");
+        assert_eq!(config.reasoning_effort, Some(ReasoningEffort::High));
+        assert!(Config::from_toml("reasoning_effort = \"extreme\"").is_err());
+        assert_eq!(Config::default().reasoning_effort, None);
+    }
+
+    const PROFILES_CONFIG: &str = r#"
+context_format = "ls"
+model = "gpt-4o"
+system_prompt = "Be brief."
+
+[profiles.work]
+api_key = "sk-work"
+model = "o3-mini"
+reasoning_effort = "low"
+
+[profiles.personal]
+context_format = "tree"
+"#;
+
+    #[test]
+    fn test_profile_merges_over_base_config() {
+        let config = Config::from_toml_with_profile(PROFILES_CONFIG, Some("work"))
+            .expect("Failed to parse config");
+        assert_eq!(config.profile.as_deref(), Some("work"));
+        assert_eq!(config.api_key.as_deref(), Some("sk-work"));
+        assert_eq!(config.model.as_deref(), Some("o3-mini"));
+        assert_eq!(config.reasoning_effort, Some(ReasoningEffort::Low));
+        // Values the profile doesn't set come from the base config
+        assert_eq!(config.context_format, ContextFormat::Ls);
+        assert_eq!(config.system_prompt.as_deref(), Some("Be brief."));
+
+        let config = Config::from_toml_with_profile(PROFILES_CONFIG, Some("personal"))
+            .expect("Failed to parse config");
+        assert_eq!(config.context_format, ContextFormat::Tree);
+        assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.api_key, None);
+    }
+
+    #[test]
+    fn test_profiles_ignored_without_profile() {
+        let config = Config::from_toml(PROFILES_CONFIG).expect("Failed to parse config");
+        assert_eq!(config.profile, None);
+        assert_eq!(config.api_key, None);
+        assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let error = Config::from_toml_with_profile(PROFILES_CONFIG, Some("home"))
+            .expect_err("Unknown profile should fail")
+            .to_string();
+        assert!(error.contains("'home'"));
+        assert!(error.contains("personal, work"));
+    }
+
+    #[test]
+    fn test_custom_system_prompt() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let prompt_path = temp_dir.path().join("prompt.md");
+        fs::write(&prompt_path, "Answer in French.\n").expect("Failed to write prompt file");
+
+        let config = Config {
+            system_prompt: Some("Be brief.".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            config
+                .custom_system_prompt()
+                .expect("Failed to read prompt"),
+            Some("Be brief.".to_string())
+        );
+
+        // The file takes precedence over the inline prompt
+        let config = Config {
+            system_prompt_path: Some(prompt_path),
+            ..config
+        };
+        assert_eq!(
+            config
+                .custom_system_prompt()
+                .expect("Failed to read prompt"),
+            Some("Answer in French.".to_string())
+        );
+
+        let config = Config {
+            system_prompt_path: Some(temp_dir.path().join("missing.md")),
+            ..config
+        };
+        assert!(config.custom_system_prompt().is_err());
+
+        assert_eq!(
+            Config::default()
+                .custom_system_prompt()
+                .expect("Failed to read prompt"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_expand_home() {
+        assert_eq!(
+            expand_home(Path::new("/tmp/prompt.md")),
+            Path::new("/tmp/prompt.md")
+        );
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(
+                expand_home(Path::new("~/prompt.md")),
+                home.join("prompt.md")
+            );
+        }
+    }
+}
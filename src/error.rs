@@ -0,0 +1,128 @@
+// Error types for ShellAI
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Billing page shown when the OpenAI account runs out of credit
+pub const OPENAI_BILLING_URL: &str = "https://platform.openai.com/settings/organization/billing";
+
+/// Errors reported by the ShellAI agents
+#[derive(Debug)]
+pub enum ShellAIError {
+    /// The account has no credit left (`insufficient_quota`)
+    InsufficientQuota { message: String },
+    /// The API answered with an error object
+    ApiError {
+        message: String,
+        error_type: Option<String>,
+        code: Option<String>,
+    },
+    /// The API answered with an error status and a body that isn't an error object
+    RequestFailed(String),
+    /// The request couldn't be sent or the response couldn't be read
+    Http(reqwest::Error),
+    /// A local file couldn't be read
+    Io(io::Error),
+    /// The audio file has an extension the transcription API doesn't accept
+    UnsupportedAudioFormat(String),
+    /// The model's answer doesn't match the requested JSON schema
+    InvalidStructuredResponse(String),
+    /// The API rejected the API key (HTTP 401)
+    AuthenticationFailed,
+    /// The request body is larger than the configured limit, so it wasn't sent
+    RequestTooLarge {
+        actual_bytes: usize,
+        limit_bytes: usize,
+    },
+    /// The API answered with a body that isn't the expected JSON, such as an HTML error
+    /// page from a proxy; `preview` holds the start of it
+    MalformedResponse { preview: String },
+}
+
+impl ShellAIError {
+    /// Returns true if repeating the same request could succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Retrying won't help until the account is topped up
+            ShellAIError::InsufficientQuota { .. } => false,
+            ShellAIError::Http(e) => e.is_timeout() || e.is_connect(),
+            // Rate limits lift after a while, other API errors need the request changed
+            ShellAIError::ApiError { code, .. } => code.as_deref() == Some("rate_limit_exceeded"),
+            ShellAIError::RequestFailed(_)
+            | ShellAIError::Io(_)
+            | ShellAIError::UnsupportedAudioFormat(_)
+            | ShellAIError::InvalidStructuredResponse(_)
+            | ShellAIError::AuthenticationFailed
+            | ShellAIError::RequestTooLarge { .. }
+            | ShellAIError::MalformedResponse { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for ShellAIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellAIError::InsufficientQuota { message } => {
+                write!(f, "Account is out of quota: {}", message)
+            }
+            ShellAIError::ApiError {
+                message,
+                error_type,
+                code,
+            } => {
+                write!(f, "OpenAI API error: {}", message)?;
+                match (code, error_type) {
+                    (Some(code), _) => write!(f, " (code: {})", code),
+                    (None, Some(error_type)) => write!(f, " ({})", error_type),
+                    (None, None) => Ok(()),
+                }
+            }
+            ShellAIError::RequestFailed(message) => write!(f, "{}", message),
+            ShellAIError::Http(e) => write!(f, "HTTP error: {}", e),
+            ShellAIError::Io(e) => write!(f, "IO error: {}", e),
+            ShellAIError::UnsupportedAudioFormat(extension) => {
+                write!(f, "Unsupported audio format: '{}'", extension)
+            }
+            ShellAIError::InvalidStructuredResponse(reason) => {
+                write!(f, "Response doesn't match the requested schema: {}", reason)
+            }
+            ShellAIError::AuthenticationFailed => {
+                write!(f, "Authentication failed — check OPENAI_API_KEY")
+            }
+            ShellAIError::RequestTooLarge {
+                actual_bytes,
+                limit_bytes,
+            } => write!(
+                f,
+                "Request not sent: it is {} bytes, more than the limit of {} bytes (max_request_bytes)",
+                actual_bytes, limit_bytes
+            ),
+            ShellAIError::MalformedResponse { preview } => {
+                write!(f, "Malformed API response, the server sent: {}", preview)
+            }
+        }
+    }
+}
+
+impl Error for ShellAIError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShellAIError::Http(e) => Some(e),
+            ShellAIError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ShellAIError {
+    fn from(e: reqwest::Error) -> Self {
+        ShellAIError::Http(e)
+    }
+}
+
+impl From<io::Error> for ShellAIError {
+    fn from(e: io::Error) -> Self {
+        ShellAIError::Io(e)
+    }
+}
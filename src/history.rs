@@ -0,0 +1,207 @@
+// Prompts submitted in earlier sessions, recalled with Up/Down and Ctrl+R
+
+use crate::utils::files::write_private;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most prompts kept in the history file, the oldest being forgotten first
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Submitted prompts, oldest first
+///
+/// The file holds one prompt per line, with line breaks and backslashes escaped as `\n`
+/// and `\\` so multi-line prompts round-trip intact.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    entries: Vec<String>,
+}
+
+impl History {
+    /// Gets the default history file path (`~/.local/share/shellai/history` on Linux)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("shellai").join("history"))
+    }
+
+    /// Loads the history from the default path
+    ///
+    /// # Returns
+    ///
+    /// The saved prompts, an empty history if nothing was saved yet, or an error if the
+    /// file can't be read
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match Self::default_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads the history from a file, starting empty if it doesn't exist
+    pub fn load_from(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut history = Self::default();
+        for line in fs::read_to_string(path)?.lines() {
+            history.add(&unescape_entry(line));
+        }
+        Ok(history)
+    }
+
+    /// Saves the history to the default path
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::default_path().ok_or("No directory to save the history in")?;
+        self.save_to(&path)
+    }
+
+    /// Saves the history to a file only its owner can read, creating its directory if
+    /// needed
+    pub fn save_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|entry| escape_entry(entry) + "\n")
+            .collect();
+        write_private(path, contents)?;
+        Ok(())
+    }
+
+    /// Gets the prompts, oldest first
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Adds a submitted prompt
+    ///
+    /// Blank prompts and repeats of the latest prompt are left out, and the oldest prompts
+    /// are dropped beyond `HISTORY_LIMIT`.
+    pub fn add(&mut self, prompt: &str) {
+        if prompt.trim().is_empty() || self.entries.last().is_some_and(|last| last == prompt) {
+            return;
+        }
+        self.entries.push(prompt.to_string());
+        if self.entries.len() > HISTORY_LIMIT {
+            self.entries.remove(0);
+        }
+    }
+}
+
+/// Finds the latest prompt containing `query`, as Ctrl+R does
+///
+/// # Arguments
+///
+/// * `entries` - The prompts, oldest first
+/// * `query` - The text to look for
+/// * `before` - Only prompts before this index are searched, to find older matches
+///
+/// # Returns
+///
+/// The index of the matching prompt, or None if no earlier prompt contains the query
+pub fn search(entries: &[String], query: &str, before: usize) -> Option<usize> {
+    entries[..before.min(entries.len())]
+        .iter()
+        .rposition(|entry| entry.contains(query))
+}
+
+// Write a prompt on one line of the history file
+fn escape_entry(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+// Read a prompt back from its line of the history file
+fn unescape_entry(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_multiline_entries_round_trip() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let path = temp_dir.path().join("shellai").join("history");
+
+        let mut history = History::default();
+        history.add("list files");
+        history.add("explain this:\nfor f in *; do echo \"$f\"; done");
+        history.add(r"printf 'a\nb' | grep 'C:\\Users'");
+        history.save_to(&path).expect("Failed to save history");
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(
+            History::load_from(&path).expect("Failed to load history"),
+            history
+        );
+    }
+
+    #[test]
+    fn test_add_skips_blanks_and_repeats() {
+        let mut history = History::default();
+        history.add("ls");
+        history.add("ls");
+        history.add("  \n");
+        history.add("pwd");
+        history.add("ls");
+        assert_eq!(history.entries(), ["ls", "pwd", "ls"]);
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut history = History::default();
+        for i in 0..HISTORY_LIMIT + 5 {
+            history.add(&i.to_string());
+        }
+        assert_eq!(history.entries().len(), HISTORY_LIMIT);
+        assert_eq!(history.entries()[0], "5");
+    }
+
+    #[test]
+    fn test_search_finds_latest_match_first() {
+        let entries = ["git status", "ls -la", "git log", "pwd"].map(String::from);
+        assert_eq!(search(&entries, "git", entries.len()), Some(2));
+        assert_eq!(search(&entries, "git", 2), Some(0));
+        assert_eq!(search(&entries, "git", 0), None);
+        assert_eq!(search(&entries, "cargo", entries.len()), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_history_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let path = temp_dir.path().join("history");
+        History::default()
+            .save_to(&path)
+            .expect("Failed to save history");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_missing_file_is_empty_history() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let history = History::load_from(&temp_dir.path().join("history")).unwrap();
+        assert!(history.entries().is_empty());
+    }
+}
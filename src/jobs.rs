@@ -0,0 +1,221 @@
+// Background prompt jobs for ShellAI
+
+use std::future::Future;
+use tokio::task::JoinHandle;
+
+/// State of a background job
+#[derive(Debug)]
+pub enum JobState {
+    /// The request is still running
+    Running(JoinHandle<Result<String, String>>),
+    /// The request finished with a response
+    Finished(String),
+    /// The request failed with an error message
+    Failed(String),
+    /// The job was cancelled before it finished
+    Cancelled,
+}
+
+/// A prompt submitted to run in the background
+#[derive(Debug)]
+pub struct BackgroundJob {
+    pub id: usize,
+    pub prompt: String,
+    pub state: JobState,
+    /// Whether the answer was added to the conversation with `mark_merged`
+    pub merged: bool,
+}
+
+impl BackgroundJob {
+    /// Returns true if the job is still waiting for a response
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, JobState::Running(_))
+    }
+}
+
+/// Keeps track of background jobs for the session
+///
+/// Jobs run on their own tasks. Results are only collected when the caller asks for them,
+/// so output can be held back until the prompt is idle.
+#[derive(Debug, Default)]
+pub struct JobManager {
+    jobs: Vec<BackgroundJob>,
+}
+
+impl JobManager {
+    /// Creates an empty job manager
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a background job for a prompt
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt being answered, kept for listing
+    /// * `request` - The future producing the response
+    ///
+    /// # Returns
+    ///
+    /// The job number, starting at 1
+    pub fn spawn<F>(&mut self, prompt: String, request: F) -> usize
+    where
+        F: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        let id = self.jobs.len() + 1;
+        self.jobs.push(BackgroundJob {
+            id,
+            prompt,
+            state: JobState::Running(tokio::spawn(request)),
+            merged: false,
+        });
+        id
+    }
+
+    /// Collects the results of jobs that finished since the last call
+    ///
+    /// # Returns
+    ///
+    /// The numbers of the jobs that just finished
+    pub async fn collect_finished(&mut self) -> Vec<usize> {
+        let mut finished = Vec::new();
+
+        for job in &mut self.jobs {
+            let done = matches!(&job.state, JobState::Running(handle) if handle.is_finished());
+            if !done {
+                continue;
+            }
+
+            if let JobState::Running(handle) =
+                std::mem::replace(&mut job.state, JobState::Cancelled)
+            {
+                job.state = match handle.await {
+                    Ok(Ok(response)) => JobState::Finished(response),
+                    Ok(Err(message)) => JobState::Failed(message),
+                    Err(e) => JobState::Failed(e.to_string()),
+                };
+                finished.push(job.id);
+            }
+        }
+
+        finished
+    }
+
+    /// Cancels a running job
+    ///
+    /// # Returns
+    ///
+    /// True if the job was running and has been cancelled
+    pub fn cancel(&mut self, id: usize) -> bool {
+        match self.jobs.iter_mut().find(|job| job.id == id) {
+            Some(job) if job.is_running() => {
+                if let JobState::Running(handle) = &job.state {
+                    handle.abort();
+                }
+                job.state = JobState::Cancelled;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records that the answer of a finished job was added to the conversation
+    ///
+    /// # Returns
+    ///
+    /// True if the job finished with an answer that wasn't merged before
+    pub fn mark_merged(&mut self, id: usize) -> bool {
+        match self.jobs.iter_mut().find(|job| job.id == id) {
+            Some(job) if matches!(job.state, JobState::Finished(_)) && !job.merged => {
+                job.merged = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Gets a job by number
+    pub fn get(&self, id: usize) -> Option<&BackgroundJob> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// Gets all jobs submitted this session
+    pub fn jobs(&self) -> &[BackgroundJob] {
+        &self.jobs
+    }
+
+    /// Counts the jobs that are still running
+    pub fn running_count(&self) -> usize {
+        self.jobs.iter().filter(|job| job.is_running()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Wait until every job has finished, returning the collected job numbers
+    async fn wait_for_all(manager: &mut JobManager) -> Vec<usize> {
+        let mut finished = Vec::new();
+        for _ in 0..100 {
+            finished.extend(manager.collect_finished().await);
+            if manager.running_count() == 0 {
+                finished.sort();
+                return finished;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Jobs didn't finish");
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_are_collected_once() {
+        let mut manager = JobManager::new();
+        let ok = manager.spawn("first".to_string(), async { Ok("answer".to_string()) });
+        let failed = manager.spawn("second".to_string(), async { Err("boom".to_string()) });
+        assert_eq!((ok, failed), (1, 2));
+
+        assert_eq!(wait_for_all(&mut manager).await, vec![ok, failed]);
+        assert!(manager.collect_finished().await.is_empty());
+
+        // Finished jobs can still be listed and read
+        assert!(matches!(&manager.get(ok).unwrap().state, JobState::Finished(r) if r == "answer"));
+        assert!(matches!(&manager.get(failed).unwrap().state, JobState::Failed(m) if m == "boom"));
+        assert_eq!(manager.jobs().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_finished_answers_are_merged_once() {
+        let mut manager = JobManager::new();
+        let ok = manager.spawn("first".to_string(), async { Ok("answer".to_string()) });
+        let failed = manager.spawn("second".to_string(), async { Err("boom".to_string()) });
+        wait_for_all(&mut manager).await;
+
+        assert!(!manager.get(ok).unwrap().merged);
+        assert!(manager.mark_merged(ok));
+        assert!(manager.get(ok).unwrap().merged);
+        assert!(!manager.mark_merged(ok));
+        assert!(!manager.mark_merged(failed));
+        assert!(!manager.mark_merged(3));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_job() {
+        let mut manager = JobManager::new();
+        let id = manager.spawn("slow".to_string(), async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok("too late".to_string())
+        });
+        assert_eq!(manager.running_count(), 1);
+
+        assert!(manager.cancel(id));
+        assert!(!manager.cancel(id));
+        assert_eq!(manager.running_count(), 0);
+        assert!(matches!(
+            manager.get(id).unwrap().state,
+            JobState::Cancelled
+        ));
+        assert!(manager.collect_finished().await.is_empty());
+    }
+}
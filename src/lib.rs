@@ -2,7 +2,19 @@
 
 // Export the modules
 pub mod agents;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod history;
+pub mod jobs;
+pub mod logging;
+pub mod sessions;
+pub mod state;
+pub mod ui;
 pub mod utils;
 
 // Re-export commonly used items for convenience
+pub use agents::models::AIModel;
 pub use agents::openai::OpenAIAgent;
+pub use config::Config;
+pub use error::ShellAIError;
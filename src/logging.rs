@@ -0,0 +1,76 @@
+// Diagnostic logging for debugging failed requests
+
+use tracing_subscriber::EnvFilter;
+
+/// Gets the log filter for a `--verbose` count
+///
+/// # Arguments
+///
+/// * `verbosity` - How many times `-v` was given
+///
+/// # Returns
+///
+/// The filter directives, or None to leave the level to `RUST_LOG`
+pub fn verbosity_filter(verbosity: u8) -> Option<&'static str> {
+    match verbosity {
+        0 => None,
+        1 => Some("shellai=debug"),
+        _ => Some("shellai=trace,reqwest=debug"),
+    }
+}
+
+/// Sends log lines to stderr, at the level set by `-v` or else by `RUST_LOG`
+///
+/// Nothing is logged unless one of them asks for it.
+///
+/// # Arguments
+///
+/// * `verbosity` - How many times `-v` was given
+pub fn init_logging(verbosity: u8) {
+    let filter = match verbosity_filter(verbosity) {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("off")),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Hides an API key so it can appear in logs and debug output
+///
+/// # Arguments
+///
+/// * `api_key` - The key to hide
+///
+/// # Returns
+///
+/// The key's `sk-` style prefix followed by asterisks, as in "sk-***"
+pub fn redact_api_key(api_key: &str) -> String {
+    let prefix: String = api_key.chars().take_while(|c| *c != '-').collect();
+    if prefix.len() < api_key.len() && prefix.len() <= 4 {
+        format!("{}-***", prefix)
+    } else {
+        "***".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_filter() {
+        assert_eq!(verbosity_filter(0), None);
+        assert_eq!(verbosity_filter(1), Some("shellai=debug"));
+        assert_eq!(verbosity_filter(3), Some("shellai=trace,reqwest=debug"));
+    }
+
+    #[test]
+    fn test_redact_api_key() {
+        assert_eq!(redact_api_key("sk-proj-abcdefghijklmnop"), "sk-***");
+        assert_eq!(redact_api_key("abcdefghijklmnop"), "***");
+        assert_eq!(redact_api_key("secretvalue-123"), "***");
+        assert_eq!(redact_api_key(""), "***");
+    }
+}
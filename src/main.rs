@@ -1,255 +1,2620 @@
+use clap::{ArgGroup, CommandFactory, Parser, ValueEnum};
 use colored::*;
 use crossterm::{
-    cursor::{MoveToColumn, MoveUp},
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    cursor::MoveTo,
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode},
+    terminal::{Clear, ClearType},
 };
-use regex::Regex;
-use shellai::OpenAIAgent;
-use std::io::{self, Write};
+use serde::Serialize;
+use shellai::agents::latency::{configure_timings, format_latency, timings_shown};
+use shellai::agents::models::{unknown_model_warning, ReasoningEffort};
+use shellai::agents::openai::{ChatSession, Usage};
+use shellai::agents::provider::{create_provider_agent, Provider};
+use shellai::agents::structured::{ReviewResult, SecurityReport, Severity, ShellSuggestion};
+use shellai::agents::throttle::RequestThrottle;
+use shellai::cache::ResponseCache;
+use shellai::history::History;
+use shellai::jobs::{JobManager, JobState};
+use shellai::logging::init_logging;
+use shellai::sessions::{SessionStore, MAIN_BRANCH};
+use shellai::state::{resolve_model, ModelSource, State};
+use shellai::ui::accessible::{configure_accessible, is_accessible, symbols};
+use shellai::ui::color::configure_colors;
+use shellai::ui::commands::{
+    parse_command, unknown_command_message, Command as SlashCommand, COMMANDS,
+};
+use shellai::ui::dropdown::select_dropdown;
+use shellai::ui::editor::display_width;
+use shellai::ui::highlight::highlight_rows;
+use shellai::ui::input::{read_multiline_input, InputEvent};
+use shellai::ui::keys::KeyAction;
+use shellai::ui::markdown::{configure_markdown, render_response};
+use shellai::ui::spinner::{configure_spinner, Spinner};
+use shellai::ui::terminal::{
+    horizontal_rule, install_terminal_recovery, rule_width, terminal_width,
+};
+use shellai::ui::theme::{configure_theme, Themed};
+use shellai::ui::tmux::PaneTitle;
+use shellai::utils::attachments::{
+    attach_files, file_references, with_piped_input, MissingFileChoice,
+};
+use shellai::utils::clipboard::ClipboardWatcher;
+use shellai::utils::directory::{
+    generate_shellai_ignore_from_gitignore, scan_directory_with_options, ContextFormat, PathFilter,
+    ScanOptions, SHELLAI_IGNORE_FILE,
+};
+use shellai::utils::execute::{
+    apply_choice, build_output_follow_up, build_related_commands_prompt, parse_related_commands,
+    run_batch, working_directory, BashRunner, BatchOutcome, CommandRun, CommandRunner,
+    ExecutionChoice, ExecutionOutcome,
+};
+use shellai::utils::git::GitContextOptions;
+use shellai::utils::mermaid::{
+    diagram_path, live_editor_url, mmdc_available, open_in_browser, render_png, MermaidChoice,
+};
+use shellai::utils::render::{
+    extract_bash_blocks, extract_mermaid_blocks, extract_sql_blocks, last_code_block,
+    render_as_html, render_model_table, wrap_labeled,
+};
+use shellai::utils::risk::RiskSummary;
+use shellai::utils::shell::Shell;
+use shellai::utils::sql::{choose_client, client_available, sql_command};
+use shellai::{AIModel, Config, OpenAIAgent, ShellAIError};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// ShellAI - Your AI assistant in the terminal
+#[derive(Parser, Debug)]
+#[command(name = "shellai", version, about)]
+#[command(group(ArgGroup::new("one_shot").args(["question", "prompt", "pipe"]).multiple(true)))]
+struct Cli {
+    /// Answer this question and exit, with any text piped on stdin added as context
+    #[arg(value_name = "QUESTION", conflicts_with_all = ["prompt", "pipe"])]
+    question: Option<String>,
+
+    /// Send a single prompt, print the response to stdout and exit
+    #[arg(long, value_name = "TEXT")]
+    prompt: Option<String>,
+
+    /// Log requests to stderr, -vv for more detail (RUST_LOG is used otherwise)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Read a single prompt from stdin, print the response to stdout and exit
+    #[arg(long)]
+    pipe: bool,
+
+    /// Offer to run the bash blocks of a one-shot answer, which are otherwise only printed
+    #[arg(short = 'x', long, requires = "one_shot", conflicts_with_all = ["json", "structured", "output_format", "pipe"])]
+    exec: bool,
+
+    /// Model to use instead of the configured one
+    #[arg(long, value_name = "ID")]
+    model: Option<String>,
+
+    /// API provider to use instead of the configured one: openai, anthropic or ollama
+    #[arg(long, value_name = "NAME")]
+    provider: Option<Provider>,
+
+    /// Leave the description of the current directory out of the system prompt
+    #[arg(long)]
+    no_context: bool,
+
+    /// Print the result as a JSON object instead of plain text (with a question, --prompt or --pipe)
+    #[arg(long, requires = "one_shot")]
+    json: bool,
+
+    /// Print the answer in this format instead of plain text (with a question, --prompt or --pipe)
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "one_shot", conflicts_with_all = ["json", "structured"])]
+    output_format: Option<OutputFormat>,
+
+    /// Ask for a shell suggestion as {"explanation", "commands"} JSON and print only that
+    #[arg(long, requires = "one_shot", conflicts_with = "json")]
+    structured: bool,
+
+    /// Transcribe an audio file (mp3, wav, m4a, webm) and send it as the first prompt
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["prompt", "pipe"])]
+    transcribe: Option<PathBuf>,
+
+    /// Merge the [profiles.<NAME>] section of config.toml over the base configuration
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Watch the clipboard and answer every new text copied to it, until Ctrl+C
+    #[arg(long, conflicts_with_all = ["prompt", "pipe", "transcribe"])]
+    watch_clipboard: bool,
+
+    /// Sampling seed for more reproducible answers (best effort, see the system fingerprint)
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Upper bound on the number of tokens in each response
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<u32>,
+
+    /// Only show files matching this glob in the directory scan (repeatable)
+    #[arg(long = "include", value_name = "GLOB")]
+    include_globs: Vec<String>,
+
+    /// Leave files and directories matching this glob out of the directory scan (repeatable)
+    #[arg(long = "exclude", value_name = "GLOB")]
+    exclude_globs: Vec<String>,
+
+    /// Show file sizes and directory subtotals in the directory tree sent to the model
+    #[arg(long)]
+    sizes: bool,
+
+    /// Leave the git branch, status and recent commits out of the system prompt
+    #[arg(long)]
+    no_git_context: bool,
+
+    /// Print without colors (also when NO_COLOR is set or stdout isn't a terminal)
+    #[arg(long, visible_alias = "raw")]
+    no_color: bool,
+
+    /// Print answers with their Markdown as written, instead of rendered with terminal styles
+    /// (also when stdout isn't a terminal)
+    #[arg(long)]
+    plain: bool,
+
+    /// Don't animate a spinner while waiting for the model (also when stdout isn't a terminal)
+    #[arg(long)]
+    no_spinner: bool,
+
+    /// Always call the API, even if response caching is enabled in the configuration
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Replace the built-in system prompt guidelines with the contents of a file
+    #[arg(long, value_name = "FILE")]
+    system_prompt: Option<PathBuf>,
+
+    /// Run executed code blocks in this directory instead of the current one
+    #[arg(long, value_name = "DIR")]
+    cwd: Option<PathBuf>,
+
+    /// Run every bash block of an answer in a row without asking, unless one is dangerous
+    #[arg(long)]
+    execute_all: bool,
+
+    /// Keep running the blocks of --execute-all, or of an "a" answer, after one fails
+    #[arg(long)]
+    continue_on_error: bool,
+
+    /// Continue a saved conversation, given its resume key such as orange-whale-42 or its id
+    #[arg(long, value_name = "KEY", conflicts_with_all = ["question", "prompt", "pipe", "transcribe", "watch_clipboard"])]
+    resume: Option<String>,
+}
+
+// How often --watch-clipboard checks for new text
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Result printed by `--json`, for tools that wrap ShellAI
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    response: &'a str,
+    bash_blocks: Vec<String>,
+    model: &'a str,
+    usage: Option<&'a Usage>,
+    system_fingerprint: Option<&'a str>,
+}
+
+/// Formats selectable with `--output-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// The response text as is
+    Text,
+    /// A self-contained HTML page
+    Html,
+}
+
+/// How `run_pipe_mode` prints its answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PipeOutput {
+    /// The response text as is
+    Text,
+    /// The response wrapped in a `JsonOutput` object
+    Json,
+    /// Only the `ShellSuggestion` JSON the model was asked to answer with
+    Structured,
+    /// The response rendered as an HTML page
+    Html,
+    /// The response, then an offer to run each of its bash blocks
+    Execute,
+}
+
+/// Answer a single prompt without any terminal UI, for use in scripts and pipelines
+///
+/// Exits with code 0 on success, 1 if the request fails, a structured answer doesn't
+/// validate or the offered code can't be run, and 2 if no prompt was given.
+async fn run_pipe_mode(
+    agent: &OpenAIAgent,
+    runner: &BashRunner,
+    prompt: Option<String>,
+    output: PipeOutput,
+) -> ! {
+    let prompt = match prompt {
+        Some(prompt) => prompt,
+        None => {
+            let mut input = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut input) {
+                eprintln!("Error reading prompt from stdin: {}", e);
+                std::process::exit(1);
+            }
+            input
+        }
+    };
+
+    if prompt.trim().is_empty() {
+        eprintln!("Error: no prompt given");
+        std::process::exit(2);
+    }
+
+    if output == PipeOutput::Structured {
+        let result = agent
+            .generate_structured_response::<ShellSuggestion>(
+                prompt.trim(),
+                &ShellSuggestion::schema(),
+            )
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|suggestion| serde_json::to_string(&suggestion).map_err(|e| e.to_string()));
+        match result {
+            Ok(json) => {
+                println!("{}", json);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match agent.generate_completion(prompt.trim()).await {
+        Ok(completion) if output == PipeOutput::Json => {
+            let output = JsonOutput {
+                response: &completion.content,
+                bash_blocks: extract_bash_blocks(&completion.content),
+                model: &completion.model,
+                usage: completion.usage.as_ref(),
+                system_fingerprint: completion.system_fingerprint.as_deref(),
+            };
+            match serde_json::to_string(&output) {
+                Ok(json) => {
+                    println!("{}", json);
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(completion) if output == PipeOutput::Html => {
+            print!(
+                "{}",
+                render_as_html(&completion.content, &completion.model, &current_timestamp())
+            );
+            std::process::exit(0);
+        }
+        Ok(completion) if output == PipeOutput::Execute => {
+            match show_response(&completion.content, agent, runner, None, None).await {
+                Ok(_) => std::process::exit(0),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(completion) => {
+            println!("{}", completion.content);
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Read the text piped on stdin and add it below a question
+///
+/// Exits with code 1 if stdin can't be read.
+fn question_with_stdin(question: &str, max_bytes: usize) -> String {
+    let mut input = Vec::new();
+    if let Err(e) = io::stdin().read_to_end(&mut input) {
+        eprintln!("Error reading stdin: {}", e);
+        std::process::exit(1);
+    }
+    if input.len() > max_bytes {
+        eprintln!(
+            "Note: stdin is {} bytes, only {} are sent (set max_attachment_bytes to change this)",
+            input.len(),
+            max_bytes
+        );
+    }
+    with_piped_input(question, &String::from_utf8_lossy(&input), max_bytes)
+}
+
+/// Get the local time shown on saved answers
+fn current_timestamp() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The latest answer of the session, kept for /save-html
+struct LastAnswer {
+    content: String,
+    model: String,
+    timestamp: String,
+}
+
+/// Handle the /save-html command, which saves the latest answer as an HTML page
+///
+/// # Returns
+///
+/// True if the input was a /save-html command and has been handled
+fn handle_save_html_command(input: &str, last_answer: Option<&LastAnswer>) -> bool {
+    let path = match input.trim().strip_prefix("/save-html") {
+        Some(path) if path.is_empty() || path.starts_with(' ') => path.trim(),
+        _ => return false,
+    };
+
+    if path.is_empty() {
+        println!("{} /save-html <filename>", "Usage:".warning());
+        return true;
+    }
+    let Some(answer) = last_answer else {
+        println!("{}", "There is no answer to save yet.".warning());
+        return true;
+    };
+
+    let html = render_as_html(&answer.content, &answer.model, &answer.timestamp);
+    match std::fs::write(path, html) {
+        Ok(()) => println!("{} {}", "Saved the last answer to".success(), path.accent()),
+        Err(e) => eprintln!("{}: Could not write {}: {}", "Error".error(), path, e),
+    }
+    true
+}
+
+/// Handle the /stats command, which shows figures about the current session
+///
+/// # Returns
+///
+/// True if the input was a /stats command and has been handled
+fn handle_stats_command(
+    input: &str,
+    model: &str,
+    agent: &OpenAIAgent,
+    session: &ChatSession,
+) -> bool {
+    if input.trim() != "/stats" {
+        return false;
+    }
+
+    println!("{} {}", "Model:".warning(), model.success());
+    println!("{} {}", "Messages:".warning(), session.messages().len());
+    // A count growing by many per answer points at the model looping over tools
+    println!("{} {}", "Tool calls:".warning(), agent.tool_call_stats());
+    // Counted apart from other failures, as they only stop once the account has credit
+    println!(
+        "{} {}",
+        "Blocked by quota:".warning(),
+        session.quota_blocked()
+    );
+    true
+}
+
+/// Handle /branch, /branches and /switch, which fork the conversation into named branches
+/// and move between them
+///
+/// Branches are saved sessions, so the current one is saved first and switching loads the
+/// other one as it was left, messages included.
+///
+/// # Returns
+///
+/// True if the input was one of these commands and has been handled
+fn handle_branch_command(
+    input: &str,
+    store: Option<&SessionStore>,
+    agent: &OpenAIAgent,
+    session: &mut ChatSession,
+) -> bool {
+    let Some(SlashCommand::Other {
+        name: command @ ("branch" | "branches" | "switch"),
+        args: name,
+    }) = parse_command(input)
+    else {
+        return false;
+    };
+    let Some(store) = store else {
+        eprintln!("{} No directory to keep sessions in", "Error:".error());
+        return true;
+    };
+    if command != "branches" && (name.is_empty() || name.contains(char::is_whitespace)) {
+        println!("{}", format!("Usage: /{} <name>", command).warning());
+        return true;
+    }
+    save_session(Some(store), session);
+
+    match command {
+        "branch" => {
+            if session.messages().is_empty() {
+                println!(
+                    "{}",
+                    "Nothing to branch yet, send a prompt first.".warning()
+                );
+            } else if store.load_branch(session, name).is_ok() || name == MAIN_BRANCH {
+                println!(
+                    "{}",
+                    format!("A branch named {} already exists.", name).warning()
+                );
+            } else {
+                *session = session.fork(name);
+                save_session(Some(store), session);
+                println!(
+                    "{} {}",
+                    "Switched to the new branch:".warning(),
+                    name.success()
+                );
+            }
+        }
+        "switch" if name == session.branch_name() => {
+            println!("{}", format!("Already on the branch {}.", name).muted());
+        }
+        "switch" => match store.load_branch(session, name) {
+            Ok(branch) => {
+                *session = branch;
+                agent.set_tool_call_stats(session.tool_calls());
+                println!(
+                    "{} {} {}",
+                    "Switched to the branch:".warning(),
+                    name.success(),
+                    format!("({} prompts)", session.turns()).muted()
+                );
+            }
+            Err(e) => eprintln!("{} {}", "Error:".error(), e),
+        },
+        _ => match store.branches(session) {
+            Ok(branches) if branches.is_empty() => {
+                println!("{}", "No branches yet, send a prompt first.".muted());
+            }
+            Ok(branches) => {
+                for branch in &branches {
+                    let marker = if branch.id() == session.id() {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    // The divergence point, named after the branch it was forked from
+                    let origin = branch.origin().map(|origin| {
+                        let parent = branches
+                            .iter()
+                            .find(|parent| parent.id() == origin.parent_id)
+                            .map_or("a removed branch", |parent| parent.branch_name());
+                        format!(", forked from {} after prompt {}", parent, origin.turn)
+                    });
+                    println!(
+                        "{} {} {}",
+                        marker.accent(),
+                        branch.branch_name().success(),
+                        format!("({} prompts{})", branch.turns(), origin.unwrap_or_default())
+                            .muted()
+                    );
+                }
+            }
+            Err(e) => eprintln!("{} {}", "Error:".error(), e),
+        },
+    }
+    true
+}
+
+/// Answer every new text copied to the clipboard until the process is interrupted
+async fn run_watch_clipboard(agent: &OpenAIAgent) -> ! {
+    let clipboard = match arboard::Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            eprintln!("Error: Could not access the clipboard: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut watcher = ClipboardWatcher::new(clipboard);
+
+    println!(
+        "{}",
+        "Watching the clipboard, copy some text to ask about it (Ctrl+C to stop)...".warning()
+    );
+
+    let mut interval = tokio::time::interval(CLIPBOARD_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(text) = watcher.poll() else {
+            continue;
+        };
+
+        println!("\n{}", horizontal_rule().muted());
+        println!("{}: {}", "Clipboard".success(), text.trim());
+
+        match agent.generate_completion(&text).await {
+            Ok(completion) => println!("{}: {}", "AI".ai_label(), completion.content),
+            Err(e) => print_request_error(e.as_ref(), agent.provider()),
+        }
+    }
+}
+
+/// Transcribe an audio file and show the text that will be sent as the prompt
+async fn transcribe_prompt(agent: &OpenAIAgent, path: &Path) -> Option<String> {
+    println!(
+        "{} {}",
+        "Transcribing".warning(),
+        path.display().to_string().emphasis()
+    );
+
+    match agent.transcribe_audio(path).await {
+        Ok(text) => {
+            println!("{}: {}", "Transcription".warning(), text);
+            Some(text)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", "Error".error(), e);
+            None
+        }
+    }
+}
+
+/// Fetch the chat models available to the API key, falling back to the built-in list
+///
+/// Only OpenAI lists its models, the built-in list of other providers is used as is.
+async fn fetch_available_models(agent: &OpenAIAgent, provider: Provider) -> Vec<AIModel> {
+    if provider != Provider::OpenAI {
+        return provider.models();
+    }
+    match agent.list_models().await {
+        Ok(models) if !models.is_empty() => models,
+        Ok(_) => provider.models(),
+        Err(e) => {
+            println!(
+                "{} {}",
+                "Could not fetch the model list, showing built-in models:".warning(),
+                e.to_string().muted()
+            );
+            provider.models()
+        }
+    }
+}
+
+/// What was picked in the model menu
+enum MenuChoice {
+    Model(AIModel),
+    Provider(Provider),
+}
+
+/// Display available AI models and providers and let the user select a model by number or
+/// model id, or another provider by name
+fn select_ai_model(
+    models: &[AIModel],
+    provider: Provider,
+) -> Result<Option<MenuChoice>, Box<dyn std::error::Error>> {
+    println!("\n{}", "Available AI Models:".warning());
+    print!("{}", render_model_table(models));
+    println!(
+        "{}",
+        "Costs are list prices in USD per million tokens.".muted()
+    );
+    let providers: Vec<String> = Provider::ALL
+        .iter()
+        .map(|&other| {
+            if other == provider {
+                format!("{} (current)", other.name())
+            } else {
+                other.name().to_string()
+            }
+        })
+        .collect();
+    println!("{} {}", "Providers:".warning(), providers.join(", "));
+    print!(
+        "{}: ",
+        "Enter model number, model id or provider (empty to cancel)".warning()
+    );
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+
+    if let Ok(other) = input.parse::<Provider>() {
+        return Ok(Some(MenuChoice::Provider(other)));
+    }
+    match input.parse::<usize>().ok() {
+        Some(n) if n > 0 && n <= models.len() => Ok(Some(MenuChoice::Model(models[n - 1].clone()))),
+        // Anything that isn't a list number is taken as a model id typed by hand
+        None if !input.is_empty() => Ok(Some(MenuChoice::Model(
+            models
+                .iter()
+                .find(|model| model.model_id == input)
+                .cloned()
+                .unwrap_or_else(|| AIModel::custom(input)),
+        ))),
+        _ => {
+            println!("{}", "Model selection cancelled.".warning());
+            Ok(None)
+        }
+    }
+}
+
+/// Print a response from the model and offer to execute any bash or SQL blocks in it
+///
+/// Each block can be run, skipped, or explained by the model before deciding. With a
+/// `follow_up_limit`, the output of a block that ran can be sent back to the model.
+///
+/// # Returns
+///
+/// The follow-up message with the command output, if the user chose to send one
+async fn show_response(
+    response: &str,
+    agent: &OpenAIAgent,
+    runner: &BashRunner,
+    follow_up_limit: Option<usize>,
+    mut related: Option<&mut RelatedCommands>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    // Print the response
+    println!(
+        "{}",
+        wrap_labeled(
+            &format!("{}:", "AI".ai_label()),
+            &render_response(response, rule_width()),
+            terminal_width()
+        )
+    );
+
+    // Diagrams can be previewed or rendered rather than executed
+    offer_mermaid_diagrams(response)?;
+
+    // Check if the response contains bash code
+    let bash_blocks = extract_bash_blocks(response);
+
+    // Blocks flagged dangerous always ask first, so they never run in a batch
+    let first_dangerous = bash_blocks
+        .iter()
+        .position(|code| RiskSummary::analyze(code).is_dangerous());
+    let mut batch_start = None;
+    if runner.executes_all() && !bash_blocks.is_empty() {
+        match first_dangerous {
+            None => batch_start = Some(0),
+            Some(i) => println!(
+                "\n{}",
+                format!(
+                    "Not running every block with --execute-all: block #{} is flagged dangerous, so each block asks first.",
+                    i + 1
+                )
+                .error()
+            ),
+        }
+    }
+
+    // Blocks printed so far, the others are printed before a batch runs them
+    let mut shown = 0;
+
+    // If bash code is found, ask if the user wants to execute it
+    for (i, bash_code) in bash_blocks.iter().enumerate() {
+        if batch_start.is_some() {
+            break;
+        }
+        print_bash_block(i, bash_code);
+        shown = i + 1;
+
+        // Running the rest without asking is offered when none of it is dangerous
+        let offer_all =
+            i + 1 < bash_blocks.len() && first_dangerous.is_none_or(|dangerous| dangerous < i);
+
+        // Code made only of allowlisted commands runs without asking
+        let auto_approved = runner.auto_approves(bash_code);
+        match confirm_and_run(
+            bash_code,
+            agent,
+            runner,
+            follow_up_limit,
+            related.as_deref_mut(),
+            auto_approved,
+            offer_all,
+        )
+        .await?
+        {
+            Confirmation::Done(Some(follow_up)) => return Ok(Some(follow_up)),
+            Confirmation::Done(None) => {}
+            Confirmation::RunAll => batch_start = Some(i),
+        }
+    }
 
-// Define available AI models/agents
-#[derive(Debug, Clone)]
-struct AIModel {
-    name: String,
-    model_id: String,
-    description: String,
-}
-
-// List of available AI models
-fn get_available_models() -> Vec<AIModel> {
-    vec![
-        AIModel {
-            name: "GPT-4".to_string(),
-            model_id: "gpt-4".to_string(),
-            description: "Advanced model with strong reasoning capabilities".to_string(),
+    if let Some(start) = batch_start {
+        for (i, bash_code) in bash_blocks.iter().enumerate().skip(shown) {
+            print_bash_block(i, bash_code);
+        }
+        run_bash_batch(&bash_blocks, start, runner);
+    }
+
+    // SQL queries are run with the client of their dialect, if it is installed
+    for (i, (dialect, sql)) in extract_sql_blocks(response).iter().enumerate() {
+        let client = choose_client(*dialect, client_available);
+        println!(
+            "\n{} #{}{}",
+            "SQL query".warning(),
+            (i + 1).to_string().warning(),
+            client
+                .map(|client| format!(" ({})", client.program()))
+                .unwrap_or_default()
+                .muted()
+        );
+        let symbols = symbols();
+        let [top_left, top_right, bottom_left, bottom_right] = symbols.corners;
+        let line = symbols.horizontal.repeat(rule_width() - 2);
+        println!(
+            "{}",
+            format!("{}{}{}", top_left, line, top_right).code_border()
+        );
+        for line in sql.lines() {
+            for row in highlight_rows("sql", line, rule_width() - 2) {
+                println!("{} {}", symbols.vertical.code_border(), row);
+            }
+        }
+        println!(
+            "{}",
+            format!("{}{}{}", bottom_left, line, bottom_right).code_border()
+        );
+
+        let Some(client) = client else {
+            println!(
+                "{}",
+                "The query can't run here, the sqlite3 or psql client it needs isn't installed."
+                    .muted()
+            );
+            continue;
+        };
+        let command = sql_command(client, sql, runner.sqlite_database());
+        let auto_approved = runner.auto_approves(&command);
+        if let Confirmation::Done(Some(follow_up)) = confirm_and_run(
+            &command,
+            agent,
+            runner,
+            follow_up_limit,
+            related.as_deref_mut(),
+            auto_approved,
+            false,
+        )
+        .await?
+        {
+            return Ok(Some(follow_up));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Print a bash code block in a box, numbered from 1, with a summary of what it does
+fn print_bash_block(i: usize, code: &str) {
+    println!(
+        "\n{} #{}",
+        "Bash code block".warning(),
+        (i + 1).to_string().warning()
+    );
+    let symbols = symbols();
+    let [top_left, top_right, bottom_left, bottom_right] = symbols.corners;
+    let line = symbols.horizontal.repeat(rule_width() - 2);
+    println!(
+        "{}",
+        format!("{}{}{}", top_left, line, top_right).code_border()
+    );
+
+    // Print each line highlighted, wrapped inside the box if it's too long
+    for line in code.lines() {
+        for row in highlight_rows("bash", line, rule_width() - 2) {
+            println!("{} {}", symbols.vertical.code_border(), row);
+        }
+    }
+
+    println!(
+        "{}",
+        format!("{}{}{}", bottom_left, line, bottom_right).code_border()
+    );
+
+    // Summarize what the code does before asking to run it
+    let risk = RiskSummary::analyze(code);
+    let summary = risk.to_string();
+    println!(
+        "{}",
+        if risk.is_dangerous() {
+            summary.error().bold()
+        } else if risk.has_risks() {
+            summary.warning()
+        } else {
+            summary.muted()
+        }
+    );
+}
+
+/// Run bash code blocks in a row without asking, from `start` on, stopping at the first
+/// one that fails unless `--continue-on-error` is set
+fn run_bash_batch(bash_blocks: &[String], start: usize, runner: &BashRunner) {
+    println!(
+        "\n{}",
+        format!(
+            "Executing bash code blocks #{} to #{}...",
+            start + 1,
+            bash_blocks.len()
+        )
+        .success()
+    );
+    let outcome = run_batch(
+        &bash_blocks[start..],
+        runner,
+        runner.continues_on_error(),
+        |i, result| {
+            println!(
+                "\n{} #{}",
+                "Ran bash code block".warning(),
+                (start + i + 1).to_string().warning()
+            );
+            match result {
+                Ok(output) => print_execution_output(output, runner.last_run()),
+                Err(e) => eprintln!("{} {}", "Failed to run the code:".error(), e),
+            }
         },
-        AIModel {
-            name: "GPT-3.5 Turbo".to_string(),
-            model_id: "gpt-3.5-turbo".to_string(),
-            description: "Fast and efficient for most tasks".to_string(),
+    );
+
+    if let BatchOutcome::Aborted(i) = outcome {
+        let skipped = bash_blocks.len() - (start + i + 1);
+        if skipped > 0 {
+            println!(
+                "{}",
+                format!(
+                    "Stopped after block #{} failed, {} block(s) after it didn't run (--continue-on-error keeps going).",
+                    start + i + 1,
+                    skipped
+                )
+                .warning()
+            );
+        }
+    }
+}
+
+/// What came of asking whether to run a block of code
+enum Confirmation {
+    /// The code ran, was skipped or was explained, with the follow-up message to send if
+    /// the user chose to send the output
+    Done(Option<String>),
+    /// The user chose to run this block and every one after it, see `run_bash_batch`
+    RunAll,
+}
+
+/// Ask whether to run a block of code, then run it, skip it or explain it
+///
+/// # Arguments
+///
+/// * `code` - The bash code to run
+/// * `auto_approved` - Whether the code runs without asking, see `auto_approve`
+/// * `offer_all` - Whether to offer running this block and every one after it
+///
+/// # Returns
+///
+/// The follow-up message with the command output, if the user chose to send one, or
+/// `RunAll` if the user chose to run the remaining blocks, before anything ran
+async fn confirm_and_run(
+    code: &str,
+    agent: &OpenAIAgent,
+    runner: &BashRunner,
+    follow_up_limit: Option<usize>,
+    mut related: Option<&mut RelatedCommands>,
+    mut auto_approved: bool,
+    offer_all: bool,
+) -> Result<Confirmation, Box<dyn std::error::Error>> {
+    // Ask again after an explanation, until the code is run or skipped
+    loop {
+        let choice = if std::mem::take(&mut auto_approved) {
+            println!("{}", "(auto-approved by auto_approve)".muted());
+            ExecutionChoice::Run
+        } else {
+            print!(
+                "{} (y/n, {}x to explain): ",
+                "Do you want to execute this code?".warning(),
+                if offer_all { "a for all, " } else { "" }
+            );
+            io::stdout().flush()?;
+
+            let mut execute_input = String::new();
+            io::stdin().read_line(&mut execute_input)?;
+            ExecutionChoice::parse(&execute_input)
+        };
+        if choice == ExecutionChoice::RunAll && offer_all {
+            return Ok(Confirmation::RunAll);
+        }
+
+        let spinner = match choice {
+            ExecutionChoice::Run | ExecutionChoice::RunAll => {
+                println!("{}", "Executing bash code...".success());
+                None
+            }
+            ExecutionChoice::Explain => Some(Spinner::start("Asking for an explanation...")),
+            ExecutionChoice::Skip => None,
+        };
+
+        let outcome = apply_choice(choice, code, runner, |prompt| async move {
+            agent.generate_response(&prompt).await
+        })
+        .await;
+        if let Some(spinner) = spinner {
+            spinner.stop().await;
+        }
+
+        match outcome {
+            Ok(ExecutionOutcome::Ran(output)) => {
+                print_execution_output(&output, runner.last_run());
+                if output.status.success() {
+                    if let Some(related) = related.as_deref_mut() {
+                        related.request(code);
+                    }
+                }
+
+                if let Some(limit) = follow_up_limit {
+                    print!(
+                        "{} (y/n): ",
+                        "Send the output to the AI for a follow-up?".warning()
+                    );
+                    io::stdout().flush()?;
+
+                    let mut send_input = String::new();
+                    io::stdin().read_line(&mut send_input)?;
+                    if send_input.trim().eq_ignore_ascii_case("y") {
+                        // The model answers about the output instead
+                        if let Some(related) = related.as_deref_mut() {
+                            related.cancel();
+                        }
+                        return Ok(Confirmation::Done(Some(build_output_follow_up(
+                            code, &output, limit,
+                        ))));
+                    }
+                }
+                break;
+            }
+            Ok(ExecutionOutcome::Explained(explanation)) => {
+                println!(
+                    "{}",
+                    wrap_labeled(
+                        &format!("{}:", "Explanation".ai_label()),
+                        &render_response(&explanation, rule_width()),
+                        terminal_width()
+                    )
+                );
+            }
+            Ok(ExecutionOutcome::Skipped) => {
+                println!("{}", "Code execution skipped.".warning());
+                break;
+            }
+            Err(e) if choice == ExecutionChoice::Explain => {
+                print_request_error(e.as_ref(), agent.provider());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Confirmation::Done(None))
+}
+
+/// Print the output, errors and exit status of code that ran, and how long it took
+fn print_execution_output(output: &std::process::Output, run: Option<CommandRun>) {
+    // Print the command output
+    if !output.stdout.is_empty() {
+        println!("{}", "Output:".success());
+        println!("{}", String::from_utf8_lossy(&output.stdout));
+    }
+
+    // Print any errors
+    if !output.stderr.is_empty() {
+        println!("{}", "Errors:".error());
+        println!("{}", String::from_utf8_lossy(&output.stderr).error());
+    }
+
+    let status_str = format!("Execution completed with status: {}", output.status);
+    if output.status.success() {
+        println!("{}", status_str.success());
+    } else {
+        println!("{}", status_str.error());
+    }
+
+    if let Some(run) = run.filter(|_| timings_shown()) {
+        let exit = match run.exit_code {
+            Some(code) => format!("exit code {}", code),
+            None => "terminated by a signal".to_string(),
+        };
+        println!(
+            "{}",
+            format!(
+                "(command finished in {}, {})",
+                format_latency(run.duration),
+                exit
+            )
+            .muted()
+        );
+    }
+}
+
+/// Record how long the code run since the last call took in the session, as run after
+/// its latest answer
+fn record_command_runs(runner: &BashRunner, session: &mut ChatSession) {
+    for run in runner.take_runs() {
+        session.record_command_time(run.duration, run.exit_code);
+    }
+}
+
+/// Show each Mermaid diagram of a response and offer to preview or render it
+fn offer_mermaid_diagrams(response: &str) -> io::Result<()> {
+    let diagrams = extract_mermaid_blocks(response);
+    if diagrams.is_empty() {
+        return Ok(());
+    }
+    let can_render = mmdc_available();
+
+    for (i, diagram) in diagrams.iter().enumerate() {
+        println!(
+            "\n{} #{}",
+            "Mermaid diagram".info(),
+            (i + 1).to_string().info()
+        );
+        let symbols = symbols();
+        let [top_left, top_right, bottom_left, bottom_right] = symbols.corners;
+        let line = symbols.horizontal.repeat(rule_width() - 2);
+        println!("{}", format!("{}{}{}", top_left, line, top_right).info());
+        for line in diagram.lines() {
+            for row in highlight_rows("mermaid", line, rule_width() - 2) {
+                println!("{} {}", symbols.vertical.info(), row);
+            }
+        }
+        println!(
+            "{}",
+            format!("{}{}{}", bottom_left, line, bottom_right).info()
+        );
+
+        let options = if can_render {
+            "p to preview in the browser, r to render to PNG, n to skip"
+        } else {
+            "p to preview in the browser, n to skip"
+        };
+        print!("{} ({}): ", "View this diagram?".warning(), options);
+        io::stdout().flush()?;
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice)?;
+
+        match MermaidChoice::parse(&choice) {
+            MermaidChoice::Preview => {
+                let url = live_editor_url(diagram);
+                if let Err(e) = open_in_browser(&url) {
+                    // Still let the user open it by hand
+                    eprintln!("{}: Could not open a browser: {}", "Error".error(), e);
+                    println!("{} {}", "Open:".warning(), url.accent());
+                }
+            }
+            MermaidChoice::Render if can_render => {
+                let path = diagram_path(Path::new("."));
+                match render_png(diagram, &path) {
+                    Ok(status) if status.success() => println!(
+                        "{} {}",
+                        "Rendered the diagram to".success(),
+                        path.display().to_string().accent()
+                    ),
+                    Ok(status) => {
+                        eprintln!("{}: mmdc failed with {}", "Error".error(), status)
+                    }
+                    Err(e) => eprintln!("{}: Could not run mmdc: {}", "Error".error(), e),
+                }
+            }
+            MermaidChoice::Render | MermaidChoice::Skip => {}
+        }
+    }
+    Ok(())
+}
+
+/// What to do with a prompt after attaching the files it references
+enum AttachResult {
+    /// Send this prompt
+    Send(String),
+    /// Go back to editing the prompt as typed
+    Edit,
+    /// Drop the prompt
+    Cancel,
+}
+
+/// Attach the files referenced with `@path` in a prompt, asking what to do if some can't be read
+fn attach_prompt_files(
+    input: &str,
+    max_bytes: usize,
+) -> Result<AttachResult, Box<dyn std::error::Error>> {
+    if file_references(input).is_empty() {
+        return Ok(AttachResult::Send(input.to_string()));
+    }
+
+    let (prompt, errors) = attach_files(&std::env::current_dir()?, input, max_bytes);
+    if errors.is_empty() {
+        return Ok(AttachResult::Send(prompt));
+    }
+
+    for error in &errors {
+        println!("{}", error.to_string().error());
+    }
+    print!("{} ", "(s)end anyway, (e)dit or (c)ancel?".warning());
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(match MissingFileChoice::parse(&answer) {
+        MissingFileChoice::Send => AttachResult::Send(prompt),
+        MissingFileChoice::Edit => AttachResult::Edit,
+        MissingFileChoice::Cancel => AttachResult::Cancel,
+    })
+}
+
+/// Related command suggestions, requested in the background after code runs successfully
+///
+/// Like background jobs, suggestions are only shown once the prompt is idle.
+#[derive(Default)]
+struct RelatedCommands {
+    agent: Option<OpenAIAgent>,
+    pending: Option<tokio::task::JoinHandle<Vec<String>>>,
+}
+
+impl RelatedCommands {
+    /// Asks which commands could follow `bash_code`, replacing any pending request
+    fn request(&mut self, bash_code: &str) {
+        let Some(agent) = self.agent.clone() else {
+            return;
+        };
+        self.cancel();
+
+        let prompt = build_related_commands_prompt(bash_code);
+        self.pending = Some(tokio::spawn(async move {
+            // Suggestions are a nicety, failures are not worth reporting
+            match agent.generate_response(&prompt).await {
+                Ok(response) => parse_related_commands(&response),
+                Err(_) => Vec::new(),
+            }
+        }));
+    }
+
+    /// Drops the pending request, if any
+    fn cancel(&mut self) {
+        if let Some(handle) = self.pending.take() {
+            handle.abort();
+        }
+    }
+
+    /// Takes the suggestions if the request finished
+    async fn take_finished(&mut self) -> Option<Vec<String>> {
+        if !self.pending.as_ref()?.is_finished() {
+            return None;
+        }
+        self.pending.take()?.await.ok()
+    }
+}
+
+/// Get the model for quick side requests: the first fallback model, which is usually a
+/// cheap one, or the current model
+fn cheap_model<'a>(config: &'a Config, current_model: &'a str) -> &'a str {
+    config
+        .fallback_models
+        .first()
+        .map_or(current_model, String::as_str)
+}
+
+/// Create the agent asking for related commands
+fn related_commands_agent(config: &Config, current_model: &str) -> Option<OpenAIAgent> {
+    if !config.suggest_related_commands {
+        return None;
+    }
+    create_agent(cheap_model(config, current_model), config).ok()
+}
+
+// Shorter sessions end without a summary
+const SESSION_SUMMARY_MIN_TURNS: usize = 3;
+
+/// Save the conversation so it can be resumed with --resume, warning if it can't be
+fn save_session(store: Option<&SessionStore>, session: &ChatSession) {
+    let Some(store) = store else {
+        return;
+    };
+    // Losing the saved session shouldn't interrupt the session itself
+    if let Err(e) = store.save(session) {
+        eprintln!("Warning: Failed to save the session: {}", e);
+    }
+}
+
+/// Print a summary of what the session accomplished, if enabled in the configuration and
+/// the session is long enough to be worth one
+async fn print_session_summary(config: &Config, current_model: &str, session: &ChatSession) {
+    if !config.session_summary_on_exit || session.turns() <= SESSION_SUMMARY_MIN_TURNS {
+        return;
+    }
+    let Ok(agent) = create_agent(cheap_model(config, current_model), config) else {
+        return;
+    };
+
+    let spinner = Spinner::start("Summarizing the session...");
+    let summary = agent.summarize_session(session).await;
+    spinner.stop().await;
+    // Leaving shouldn't fail because of the summary
+    match summary {
+        Ok(summary) => println!("{} {}", "Session summary:".warning(), summary),
+        Err(e) => eprintln!("{} {}", "Could not summarize the session:".muted(), e),
+    }
+}
+
+/// Build the message of the spinner shown while the model answers
+///
+/// # Returns
+///
+/// The message, with the typical round-trip time of the model's earlier requests when
+/// there are some and timings are shown, such as `AI is thinking... (usually 2.4s)`
+fn thinking_message(agent: &OpenAIAgent, model: &str) -> String {
+    match agent
+        .latency_history()
+        .typical(model)
+        .filter(|_| timings_shown())
+    {
+        Some(typical) => format!("AI is thinking... (usually {})", format_latency(typical)),
+        None => "AI is thinking...".to_string(),
+    }
+}
+
+/// Print an error returned by a request to the model
+///
+/// # Arguments
+///
+/// * `e` - The error
+/// * `provider` - The provider the request was sent to, named when its account is out of
+///   quota
+fn print_request_error(e: &(dyn std::error::Error + 'static), provider: Provider) {
+    if !is_quota_error(e) {
+        eprintln!("{}: {}", "Error".error(), e);
+        return;
+    }
+    eprintln!(
+        "{}: {}",
+        "Error".error(),
+        format!("Your {} account is out of quota.", provider).error()
+    );
+    if let Some(url) = provider.billing_url() {
+        eprintln!("Add credit on the billing page: {}", url);
+    }
+    eprintln!("Or switch to another provider with {}.", "/model".accent());
+}
+
+/// Check whether a request failed because the account is out of quota
+fn is_quota_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<ShellAIError>(),
+        Some(ShellAIError::InsufficientQuota { .. })
+    )
+}
+
+/// Offer to answer the rest of the session with the local `ollama_model`, after a request
+/// was blocked by an exhausted quota
+///
+/// The fallback models were already tried before the quota error reached the user, so
+/// only a model of another provider can answer.
+///
+/// # Returns
+///
+/// The agent of the Ollama model and its id, if one is configured, the session doesn't
+/// already use Ollama and the user accepted
+fn offer_quota_switch(config: &mut Config) -> io::Result<Option<(OpenAIAgent, String)>> {
+    let Some(model) = config
+        .ollama_model
+        .clone()
+        .filter(|_| config.provider != Provider::Ollama)
+    else {
+        return Ok(None);
+    };
+    print!(
+        "{} (y/N): ",
+        format!("Switch to {} on Ollama for the rest of the session?", model).warning()
+    );
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return Ok(None);
+    }
+
+    // The new agent is created first, so a failure keeps the current provider
+    let previous = std::mem::replace(&mut config.provider, Provider::Ollama);
+    match create_agent(&model, config) {
+        Ok(agent) => Ok(Some((agent, model))),
+        Err(e) => {
+            config.provider = previous;
+            println!("{} {}", "Can't switch to Ollama:".error(), e);
+            Ok(None)
+        }
+    }
+}
+
+/// Handle the /effort command, which shows or changes the reasoning effort for this session
+///
+/// # Returns
+///
+/// True if the input was an /effort command and has been handled
+fn handle_effort_command(input: &str, config: &mut Config, agent: &mut OpenAIAgent) -> bool {
+    let argument = match input.trim().strip_prefix("/effort") {
+        Some(argument) if argument.is_empty() || argument.starts_with(' ') => argument.trim(),
+        _ => return false,
+    };
+
+    if argument.is_empty() {
+        let effort = config
+            .reasoning_effort
+            .map_or("model default".to_string(), |effort| effort.to_string());
+        println!("{} {}", "Reasoning effort:".warning(), effort.success());
+        if !agent.capabilities().reasoning {
+            println!(
+                "{}",
+                "The current model isn't a reasoning model, so the effort is not sent.".muted()
+            );
+        }
+        return true;
+    }
+
+    match argument.parse::<ReasoningEffort>() {
+        Ok(effort) => {
+            // Kept in the session config so it survives model switches
+            config.reasoning_effort = Some(effort);
+            agent.set_reasoning_effort(Some(effort));
+            println!(
+                "{} {}",
+                "Reasoning effort set to:".warning(),
+                effort.to_string().success()
+            );
+        }
+        Err(e) => eprintln!("{}: {}", "Error".error(), e),
+    }
+    true
+}
+
+/// Handle the /seed command, which shows, sets or clears the sampling seed
+///
+/// # Returns
+///
+/// True if the input was a /seed command and has been handled
+fn handle_seed_command(input: &str, config: &mut Config, agent: &mut OpenAIAgent) -> bool {
+    let argument = match input.trim().strip_prefix("/seed") {
+        Some(argument) if argument.is_empty() || argument.starts_with(' ') => argument.trim(),
+        _ => return false,
+    };
+
+    match argument {
+        "" => match agent.seed() {
+            Some(seed) => println!("{} {}", "Seed:".warning(), seed.to_string().success()),
+            None => println!("{} {}", "Seed:".warning(), "off".success()),
         },
-        AIModel {
-            name: "GPT-4o".to_string(),
-            model_id: "gpt-4o".to_string(),
-            description: "Latest model with improved capabilities".to_string(),
+        "off" => {
+            config.seed = None;
+            agent.set_seed(None);
+            println!("{}", "Seed cleared.".warning());
+        }
+        _ => match argument.parse::<u64>() {
+            Ok(seed) => {
+                // Kept in the session config so it survives model switches
+                config.seed = Some(seed);
+                agent.set_seed(Some(seed));
+                println!(
+                    "{} {}",
+                    "Seed set to:".warning(),
+                    seed.to_string().success()
+                );
+            }
+            Err(_) => eprintln!("{}: expected /seed <number> or /seed off", "Error".error()),
         },
-    ]
+    }
+    true
+}
+
+/// Run a git command in the current directory and return its output
+fn git_output(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Handle the /pr-description command, which describes the changes of the current branch
+///
+/// The title is the command argument, or the subject of the latest commit.
+///
+/// # Returns
+///
+/// True if the input was a /pr-description command and has been handled
+async fn handle_pr_description_command(input: &str, agent: &OpenAIAgent) -> bool {
+    let title = match input.trim().strip_prefix("/pr-description") {
+        Some(title) if title.is_empty() || title.starts_with(' ') => title.trim(),
+        _ => return false,
+    };
+
+    let diff = match git_output(&["diff", "main...HEAD"]) {
+        Ok(diff) if diff.trim().is_empty() => {
+            println!("{}", "No changes between main and HEAD.".warning());
+            return true;
+        }
+        Ok(diff) => diff,
+        Err(e) => {
+            eprintln!("{}: {}", "Error".error(), e);
+            return true;
+        }
+    };
+
+    let title = if title.is_empty() {
+        git_output(&["log", "-1", "--format=%s"])
+            .map(|subject| subject.trim().to_string())
+            .unwrap_or_default()
+    } else {
+        title.to_string()
+    };
+
+    println!();
+    let spinner = Spinner::start("Writing the pull request description...");
+    let result = agent.generate_pr_description(&diff, &title).await;
+    spinner.stop().await;
+
+    match result {
+        Ok(description) => {
+            // Printed without decorations so it can be copied as is
+            println!("{}", horizontal_rule().muted());
+            println!("{}", description);
+            println!("{}", horizontal_rule().muted());
+        }
+        Err(e) => print_request_error(&e, agent.provider()),
+    }
+    true
+}
+
+/// Handle the /security-check command, which reviews the last code block of the latest
+/// answer for security issues
+///
+/// # Returns
+///
+/// True if the input was a /security-check command and has been handled
+async fn handle_security_check_command(
+    input: &str,
+    last_answer: Option<&LastAnswer>,
+    agent: &OpenAIAgent,
+) -> bool {
+    let Some(SlashCommand::Other {
+        name: "security-check",
+        ..
+    }) = parse_command(input)
+    else {
+        return false;
+    };
+    let Some((language, code)) = last_answer.and_then(|answer| last_code_block(&answer.content))
+    else {
+        println!("{}", "There is no code block to check yet.".warning());
+        return true;
+    };
+
+    println!();
+    let spinner = Spinner::start("Checking the code for security issues...");
+    let result = agent.analyze_security(&code, &language).await;
+    spinner.stop().await;
+
+    match result {
+        Ok(report) => print_security_report(&report),
+        Err(e) => print_request_error(&e, agent.provider()),
+    }
+    true
 }
 
-/// Display available AI models and let the user select one
-fn select_ai_model() -> Result<Option<AIModel>, Box<dyn std::error::Error>> {
-    let models = get_available_models();
+/// Handle the /init-ignore command, which writes a `.shellai-ignore` in the current
+/// directory from its `.gitignore`
+///
+/// # Returns
+///
+/// True if the input was an /init-ignore command and has been handled
+fn handle_init_ignore_command(input: &str) -> bool {
+    let Some(SlashCommand::Other {
+        name: "init-ignore",
+        ..
+    }) = parse_command(input)
+    else {
+        return false;
+    };
+    let path = Path::new(SHELLAI_IGNORE_FILE);
+    // The user may have edited an existing file, so never replace it
+    if path.exists() {
+        println!(
+            "{} already exists; delete it first to generate a new one.",
+            SHELLAI_IGNORE_FILE
+        );
+        return true;
+    }
+
+    let template = match generate_shellai_ignore_from_gitignore(Path::new(".")) {
+        Ok(template) => template,
+        Err(e) => {
+            eprintln!("{} {}", "Error:".error(), e);
+            return true;
+        }
+    };
+    match std::fs::write(path, &template) {
+        Ok(()) => {
+            println!("{}", template.trim_end().muted());
+            println!("{} {}", "Wrote".success(), SHELLAI_IGNORE_FILE.success());
+        }
+        Err(e) => eprintln!(
+            "{} Could not write {}: {}",
+            "Error:".error(),
+            SHELLAI_IGNORE_FILE,
+            e
+        ),
+    }
+    true
+}
+
+/// Handle the /review command, which reviews the changes between two versions of a file
+///
+/// # Returns
+///
+/// True if the input was a /review command and has been handled
+async fn handle_review_command(input: &str, agent: &OpenAIAgent) -> bool {
+    let Some(SlashCommand::Other {
+        name: "review",
+        args,
+    }) = parse_command(input)
+    else {
+        return false;
+    };
+    let paths: Vec<&str> = args.split_whitespace().collect();
+    let [original_path, modified_path] = paths[..] else {
+        println!("{}", "Usage: /review <original> <modified>".warning());
+        return true;
+    };
+
+    let files = std::fs::read_to_string(original_path).and_then(|original| {
+        std::fs::read_to_string(modified_path).map(|modified| (original, modified))
+    });
+    let (original, modified) = match files {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{} {}", "Could not read the files:".error(), e);
+            return true;
+        }
+    };
+    // The extension names the language well enough for the model, as in ```rs
+    let language = Path::new(modified_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("text");
+
+    println!();
+    let spinner = Spinner::start("Reviewing the changes...");
+    let result = agent.review_code(&original, &modified, language).await;
+    spinner.stop().await;
+
+    match result {
+        Ok(review) => print_review(&review),
+        Err(e) => print_request_error(&e, agent.provider()),
+    }
+    true
+}
+
+/// Handle the /run command, which runs a bash command typed at the prompt
+///
+/// # Returns
+///
+/// True if the input was a /run command and has been handled
+fn handle_run_command(input: &str, runner: &BashRunner) -> bool {
+    let Some(SlashCommand::Other { name: "run", args }) = parse_command(input) else {
+        return false;
+    };
+    if args.is_empty() {
+        println!(
+            "{}",
+            "Usage: /run <command>, Tab completes the command".warning()
+        );
+        return true;
+    }
+
+    match runner.run(args) {
+        Ok(output) => print_execution_output(&output, runner.last_run()),
+        Err(e) => eprintln!("{} {}", "Could not run the command:".error(), e),
+    }
+    true
+}
+
+/// Ask the model to complete the command of a `/run` prompt and let the user pick one
+///
+/// # Arguments
+///
+/// * `text` - The prompt as typed, starting with `/run`
+/// * `agent` - The agent completing the command
+///
+/// # Returns
+///
+/// The prompt with the picked completion, or as typed if none was picked
+async fn complete_run_command(text: String, agent: &OpenAIAgent) -> io::Result<String> {
+    // The trailing space matters, "git " asks for a subcommand and "git" doesn't
+    let partial = text.trim_start().strip_prefix("/run").unwrap_or_default();
+    let partial = partial.trim_start();
+
+    let spinner = Spinner::start("Completing the command...");
+    let result = agent.complete_command(partial).await;
+    spinner.stop().await;
+
+    match result {
+        Ok(completions) => Ok(match select_dropdown(&completions)? {
+            Some(picked) => format!("/run {}", completions[picked]),
+            None => text,
+        }),
+        Err(e) => {
+            print_request_error(&e, agent.provider());
+            Ok(text)
+        }
+    }
+}
+
+/// Handles `/translate <command> --to <shell> [--from <shell>]`, which prints the command
+/// written for another shell
+///
+/// # Arguments
+///
+/// * `input` - The user's input
+/// * `agent` - The agent translating the command
+///
+/// # Returns
+///
+/// True if the input was the /translate command
+async fn handle_translate_command(input: &str, agent: &OpenAIAgent) -> bool {
+    let Some(SlashCommand::Other {
+        name: "translate",
+        args,
+    }) = parse_command(input)
+    else {
+        return false;
+    };
+    let usage = format!(
+        "Usage: /translate <command> --to <{}> [--from <shell>], from bash by default",
+        Shell::NAMES.join("|")
+    );
+
+    // The options follow the command, which can have options of its own
+    let args = format!(" {}", args);
+    let options_start = [" --to ", " --from "]
+        .iter()
+        .filter_map(|option| args.rfind(option))
+        .min();
+    let Some(options_start) = options_start else {
+        println!("{}", usage.warning());
+        return true;
+    };
+    let command = args[..options_start].trim();
+    let mut from_shell = Shell::Bash;
+    let mut to_shell = None;
+    let mut options = args[options_start..].split_whitespace();
+    while let Some(option) = options.next() {
+        let shell = match options.next().map(str::parse::<Shell>) {
+            Some(Ok(shell)) => shell,
+            Some(Err(e)) => {
+                println!("{}", e.warning());
+                return true;
+            }
+            None => {
+                println!("{}", usage.warning());
+                return true;
+            }
+        };
+        match option {
+            "--to" => to_shell = Some(shell),
+            "--from" => from_shell = shell,
+            _ => {
+                println!("{}", usage.warning());
+                return true;
+            }
+        }
+    }
+    let Some(to_shell) = to_shell.filter(|_| !command.is_empty()) else {
+        println!("{}", usage.warning());
+        return true;
+    };
 
-    println!("\n{}", "Available AI Models:".bright_yellow());
-    println!("{}", "─".repeat(60).bright_black());
+    println!();
+    let spinner = Spinner::start(&format!("Translating to {}...", to_shell));
+    let result = agent.translate_command(command, from_shell, to_shell).await;
+    spinner.stop().await;
 
-    for (i, model) in models.iter().enumerate() {
-        println!(
-            "{}: {} - {}",
-            (i + 1).to_string().bright_cyan(),
-            model.name.bright_green(),
-            model.description.bright_white()
-        );
+    match result {
+        // Printed as is so it can be copied straight into the other shell
+        Ok(translated) => println!("{}", translated),
+        Err(e) => print_request_error(&e, agent.provider()),
     }
+    true
+}
 
-    println!("{}", "─".repeat(60).bright_black());
-    print!(
-        "{}: ",
-        "Enter model number (or any other key to cancel)".bright_yellow()
-    );
-    io::stdout().flush()?;
+/// Print a code review with its rating, bugs and suggestions
+fn print_review(review: &ReviewResult) {
+    let rating = format!("{}/5", review.rating);
+    let rating = match review.rating {
+        1 | 2 => rating.error(),
+        3 => rating.warning(),
+        _ => rating.success(),
+    };
+    println!("\n{} {}", "Rating:".warning(), rating);
+    if review.bugs.is_empty() {
+        println!("{}", "No bugs found.".success());
+    }
+    for bug in &review.bugs {
+        println!("{} {}", "Bug:".error(), bug);
+    }
+    for suggestion in &review.suggestions {
+        println!("{} {}", "Suggestion:".accent(), suggestion);
+    }
+}
 
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
+/// Print a security report, one finding after the other
+fn print_security_report(report: &SecurityReport) {
+    println!("\n{} {}", "Security check:".warning(), report.summary);
+    if report.findings.is_empty() {
+        println!("{}", "No issues found.".success());
+        return;
+    }
+    for finding in &report.findings {
+        let severity = format!("[{}]", finding.severity.name().to_uppercase());
+        let severity = match finding.severity {
+            Severity::Critical | Severity::High => severity.error(),
+            Severity::Medium => severity.warning(),
+            Severity::Low => severity.muted(),
+        };
+        println!("\n{} {}", severity, finding.pattern.accent());
+        println!("  {}", finding.explanation);
+        println!("  {} {}", "Fix:".success(), finding.remediation);
+    }
+}
 
-    let selection = input.trim().parse::<usize>().ok();
+/// Handle the background job commands (/bg, /jobs, /job, /cancel)
+///
+/// `/job` offers to add a finished answer to the conversation, with its prompt, and saves
+/// the session if it is added.
+///
+/// # Returns
+///
+/// True if the input was a job command and has been handled
+async fn handle_job_command(
+    input: &str,
+    jobs: &mut JobManager,
+    agent: &OpenAIAgent,
+    runner: &BashRunner,
+    session: &mut ChatSession,
+    store: Option<&SessionStore>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let input = input.trim();
+    let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+    let argument = argument.trim();
 
-    match selection {
-        Some(n) if n > 0 && n <= models.len() => Ok(Some(models[n - 1].clone())),
-        _ => {
-            println!("{}", "Model selection cancelled.".bright_yellow());
-            Ok(None)
+    match command {
+        "/bg" if !argument.is_empty() => {
+            let agent = agent.clone();
+            let prompt = argument.to_string();
+            let id = jobs.spawn(prompt.clone(), async move {
+                // Nothing is printed here, the main loop reports the job once the prompt is idle
+                agent
+                    .generate_response(&prompt)
+                    .await
+                    .map_err(|e| e.to_string())
+            });
+            println!(
+                "{} #{} {}",
+                "Started background job".warning(),
+                id.to_string().accent(),
+                "- check on it with /jobs".muted()
+            );
         }
+        "/jobs" => {
+            if jobs.jobs().is_empty() {
+                println!("{}", "No background jobs.".warning());
+            }
+            for job in jobs.jobs() {
+                let state = match &job.state {
+                    JobState::Running(_) => "running".warning(),
+                    JobState::Finished(_) => "finished".success(),
+                    JobState::Failed(_) => "failed".error(),
+                    JobState::Cancelled => "cancelled".muted(),
+                };
+                let summary: String = job
+                    .prompt
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .chars()
+                    .take(50)
+                    .collect();
+                println!("{}: [{}] {}", job.id.to_string().accent(), state, summary);
+            }
+        }
+        "/job" => match argument.parse().ok().and_then(|id| jobs.get(id)) {
+            Some(job) => match &job.state {
+                JobState::Finished(response) => {
+                    let (id, prompt, response) = (job.id, job.prompt.clone(), response.clone());
+                    let merged = job.merged;
+                    // Until merged, the answer isn't part of the conversation, so there's
+                    // nothing to follow up on
+                    show_response(&response, agent, runner, None, None).await?;
+                    if merged {
+                        return Ok(true);
+                    }
+                    print!(
+                        "{} (y/n): ",
+                        "Add this answer to the conversation?".warning()
+                    );
+                    io::stdout().flush()?;
+
+                    let mut answer = String::new();
+                    io::stdin().read_line(&mut answer)?;
+                    if answer.trim().eq_ignore_ascii_case("y") && jobs.mark_merged(id) {
+                        session.push_exchange(&prompt, &response);
+                        save_session(store, session);
+                        println!("{}", "Added to the conversation.".muted());
+                    }
+                }
+                JobState::Failed(message) => eprintln!("{}: {}", "Error".error(), message),
+                JobState::Running(_) => {
+                    println!("{}", "That job is still running.".warning())
+                }
+                JobState::Cancelled => println!("{}", "That job was cancelled.".warning()),
+            },
+            None => println!("{} /job <number from /jobs>", "Usage:".warning()),
+        },
+        "/cancel" => match argument.parse() {
+            Ok(id) if jobs.cancel(id) => println!(
+                "{} #{}",
+                "Cancelled background job".warning(),
+                id.to_string().accent()
+            ),
+            _ => println!("{}", "No running job with that number.".warning()),
+        },
+        "/bg" => println!("{} /bg <prompt>", "Usage:".warning()),
+        _ => return Ok(false),
     }
+
+    Ok(true)
 }
 
-/// Read multiline input from the user, with Enter adding a new line and Ctrl+S submitting
-fn read_multiline_input() -> Result<String, Box<dyn std::error::Error>> {
-    let mut buffer = String::new();
+/// Clear the visible screen and move to its top, leaving the scrollback as it is
+fn clear_screen() -> io::Result<()> {
+    execute!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0))
+}
 
-    // Enable raw mode to capture key events
-    enable_raw_mode()?;
+/// Create the response cache described by the configuration, if caching is enabled
+fn response_cache(config: &Config) -> Option<ResponseCache> {
+    if !config.cache.enabled {
+        return None;
+    }
+    ResponseCache::default_dir().map(|dir| {
+        ResponseCache::new(
+            dir,
+            Duration::from_secs(config.cache.ttl_hours * 60 * 60),
+            config.cache.max_entries,
+        )
+    })
+}
 
-    // Print initial prompt
-    print!(""); // Ensure cursor is at the right position
-    io::stdout().flush()?;
+/// Create an agent of the configured provider for the given model, applying the user's
+/// configuration
+fn create_agent(model: &str, config: &Config) -> Result<OpenAIAgent, Box<dyn std::error::Error>> {
+    let agent = create_provider_agent(
+        config.provider,
+        model,
+        config.api_key.as_deref(),
+        config.api_key_file().as_deref(),
+        |var| std::env::var(var).ok(),
+    )?;
 
-    loop {
-        // Wait for a key event
-        if let Event::Key(KeyEvent {
-            code, modifiers, ..
-        }) = event::read()?
+    // `base_url` points at an OpenAI-compatible API standing in for OpenAI, unless
+    // `OPENAI_BASE_URL` already picked one
+    let agent = match &config.base_url {
+        Some(base_url)
+            if config.provider == Provider::OpenAI
+                && std::env::var_os("OPENAI_BASE_URL").is_none() =>
         {
-            match code {
-                // Ctrl+S to submit
-                KeyCode::Char('s') if modifiers.contains(KeyModifiers::CONTROL) => {
-                    disable_raw_mode()?;
-                    println!(); // Move to next line after submission
-                    break;
-                }
+            agent.with_base_url(base_url)
+        }
+        _ => agent,
+    };
 
-                // Enter key adds a newline character
-                KeyCode::Enter => {
-                    buffer.push('\n');
-                    println!();
-                    io::stdout().flush()?;
-                }
+    let agent = match (config.tools, std::env::current_dir()) {
+        (true, Ok(dir)) => agent.with_builtin_tools(dir),
+        _ => agent,
+    };
 
-                // Backspace key
-                KeyCode::Backspace => {
-                    if !buffer.is_empty() {
-                        // Remove the last character
-                        if buffer.ends_with('\n') {
-                            // If we're at the start of a line, move up
-                            buffer.pop();
-                            execute!(io::stdout(), MoveUp(1), MoveToColumn(0))?;
-
-                            // Find the length of the previous line
-                            let last_line_len = buffer.lines().last().map_or(0, |line| line.len());
-
-                            // Move to the end of the previous line
-                            execute!(io::stdout(), MoveToColumn(last_line_len as u16))?;
-                        } else {
-                            buffer.pop();
-                            // Move cursor back and erase the character
-                            print!("\x08 \x08");
-                            io::stdout().flush()?;
-                        }
-                    }
-                }
+    Ok(agent
+        .with_system_prompt(config.system_prompt.clone())
+        .with_context_budget(config.context_budget)
+        .with_max_request_bytes(config.max_request_bytes)
+        .with_request_throttle(request_throttle(config))
+        .with_fallback_models(config.fallback_models.clone())
+        .with_max_retries(config.max_retries)
+        .with_cache(response_cache(config))
+        .with_context_format(config.context_format)
+        .with_scan_options(scan_options(config))
+        .with_reasoning_effort(config.reasoning_effort)
+        .with_seed(config.seed)
+        .with_max_tokens(config.max_tokens)
+        .with_git_context(config.git_context.then(|| GitContextOptions {
+            staged_diff_bytes: config.git_diff_kb.map(|kb| kb * 1024),
+        }))
+        .with_dependency_versions(config.include_dependency_versions))
+}
 
-                // Regular character input
-                KeyCode::Char(c) => {
-                    // Handle Ctrl+C to exit
-                    if c == 'c' && modifiers.contains(KeyModifiers::CONTROL) {
-                        disable_raw_mode()?;
-                        println!("\n{}", "Goodbye!".bright_blue());
-                        std::process::exit(0); // Immediately exit the program
-                    }
+/// Gets the throttle spacing out API requests, if `min_request_interval` is a positive
+/// number of seconds
+fn request_throttle(config: &Config) -> Option<RequestThrottle> {
+    config
+        .min_request_interval
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .filter(|interval| !interval.is_zero())
+        .map(RequestThrottle::new)
+}
 
-                    // Handle Ctrl+A to show available models (A for Agents)
-                    if c == 'a' && modifiers.contains(KeyModifiers::CONTROL) {
-                        disable_raw_mode()?;
-                        return Ok("ctrl+a".to_string());
-                    }
+/// Gets the options of the directory scan from the configuration
+fn scan_options(config: &Config) -> ScanOptions {
+    ScanOptions {
+        include_globs: config.include_globs.clone(),
+        exclude_globs: config.exclude_globs.clone(),
+        respect_gitignore: config.respect_gitignore,
+        show_sizes: config.show_sizes,
+        change_frequency_days: config.change_frequency_days,
+        tree_style: config.tree_style,
+        ..ScanOptions::default()
+    }
+}
 
-                    // Handle Ctrl+h to show expanded menu (h for help)
-                    if c == 'h' && modifiers.contains(KeyModifiers::CONTROL) {
-                        disable_raw_mode()?;
-                        return Ok("ctrl+h".to_string());
-                    }
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
 
-                    buffer.push(c);
-                    print!("{}", c);
-                    io::stdout().flush()?;
-                }
+    // Without a terminal to type into, there must be a question to answer
+    let one_shot = cli.question.is_some() || cli.prompt.is_some() || cli.pipe;
+    if !io::stdin().is_terminal() && !one_shot && !cli.watch_clipboard {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a QUESTION is required when stdin isn't a terminal, e.g. `cat error.log | shellai \"why is this failing?\"`",
+            )
+            .exit();
+    }
 
-                // Escape key to cancel
-                KeyCode::Esc => {
-                    disable_raw_mode()?;
-                    return Ok("".to_string());
-                }
+    init_logging(cli.verbose);
 
-                _ => {}
-            }
+    // Colors only make sense when a terminal is reading the output and nobody opted out,
+    // and screen readers are better served by plain text
+    let accessible = configure_accessible();
+    configure_colors(cli.no_color || accessible);
+    configure_spinner(!cli.no_spinner && io::stdout().is_terminal());
+    configure_markdown(!cli.plain && io::stdout().is_terminal());
+
+    // Load the user configuration, falling back to defaults if it can't be read
+    let mut config = match Config::load(cli.profile.as_deref()) {
+        Ok(config) => config,
+        // A profile that was asked for by name must not be silently ignored
+        Err(e) if cli.profile.is_some() => {
+            eprintln!("Error loading configuration: {}", e);
+            std::process::exit(2);
+        }
+        Err(e) => {
+            eprintln!(
+                "Warning: Failed to load configuration, using defaults: {}",
+                e
+            );
+            Config::default()
         }
+    };
+    configure_theme(config.theme);
+    configure_timings(config.show_timings);
+
+    if cli.no_cache {
+        config.cache.enabled = false;
+    }
+    if cli.no_context {
+        config.context_format = ContextFormat::None;
+    }
+    if cli.no_git_context {
+        config.git_context = false;
+    }
+    if cli.seed.is_some() {
+        config.seed = cli.seed;
+    }
+    if cli.max_tokens.is_some() {
+        config.max_tokens = cli.max_tokens;
+    }
+    if cli.sizes {
+        config.show_sizes = true;
+    }
+    config.include_globs.extend(cli.include_globs);
+    config.exclude_globs.extend(cli.exclude_globs);
+    if let Err(e) = PathFilter::new(&config.include_globs, &config.exclude_globs) {
+        eprintln!("Error: Invalid glob: {}", e);
+        std::process::exit(2);
     }
 
-    Ok(buffer)
-}
+    // Resolve the custom system prompt once, the flag taking precedence over the configuration
+    if let Some(path) = cli.system_prompt {
+        config.system_prompt_path = Some(path);
+    }
+    config.system_prompt = match config.custom_system_prompt() {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            eprintln!("Warning: {}, using the default system prompt", e);
+            None
+        }
+    };
+    config.system_prompt_path = None;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("ShellAI - Your AI assistant in the terminal");
+    // A broken state file only loses the remembered model
+    let mut state = State::load().unwrap_or_else(|e| {
+        eprintln!("Warning: Ignoring the saved session state: {}", e);
+        State::default()
+    });
 
-    // Default model
-    let mut current_model = "gpt-4".to_string();
+    // The remembered and configured models only apply to the provider they were picked for
+    let provider = cli.provider.unwrap_or(config.provider);
+    let remembered_model = state
+        .model
+        .clone()
+        .filter(|_| state.provider.unwrap_or_default() == provider);
+    let configured_model = config.model.clone().filter(|_| config.provider == provider);
+    config.provider = provider;
+    let (mut current_model, mut model_source) =
+        resolve_model(cli.model, remembered_model, configured_model);
+    if model_source == ModelSource::Default {
+        current_model = provider.default_model().to_string();
+    }
 
-    // Create an OpenAI agent
-    let mut agent = match OpenAIAgent::new(current_model.clone()) {
+    // Create the agent of the selected provider
+    let mut agent = match create_agent(&current_model, &config) {
         Ok(agent) => agent,
         Err(e) => {
-            eprintln!("Error initializing OpenAI agent: {}", e);
-            eprintln!("Make sure the OPENAI_API_KEY environment variable is set.");
+            eprintln!("Error initializing the {} agent: {}", provider, e);
             return Err(e);
         }
     };
 
-    // Compile regex patterns for code blocks
-    // This pattern matches ```bash, ```sh, or just ``` followed by content that looks like bash
-    let bash_regex = Regex::new(r"```(?:bash|sh|)([\s\S]*?)```").unwrap();
+    // Executed code blocks get the variables of `[env]` in the configuration
+    let mut runner = BashRunner::with_env(config.env.clone())
+        .with_auto_approve(config.auto_approve.clone())
+        .with_sqlite_database(config.sqlite_database_path())
+        .with_execute_all(cli.execute_all)
+        .with_continue_on_error(cli.continue_on_error);
+    if let Some(dir) = cli.cwd {
+        match working_directory(&dir) {
+            Ok(dir) => runner = runner.with_current_dir(dir),
+            Err(e) => {
+                eprintln!("Error: Invalid --cwd: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if one_shot {
+        let prompt = match cli.question {
+            Some(question) if !io::stdin().is_terminal() => {
+                Some(question_with_stdin(&question, config.max_attachment_bytes))
+            }
+            Some(question) => Some(question),
+            None => cli.prompt,
+        };
+        let output = if cli.exec {
+            PipeOutput::Execute
+        } else if cli.structured {
+            PipeOutput::Structured
+        } else if cli.output_format == Some(OutputFormat::Html) {
+            PipeOutput::Html
+        } else if cli.json {
+            PipeOutput::Json
+        } else {
+            PipeOutput::Text
+        };
+        run_pipe_mode(&agent, &runner, prompt, output).await;
+    }
+
+    if cli.watch_clipboard {
+        run_watch_clipboard(&agent).await;
+    }
+
+    // A model given with --model is checked against the provider's models, fetching them
+    // only if it isn't built in; unknown ids are still used, as fine-tuned ones aren't listed.
+    // One-shot answers skip this to send no request but the prompt.
+    let mut fetched_models = None;
+    if model_source == ModelSource::Flag {
+        let mut known = provider.models();
+        if provider == Provider::OpenAI && unknown_model_warning(&current_model, &known).is_some() {
+            if let Ok(models) = agent.list_models().await {
+                if !models.is_empty() {
+                    known = models;
+                    fetched_models = Some(known.clone());
+                }
+            }
+        }
+        if let Some(warning) = unknown_model_warning(&current_model, &known) {
+            eprintln!("{} {}", "Warning:".warning(), warning);
+        }
+    }
+
+    install_terminal_recovery()?;
+
+    match &config.profile {
+        Some(profile) => println!(
+            "ShellAI - Your AI assistant in the terminal {}",
+            format!("(profile: {})", profile).info()
+        ),
+        None => println!("ShellAI - Your AI assistant in the terminal"),
+    }
+
+    // Prompt to send before reading any input, e.g. from --transcribe
+    let mut initial_prompt = match &cli.transcribe {
+        Some(path) => transcribe_prompt(&agent, path).await,
+        None => None,
+    };
+
+    // Model list for the selection menu, fetched on first use and kept for the session
+    let mut available_models: Option<Vec<AIModel>> = fetched_models;
+
+    // Prompts running in the background, started with /bg
+    let mut jobs = JobManager::new();
+
+    // Messages exchanged so far, sent along with each prompt and saved after each answer
+    let session_store = SessionStore::default_dir().map(SessionStore::new);
+    let mut session = match cli.resume.as_deref() {
+        Some(key) => match session_store
+            .as_ref()
+            .ok_or_else(|| "No directory to keep sessions in".into())
+            .and_then(|store| store.load(key))
+        {
+            Ok(session) => {
+                println!(
+                    "{}",
+                    format!("Resumed a session of {} prompts.", session.turns()).muted()
+                );
+                session
+            }
+            Err(e) => {
+                eprintln!("Error: Can't resume the session {}: {}", key, e);
+                std::process::exit(2);
+            }
+        },
+        None => ChatSession::new(),
+    };
+    // A resumed session goes on counting from its own tool calls
+    agent.set_tool_call_stats(session.tool_calls());
+    // The key can get a suffix if another saved session already has it
+    let resume_key = session_store
+        .as_ref()
+        .and_then(|store| store.resume_key(&session).ok())
+        .unwrap_or_else(|| session.resume_key());
+    println!(
+        "{} {}",
+        "Resume this session with:".muted(),
+        format!("shellai --resume {}", resume_key).accent()
+    );
+
+    // Output of executed code to send back to the model as the next prompt
+    let mut follow_up: Option<String> = None;
+
+    // Latest answer, for /save-html
+    let mut last_answer: Option<LastAnswer> = None;
+
+    // Prompt to edit again, after a referenced file couldn't be attached
+    let mut draft: Option<String> = None;
+
+    // Prompt dropped with Esc or whose request failed, restored with Up or Ctrl+R
+    let mut saved_draft: Option<String> = None;
+
+    // Prompts of earlier sessions, recalled with Up/Down and Ctrl+R
+    let mut history = History::load().unwrap_or_else(|e| {
+        eprintln!("Warning: Ignoring the prompt history: {}", e);
+        History::default()
+    });
+
+    // Commands suggested after code runs, shown when ready
+    let mut related = RelatedCommands {
+        agent: related_commands_agent(&config, &current_model),
+        pending: None,
+    };
+
+    // Inside tmux, the pane title shows the model until ShellAI exits
+    let mut pane_title = if io::stdout().is_terminal() {
+        PaneTitle::start(&current_model)
+    } else {
+        None
+    };
 
     // Interactive loop
     loop {
+        // Report background jobs that finished while we were busy, now that the prompt is idle
+        let finished = jobs.collect_finished().await;
+        for id in &finished {
+            println!(
+                "\n{} #{} {}",
+                "Background job".warning(),
+                id.to_string().accent(),
+                "finished - view it with /job <number>".warning()
+            );
+        }
+        if !finished.is_empty() && config.job_bell {
+            print!("\x07");
+            io::stdout().flush()?;
+        }
+
+        if let Some(commands) = related.take_finished().await {
+            let prefix = format!("{} You might also try: ", symbols().hint);
+            if !commands.is_empty() {
+                println!();
+            }
+            for (i, command) in commands.iter().enumerate() {
+                // Align the following commands under the first one, the emoji is two columns
+                let lead = if i == 0 {
+                    prefix.clone()
+                } else {
+                    " ".repeat(prefix.chars().count() + usize::from(!is_accessible()))
+                };
+                println!("{}{}", lead.muted(), command.muted());
+            }
+        }
+
+        if let Some(title) = &mut pane_title {
+            title.update(&current_model);
+        }
+
         // Print simplified inline menu
-        println!("\n{}", "─".repeat(60).bright_black());
-        println!(
+        println!("\n{}", horizontal_rule().muted());
+        if let Some(profile) = &config.profile {
+            print!(
+                "{} {} {} ",
+                "Profile:".warning(),
+                profile.info(),
+                symbols().bullet.emphasis()
+            );
+        }
+        print!(
             "{} {} {} {} {} {} {} {}",
-            "Model:".bright_yellow(),
-            current_model.bright_green(),
-            "•".bright_white(),
-            "Commands:".bright_yellow(),
-            "<c-s> to send".bright_cyan(),
-            "•".bright_white(),
-            "<c-h> for help".bright_cyan(),
-            "<c-a> for models".bright_cyan()
+            "Model:".warning(),
+            current_model.success(),
+            symbols().bullet.emphasis(),
+            "Commands:".warning(),
+            format!("{} to send", config.keys.short_name(KeyAction::Submit)).accent(),
+            symbols().bullet.emphasis(),
+            format!("{} for help", config.keys.short_name(KeyAction::Help)).accent(),
+            format!(
+                "{} for models",
+                config.keys.short_name(KeyAction::ModelMenu)
+            )
+            .accent()
         );
-        println!("{}", "─".repeat(60).bright_black());
+        if agent.capabilities().reasoning {
+            if let Some(effort) = agent.reasoning_effort() {
+                print!(
+                    " {} {}",
+                    symbols().bullet.emphasis(),
+                    format!("effort: {}", effort).info()
+                );
+            }
+        }
+        if session.branch_name() != MAIN_BRANCH {
+            print!(
+                " {} {}",
+                symbols().bullet.emphasis(),
+                format!("branch: {}", session.branch_name()).info()
+            );
+        }
+        let running_jobs = jobs.running_count();
+        if running_jobs > 0 {
+            print!(
+                " {} {}",
+                symbols().bullet.emphasis(),
+                format!("{} job(s) running", running_jobs).info()
+            );
+        }
+        println!();
+        println!("{}", horizontal_rule().muted());
+
+        if saved_draft.is_some() {
+            println!(
+                "{}",
+                "(draft available — press Ctrl+R or Up to restore)".muted()
+            );
+        }
 
         // Print prompt
-        print!("{}: ", "You".bright_green());
+        let prompt = "You";
+        print!("{}: ", prompt.prompt_label());
         io::stdout().flush()?;
 
-        // Read multiline user input
-        let user_input = read_multiline_input()?;
-
-        // We're no longer checking for "exit" or "quit" text commands
-        // as we prefer to use Ctrl+C for exiting
-
-        // Check for expanded menu command
-        if user_input == "ctrl+h" {
-            println!("\n{}", "ShellAI Expanded Help:".bright_yellow());
-            println!("{}", "─".repeat(60).bright_black());
-            println!("{} - Add a new line", "Enter".bright_cyan());
-            println!("{} - Submit your question", "Ctrl+S".bright_cyan());
-            println!("{} - Exit the application", "Ctrl+C".bright_cyan());
-            println!("{} - Cancel current input", "Esc".bright_cyan());
-            println!("{} - Navigate and edit text", "Backspace".bright_cyan());
-            println!("{} - Show this expanded help menu", "Ctrl+H".bright_cyan());
-            println!("{} - Select a different AI model", "Ctrl+A".bright_cyan());
-            println!("{}", "─".repeat(60).bright_black());
-            continue;
-        }
+        // Read multiline user input, unless a prompt is already waiting to be sent
+        let user_input = if let Some(text) = follow_up.take() {
+            // The output was just printed, so don't repeat it
+            println!("{}", "(output of the executed code)".muted());
+            text
+        } else {
+            match initial_prompt.take() {
+                Some(text) => {
+                    println!("{}", text);
+                    text
+                }
+                None => {
+                    // The saved draft is recalled as if it were the latest prompt
+                    let mut entries = history.entries().to_vec();
+                    if let Some(text) = &saved_draft {
+                        if entries.last() != Some(text) {
+                            entries.push(text.clone());
+                        }
+                    }
+                    let input = read_multiline_input(
+                        display_width(prompt) as u16 + 2,
+                        &draft.take().unwrap_or_default(),
+                        &entries,
+                        &config.keys,
+                    )?;
+                    // The shortcuts do the same as their slash commands
+                    match input {
+                        InputEvent::Submit(text) => {
+                            saved_draft = None;
+                            history.add(&text);
+                            // Losing the history shouldn't interrupt the session
+                            if let Err(e) = history.save() {
+                                eprintln!("Warning: Failed to save the prompt history: {}", e);
+                            }
+                            text
+                        }
+                        InputEvent::OpenModelMenu => "/model".to_string(),
+                        InputEvent::OpenHelp => "/help".to_string(),
+                        InputEvent::Cancel(text) => {
+                            if !text.trim().is_empty() {
+                                saved_draft = Some(text);
+                            }
+                            continue;
+                        }
+                        // The status line and the prompt are printed again, with the text
+                        // typed so far
+                        InputEvent::ClearScreen(text) => {
+                            clear_screen()?;
+                            draft = Some(text);
+                            continue;
+                        }
+                        // The prompt is edited again with the picked completion
+                        InputEvent::Complete(text) => {
+                            draft = Some(complete_run_command(text, &agent).await?);
+                            continue;
+                        }
+                        InputEvent::Quit => {
+                            println!();
+                            print_session_summary(&config, &current_model, &session).await;
+                            println!("{}", "Goodbye!".ai_label());
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        };
+
+        // Slash commands that don't send anything, /model yields the model picked, if any
+        let model_choice = match parse_command(&user_input) {
+            Some(SlashCommand::Help) => {
+                println!("\n{}", "ShellAI Expanded Help:".warning());
+                println!("{}", horizontal_rule().muted());
+                println!(
+                    "{} {} ({})",
+                    "Model:".warning(),
+                    current_model.success(),
+                    model_source.describe()
+                );
+                println!(
+                    "{} {}",
+                    "Provider:".warning(),
+                    config.provider.to_string().success()
+                );
+                let keys = &config.keys;
+                println!(
+                    "{} - Add a new line",
+                    keys.describe(KeyAction::Newline).accent()
+                );
+                println!(
+                    "{} - Submit your question",
+                    keys.describe(KeyAction::Submit).accent()
+                );
+                println!("{} - Exit the application", "Ctrl+C".accent());
+                println!(
+                    "{} - Cancel current input",
+                    keys.describe(KeyAction::Cancel).accent()
+                );
+                println!(
+                    "{} - Delete before or after the cursor",
+                    "Backspace/Delete".accent()
+                );
+                println!("{} - Move the cursor", "Arrows/Home/End".accent());
+                println!(
+                    "{} - Delete the word before the cursor",
+                    "Ctrl+W/Alt+Backspace".accent()
+                );
+                println!(
+                    "{} - Delete to the start or end of the line",
+                    "Ctrl+U/Ctrl+K".accent()
+                );
+                println!("{} - Insert the last deleted text", "Ctrl+Y".accent());
+                println!("{} - Undo the last edit", "Ctrl+_".accent());
+                println!(
+                    "{} - Recall earlier prompts from an empty input",
+                    "Up/Down".accent()
+                );
+                println!("{} - Search earlier prompts", "Ctrl+R".accent());
+                println!(
+                    "{} - Show this expanded help menu",
+                    keys.describe(KeyAction::Help).accent()
+                );
+                println!(
+                    "{} - Select a different AI model or provider",
+                    keys.describe(KeyAction::ModelMenu).accent()
+                );
+                println!(
+                    "{} - Clear the screen, keeping the conversation",
+                    keys.describe(KeyAction::ClearScreen).accent()
+                );
+                for command in COMMANDS {
+                    println!("{} - {}", command.usage.accent(), command.description);
+                }
+                println!("{}", horizontal_rule().muted());
+                continue;
+            }
+            Some(SlashCommand::Model(None)) => {
+                if available_models.is_none() {
+                    available_models = Some(fetch_available_models(&agent, config.provider).await);
+                }
+                match select_ai_model(
+                    available_models.get_or_insert_with(Vec::new),
+                    config.provider,
+                )? {
+                    Some(MenuChoice::Model(model)) => Some(Some(model)),
+                    Some(MenuChoice::Provider(provider)) if provider == config.provider => {
+                        println!(
+                            "{} {}",
+                            "Continuing with provider:".warning(),
+                            provider.to_string().success()
+                        );
+                        continue;
+                    }
+                    Some(MenuChoice::Provider(provider)) => {
+                        // The new agent is created first, so missing credentials keep the
+                        // current provider
+                        let previous = std::mem::replace(&mut config.provider, provider);
+                        let model = provider.default_model();
+                        match create_agent(model, &config) {
+                            Ok(new_agent) => {
+                                println!(
+                                    "{} {} ({})",
+                                    "Switching to provider:".warning(),
+                                    provider.to_string().success(),
+                                    model
+                                );
+                                agent = new_agent
+                                    .with_tool_call_counter(agent.tool_call_counter())
+                                    .with_latency_history(agent.latency_history());
+                                available_models = None;
+                                current_model = model.to_string();
+                                model_source = ModelSource::Selected;
+                                state.model = Some(current_model.clone());
+                                state.provider = Some(provider);
+                                if let Err(e) = state.save() {
+                                    eprintln!(
+                                        "Warning: Could not remember the selected model: {}",
+                                        e
+                                    );
+                                }
+                                related.agent = related_commands_agent(&config, &current_model);
+                            }
+                            Err(e) => {
+                                config.provider = previous;
+                                println!(
+                                    "{} {}",
+                                    format!("Can't switch to {}:", provider).error(),
+                                    e
+                                );
+                            }
+                        }
+                        continue;
+                    }
+                    None => Some(None),
+                }
+            }
+            Some(SlashCommand::Model(Some(id))) => Some(Some(
+                available_models
+                    .iter()
+                    .flatten()
+                    .chain(config.provider.models().iter())
+                    .find(|model| model.model_id == id)
+                    .cloned()
+                    .unwrap_or_else(|| AIModel::custom(id)),
+            )),
+            Some(SlashCommand::Clear) => {
+                session.clear();
+                agent.set_tool_call_stats(0);
+                save_session(session_store.as_ref(), &session);
+                clear_screen()?;
+                println!("{}", "Conversation cleared.".muted());
+                continue;
+            }
+            Some(SlashCommand::ClearScreen) => {
+                clear_screen()?;
+                continue;
+            }
+            Some(SlashCommand::Exit) => {
+                print_session_summary(&config, &current_model, &session).await;
+                println!("{}", "Goodbye!".ai_label());
+                return Ok(());
+            }
+            Some(SlashCommand::Tree) => {
+                let cwd = std::env::current_dir()?;
+                match scan_directory_with_options(&cwd, &scan_options(&config)) {
+                    Ok(tree) => println!("{}", tree),
+                    Err(e) => eprintln!("{} {}", "Error scanning directory:".error(), e),
+                }
+                continue;
+            }
+            Some(SlashCommand::System) => {
+                println!("{}", agent.build_system_prompt());
+                continue;
+            }
+            Some(SlashCommand::Unknown(name)) => {
+                println!("{}", unknown_command_message(name).warning());
+                continue;
+            }
+            Some(SlashCommand::Other { .. }) | None => None,
+        };
+
+        // Switch to the model picked with /model
+        if let Some(selected_model) = model_choice {
+            let models = available_models.get_or_insert_with(Vec::new);
 
-        // Check for model selection command
-        if user_input == "ctrl+a" {
-            match select_ai_model()? {
+            match selected_model {
                 Some(model) => {
                     println!(
                         "{} {}",
-                        "Switching to model:".bright_yellow(),
-                        model.name.bright_green()
+                        "Switching to model:".warning(),
+                        model.name.success()
                     );
+
+                    // Remember custom model ids so they show up in later menus. They aren't
+                    // validated here: an unknown id fails with the API error on the next request.
+                    if !models.contains(&model) {
+                        println!(
+                            "{}",
+                            "Custom model id, it will be checked on your next request.".muted()
+                        );
+                        models.push(model.clone());
+                    }
                     current_model = model.model_id.clone();
+                    model_source = ModelSource::Selected;
+                    state.model = Some(current_model.clone());
+                    state.provider = Some(config.provider);
+                    if let Err(e) = state.save() {
+                        eprintln!("Warning: Could not remember the selected model: {}", e);
+                    }
+                    related.agent = related_commands_agent(&config, &current_model);
 
                     // Create a new agent with the selected model
-                    agent = match OpenAIAgent::new(current_model.clone()) {
-                        Ok(new_agent) => new_agent,
+                    agent = match create_agent(&current_model, &config) {
+                        Ok(new_agent) => new_agent
+                            .with_tool_call_counter(agent.tool_call_counter())
+                            .with_latency_history(agent.latency_history()),
                         Err(e) => {
-                            eprintln!("Error initializing OpenAI agent with new model: {}", e);
+                            eprintln!("Error initializing the agent with the new model: {}", e);
                             continue;
                         }
                     };
@@ -257,111 +2622,270 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 None => {
                     println!(
                         "{} {}",
-                        "Continuing with current model:".bright_yellow(),
-                        current_model.bright_green()
+                        "Continuing with current model:".warning(),
+                        current_model.success()
                     );
                 }
             }
             continue;
         }
 
+        if handle_effort_command(&user_input, &mut config, &mut agent) {
+            continue;
+        }
+
+        if handle_seed_command(&user_input, &mut config, &mut agent) {
+            continue;
+        }
+
+        if handle_pr_description_command(&user_input, &agent).await {
+            continue;
+        }
+
+        if handle_init_ignore_command(&user_input) {
+            continue;
+        }
+        if handle_run_command(&user_input, &runner) {
+            record_command_runs(&runner, &mut session);
+            save_session(session_store.as_ref(), &session);
+            continue;
+        }
+        if handle_translate_command(&user_input, &agent).await {
+            continue;
+        }
+        if handle_review_command(&user_input, &agent).await {
+            continue;
+        }
+        if handle_security_check_command(&user_input, last_answer.as_ref(), &agent).await {
+            continue;
+        }
+
+        if handle_save_html_command(&user_input, last_answer.as_ref()) {
+            continue;
+        }
+
+        if handle_stats_command(&user_input, &current_model, &agent, &session) {
+            continue;
+        }
+
+        if handle_branch_command(&user_input, session_store.as_ref(), &agent, &mut session) {
+            continue;
+        }
+
+        // Background jobs are handled without sending anything now
+        if handle_job_command(
+            &user_input,
+            &mut jobs,
+            &agent,
+            &runner,
+            &mut session,
+            session_store.as_ref(),
+        )
+        .await?
+        {
+            continue;
+        }
+
+        // Transcribe an audio file and send the text as the prompt
+        let user_input = match parse_command(&user_input) {
+            Some(SlashCommand::Other {
+                name: "transcribe",
+                args: path,
+            }) if !path.is_empty() => match transcribe_prompt(&agent, Path::new(path)).await {
+                Some(text) => text,
+                None => continue,
+            },
+            Some(SlashCommand::Other {
+                name: "transcribe", ..
+            }) => {
+                println!(
+                    "{} /transcribe <path to .mp3, .wav, .m4a or .webm file>",
+                    "Usage:".warning()
+                );
+                continue;
+            }
+            _ => user_input,
+        };
+
         // Skip empty inputs
         if user_input.is_empty() {
             continue;
         }
 
-        // Show thinking indicator
-        print!("{}", "\nAI is thinking...".bright_yellow());
-        io::stdout().flush()?;
-
-        // Get response from OpenAI
-        match agent.generate_response(&user_input).await {
-            Ok(response) => {
-                // Clear the "thinking" indicator
-                print!("\r{}", " ".repeat(16));
-                print!("\r");
-                // Print the response
-                println!("{}: {}", "AI".bright_blue(), response);
-
-                // Check if the response contains bash code
-                let bash_blocks: Vec<_> = bash_regex.captures_iter(&response).collect();
-
-                // If bash code is found, ask if the user wants to execute it
-                if !bash_blocks.is_empty() {
-                    for (i, capture) in bash_blocks.iter().enumerate() {
-                        if let Some(code) = capture.get(1) {
-                            let bash_code = code.as_str().trim();
-
-                            println!(
-                                "\n{} #{}",
-                                "Bash code block".bright_yellow(),
-                                (i + 1).to_string().bright_yellow()
-                            );
-                            println!(
-                                "{}",
-                                "┌─────────────────────────────────────────────┐".bright_red()
-                            );
-
-                            // Split the code into lines and print each with proper formatting
-                            for line in bash_code.lines() {
-                                println!("{} {}", "│".bright_red(), line.bright_white().on_black());
-                            }
+        // Kept as typed, to be restored if the request fails
+        let typed_input = user_input.clone();
 
-                            println!(
-                                "{}",
-                                "└─────────────────────────────────────────────┘".bright_red()
-                            );
+        // /regenerate sends the last prompt again and replaces its answer, unseeded so the
+        // answer can change
+        let regenerate = matches!(
+            parse_command(&user_input),
+            Some(SlashCommand::Other {
+                name: "regenerate",
+                ..
+            })
+        );
 
-                            print!(
-                                "{} (y/n): ",
-                                "Do you want to execute this code?".bright_yellow()
-                            );
-                            io::stdout().flush()?;
+        // A /nocache prefix forces a fresh answer for this prompt
+        let (user_input, request_agent) = match parse_command(&user_input) {
+            Some(SlashCommand::Other {
+                name: "nocache",
+                args: "",
+            }) => {
+                println!("{} /nocache <prompt>", "Usage:".warning());
+                continue;
+            }
+            Some(SlashCommand::Other {
+                name: "nocache",
+                args: prompt,
+            }) => (prompt.to_string(), agent.without_cache()),
+            _ if regenerate => (user_input, agent.without_cache().with_seed(None)),
+            _ => (user_input, agent.clone()),
+        };
 
-                            let mut execute_input = String::new();
-                            io::stdin().read_line(&mut execute_input)?;
+        // Append the files referenced with @path, the prompt as typed stays on screen
+        let user_input = match attach_prompt_files(&user_input, config.max_attachment_bytes)? {
+            AttachResult::Send(prompt) => prompt,
+            AttachResult::Edit => {
+                draft = Some(user_input);
+                continue;
+            }
+            AttachResult::Cancel => continue,
+        };
 
-                            if execute_input.trim().eq_ignore_ascii_case("y") {
-                                println!("{}", "Executing bash code...".bright_green());
+        // Show thinking indicator
+        println!();
+        let spinner = Spinner::start(&thinking_message(&agent, &current_model));
 
-                                // Execute the bash code
-                                let output =
-                                    Command::new("bash").arg("-c").arg(bash_code).output()?;
+        // Get response from OpenAI
+        let started = Instant::now();
+        let result = if regenerate {
+            request_agent.regenerate_chat_completion(&mut session).await
+        } else {
+            request_agent
+                .generate_chat_completion(&mut session, &user_input)
+                .await
+        };
 
-                                // Print the command output
-                                if !output.stdout.is_empty() {
-                                    println!("{}", "Output:".bright_green());
-                                    println!("{}", String::from_utf8_lossy(&output.stdout));
-                                }
+        // Clear the "thinking" indicator, on success and on error
+        spinner.stop().await;
 
-                                // Print any errors
-                                if !output.stderr.is_empty() {
-                                    println!("{}", "Errors:".bright_red());
-                                    println!(
-                                        "{}",
-                                        String::from_utf8_lossy(&output.stderr).bright_red()
-                                    );
-                                }
+        match result {
+            Ok(completion) => {
+                if completion.cached {
+                    println!("{}", "(cached)".muted());
+                }
+                if regenerate {
+                    println!("{}", "(regenerated, replacing the previous answer)".muted());
+                }
+                // The fingerprint tells whether a seeded answer can be expected to repeat
+                if let Some(seed) = request_agent.seed() {
+                    println!(
+                        "{}",
+                        format!(
+                            "(seed {}, fingerprint {})",
+                            seed,
+                            completion
+                                .system_fingerprint
+                                .as_deref()
+                                .unwrap_or("unknown")
+                        )
+                        .muted()
+                    );
+                }
+                if completion.fallback {
+                    println!(
+                        "{}",
+                        format!("answered by {} (fallback)", completion.model).warning()
+                    );
+                }
+                if completion.attempt > 1 {
+                    println!(
+                        "{}",
+                        format!("answered on attempt {}", completion.attempt).muted()
+                    );
+                }
+                // A cached answer says nothing about how fast the model is
+                let elapsed = Some(started.elapsed()).filter(|_| !completion.cached);
+                if let Some(elapsed) = elapsed {
+                    session.record_response_time(elapsed);
+                }
+                if completion.trimmed_messages > 0 {
+                    println!(
+                        "{}",
+                        format!("{}older messages trimmed", symbols().ellipsis).muted()
+                    );
+                }
 
-                                let status_str =
-                                    format!("Execution completed with status: {}", output.status);
-                                if output.status.success() {
-                                    println!("{}", status_str.bright_green());
-                                } else {
-                                    println!("{}", status_str.bright_red());
-                                }
-                            } else {
-                                println!("{}", "Code execution skipped.".bright_yellow());
-                            }
-                        }
-                    }
+                // Reasoning tokens are billed but never shown, so say how many were spent
+                if let Some(reasoning_tokens) = completion
+                    .usage
+                    .as_ref()
+                    .map(|usage| usage.reasoning_tokens())
+                    .filter(|tokens| *tokens > 0)
+                {
+                    println!(
+                        "{}",
+                        format!("({} reasoning tokens)", reasoning_tokens).muted()
+                    );
                 }
+                session.set_tool_calls(agent.tool_call_stats());
+                save_session(session_store.as_ref(), &session);
+                last_answer = Some(LastAnswer {
+                    content: completion.content.clone(),
+                    model: completion.model.clone(),
+                    timestamp: current_timestamp(),
+                });
+                follow_up = show_response(
+                    &completion.content,
+                    &agent,
+                    &runner,
+                    Some(config.max_output_bytes),
+                    Some(&mut related),
+                )
+                .await?;
+                if let Some(elapsed) = elapsed.filter(|_| timings_shown()) {
+                    println!(
+                        "{}",
+                        format!("(model responded in {})", format_latency(elapsed)).muted()
+                    );
+                }
+                if let Some(latency_ms) = completion.latency_ms.filter(|_| timings_shown()) {
+                    println!(
+                        "{}",
+                        format!(
+                            "[API: {}]",
+                            format_latency(Duration::from_millis(latency_ms))
+                        )
+                        .muted()
+                    );
+                }
+                // The session is saved again with the timings of the code that ran
+                record_command_runs(&runner, &mut session);
+                save_session(session_store.as_ref(), &session);
             }
             Err(e) => {
-                // Clear the "thinking" indicator
-                print!("\r{}", " ".repeat(16));
-                print!("\r");
-                eprintln!("{}: {}", "Error".bright_red(), e);
+                print_request_error(e.as_ref(), config.provider);
+                if is_quota_error(e.as_ref()) {
+                    session.record_quota_blocked();
+                    save_session(session_store.as_ref(), &session);
+                    // Only for this session, the remembered model stays as it was
+                    if let Some((new_agent, model)) = offer_quota_switch(&mut config)? {
+                        println!(
+                            "{} {} ({})",
+                            "Switching to provider:".warning(),
+                            Provider::Ollama.to_string().success(),
+                            model
+                        );
+                        agent = new_agent
+                            .with_tool_call_counter(agent.tool_call_counter())
+                            .with_latency_history(agent.latency_history());
+                        available_models = None;
+                        current_model = model;
+                        model_source = ModelSource::Selected;
+                        related.agent = related_commands_agent(&config, &current_model);
+                    }
+                }
+                saved_draft = Some(typed_input);
             }
         }
     }
@@ -0,0 +1,431 @@
+// Conversations saved as they go, so they can be picked up again with --resume
+
+use crate::agents::openai::ChatSession;
+use crate::utils::files::write_private;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// First word of a resume key
+const ADJECTIVES: [&str; 32] = [
+    "amber", "bold", "brave", "calm", "clever", "coral", "crimson", "eager", "fancy", "gentle",
+    "golden", "happy", "indigo", "jolly", "kind", "lively", "lucky", "mellow", "misty", "noble",
+    "orange", "proud", "quiet", "rapid", "silver", "sunny", "swift", "tidy", "violet", "witty",
+    "young", "zesty",
+];
+
+// Second word of a resume key
+const NOUNS: [&str; 32] = [
+    "badger", "bison", "crane", "dolphin", "eagle", "falcon", "fox", "gecko", "heron", "ibis",
+    "jaguar", "koala", "lemur", "lynx", "marmot", "moose", "narwhal", "otter", "owl", "panda",
+    "puffin", "quail", "raven", "salmon", "seal", "tiger", "toucan", "turtle", "walrus", "whale",
+    "wombat", "yak",
+];
+
+/// Name of the branch a conversation starts on
+pub const MAIN_BRANCH: &str = "main";
+
+// Name of the file mapping resume keys to session ids
+const INDEX_FILE: &str = "index.toml";
+
+// Told apart sessions created in the same process at the same instant
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Builds the short key a session is resumed with, such as `orange-whale-42`
+///
+/// # Arguments
+///
+/// * `created_at` - When the session was created, in milliseconds since the Unix epoch
+///
+/// # Returns
+///
+/// An adjective, an animal and a number below 100, always the same for the same time
+pub fn resume_key(created_at: u64) -> String {
+    let digest = Sha256::digest(created_at.to_le_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    let hash = u64::from_le_bytes(bytes);
+
+    let adjective = ADJECTIVES[(hash % 32) as usize];
+    let noun = NOUNS[(hash / 32 % 32) as usize];
+    format!("{}-{}-{}", adjective, noun, hash / 1024 % 100)
+}
+
+/// Generates the id a new session is saved under, a random UUID
+pub fn new_session_id(created_at: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(created_at.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(
+        SESSION_COUNTER
+            .fetch_add(1, Ordering::Relaxed)
+            .to_le_bytes(),
+    );
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    hasher.update(nanos.to_le_bytes());
+
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hasher.finalize()[..16]);
+    // Version 4 and the RFC 4122 variant, as for any random UUID
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// Whether text has the shape of a session id, so it can safely name a file
+fn is_session_id(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Saved sessions, one JSON file per session id, with an index of their resume keys
+///
+/// A resume key only has 102,400 values, so sessions created at different times can share
+/// one. A session whose key is already taken by another gets a numbered suffix instead,
+/// such as `orange-whale-42-2`. Sessions are only readable by their owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    /// Gets the default sessions directory (`~/.local/share/shellai/sessions` on Linux)
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("shellai").join("sessions"))
+    }
+
+    /// Creates a store keeping its sessions in a directory, created on the first save
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    // The file a session is saved in
+    fn session_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    // Read the resume keys, none if nothing was saved yet
+    fn load_index(&self) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+        let path = self.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn save_index(&self, index: &BTreeMap<String, String>) -> Result<(), Box<dyn Error>> {
+        write_private(&self.dir.join(INDEX_FILE), toml::to_string(index)?)?;
+        Ok(())
+    }
+
+    // The key a session is saved under: the one it already has, or the first one free
+    fn key_in(index: &BTreeMap<String, String>, session: &ChatSession) -> String {
+        if let Some((key, _)) = index.iter().find(|(_, id)| *id == session.id()) {
+            return key.clone();
+        }
+        let key = session.resume_key();
+        std::iter::once(key.clone())
+            .chain((2..).map(|n| format!("{}-{}", key, n)))
+            .find(|candidate| !index.contains_key(candidate))
+            .unwrap_or(key)
+    }
+
+    /// Gets the key a session is resumed with, once saved
+    ///
+    /// # Returns
+    ///
+    /// The session's resume key, with a suffix if another saved session already has it
+    pub fn resume_key(&self, session: &ChatSession) -> Result<String, Box<dyn Error>> {
+        Ok(Self::key_in(&self.load_index()?, session))
+    }
+
+    /// Saves a session under its id and its resume key
+    ///
+    /// A session without messages, such as one just cleared, is removed instead, as there
+    /// is nothing in it to resume.
+    ///
+    /// # Returns
+    ///
+    /// The key the session was saved under, see `resume_key`
+    pub fn save(&self, session: &ChatSession) -> Result<String, Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.session_path(session.id());
+        let mut index = self.load_index()?;
+        let key = Self::key_in(&index, session);
+
+        if session.messages().is_empty() {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            index.retain(|_, id| id != session.id());
+        } else {
+            write_private(&path, serde_json::to_string_pretty(session)?)?;
+            index.insert(key.clone(), session.id().to_string());
+        }
+        self.save_index(&index)?;
+        Ok(key)
+    }
+
+    /// Loads a saved session
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The resume key of the session, such as `orange-whale-42`, or its id
+    ///
+    /// # Returns
+    ///
+    /// The session, or an error if no session was saved under that key or it can't be read
+    pub fn load(&self, key: &str) -> Result<ChatSession, Box<dyn Error>> {
+        let id = match self.load_index()?.remove(key) {
+            Some(id) => id,
+            None if is_session_id(key) => key.to_string(),
+            None => return Err(format!("No saved session with the key {}", key).into()),
+        };
+        let path = self.session_path(&id);
+        if !is_session_id(&id) || !path.exists() {
+            return Err(format!("No saved session with the key {}", key).into());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(&path)?)?)
+    }
+
+    /// Lists the saved branches of a conversation
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Any branch of the conversation
+    ///
+    /// # Returns
+    ///
+    /// The saved sessions forked from the same main branch, and that branch, oldest first
+    pub fn branches(&self, session: &ChatSession) -> Result<Vec<ChatSession>, Box<dyn Error>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut branches = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_session = path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(is_session_id);
+            if !is_session {
+                continue;
+            }
+            // A file that can't be read isn't a branch of this conversation
+            let Ok(saved) = serde_json::from_str::<ChatSession>(&fs::read_to_string(&path)?) else {
+                continue;
+            };
+            if saved.root_id() == session.root_id() {
+                branches.push(saved);
+            }
+        }
+        branches.sort_by_key(ChatSession::created_at);
+        Ok(branches)
+    }
+
+    /// Loads a saved branch of a conversation by name
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Any branch of the conversation
+    /// * `name` - The name of the branch, `main` for the session it started as
+    ///
+    /// # Returns
+    ///
+    /// The branch, or an error if the conversation has no saved branch with that name
+    pub fn load_branch(
+        &self,
+        session: &ChatSession,
+        name: &str,
+    ) -> Result<ChatSession, Box<dyn Error>> {
+        self.branches(session)?
+            .into_iter()
+            .find(|branch| branch.branch_name() == name)
+            .ok_or_else(|| format!("No branch named {}", name).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resume_key() {
+        let key = resume_key(1_700_000_000_000);
+        assert_eq!(key, resume_key(1_700_000_000_000));
+        assert_ne!(key, resume_key(1_700_000_000_001));
+
+        let parts: Vec<&str> = key.split('-').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(ADJECTIVES.contains(&parts[0]));
+        assert!(NOUNS.contains(&parts[1]));
+        assert!(parts[2].parse::<u32>().unwrap() < 100);
+    }
+
+    #[test]
+    fn test_new_session_id() {
+        let id = new_session_id(1_700_000_000_000);
+        assert!(is_session_id(&id));
+        assert_eq!(&id[14..15], "4");
+        assert_ne!(id, new_session_id(1_700_000_000_000));
+        assert!(!is_session_id("../../etc/passwd"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::new(dir.path().join("sessions"));
+        let mut session = ChatSession::new();
+        session.push_exchange("list files", "```bash\nls\n```");
+        store.save(&session).unwrap();
+
+        assert_eq!(store.load(&session.resume_key()).unwrap(), session);
+        assert_eq!(store.load(session.id()).unwrap(), session);
+
+        let error = store.load("orange-whale-100").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No saved session with the key orange-whale-100"
+        );
+    }
+
+    #[test]
+    fn test_cleared_session_is_removed() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let mut session = ChatSession::new();
+        session.push_exchange("hello", "Hi!");
+        store.save(&session).unwrap();
+
+        session.clear();
+        store.save(&session).unwrap();
+        assert!(store.load(&session.resume_key()).is_err());
+        assert!(store.load(session.id()).is_err());
+    }
+
+    #[test]
+    fn test_colliding_keys_get_a_suffix() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let mut first = ChatSession::new();
+        first.push_exchange("hello", "Hi!");
+        let key = store.save(&first).unwrap();
+        assert_eq!(key, first.resume_key());
+
+        // A later session whose key is taken, as if both had the same creation time
+        let mut index = store.load_index().unwrap();
+        let mut second = ChatSession::new();
+        second.push_exchange("bye", "Goodbye!");
+        index.insert(second.resume_key(), first.id().to_string());
+        store.save_index(&index).unwrap();
+
+        let second_key = store.save(&second).unwrap();
+        assert_eq!(second_key, format!("{}-2", second.resume_key()));
+        assert_eq!(store.resume_key(&second).unwrap(), second_key);
+        assert_eq!(store.save(&second).unwrap(), second_key);
+        assert_eq!(store.load(&second_key).unwrap(), second);
+        assert_eq!(store.load(&second.resume_key()).unwrap(), first);
+    }
+
+    #[test]
+    fn test_branches() {
+        let dir = tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let mut main = ChatSession::new();
+        main.push_exchange("back up my photos", "Use a cron job.");
+        store.save(&main).unwrap();
+
+        // Branches are listed by creation time, in milliseconds
+        let later = || std::thread::sleep(std::time::Duration::from_millis(2));
+        later();
+        let mut timers = main.fork("timers");
+        assert_eq!(timers.branch_name(), "timers");
+        assert_ne!(timers.id(), main.id());
+        let origin = timers.origin().unwrap();
+        assert_eq!((origin.parent_id.as_str(), origin.turn), (main.id(), 1));
+
+        // Both branches go on from the fork on their own
+        timers.push_exchange("what about systemd timers?", "Write a .timer unit.");
+        main.push_exchange("run it hourly", "Use 0 * * * *.");
+        store.save(&timers).unwrap();
+        store.save(&main).unwrap();
+
+        later();
+        let mut nested = timers.fork("nested");
+        nested.push_exchange("and on battery?", "Add ConditionACPower.");
+        store.save(&nested).unwrap();
+        assert_eq!(nested.root_id(), main.id());
+
+        // Another conversation isn't listed
+        let mut other = ChatSession::new();
+        other.push_exchange("hello", "Hi!");
+        store.save(&other).unwrap();
+
+        let names: Vec<String> = store
+            .branches(&nested)
+            .unwrap()
+            .iter()
+            .map(|branch| branch.branch_name().to_string())
+            .collect();
+        assert_eq!(names, ["main", "timers", "nested"]);
+
+        // Switching loads each branch as it was continued
+        let switched = store.load_branch(&nested, "main").unwrap();
+        assert_eq!(switched, main);
+        assert_eq!(switched.messages()[2].content, "run it hourly");
+        let switched = store.load_branch(&main, "timers").unwrap();
+        assert_eq!(switched, timers);
+        assert_eq!(switched.messages()[2].content, "what about systemd timers?");
+        let error = store.load_branch(&main, "cron").unwrap_err();
+        assert_eq!(error.to_string(), "No branch named cron");
+
+        // Branches are resumed like any session, after a restart
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let key = store.resume_key(&timers).unwrap();
+        let resumed = store.load(&key).unwrap();
+        assert_eq!(resumed, timers);
+        assert_eq!(store.branches(&resumed).unwrap().len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sessions_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let store = SessionStore::new(dir.path().to_path_buf());
+        let mut session = ChatSession::new();
+        session.push_exchange("hello", "Hi!");
+        store.save(&session).unwrap();
+
+        for path in [
+            store.session_path(session.id()),
+            dir.path().join(INDEX_FILE),
+        ] {
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Settings ShellAI remembers between sessions
+
+use crate::agents::provider::Provider;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Model used when nothing else picks one
+pub const DEFAULT_MODEL: &str = "gpt-4";
+
+/// Choices remembered from the last session
+///
+/// Unlike the configuration, this file is written by ShellAI itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Model last selected with Ctrl+A
+    pub model: Option<String>,
+    /// Provider of the remembered model, OpenAI when unset
+    pub provider: Option<Provider>,
+}
+
+impl State {
+    /// Gets the default state file path (`~/.local/state/shellai/state.toml` on Linux)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| dir.join("shellai").join("state.toml"))
+    }
+
+    /// Loads the state from the default path
+    ///
+    /// # Returns
+    ///
+    /// The saved state, the defaults if nothing was saved yet, or an error if the file
+    /// can't be read or parsed
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        match Self::default_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads the state from a file, using the defaults if it doesn't exist
+    pub fn load_from(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Saves the state to the default path
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::default_path().ok_or("No directory to save the state in")?;
+        self.save_to(&path)
+    }
+
+    /// Saves the state to a file, creating its directory if needed
+    pub fn save_to(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Where the model in use came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelSource {
+    /// The `--model` flag
+    Flag,
+    /// Selected with Ctrl+A during this session
+    Selected,
+    /// The model selected in the last session
+    State,
+    /// The `model` setting of `config.toml`
+    Config,
+    /// `DEFAULT_MODEL`
+    Default,
+}
+
+impl ModelSource {
+    /// Describes the source for the help screen, as in "gpt-4o (remembered from last session)"
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ModelSource::Flag => "from --model",
+            ModelSource::Selected => "selected in this session",
+            ModelSource::State => "remembered from last session",
+            ModelSource::Config => "from config.toml",
+            ModelSource::Default => "built-in default",
+        }
+    }
+}
+
+/// Picks the model to start with
+///
+/// The `--model` flag wins, then the model remembered from the last session, then the
+/// configuration, then `DEFAULT_MODEL`.
+///
+/// # Arguments
+///
+/// * `flag` - The model given with `--model`
+/// * `state` - The model remembered from the last session
+/// * `config` - The model set in the configuration
+///
+/// # Returns
+///
+/// The model and where it came from
+pub fn resolve_model(
+    flag: Option<String>,
+    state: Option<String>,
+    config: Option<String>,
+) -> (String, ModelSource) {
+    [
+        (flag, ModelSource::Flag),
+        (state, ModelSource::State),
+        (config, ModelSource::Config),
+    ]
+    .into_iter()
+    .find_map(|(model, source)| model.map(|model| (model, source)))
+    .unwrap_or_else(|| (DEFAULT_MODEL.to_string(), ModelSource::Default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_state_round_trip() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let path = temp_dir.path().join("shellai").join("state.toml");
+
+        // Nothing saved yet
+        assert_eq!(
+            State::load_from(&path).expect("Failed to load state"),
+            State::default()
+        );
+
+        let state = State {
+            model: Some("claude-3-5-haiku-latest".to_string()),
+            provider: Some(Provider::Anthropic),
+        };
+        state.save_to(&path).expect("Failed to save state");
+        assert_eq!(
+            State::load_from(&path).expect("Failed to load state"),
+            state
+        );
+    }
+
+    #[test]
+    fn test_corrupted_state_is_an_error() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let path = temp_dir.path().join("state.toml");
+        fs::write(&path, "model = [unterminated").expect("Failed to write state");
+
+        assert!(State::load_from(&path).is_err());
+    }
+
+    #[test]
+    fn test_resolve_model_precedence() {
+        let model = |name: &str| Some(name.to_string());
+
+        assert_eq!(
+            resolve_model(model("o1"), model("gpt-4o"), model("gpt-4o-mini")),
+            ("o1".to_string(), ModelSource::Flag)
+        );
+        assert_eq!(
+            resolve_model(None, model("gpt-4o"), model("gpt-4o-mini")),
+            ("gpt-4o".to_string(), ModelSource::State)
+        );
+        assert_eq!(
+            resolve_model(None, None, model("gpt-4o-mini")),
+            ("gpt-4o-mini".to_string(), ModelSource::Config)
+        );
+        assert_eq!(
+            resolve_model(None, None, None),
+            (DEFAULT_MODEL.to_string(), ModelSource::Default)
+        );
+    }
+}
@@ -0,0 +1,147 @@
+// Plain ASCII output for screen readers
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Characters used to draw the interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbols {
+    /// Marks a directory in a tree
+    pub dir: &'static str,
+    /// Marks a file in a tree
+    pub file: &'static str,
+    /// Marks a symbolic link in a tree
+    pub link: &'static str,
+    /// Horizontal line of separators and boxes
+    pub horizontal: &'static str,
+    /// Vertical line of boxes
+    pub vertical: &'static str,
+    /// Corners of boxes: top left, top right, bottom left and bottom right
+    pub corners: [&'static str; 4],
+    /// Junctions of table borders: top, bottom, left, right and middle
+    pub junctions: [&'static str; 5],
+    /// Starts an item of a list
+    pub bullet: &'static str,
+    /// Starts an item of an arrow list
+    pub arrow: &'static str,
+    /// Starts a done item of a checklist
+    pub check: &'static str,
+    /// Marks text that was left out
+    pub ellipsis: &'static str,
+    /// Introduces a suggestion
+    pub hint: &'static str,
+}
+
+/// Box drawing characters and emoji, for terminals
+pub const UNICODE: Symbols = Symbols {
+    dir: "📁",
+    file: "📄",
+    link: "🔗",
+    horizontal: "─",
+    vertical: "│",
+    corners: ["┌", "┐", "└", "┘"],
+    junctions: ["┬", "┴", "├", "┤", "┼"],
+    bullet: "•",
+    arrow: "→",
+    check: "✓",
+    ellipsis: "…",
+    hint: "💡",
+};
+
+/// Plain ASCII, for screen readers
+pub const ASCII: Symbols = Symbols {
+    dir: "[DIR]",
+    file: "[FILE]",
+    link: "[LINK]",
+    horizontal: "-",
+    vertical: "|",
+    corners: ["+", "+", "+", "+"],
+    junctions: ["+", "+", "+", "+", "+"],
+    bullet: "*",
+    arrow: "->",
+    check: "[x]",
+    ellipsis: "...",
+    hint: "Hint:",
+};
+
+// Whether accessible output was turned on by `configure_accessible`
+static ACCESSIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Decides whether the `SHELLAI_ACCESSIBLE` environment variable asks for accessible output
+///
+/// # Arguments
+///
+/// * `value` - The value of the variable, if set
+///
+/// # Returns
+///
+/// True if the variable is set to anything but an empty string or `0`
+pub fn accessible_requested(value: Option<&OsStr>) -> bool {
+    value.is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Turns accessible output on for the whole process if `SHELLAI_ACCESSIBLE` asks for it
+///
+/// # Returns
+///
+/// Whether accessible output is on, in which case colors should be turned off as well
+pub fn configure_accessible() -> bool {
+    let enabled = accessible_requested(std::env::var_os("SHELLAI_ACCESSIBLE").as_deref());
+    ACCESSIBLE.store(enabled, Ordering::Relaxed);
+    enabled
+}
+
+/// Whether output should avoid emoji, box drawing and animations
+pub fn is_accessible() -> bool {
+    ACCESSIBLE.load(Ordering::Relaxed)
+}
+
+/// Gets the characters to draw the interface with, plain ASCII in accessible mode
+pub fn symbols() -> &'static Symbols {
+    if is_accessible() {
+        &ASCII
+    } else {
+        &UNICODE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accessible_requested() {
+        assert!(!accessible_requested(None));
+        assert!(!accessible_requested(Some(OsStr::new(""))));
+        assert!(!accessible_requested(Some(OsStr::new("0"))));
+        assert!(accessible_requested(Some(OsStr::new("1"))));
+        assert!(accessible_requested(Some(OsStr::new("yes"))));
+    }
+
+    #[test]
+    fn test_ascii_symbols_are_ascii() {
+        let Symbols {
+            dir,
+            file,
+            link,
+            horizontal,
+            vertical,
+            corners,
+            junctions,
+            bullet,
+            arrow,
+            check,
+            ellipsis,
+            hint,
+        } = ASCII;
+        let all = [
+            dir, file, link, horizontal, vertical, bullet, arrow, check, ellipsis, hint,
+        ]
+        .into_iter()
+        .chain(corners)
+        .chain(junctions);
+        for symbol in all {
+            assert!(symbol.is_ascii(), "{} isn't ASCII", symbol);
+        }
+    }
+}
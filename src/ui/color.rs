@@ -0,0 +1,70 @@
+// Color output control for ShellAI
+
+use std::ffi::OsStr;
+use std::io::{self, IsTerminal};
+
+/// Decides whether output should be colored
+///
+/// # Arguments
+///
+/// * `no_color_flag` - Whether `--no-color` was given
+/// * `no_color_env` - The value of the `NO_COLOR` environment variable, if set
+/// * `stdout_is_terminal` - Whether a terminal is reading the output
+///
+/// # Returns
+///
+/// False if any of them asks for plain output. Following https://no-color.org, an empty
+/// `NO_COLOR` doesn't count.
+pub fn colors_enabled(
+    no_color_flag: bool,
+    no_color_env: Option<&OsStr>,
+    stdout_is_terminal: bool,
+) -> bool {
+    let no_color_env = no_color_env.is_some_and(|value| !value.is_empty());
+    !no_color_flag && !no_color_env && stdout_is_terminal
+}
+
+/// Turns colored output off for the whole process if it shouldn't be used
+///
+/// Checks `--no-color`, the `NO_COLOR` environment variable and whether stdout is a terminal.
+pub fn configure_colors(no_color_flag: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR");
+    if !colors_enabled(
+        no_color_flag,
+        no_color_env.as_deref(),
+        io::stdout().is_terminal(),
+    ) {
+        colored::control::set_override(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colored::Colorize;
+
+    #[test]
+    fn test_colors_enabled() {
+        assert!(colors_enabled(false, None, true));
+        assert!(!colors_enabled(true, None, true));
+        assert!(!colors_enabled(false, Some(OsStr::new("1")), true));
+        assert!(!colors_enabled(false, None, false));
+        // An empty NO_COLOR is ignored
+        assert!(colors_enabled(false, Some(OsStr::new("")), true));
+    }
+
+    #[test]
+    fn test_disabled_colors_render_plain_text() {
+        colored::control::set_override(false);
+        let rendered = format!(
+            "{}: {} {}",
+            "AI".bright_blue(),
+            "answer".bright_yellow().on_black(),
+            "dim".bright_black()
+        );
+        colored::control::unset_override();
+
+        assert_eq!(rendered, "AI: answer dim");
+        assert!(!rendered.contains('\x1b'));
+    }
+}
@@ -0,0 +1,258 @@
+// Slash commands typed at the interactive prompt
+
+/// A slash command known to the interactive prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// Name typed after the slash
+    pub name: &'static str,
+    /// How the command is written, with its arguments
+    pub usage: &'static str,
+    /// What the command does, for the help screen
+    pub description: &'static str,
+}
+
+// Shorthand for the entries of `COMMANDS`
+const fn info(name: &'static str, usage: &'static str, description: &'static str) -> CommandInfo {
+    CommandInfo {
+        name,
+        usage,
+        description,
+    }
+}
+
+/// Every slash command, in the order the help screen lists them
+pub const COMMANDS: &[CommandInfo] = &[
+    info("help", "/help", "Show this help screen (also Ctrl+H)"),
+    info(
+        "model",
+        "/model [id]",
+        "Switch to a model, or pick one from the list (also Ctrl+A)",
+    ),
+    info(
+        "clear",
+        "/clear",
+        "Forget the conversation and clear the screen",
+    ),
+    info(
+        "clear-screen",
+        "/clear-screen",
+        "Clear the screen, keeping the conversation (also Ctrl+L)",
+    ),
+    info("exit", "/exit", "Exit the application (also Ctrl+C)"),
+    info("tree", "/tree", "Show the directory scan sent to the model"),
+    info(
+        "init-ignore",
+        "/init-ignore",
+        "Write a .shellai-ignore of the large directories in .gitignore",
+    ),
+    info("system", "/system", "Show the current system prompt"),
+    info(
+        "transcribe",
+        "/transcribe <path>",
+        "Transcribe an audio file and send it",
+    ),
+    info("bg", "/bg <prompt>", "Answer a prompt in the background"),
+    info("jobs", "/jobs", "List background jobs"),
+    info("job", "/job <n>", "Show the answer of a finished job"),
+    info("cancel", "/cancel <n>", "Cancel a running job"),
+    info("seed", "/seed [n|off]", "Set or clear the sampling seed"),
+    info(
+        "run",
+        "/run <command>",
+        "Run a bash command, Tab asks the AI to complete it",
+    ),
+    info(
+        "regenerate",
+        "/regenerate",
+        "Ask again for the last answer, replacing it",
+    ),
+    info(
+        "nocache",
+        "/nocache <prompt>",
+        "Send a prompt without using cached answers",
+    ),
+    info(
+        "pr-description",
+        "/pr-description [title]",
+        "Describe the changes since main as a pull request",
+    ),
+    info(
+        "security-check",
+        "/security-check",
+        "Review the last code block for security issues",
+    ),
+    info(
+        "translate",
+        "/translate <command> --to <shell>",
+        "Write a bash command for fish, zsh or PowerShell",
+    ),
+    info(
+        "review",
+        "/review <original> <modified>",
+        "Review the changes between two versions of a file",
+    ),
+    info(
+        "save-html",
+        "/save-html <filename>",
+        "Save the last answer as an HTML page",
+    ),
+    info(
+        "effort",
+        "/effort [low|medium|high]",
+        "Set the effort of reasoning models",
+    ),
+    info(
+        "stats",
+        "/stats",
+        "Show the message and tool call counts of the session",
+    ),
+    info(
+        "branch",
+        "/branch <name>",
+        "Fork the conversation into a new branch and switch to it",
+    ),
+    info(
+        "branches",
+        "/branches",
+        "List the branches of the conversation",
+    ),
+    info(
+        "switch",
+        "/switch <name>",
+        "Switch to another branch of the conversation",
+    ),
+];
+
+/// A slash command parsed from the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Show the help screen
+    Help,
+    /// Switch to the given model, or pick one from the list
+    Model(Option<&'a str>),
+    /// Forget the conversation and clear the screen
+    Clear,
+    /// Clear the screen, keeping the conversation
+    ClearScreen,
+    /// Leave ShellAI
+    Exit,
+    /// Show the directory scan
+    Tree,
+    /// Show the system prompt
+    System,
+    /// A known command that has a handler of its own, with its arguments
+    Other { name: &'a str, args: &'a str },
+    /// A name that isn't a known command
+    Unknown(&'a str),
+}
+
+/// Parses the prompt as a slash command
+///
+/// # Arguments
+///
+/// * `input` - The submitted prompt
+///
+/// # Returns
+///
+/// The command, or None if the prompt doesn't start with a slash or starts with a path
+/// such as `/etc/hosts`, in which case it is meant for the model
+pub fn parse_command(input: &str) -> Option<Command<'_>> {
+    let rest = input.trim().strip_prefix('/')?;
+    let (name, args) = match rest.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (rest, ""),
+    };
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+
+    Some(match name {
+        "help" => Command::Help,
+        "model" => Command::Model(Some(args).filter(|args| !args.is_empty())),
+        "clear" => Command::Clear,
+        "clear-screen" => Command::ClearScreen,
+        "exit" => Command::Exit,
+        "tree" => Command::Tree,
+        "system" => Command::System,
+        _ if COMMANDS.iter().any(|command| command.name == name) => Command::Other { name, args },
+        _ => Command::Unknown(name),
+    })
+}
+
+/// Builds the message shown for an unknown command
+///
+/// # Arguments
+///
+/// * `name` - The name that was typed, without the slash
+///
+/// # Returns
+///
+/// The message, listing the available commands
+pub fn unknown_command_message(name: &str) -> String {
+    let names: Vec<String> = COMMANDS
+        .iter()
+        .map(|command| format!("/{}", command.name))
+        .collect();
+    format!(
+        "Unknown command /{}. Available commands: {}",
+        name,
+        names.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_builtin_commands() {
+        assert_eq!(parse_command("/help"), Some(Command::Help));
+        assert_eq!(parse_command("  /exit \n"), Some(Command::Exit));
+        assert_eq!(parse_command("/clear"), Some(Command::Clear));
+        assert_eq!(parse_command("/clear-screen"), Some(Command::ClearScreen));
+        assert_eq!(parse_command("/tree"), Some(Command::Tree));
+        assert_eq!(parse_command("/system"), Some(Command::System));
+        assert_eq!(parse_command("/model"), Some(Command::Model(None)));
+        assert_eq!(
+            parse_command("/model  gpt-4o "),
+            Some(Command::Model(Some("gpt-4o")))
+        );
+    }
+
+    #[test]
+    fn test_parse_commands_with_own_handlers() {
+        assert_eq!(
+            parse_command("/bg list large files"),
+            Some(Command::Other {
+                name: "bg",
+                args: "list large files"
+            })
+        );
+        assert_eq!(
+            parse_command("/jobs"),
+            Some(Command::Other {
+                name: "jobs",
+                args: ""
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_non_commands() {
+        assert_eq!(parse_command("how do I list files?"), None);
+        assert_eq!(parse_command("/"), None);
+        // Prompts starting with a path are meant for the model
+        assert_eq!(parse_command("/etc/hosts is missing an entry"), None);
+    }
+
+    #[test]
+    fn test_unknown_commands() {
+        assert_eq!(parse_command("/quit"), Some(Command::Unknown("quit")));
+
+        let message = unknown_command_message("quit");
+        assert!(message.starts_with("Unknown command /quit."));
+        for command in COMMANDS {
+            assert!(message.contains(&format!("/{}", command.name)));
+        }
+    }
+}
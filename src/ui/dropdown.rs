@@ -0,0 +1,187 @@
+// A list of choices shown below the prompt, picked with the arrow keys
+
+use crate::ui::accessible;
+use crate::ui::editor::display_width;
+use crate::ui::input::{CrosstermEvents, EventSource};
+use crate::ui::terminal::{terminal_width, RawModeGuard};
+use crate::ui::theme::Themed;
+use colored::*;
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    event::{Event, KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+
+/// Shows the choices below the cursor and lets the user pick one
+///
+/// Up and Down move the selection, Tab or Enter take it, and Esc or Ctrl+C give up. The
+/// terminal is in raw mode while choosing, and the list is cleared afterwards.
+///
+/// # Returns
+///
+/// The index of the picked choice, or None if the user gave up
+pub fn select_dropdown(choices: &[String]) -> io::Result<Option<usize>> {
+    let raw_mode = RawModeGuard::enable()?;
+    let mut stdout = io::stdout();
+    let selected = run_dropdown(&mut CrosstermEvents, &mut stdout, choices, terminal_width());
+    drop(raw_mode);
+    selected
+}
+
+/// Lets the user pick one of the choices from key events, drawing the list to `out`
+///
+/// # Arguments
+///
+/// * `events` - Where key events are read from
+/// * `out` - Where the list is drawn, normally stdout in raw mode
+/// * `choices` - The choices, listed in this order
+/// * `width` - The number of columns of the terminal, to count the rows of long choices
+///
+/// # Returns
+///
+/// The index of the picked choice, or None if the user gave up or there was nothing to pick
+pub fn run_dropdown(
+    events: &mut impl EventSource,
+    out: &mut impl Write,
+    choices: &[String],
+    width: usize,
+) -> io::Result<Option<usize>> {
+    if choices.is_empty() {
+        return Ok(None);
+    }
+    let rows = list_rows(choices, width);
+    let mut selected = 0;
+    draw_choices(out, choices, selected, None)?;
+
+    let picked = loop {
+        let Event::Key(KeyEvent {
+            code, modifiers, ..
+        }) = events.read_event()?
+        else {
+            continue;
+        };
+        match code {
+            KeyCode::Up => selected = selected.checked_sub(1).unwrap_or(choices.len() - 1),
+            KeyCode::Down => selected = (selected + 1) % choices.len(),
+            KeyCode::Tab | KeyCode::Enter => break Some(selected),
+            KeyCode::Esc => break None,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break None,
+            _ => continue,
+        }
+        draw_choices(out, choices, selected, Some(rows))?;
+    };
+
+    // Leave the screen as it was before the list
+    queue!(
+        out,
+        MoveUp(rows),
+        MoveToColumn(0),
+        Clear(ClearType::FromCursorDown)
+    )?;
+    out.flush()?;
+    Ok(picked)
+}
+
+// Number of terminal rows the list takes, a choice wider than the terminal wrapping onto
+// more than one
+fn list_rows(choices: &[String], width: usize) -> u16 {
+    let marker = display_width(accessible::symbols().arrow) + 1;
+    let rows: usize = choices
+        .iter()
+        .map(|choice| {
+            (marker + display_width(choice))
+                .div_ceil(width.max(1))
+                .max(1)
+        })
+        .sum();
+    rows.try_into().unwrap_or(u16::MAX)
+}
+
+// Draw the choices below the cursor, one line each, going back over the `redraw` rows drawn
+// before, and leave the cursor below the last line
+fn draw_choices(
+    out: &mut impl Write,
+    choices: &[String],
+    selected: usize,
+    redraw: Option<u16>,
+) -> io::Result<()> {
+    if let Some(rows) = redraw {
+        queue!(out, MoveUp(rows))?;
+    }
+    queue!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+    let arrow = accessible::symbols().arrow;
+    for (i, choice) in choices.iter().enumerate() {
+        if i == selected {
+            write!(out, "{} {}\r\n", arrow.accent(), choice.emphasis().bold())?;
+        } else {
+            write!(out, "{} {}\r\n", " ".repeat(arrow.chars().count()), choice)?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::input::ScriptedEvents;
+
+    fn keys(keys: &[KeyCode]) -> ScriptedEvents {
+        ScriptedEvents(
+            keys.iter()
+                .map(|&code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+                .collect(),
+        )
+    }
+
+    fn pick(codes: &[KeyCode]) -> io::Result<Option<usize>> {
+        let choices = ["git status", "git stash", "git switch"].map(String::from);
+        run_dropdown(&mut keys(codes), &mut Vec::new(), &choices, 80)
+    }
+
+    #[test]
+    fn test_arrows_move_the_selection() {
+        assert_eq!(pick(&[KeyCode::Tab]).unwrap(), Some(0));
+        assert_eq!(
+            pick(&[KeyCode::Down, KeyCode::Down, KeyCode::Enter]).unwrap(),
+            Some(2)
+        );
+        // The selection wraps around at both ends
+        assert_eq!(pick(&[KeyCode::Up, KeyCode::Tab]).unwrap(), Some(2));
+        assert_eq!(
+            pick(&[KeyCode::Up, KeyCode::Down, KeyCode::Tab]).unwrap(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_escape_picks_nothing() {
+        assert_eq!(pick(&[KeyCode::Down, KeyCode::Esc]).unwrap(), None);
+        assert_eq!(
+            run_dropdown(&mut ScriptedEvents::default(), &mut Vec::new(), &[], 80).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wrapped_choices_are_cleared() {
+        // The marker and a space take two columns, so the second choice needs two rows of 10
+        let choices = ["ls", "git log --oneline", "日本語日本語日本"].map(String::from);
+        assert_eq!(list_rows(&choices, 10), 5);
+        assert_eq!(list_rows(&choices, 80), 3);
+
+        let mut out = Vec::new();
+        run_dropdown(
+            &mut keys(&[KeyCode::Down, KeyCode::Tab]),
+            &mut out,
+            &choices,
+            10,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // Redrawn once and cleared once, going back over all five rows each time
+        assert_eq!(out.matches("\x1b[5A").count(), 2);
+        assert!(!out.contains("\x1b[3A"));
+    }
+}
@@ -0,0 +1,640 @@
+// Input buffer with cursor tracking for the multiline editor
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Text typed by the user together with the cursor position
+///
+/// The cursor is a byte offset into the text that always sits on a grapheme boundary,
+/// so all edits happen at the cursor rather than at the end of the text. Deleting and
+/// moving go one grapheme at a time, so an emoji or a letter with combining accents
+/// counts as one character.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputBuffer {
+    text: String,
+    cursor: usize,
+}
+
+impl InputBuffer {
+    /// Creates an empty buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a buffer holding `text`, with the cursor at the end
+    pub fn with_text(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    /// Gets the text in the buffer
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Consumes the buffer and returns its text
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    /// Returns true if nothing has been typed
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Inserts a character at the cursor and moves the cursor after it
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Inserts text at the cursor and moves the cursor after it
+    pub fn insert_str(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Deletes the grapheme after the cursor, as the Delete key does
+    ///
+    /// # Returns
+    ///
+    /// The deleted grapheme, or None if the cursor is at the end of the buffer
+    pub fn delete(&mut self) -> Option<String> {
+        let grapheme = self.text[self.cursor..].graphemes(true).next()?.to_string();
+        self.text
+            .replace_range(self.cursor..self.cursor + grapheme.len(), "");
+        Some(grapheme)
+    }
+
+    /// Deletes the grapheme before the cursor
+    ///
+    /// # Returns
+    ///
+    /// The deleted grapheme, or None if the cursor is at the start of the buffer
+    pub fn backspace(&mut self) -> Option<String> {
+        let grapheme = self.text[..self.cursor]
+            .graphemes(true)
+            .next_back()?
+            .to_string();
+        self.cursor -= grapheme.len();
+        self.text
+            .replace_range(self.cursor..self.cursor + grapheme.len(), "");
+        Some(grapheme)
+    }
+
+    /// Deletes the word before the cursor, as Ctrl+W does
+    ///
+    /// Words are separated by whitespace, line breaks included, and punctuation; the
+    /// separators just before the cursor go with the word.
+    ///
+    /// # Returns
+    ///
+    /// The deleted text, or None if the cursor is at the start of the buffer
+    pub fn delete_word_before(&mut self) -> Option<String> {
+        let mut start = self.cursor;
+        let mut in_word = false;
+        for (index, grapheme) in self.text[..self.cursor].grapheme_indices(true).rev() {
+            let is_word = is_word_grapheme(grapheme);
+            if in_word && !is_word {
+                break;
+            }
+            in_word |= is_word;
+            start = index;
+        }
+        self.remove(start, self.cursor)
+    }
+
+    /// Deletes from the start of the current line to the cursor, as Ctrl+U does
+    ///
+    /// # Returns
+    ///
+    /// The deleted text, or None if the cursor is at the start of its line
+    pub fn kill_to_line_start(&mut self) -> Option<String> {
+        self.remove(self.line_start(), self.cursor)
+    }
+
+    /// Deletes from the cursor to the end of the current line, as Ctrl+K does
+    ///
+    /// # Returns
+    ///
+    /// The deleted text, or None if the cursor is at the end of its line
+    pub fn kill_to_line_end(&mut self) -> Option<String> {
+        self.remove(self.cursor, self.line_end())
+    }
+
+    /// Moves the cursor one grapheme to the left, crossing line breaks
+    ///
+    /// # Returns
+    ///
+    /// True if the cursor moved
+    pub fn move_left(&mut self) -> bool {
+        match self.text[..self.cursor].graphemes(true).next_back() {
+            Some(grapheme) => {
+                self.cursor -= grapheme.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor one grapheme to the right, crossing line breaks
+    ///
+    /// # Returns
+    ///
+    /// True if the cursor moved
+    pub fn move_right(&mut self) -> bool {
+        match self.text[self.cursor..].graphemes(true).next() {
+            Some(grapheme) => {
+                self.cursor += grapheme.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to the same column of the previous line, or the end of that line
+    /// if it is shorter
+    ///
+    /// # Returns
+    ///
+    /// True if the cursor moved, false on the first line
+    pub fn move_up(&mut self) -> bool {
+        let row = self.cursor_row();
+        if row == 0 {
+            return false;
+        }
+        self.move_to(row - 1, self.cursor_column());
+        true
+    }
+
+    /// Moves the cursor to the same column of the next line, or the end of that line
+    /// if it is shorter
+    ///
+    /// # Returns
+    ///
+    /// True if the cursor moved, false on the last line
+    pub fn move_down(&mut self) -> bool {
+        let row = self.cursor_row();
+        if row + 1 >= self.lines().len() {
+            return false;
+        }
+        self.move_to(row + 1, self.cursor_column());
+        true
+    }
+
+    /// Moves the cursor to the start of the current line
+    pub fn move_home(&mut self) {
+        self.cursor = self.line_start();
+    }
+
+    /// Moves the cursor to the end of the current line
+    pub fn move_end(&mut self) {
+        self.cursor = self.line_end();
+    }
+
+    /// Moves the cursor to the end of the buffer
+    pub fn move_to_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Gets the index of the line the cursor is on
+    pub fn cursor_row(&self) -> usize {
+        self.text[..self.cursor].matches('\n').count()
+    }
+
+    /// Gets the cursor position within its line, in terminal columns
+    pub fn cursor_column(&self) -> usize {
+        display_width(&self.text[self.line_start()..self.cursor])
+    }
+
+    /// Gets the lines of the buffer, including a trailing empty line after a final newline
+    pub fn lines(&self) -> Vec<&str> {
+        self.text.split('\n').collect()
+    }
+
+    // Move the cursor to a terminal column of a line, clamped to the line's width, or
+    // before the grapheme covering that column
+    fn move_to(&mut self, row: usize, column: usize) {
+        let start: usize = self
+            .text
+            .split('\n')
+            .take(row)
+            .map(|line| line.len() + 1)
+            .sum();
+        let line = self.text[start..].split('\n').next().unwrap_or("");
+        let mut width = 0;
+        let mut offset = line.len();
+        for (index, grapheme) in line.grapheme_indices(true) {
+            width += display_width(grapheme);
+            if width > column {
+                offset = index;
+                break;
+            }
+        }
+        self.cursor = start + offset;
+    }
+
+    // Byte offset of the start of the line containing the cursor
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor]
+            .rfind('\n')
+            .map_or(0, |offset| offset + 1)
+    }
+
+    // Byte offset of the end of the line containing the cursor
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..]
+            .find('\n')
+            .map_or(self.text.len(), |offset| self.cursor + offset)
+    }
+
+    // Remove the text between two byte offsets and put the cursor where it was
+    fn remove(&mut self, start: usize, end: usize) -> Option<String> {
+        if start == end {
+            return None;
+        }
+        let removed = self.text.drain(start..end).collect();
+        self.cursor = start;
+        Some(removed)
+    }
+}
+
+// Whether a grapheme belongs to a word rather than separating words
+fn is_word_grapheme(grapheme: &str) -> bool {
+    grapheme
+        .chars()
+        .next()
+        .is_some_and(|c| !c.is_whitespace() && !c.is_ascii_punctuation())
+}
+
+/// Where the buffer is drawn on the terminal, with lines wrapping at the terminal width
+///
+/// Rows are counted from the first row of the input. A line that exactly fills its last
+/// row is followed by an empty row, which the drawing code forces with a line break, so
+/// the position just after any character is always on screen. Characters take their
+/// display width, two columns for CJK and most emoji, and are assumed not to be split
+/// across rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenLayout {
+    /// Width of the prompt printed before the first line
+    pub prompt_width: usize,
+    /// Width of the terminal
+    pub width: usize,
+}
+
+impl ScreenLayout {
+    /// Creates a layout for a terminal `width` columns wide
+    pub fn new(prompt_width: u16, width: u16) -> Self {
+        Self {
+            prompt_width: prompt_width as usize,
+            width: width.max(1) as usize,
+        }
+    }
+
+    /// Gets the screen row and column of a column of a buffer line
+    pub fn position(&self, lines: &[&str], row: usize, column: usize) -> (usize, usize) {
+        let start_row: usize = lines[..row]
+            .iter()
+            .enumerate()
+            .map(|(i, line)| self.line_columns(i, line) / self.width + 1)
+            .sum();
+        let columns = self.offset(row) + column;
+        (start_row + columns / self.width, columns % self.width)
+    }
+
+    /// Gets the screen row and column of the buffer's cursor
+    pub fn cursor(&self, buffer: &InputBuffer) -> (usize, usize) {
+        self.position(&buffer.lines(), buffer.cursor_row(), buffer.cursor_column())
+    }
+
+    /// Gets the screen row and column just after the last character of the buffer
+    pub fn end(&self, buffer: &InputBuffer) -> (usize, usize) {
+        let lines = buffer.lines();
+        let last = lines.len() - 1;
+        self.position(&lines, last, display_width(lines[last]))
+    }
+
+    /// Whether a buffer line ends exactly at the right edge of the terminal, where the
+    /// terminal doesn't move to the next row until more is printed
+    pub fn fills_row(&self, row: usize, line: &str) -> bool {
+        let columns = self.line_columns(row, line);
+        columns > 0 && columns.is_multiple_of(self.width)
+    }
+
+    // Columns taken by a line, including the prompt on the first one
+    fn line_columns(&self, row: usize, line: &str) -> usize {
+        self.offset(row) + display_width(line)
+    }
+
+    // Column a line starts at
+    fn offset(&self, row: usize) -> usize {
+        if row == 0 {
+            self.prompt_width
+        } else {
+            0
+        }
+    }
+}
+
+/// Gets the number of terminal columns a text takes
+///
+/// # Arguments
+///
+/// * `text` - The text, without line breaks
+///
+/// # Returns
+///
+/// The display width, counting wide characters as two columns and combining marks as none
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// Removes the last grapheme of a text, as Backspace does at the end of a line
+///
+/// # Returns
+///
+/// The removed grapheme, or None if the text was empty
+pub fn pop_grapheme(text: &mut String) -> Option<String> {
+    let (start, grapheme) = text.grapheme_indices(true).next_back()?;
+    let grapheme = grapheme.to_string();
+    text.truncate(start);
+    Some(grapheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_from(text: &str) -> InputBuffer {
+        let mut buffer = InputBuffer::new();
+        for c in text.chars() {
+            buffer.insert(c);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_insert_appends_at_end() {
+        let buffer = buffer_from("ls -la");
+        assert_eq!(buffer.text(), "ls -la");
+        assert_eq!(buffer.cursor_column(), 6);
+    }
+
+    #[test]
+    fn test_with_text_puts_cursor_at_end() {
+        let mut buffer = InputBuffer::with_text("explain\n@main.rs");
+        assert_eq!(buffer, buffer_from("explain\n@main.rs"));
+        buffer.insert('!');
+        assert_eq!(buffer.text(), "explain\n@main.rs!");
+    }
+
+    #[test]
+    fn test_insert_in_middle() {
+        let mut buffer = buffer_from("ls la");
+        buffer.move_left();
+        buffer.move_left();
+        buffer.insert('-');
+        assert_eq!(buffer.text(), "ls -la");
+        assert_eq!(buffer.cursor_column(), 4);
+
+        // Inserting a newline in the middle splits the line
+        buffer.insert('\n');
+        assert_eq!(buffer.text(), "ls -\nla");
+        assert_eq!(buffer.cursor_row(), 1);
+        assert_eq!(buffer.cursor_column(), 0);
+    }
+
+    #[test]
+    fn test_insert_str_at_cursor() {
+        let mut buffer = buffer_from("echo done");
+        buffer.move_home();
+        buffer.insert_str("set -e\ncd /tmp\n");
+        assert_eq!(buffer.text(), "set -e\ncd /tmp\necho done");
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (2, 0));
+    }
+
+    #[test]
+    fn test_delete_in_middle() {
+        let mut buffer = buffer_from("ls -xla");
+        buffer.move_left();
+        buffer.move_left();
+        assert_eq!(buffer.backspace().as_deref(), Some("x"));
+        assert_eq!(buffer.text(), "ls -la");
+        assert_eq!(buffer.cursor_column(), 4);
+
+        buffer.move_home();
+        assert_eq!(buffer.backspace(), None);
+        assert_eq!(buffer.text(), "ls -la");
+    }
+
+    #[test]
+    fn test_backspace_joins_lines() {
+        let mut buffer = buffer_from("first\nsecond");
+        buffer.move_home();
+        assert_eq!(buffer.backspace().as_deref(), Some("\n"));
+        assert_eq!(buffer.text(), "firstsecond");
+        assert_eq!(buffer.cursor_row(), 0);
+        assert_eq!(buffer.cursor_column(), 5);
+    }
+
+    #[test]
+    fn test_delete_word_before() {
+        let mut buffer = buffer_from("git log --oneline  ");
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("oneline  "));
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("log --"));
+        assert_eq!(buffer.text(), "git ");
+
+        // Punctuation ends a word, and line breaks are whitespace
+        let mut buffer = buffer_from("cat src/main.rs\nwc");
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("wc"));
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("rs\n"));
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("main."));
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("src/"));
+        assert_eq!(buffer.delete_word_before().as_deref(), Some("cat "));
+        assert_eq!(buffer.delete_word_before(), None);
+    }
+
+    #[test]
+    fn test_kill_to_line_start_and_end() {
+        let mut buffer = buffer_from("first\nsecond line");
+        for _ in 0..5 {
+            buffer.move_left();
+        }
+        assert_eq!(buffer.kill_to_line_end().as_deref(), Some(" line"));
+        assert_eq!(buffer.kill_to_line_end(), None);
+        assert_eq!(buffer.kill_to_line_start().as_deref(), Some("second"));
+        assert_eq!(buffer.kill_to_line_start(), None);
+        assert_eq!(buffer.text(), "first\n");
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (1, 0));
+
+        let mut buffer = InputBuffer::with_text("ls");
+        buffer.move_home();
+        assert_eq!(buffer.kill_to_line_start(), None);
+        assert_eq!(buffer.text(), "ls");
+    }
+
+    #[test]
+    fn test_home_and_end_stay_on_current_line() {
+        let mut buffer = buffer_from("first\nsecond\nthird");
+        for _ in 0..8 {
+            buffer.move_left();
+        }
+        assert_eq!(buffer.cursor_row(), 1);
+
+        buffer.move_home();
+        assert_eq!(buffer.cursor_row(), 1);
+        assert_eq!(buffer.cursor_column(), 0);
+
+        buffer.move_end();
+        assert_eq!(buffer.cursor_row(), 1);
+        assert_eq!(buffer.cursor_column(), 6);
+    }
+
+    #[test]
+    fn test_moves_stop_at_buffer_edges() {
+        let mut buffer = buffer_from("é\n");
+        assert!(!buffer.move_right());
+        assert!(buffer.move_left());
+        assert!(buffer.move_left());
+        assert!(!buffer.move_left());
+        assert_eq!(buffer.cursor_column(), 0);
+        assert_eq!(buffer.lines(), vec!["é", ""]);
+    }
+
+    #[test]
+    fn test_up_and_down_keep_the_column() {
+        let mut buffer = buffer_from("first line\nab\nthird line");
+        assert!(!buffer.move_down());
+
+        // The short line clamps the column
+        assert!(buffer.move_up());
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (1, 2));
+        assert!(buffer.move_up());
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (0, 2));
+        assert!(!buffer.move_up());
+
+        buffer.move_end();
+        assert!(buffer.move_down());
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (1, 2));
+        buffer.insert('c');
+        assert_eq!(buffer.text(), "first line\nabc\nthird line");
+    }
+
+    #[test]
+    fn test_delete_removes_the_next_character() {
+        let mut buffer = buffer_from("ab\ncd");
+        buffer.move_up();
+        buffer.move_home();
+        assert_eq!(buffer.delete().as_deref(), Some("a"));
+        buffer.move_end();
+        assert_eq!(buffer.delete().as_deref(), Some("\n"));
+        assert_eq!(buffer.text(), "bcd");
+        buffer.move_to_end();
+        assert_eq!(buffer.delete(), None);
+    }
+
+    #[test]
+    fn test_backspace_deletes_one_grapheme() {
+        // "e" followed by a combining acute accent is one grapheme of one column
+        let mut buffer = buffer_from("cafe\u{301} 👍🏽");
+        assert_eq!(buffer.cursor_column(), 7);
+
+        // The thumbs up and its skin tone modifier go together
+        assert_eq!(buffer.backspace().as_deref(), Some("👍🏽"));
+        assert_eq!(buffer.cursor_column(), 5);
+        assert_eq!(buffer.backspace().as_deref(), Some(" "));
+        assert_eq!(buffer.backspace().as_deref(), Some("e\u{301}"));
+        assert_eq!(buffer.text(), "caf");
+        assert_eq!(buffer.cursor_column(), 3);
+    }
+
+    #[test]
+    fn test_wide_characters_take_two_columns() {
+        let mut buffer = buffer_from("日本語");
+        assert_eq!(buffer.cursor_column(), 6);
+        assert!(buffer.move_left());
+        assert_eq!(buffer.cursor_column(), 4);
+        assert_eq!(buffer.delete().as_deref(), Some("語"));
+        assert_eq!(buffer.backspace().as_deref(), Some("本"));
+        assert_eq!(buffer.text(), "日");
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn test_display_width_of_scripts() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("ls -la"), 6);
+        // Precomposed and combining accents both take one column
+        assert_eq!(display_width("café"), 4);
+        assert_eq!(display_width("cafe\u{301}"), 4);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("한글 ok"), 7);
+        assert_eq!(display_width("👍"), 2);
+        assert_eq!(display_width("👍🏽"), 2);
+    }
+
+    #[test]
+    fn test_pop_grapheme() {
+        let mut text = "né 日👍🏽".to_string();
+        assert_eq!(pop_grapheme(&mut text).as_deref(), Some("👍🏽"));
+        assert_eq!(pop_grapheme(&mut text).as_deref(), Some("日"));
+        assert_eq!(text, "né ");
+        let mut text = "e\u{301}".to_string();
+        assert_eq!(pop_grapheme(&mut text).as_deref(), Some("e\u{301}"));
+        assert_eq!(pop_grapheme(&mut text), None);
+    }
+
+    #[test]
+    fn test_up_and_down_use_display_columns() {
+        // Column 3 falls inside 本, so the cursor stops before it
+        let mut buffer = buffer_from("日本語\nabc");
+        assert!(buffer.move_up());
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (0, 2));
+        assert!(buffer.move_down());
+        assert_eq!((buffer.cursor_row(), buffer.cursor_column()), (1, 2));
+
+        // The emoji line is 4 columns wide, so moving up from column 5 clamps to its end
+        let mut buffer = buffer_from("🎉🎉\nabcde");
+        assert!(buffer.move_up());
+        assert_eq!(buffer.cursor_column(), 4);
+        buffer.insert('!');
+        assert_eq!(buffer.text(), "🎉🎉!\nabcde");
+    }
+
+    #[test]
+    fn test_screen_layout_wraps_long_lines() {
+        // 10 columns, with a 5 column prompt on the first line
+        let layout = ScreenLayout::new(5, 10);
+        let buffer = buffer_from("abcdefgh\nxy");
+        let lines = buffer.lines();
+
+        assert_eq!(layout.position(&lines, 0, 0), (0, 5));
+        assert_eq!(layout.position(&lines, 0, 7), (1, 2));
+        assert_eq!(layout.position(&lines, 1, 1), (2, 1));
+        assert_eq!(layout.cursor(&buffer), (2, 2));
+        assert_eq!(layout.end(&buffer), (2, 2));
+    }
+
+    #[test]
+    fn test_screen_layout_of_full_rows() {
+        let layout = ScreenLayout::new(0, 4);
+        let buffer = buffer_from("abcd\nefgh");
+        let lines = buffer.lines();
+
+        // A full row is followed by an empty one
+        assert!(layout.fills_row(0, lines[0]));
+        assert!(!layout.fills_row(0, "abc"));
+        assert!(!layout.fills_row(1, ""));
+        assert_eq!(layout.position(&lines, 0, 4), (1, 0));
+        assert_eq!(layout.position(&lines, 1, 0), (2, 0));
+        assert_eq!(layout.end(&buffer), (3, 0));
+
+        // Wide characters fill rows twice as fast
+        let buffer = buffer_from("日本\n한");
+        assert!(layout.fills_row(0, buffer.lines()[0]));
+        assert_eq!(layout.end(&buffer), (2, 2));
+    }
+}
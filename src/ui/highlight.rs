@@ -0,0 +1,532 @@
+// Keyword-based syntax highlighting of the code blocks shown in the terminal
+
+use crate::ui::editor::display_width;
+use crate::ui::theme::{theme, Themed};
+use colored::*;
+use regex::Regex;
+use std::sync::LazyLock;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Matches the indentation, list dash and key of a YAML mapping line, such as `  - name: x`
+static YAML_KEY_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^(\s*(?:-\s+)?)([^\s#'"{\[\-][^#]*?):(?:\s|$)"#).unwrap());
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "function", "in", "select", "return", "local", "export", "readonly",
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+const PYTHON_STRING_PREFIXES: &[&str] = &["f", "r", "b", "u", "rb", "br", "fr", "rf"];
+
+const JSON_KEYWORDS: &[&str] = &["true", "false", "null"];
+
+const YAML_KEYWORDS: &[&str] = &["true", "false", "null", "yes", "no", "on", "off"];
+
+// Matched whatever their case, as SQL keywords are
+const SQL_KEYWORDS: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "AND",
+    "OR",
+    "NOT",
+    "IN",
+    "IS",
+    "NULL",
+    "LIKE",
+    "BETWEEN",
+    "AS",
+    "JOIN",
+    "LEFT",
+    "RIGHT",
+    "INNER",
+    "OUTER",
+    "FULL",
+    "CROSS",
+    "ON",
+    "USING",
+    "GROUP",
+    "BY",
+    "ORDER",
+    "HAVING",
+    "LIMIT",
+    "OFFSET",
+    "DISTINCT",
+    "UNION",
+    "ALL",
+    "INSERT",
+    "INTO",
+    "VALUES",
+    "UPDATE",
+    "SET",
+    "DELETE",
+    "CREATE",
+    "TABLE",
+    "INDEX",
+    "VIEW",
+    "DROP",
+    "ALTER",
+    "ADD",
+    "PRIMARY",
+    "KEY",
+    "FOREIGN",
+    "REFERENCES",
+    "DEFAULT",
+    "UNIQUE",
+    "CASE",
+    "WHEN",
+    "THEN",
+    "ELSE",
+    "END",
+    "EXISTS",
+    "WITH",
+    "RETURNING",
+    "BEGIN",
+    "COMMIT",
+    "ROLLBACK",
+    "ASC",
+    "DESC",
+    "TRUE",
+    "FALSE",
+    "IF",
+    "INTEGER",
+    "TEXT",
+    "VARCHAR",
+    "BOOLEAN",
+    "SERIAL",
+    "TIMESTAMP",
+];
+
+/// What a piece of a highlighted line is, deciding its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Anything without a color of its own
+    Plain,
+    /// A keyword of the language, or a literal such as `true`
+    Keyword,
+    /// A quoted string
+    Str,
+    /// A comment, up to the end of the line
+    Comment,
+    /// A number
+    Number,
+    /// A shell variable such as `$HOME` or `${1}`
+    Variable,
+    /// A JSON or YAML key
+    Key,
+}
+
+// The languages with highlighting, chosen from the info string of a code block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Shell,
+    Python,
+    Json,
+    Yaml,
+    Sql,
+}
+
+impl Language {
+    // Get the language of an info string such as `bash` or `yaml title=ci.yml`
+    fn from_info(info: &str) -> Option<Self> {
+        let name = info.split_whitespace().next()?.to_ascii_lowercase();
+        match name.as_str() {
+            "bash" | "sh" | "shell" | "zsh" | "console" => Some(Language::Shell),
+            "python" | "python3" | "py" => Some(Language::Python),
+            "json" | "jsonc" => Some(Language::Json),
+            "yaml" | "yml" => Some(Language::Yaml),
+            "sql" | "sqlite" | "sqlite3" | "postgresql" | "postgres" | "psql" | "pgsql"
+            | "plpgsql" => Some(Language::Sql),
+            _ => None,
+        }
+    }
+
+    fn is_keyword(self, word: &str) -> bool {
+        match self {
+            Language::Shell => SHELL_KEYWORDS.contains(&word),
+            Language::Python => PYTHON_KEYWORDS.contains(&word),
+            Language::Json => JSON_KEYWORDS.contains(&word),
+            Language::Yaml => YAML_KEYWORDS.contains(&word),
+            Language::Sql => SQL_KEYWORDS.contains(&word.to_ascii_uppercase().as_str()),
+        }
+    }
+
+    // Whether `#` starts a comment, when it starts a word
+    fn has_hash_comments(self) -> bool {
+        matches!(self, Language::Shell | Language::Python | Language::Yaml)
+    }
+}
+
+/// Splits a line of code into highlighted pieces
+///
+/// Lines are highlighted one at a time, so a string or comment spanning several lines is
+/// only recognized on its first line.
+///
+/// # Arguments
+///
+/// * `language` - The info string of the code block, such as `bash` or `python`
+/// * `line` - One line of the code, without its line break
+///
+/// # Returns
+///
+/// The pieces, which joined give back the line exactly. A language without highlighting
+/// gives the whole line as one plain piece.
+pub fn tokenize<'a>(language: &str, line: &'a str) -> Vec<(TokenKind, &'a str)> {
+    match Language::from_info(language) {
+        Some(language) => scan(language, line),
+        None if line.is_empty() => Vec::new(),
+        None => vec![(TokenKind::Plain, line)],
+    }
+}
+
+/// Highlights a line of code, wrapping it into rows no wider than the terminal
+///
+/// The text of the rows is the line's own, so the code shown is still the code that runs.
+/// Without colors, such as with `--no-color`, the rows are plain text.
+///
+/// # Arguments
+///
+/// * `language` - The info string of the code block, such as `bash` or `python`
+/// * `line` - One line of the code, without its line break
+/// * `width` - Most terminal columns a row may take
+///
+/// # Returns
+///
+/// The rows, at least one even for an empty line
+pub fn highlight_rows(language: &str, line: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut rows = vec![String::new()];
+    let mut row_width = 0;
+    for (kind, text) in tokenize(language, line) {
+        let mut piece = String::new();
+        for grapheme in text.graphemes(true) {
+            let grapheme_width = display_width(grapheme);
+            // A grapheme that doesn't fit starts the next row, unless the row is empty
+            if row_width + grapheme_width > width && row_width > 0 {
+                push_piece(&mut rows, kind, &piece);
+                piece.clear();
+                rows.push(String::new());
+                row_width = 0;
+            }
+            piece.push_str(grapheme);
+            row_width += grapheme_width;
+        }
+        push_piece(&mut rows, kind, &piece);
+    }
+    rows
+}
+
+// Add a colored piece of text to the last row
+fn push_piece(rows: &mut [String], kind: TokenKind, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    // Tokens borrow the colors of the theme's roles, so they follow the preset
+    let colored = match kind {
+        TokenKind::Plain => text.color(theme().code),
+        TokenKind::Keyword => text.info(),
+        TokenKind::Str => text.success(),
+        TokenKind::Comment => text.muted(),
+        TokenKind::Number => text.accent(),
+        TokenKind::Variable => text.warning(),
+        TokenKind::Key => text.ai_label(),
+    };
+    if let Some(row) = rows.last_mut() {
+        row.push_str(&colored.to_string());
+    }
+}
+
+// Split a line into pieces, keeping the text between recognized pieces as plain pieces
+fn scan(language: Language, line: &str) -> Vec<(TokenKind, &str)> {
+    let mut tokens = Vec::new();
+    let mut plain_start = 0;
+    let mut start = 0;
+
+    // A YAML key starts the line, after the indentation and any list dash
+    if language == Language::Yaml {
+        if let Some(captures) = YAML_KEY_REGEX.captures(line) {
+            let key = captures.get(2).map_or(0..0, |key| key.range());
+            if key.start > 0 {
+                tokens.push((TokenKind::Plain, &line[..key.start]));
+            }
+            tokens.push((TokenKind::Key, &line[key.clone()]));
+            plain_start = key.end;
+            start = key.end;
+        }
+    }
+
+    let mut i = start;
+    while let Some(c) = line[i..].chars().next() {
+        let after_word = line[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|previous| previous.is_alphanumeric() || previous == '_');
+        let found = match c {
+            '#' if language.has_hash_comments()
+                && line[..i]
+                    .chars()
+                    .next_back()
+                    .is_none_or(char::is_whitespace) =>
+            {
+                Some((TokenKind::Comment, line.len()))
+            }
+            '-' if language == Language::Sql && line[i..].starts_with("--") => {
+                Some((TokenKind::Comment, line.len()))
+            }
+            '"' | '\'' if !after_word && (c == '"' || language != Language::Json) => {
+                // Quotes inside SQL strings are doubled rather than escaped
+                let escapes = !(matches!(language, Language::Shell | Language::Sql) && c == '\'');
+                let end = string_end(line, i, c, escapes);
+                let kind =
+                    if language == Language::Json && line[end..].trim_start().starts_with(':') {
+                        TokenKind::Key
+                    } else {
+                        TokenKind::Str
+                    };
+                Some((kind, end))
+            }
+            '$' if language == Language::Shell => {
+                variable_end(line, i).map(|end| (TokenKind::Variable, end))
+            }
+            c if (c.is_alphanumeric() || c == '_') && !after_word => {
+                let end = line[i..]
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                    .map_or(line.len(), |offset| i + offset);
+                let word = &line[i..end];
+                let quote = line[end..]
+                    .chars()
+                    .next()
+                    .filter(|&c| c == '"' || c == '\'');
+                if let (Language::Python, Some(quote)) = (language, quote) {
+                    // Prefixed strings such as f"..." and rb'...'
+                    let prefixed =
+                        PYTHON_STRING_PREFIXES.contains(&word.to_ascii_lowercase().as_str());
+                    prefixed.then(|| (TokenKind::Str, string_end(line, end, quote, true)))
+                } else if language.is_keyword(word) {
+                    Some((TokenKind::Keyword, end))
+                } else if c.is_ascii_digit()
+                    && language != Language::Shell
+                    && word.parse::<f64>().is_ok()
+                {
+                    Some((TokenKind::Number, end))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        match found {
+            Some((kind, end)) => {
+                if plain_start < i {
+                    tokens.push((TokenKind::Plain, &line[plain_start..i]));
+                }
+                tokens.push((kind, &line[i..end]));
+                plain_start = end;
+                i = end;
+            }
+            None => i += c.len_utf8(),
+        }
+    }
+    if plain_start < line.len() {
+        tokens.push((TokenKind::Plain, &line[plain_start..]));
+    }
+    tokens
+}
+
+// Byte offset just after the closing quote of a string starting at `start`, or the end of
+// the line if the string isn't closed on it
+fn string_end(line: &str, start: usize, quote: char, escapes: bool) -> usize {
+    let mut escaped = false;
+    for (i, c) in line[start + 1..].char_indices() {
+        if escaped {
+            escaped = false;
+        } else if escapes && c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return start + 1 + i + c.len_utf8();
+        }
+    }
+    line.len()
+}
+
+// Byte offset just after a shell variable starting with the `$` at `start`, None if the
+// `$` doesn't start one
+fn variable_end(line: &str, start: usize) -> Option<usize> {
+    let rest = &line[start + 1..];
+    let first = rest.chars().next()?;
+    if first == '{' {
+        return Some(rest.find('}').map_or(line.len(), |end| start + 1 + end + 1));
+    }
+    if "?@#*!$-".contains(first) || first.is_ascii_digit() {
+        return Some(start + 2);
+    }
+    let length = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    (length > 0).then_some(start + 1 + length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    // Get the pieces of a line other than the plain ones
+    fn highlighted<'a>(language: &str, line: &'a str) -> Vec<(TokenKind, &'a str)> {
+        tokenize(language, line)
+            .into_iter()
+            .filter(|(kind, _)| *kind != Plain)
+            .collect()
+    }
+
+    // Remove the color codes, to compare the text alone
+    fn plain(text: &str) -> String {
+        Regex::new(r"\x1b\[[0-9;]*m")
+            .unwrap()
+            .replace_all(text, "")
+            .to_string()
+    }
+
+    #[test]
+    fn test_shell_highlighting() {
+        assert_eq!(
+            highlighted(
+                "bash",
+                r#"for f in *.log; do echo "$f" '$HOME' ${DIR}/$1; done # tidy"#
+            ),
+            [
+                (Keyword, "for"),
+                (Keyword, "in"),
+                (Keyword, "do"),
+                (Str, r#""$f""#),
+                (Str, "'$HOME'"),
+                (Variable, "${DIR}"),
+                (Variable, "$1"),
+                (Keyword, "done"),
+                (Comment, "# tidy"),
+            ]
+        );
+        // A # inside a word isn't a comment, nor is a keyword inside a name
+        assert_eq!(
+            highlighted("sh", "echo a#b fi_le 2>&1 $$"),
+            [(Variable, "$$")]
+        );
+    }
+
+    #[test]
+    fn test_python_highlighting() {
+        assert_eq!(
+            highlighted(
+                "python",
+                r#"def f(x=1.5): return "a\"b" if x else None  # no"#
+            ),
+            [
+                (Keyword, "def"),
+                (Number, "1.5"),
+                (Keyword, "return"),
+                (Str, r#""a\"b""#),
+                (Keyword, "if"),
+                (Keyword, "else"),
+                (Keyword, "None"),
+                (Comment, "# no"),
+            ]
+        );
+        assert_eq!(
+            highlighted("py", "print(f'{x!r}', rb\"\\x00\")"),
+            [(Str, "f'{x!r}'"), (Str, "rb\"\\x00\"")]
+        );
+    }
+
+    #[test]
+    fn test_sql_highlighting() {
+        assert_eq!(
+            highlighted(
+                "sql",
+                "select name, count(*) FROM users WHERE note = 'it''s' LIMIT 10; -- top"
+            ),
+            [
+                (Keyword, "select"),
+                (Keyword, "FROM"),
+                (Keyword, "WHERE"),
+                (Str, "'it'"),
+                (Str, "'s'"),
+                (Keyword, "LIMIT"),
+                (Number, "10"),
+                (Comment, "-- top"),
+            ]
+        );
+        // Neither # nor a single dash starts a comment
+        assert_eq!(
+            highlighted("postgresql", "SELECT 5 - 3 # x"),
+            [(Keyword, "SELECT"), (Number, "5"), (Number, "3"),]
+        );
+    }
+
+    #[test]
+    fn test_json_and_yaml_highlighting() {
+        assert_eq!(
+            highlighted("json", r#"{"name": "shellai", "stars": 42, "ok": true}"#),
+            [
+                (Key, r#""name""#),
+                (Str, r#""shellai""#),
+                (Key, r#""stars""#),
+                (Number, "42"),
+                (Key, r#""ok""#),
+                (Keyword, "true"),
+            ]
+        );
+        assert_eq!(
+            highlighted("yaml", "  - name: 'shellai' # quoted"),
+            [(Key, "name"), (Str, "'shellai'"), (Comment, "# quoted")]
+        );
+        assert_eq!(
+            highlighted("yml", "retries: 3"),
+            [(Key, "retries"), (Number, "3")]
+        );
+        assert_eq!(highlighted("yaml", "- plain item"), []);
+    }
+
+    #[test]
+    fn test_pieces_give_back_the_line() {
+        let lines = [
+            ("bash", r#"echo "unterminated $x"#),
+            ("bash", "grep -E 'a|b' <<< \"$(ls)\" 日本 👍🏽"),
+            ("python", "print(f'{x!r}')"),
+            ("json", r#"["a", 1e3, -2]"#),
+            ("yaml", "key: value # note: colon"),
+            ("rust", "fn main() {}"),
+        ];
+        for (language, line) in lines {
+            let joined: String = tokenize(language, line)
+                .into_iter()
+                .map(|(_, text)| text)
+                .collect();
+            assert_eq!(joined, line, "{}", language);
+        }
+        assert_eq!(tokenize("rust", "let x = 1;"), [(Plain, "let x = 1;")]);
+        assert_eq!(tokenize("Bash title=x", "fi"), [(Keyword, "fi")]);
+    }
+
+    #[test]
+    fn test_long_lines_are_wrapped() {
+        let rows = highlight_rows("bash", "echo 'a long string' && ls", 10);
+        let rows: Vec<String> = rows.iter().map(|row| plain(row)).collect();
+        assert_eq!(rows, ["echo 'a lo", "ng string'", " && ls"]);
+
+        // Wide characters aren't split between rows
+        let rows = highlight_rows("", "日本語", 5);
+        let rows: Vec<String> = rows.iter().map(|row| plain(row)).collect();
+        assert_eq!(rows, ["日本", "語"]);
+
+        assert_eq!(highlight_rows("bash", "", 10), [""]);
+        assert_eq!(highlight_rows("python", "x", 0).len(), 1);
+    }
+}
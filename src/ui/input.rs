@@ -0,0 +1,1119 @@
+// Multiline prompt input read from terminal key events
+
+use crate::history;
+use crate::ui::commands::{parse_command, Command};
+use crate::ui::editor::{pop_grapheme, InputBuffer, ScreenLayout};
+use crate::ui::keys::{KeyAction, KeyBindings};
+use crate::ui::terminal::RawModeGuard;
+use crossterm::{
+    cursor::{MoveDown, MoveToColumn, MoveUp},
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
+    execute, queue,
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{self, Write};
+
+/// What the user did at the prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// Submitted the text with the submit key, Ctrl+S by default
+    Submit(String),
+    /// Asked for the model menu, with Ctrl+A by default
+    OpenModelMenu,
+    /// Asked for the help screen, with Ctrl+H by default
+    OpenHelp,
+    /// Dropped the input with the cancel key, Esc by default, keeping the text so it can
+    /// be restored later
+    Cancel(String),
+    /// Asked to clear the screen, with Ctrl+L by default, keeping the text to be typed on
+    /// the redrawn prompt
+    ClearScreen(String),
+    /// Pressed Tab in a `/run` command, keeping the text for the model to complete
+    Complete(String),
+    /// Asked to leave ShellAI with Ctrl+C
+    Quit,
+}
+
+/// Most edits that can be undone with Ctrl+_
+pub const UNDO_LIMIT: usize = 50;
+
+/// Pastes of more lines than this are shown as "(pasted N lines)" rather than echoed
+pub const PASTE_ECHO_LIMIT: usize = 20;
+
+/// Most deleted texts kept for Ctrl+Y and Alt+Y
+pub const KILL_RING_LIMIT: usize = 10;
+
+/// Earlier states of the input buffer, most recent last
+#[derive(Debug, Clone, Default)]
+struct UndoStack(Vec<InputBuffer>);
+
+impl UndoStack {
+    /// Remembers the buffer as it was before an edit, forgetting the oldest beyond the limit
+    fn push(&mut self, buffer: InputBuffer) {
+        if self.0.len() == UNDO_LIMIT {
+            self.0.remove(0);
+        }
+        self.0.push(buffer);
+    }
+
+    /// Takes back the state before the last edit
+    fn pop(&mut self) -> Option<InputBuffer> {
+        self.0.pop()
+    }
+}
+
+/// Texts deleted with Ctrl+W, Ctrl+U and Ctrl+K, most recent last
+#[derive(Debug, Clone, Default)]
+struct KillRing(Vec<String>);
+
+impl KillRing {
+    /// Keeps a deleted text, forgetting the oldest beyond the limit
+    fn push(&mut self, text: String) {
+        if self.0.len() == KILL_RING_LIMIT {
+            self.0.remove(0);
+        }
+        self.0.push(text);
+    }
+
+    /// Gets the text deleted `back` deletions before the latest, going round the ring
+    fn get(&self, back: usize) -> Option<&str> {
+        let len = self.0.len();
+        (len > 0).then(|| self.0[len - 1 - back % len].as_str())
+    }
+}
+
+/// Where Up and Down are in the history of prompts
+#[derive(Debug)]
+struct HistoryBrowser<'a> {
+    entries: &'a [String],
+    /// Index of the prompt shown, None while editing a draft
+    index: Option<usize>,
+    /// The input as it was before browsing, restored past the latest prompt
+    draft: InputBuffer,
+}
+
+impl<'a> HistoryBrowser<'a> {
+    fn new(entries: &'a [String]) -> Self {
+        Self {
+            entries,
+            index: None,
+            draft: InputBuffer::new(),
+        }
+    }
+
+    /// Whether the buffer holds an unchanged prompt from the history
+    fn is_showing(&self, buffer: &InputBuffer) -> bool {
+        self.index
+            .is_some_and(|index| self.entries[index] == buffer.text())
+    }
+
+    /// Gets the prompt before the one shown, or the latest one, keeping an edited buffer
+    /// as the draft
+    fn previous(&mut self, buffer: &InputBuffer) -> Option<InputBuffer> {
+        let index = match self.index {
+            Some(index) if self.is_showing(buffer) => index.checked_sub(1)?,
+            _ => {
+                let latest = self.entries.len().checked_sub(1)?;
+                self.draft = buffer.clone();
+                latest
+            }
+        };
+        self.index = Some(index);
+        Some(InputBuffer::with_text(&self.entries[index]))
+    }
+
+    /// Gets the prompt after the one shown, or the draft after the latest one
+    fn next(&mut self) -> Option<InputBuffer> {
+        let index = self.index? + 1;
+        if index < self.entries.len() {
+            self.index = Some(index);
+            Some(InputBuffer::with_text(&self.entries[index]))
+        } else {
+            self.index = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+}
+
+/// A Ctrl+R search through the history of prompts
+#[derive(Debug)]
+struct HistorySearch {
+    query: String,
+    /// Index of the latest prompt matching the query
+    found: Option<usize>,
+    /// The input as it was before searching, restored when the search is cancelled
+    original: InputBuffer,
+}
+
+impl HistorySearch {
+    /// Looks for the query again, from the latest prompt or, with `older`, from the one
+    /// before the current match
+    fn find(&mut self, entries: &[String], older: bool) {
+        let before = match self.found {
+            Some(found) if older => found,
+            _ => entries.len(),
+        };
+        if let Some(found) = history::search(entries, &self.query, before) {
+            self.found = Some(found);
+        } else if !older {
+            self.found = None;
+        }
+    }
+
+    /// Gets the search prompt with the match, shown in place of the input
+    fn display(&self, entries: &[String]) -> InputBuffer {
+        let (status, found) = match self.found {
+            Some(found) => ("reverse-i-search", entries[found].as_str()),
+            None => ("failing reverse-i-search", ""),
+        };
+        InputBuffer::with_text(&format!("({}) `{}': {}", status, self.query, found))
+    }
+}
+
+// Whether a key is Ctrl+_, which terminals report as Ctrl+7 (the 0x1F control code), or as
+// Ctrl+_ or Ctrl+/ with enhanced keyboard reporting
+fn is_undo_key(code: KeyCode, control: bool) -> bool {
+    control && matches!(code, KeyCode::Char('_' | '/' | '7'))
+}
+
+// Ctrl+W, Ctrl+U, Ctrl+K and Alt+Backspace delete a word or part of a line
+fn is_kill_key(code: KeyCode, control: bool, alt: bool) -> bool {
+    (control && matches!(code, KeyCode::Char('w' | 'u' | 'k')))
+        || (alt && code == KeyCode::Backspace)
+}
+
+// Normalize pasted text for the buffer: Windows and old Mac line endings become "\n", and
+// control characters other than tabs are dropped so they can't act as keys
+fn normalize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect()
+}
+
+// Put the long pastes shown as placeholders back into the submitted text
+fn expand_pastes(mut text: String, pastes: &[(String, String)]) -> String {
+    for (placeholder, pasted) in pastes {
+        text = text.replacen(placeholder, pasted, 1);
+    }
+    text
+}
+
+/// A source of terminal events
+pub trait EventSource {
+    fn read_event(&mut self) -> io::Result<Event>;
+}
+
+/// The real terminal's events, read through crossterm
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrosstermEvents;
+
+impl EventSource for CrosstermEvents {
+    fn read_event(&mut self) -> io::Result<Event> {
+        event::read()
+    }
+}
+
+/// Events replayed in order, failing once they run out
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ScriptedEvents(pub std::collections::VecDeque<Event>);
+
+#[cfg(test)]
+impl EventSource for ScriptedEvents {
+    fn read_event(&mut self) -> io::Result<Event> {
+        self.0
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No more events"))
+    }
+}
+
+/// Read multiline input from the user, with Enter adding a new line and Ctrl+S submitting
+///
+/// The terminal is in raw mode while reading, with bracketed paste enabled so pasted text
+/// arrives in one piece rather than as key presses.
+///
+/// # Arguments
+///
+/// * `prompt_width` - Width of the prompt already printed on the first line
+/// * `initial` - Text to start editing from, empty for a new prompt
+/// * `history` - Earlier prompts, oldest first, recalled with Up/Down and Ctrl+R
+/// * `keys` - The keys of the submit, newline, cancel, model menu and help actions
+///
+/// # Returns
+///
+/// The submitted text, or the shortcut or cancellation that ended the input
+pub fn read_multiline_input(
+    prompt_width: u16,
+    initial: &str,
+    history: &[String],
+    keys: &KeyBindings,
+) -> io::Result<InputEvent> {
+    let raw_mode = RawModeGuard::enable()?;
+    // Lines wrap at the terminal width, assume the usual 80 columns if it is unknown
+    let (width, _) = terminal::size().unwrap_or((80, 24));
+    let layout = ScreenLayout::new(prompt_width, width);
+    let mut stdout = io::stdout();
+    execute!(stdout, EnableBracketedPaste)?;
+    let input = read_input(
+        &mut CrosstermEvents,
+        &mut stdout,
+        layout,
+        initial,
+        history,
+        keys,
+    );
+    execute!(stdout, DisableBracketedPaste)?;
+    drop(raw_mode);
+    let input = input?;
+
+    // Move to the next line after submission
+    if matches!(input, InputEvent::Submit(_) | InputEvent::Complete(_)) {
+        println!();
+    }
+    Ok(input)
+}
+
+/// Edits a prompt from key events, drawing it to `out`
+///
+/// # Arguments
+///
+/// * `events` - Where key events are read from
+/// * `out` - Where the prompt is drawn, normally stdout in raw mode
+/// * `layout` - Width of the prompt already printed on the first line and of the terminal
+/// * `initial` - Text to start editing from, empty for a new prompt
+/// * `history` - Earlier prompts, oldest first
+/// * `keys` - The keys of the configurable actions
+///
+/// # Returns
+///
+/// The submitted text, or the shortcut or cancellation that ended the input
+pub fn read_input(
+    events: &mut impl EventSource,
+    out: &mut impl Write,
+    mut layout: ScreenLayout,
+    initial: &str,
+    history: &[String],
+    keys: &KeyBindings,
+) -> io::Result<InputEvent> {
+    let mut buffer = InputBuffer::with_text(initial);
+    let mut undo = UndoStack::default();
+    // Long pastes, with the placeholder standing for each in the buffer
+    let mut pastes: Vec<(String, String)> = Vec::new();
+    let mut browser = HistoryBrowser::new(history);
+    let mut search: Option<HistorySearch> = None;
+    let mut kill_ring = KillRing::default();
+    // The buffer before the last Ctrl+Y and how far back in the ring the yanked text is,
+    // while Alt+Y can still swap it for an older one
+    let mut yank: Option<(InputBuffer, usize)> = None;
+    // Screen row of the input the terminal cursor is on
+    let mut cursor_row = 0;
+
+    if !buffer.is_empty() {
+        redraw_input(out, &buffer, layout, &mut cursor_row)?;
+    }
+    out.flush()?;
+
+    loop {
+        // Wait for a key event
+        let (code, modifiers) = match events.read_event()? {
+            Event::Key(KeyEvent {
+                code, modifiers, ..
+            }) => (code, modifiers),
+            // Terminals rewrap the lines to the new width, which leaves the cursor on the
+            // row the new layout gives it, so the input is redrawn from there
+            Event::Resize(width, _) => {
+                layout = ScreenLayout::new(layout.prompt_width as u16, width);
+                let shown = match &search {
+                    Some(current) => current.display(history),
+                    None => buffer.clone(),
+                };
+                cursor_row = layout.cursor(&shown).0;
+                redraw_input(out, &shown, layout, &mut cursor_row)?;
+                continue;
+            }
+            // Pasted text is inserted at once, as a single edit
+            Event::Paste(text) => {
+                let text = normalize_paste(&text);
+                if !text.is_empty() {
+                    undo.push(buffer.clone());
+                    let lines = text.lines().count();
+                    if lines > PASTE_ECHO_LIMIT {
+                        let placeholder = format!("(pasted {} lines)", lines);
+                        buffer.insert_str(&placeholder);
+                        pastes.push((placeholder, text));
+                    } else {
+                        buffer.insert_str(&text);
+                    }
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+        let alt = modifiers.contains(KeyModifiers::ALT);
+        let last_yank = yank.take();
+        let action = keys.action(code, modifiers);
+
+        // While searching, keys edit the query until the match is taken or the search dropped
+        if let Some(current) = &mut search {
+            match code {
+                KeyCode::Char('r') if control => current.find(history, true),
+                _ if action == Some(KeyAction::Submit) => {
+                    if let Some(found) = current.found {
+                        buffer = InputBuffer::with_text(&history[found]);
+                    }
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                    buffer.move_to_end();
+                    move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+                    return Ok(InputEvent::Submit(buffer.into_text()));
+                }
+                // The cancel key and Ctrl+G give up the search, as in readline
+                _ if action == Some(KeyAction::Cancel)
+                    || (control && code == KeyCode::Char('g')) =>
+                {
+                    buffer = current.original.clone();
+                    search = None;
+                }
+                KeyCode::Backspace => {
+                    pop_grapheme(&mut current.query);
+                    current.find(history, false);
+                }
+                KeyCode::Char(c) if !control => {
+                    current.query.push(c);
+                    current.find(history, false);
+                }
+                // Any other key takes the match for editing
+                _ => {
+                    if let Some(found) = current.found {
+                        undo.push(buffer.clone());
+                        buffer = InputBuffer::with_text(&history[found]);
+                    }
+                    search = None;
+                }
+            }
+            let shown = match &search {
+                Some(current) => current.display(history),
+                None => buffer.clone(),
+            };
+            redraw_input(out, &shown, layout, &mut cursor_row)?;
+            continue;
+        }
+
+        // The configured keys, which can't be any of the fixed keys below: by default Ctrl+S
+        // or Alt+Enter to submit, Enter for a line break, Esc to cancel, Ctrl+A to show
+        // available models (A for Agents), Ctrl+H for help and Ctrl+L to clear the screen
+        match action {
+            Some(KeyAction::Submit) => {
+                // Leave the cursor below the last line of input
+                buffer.move_to_end();
+                move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+                return Ok(InputEvent::Submit(expand_pastes(
+                    buffer.into_text(),
+                    &pastes,
+                )));
+            }
+            Some(KeyAction::Newline) => {
+                undo.push(buffer.clone());
+                buffer.insert('\n');
+                redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                continue;
+            }
+            Some(KeyAction::Cancel) => {
+                return Ok(InputEvent::Cancel(expand_pastes(
+                    buffer.into_text(),
+                    &pastes,
+                )))
+            }
+            Some(KeyAction::ModelMenu) => return Ok(InputEvent::OpenModelMenu),
+            Some(KeyAction::Help) => return Ok(InputEvent::OpenHelp),
+            Some(KeyAction::ClearScreen) => {
+                return Ok(InputEvent::ClearScreen(expand_pastes(
+                    buffer.into_text(),
+                    &pastes,
+                )))
+            }
+            None => {}
+        }
+
+        match code {
+            // Tab in a /run command asks the model to complete it
+            KeyCode::Tab
+                if matches!(
+                    parse_command(buffer.text()),
+                    Some(Command::Other { name: "run", .. })
+                ) =>
+            {
+                buffer.move_to_end();
+                move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+                return Ok(InputEvent::Complete(expand_pastes(
+                    buffer.into_text(),
+                    &pastes,
+                )));
+            }
+
+            // Ctrl+R searches the history, latest prompts first
+            KeyCode::Char('r') if control => {
+                let current = HistorySearch {
+                    query: String::new(),
+                    found: None,
+                    original: buffer.clone(),
+                };
+                redraw_input(out, &current.display(history), layout, &mut cursor_row)?;
+                search = Some(current);
+            }
+
+            // Ctrl+_ restores the buffer as it was before the last edit
+            _ if is_undo_key(code, control) => {
+                if let Some(previous) = undo.pop() {
+                    buffer = previous;
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+
+            // Ctrl+C to exit
+            KeyCode::Char('c') if control => return Ok(InputEvent::Quit),
+
+            // Deleted words and lines are kept in the kill ring
+            _ if is_kill_key(code, control, alt) => {
+                let before = buffer.clone();
+                let killed = match code {
+                    KeyCode::Char('u') => buffer.kill_to_line_start(),
+                    KeyCode::Char('k') => buffer.kill_to_line_end(),
+                    _ => buffer.delete_word_before(),
+                };
+                if let Some(killed) = killed {
+                    undo.push(before);
+                    kill_ring.push(killed);
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+
+            // Ctrl+Y inserts the latest deleted text, and Alt+Y right after swaps it for the
+            // one deleted before
+            KeyCode::Char('y') if control => {
+                if let Some(text) = kill_ring.get(0) {
+                    undo.push(buffer.clone());
+                    yank = Some((buffer.clone(), 0));
+                    buffer.insert_str(text);
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+            KeyCode::Char('y') if alt => {
+                if let Some((before, back)) = last_yank {
+                    buffer = before.clone();
+                    buffer.insert_str(kill_ring.get(back + 1).unwrap_or_default());
+                    yank = Some((before, back + 1));
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+
+            // Backspace deletes the character before the cursor, Delete the one after it
+            KeyCode::Backspace | KeyCode::Delete => {
+                let before = buffer.clone();
+                let deleted = if code == KeyCode::Backspace {
+                    buffer.backspace()
+                } else {
+                    buffer.delete()
+                };
+                if deleted.is_some() {
+                    undo.push(before);
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+
+            // Up in an empty buffer, at the very start of a draft or on an unchanged recalled
+            // prompt recalls the previous prompt, and Down goes back toward the draft
+            KeyCode::Up
+                if browser.is_showing(&buffer)
+                    || (buffer.cursor_row() == 0 && buffer.cursor_column() == 0) =>
+            {
+                if let Some(previous) = browser.previous(&buffer) {
+                    buffer = previous;
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+            KeyCode::Down if browser.is_showing(&buffer) => {
+                if let Some(next) = browser.next() {
+                    buffer = next;
+                    redraw_input(out, &buffer, layout, &mut cursor_row)?;
+                }
+            }
+
+            // Cursor movement within the buffer
+            KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down => {
+                match code {
+                    KeyCode::Left => buffer.move_left(),
+                    KeyCode::Right => buffer.move_right(),
+                    KeyCode::Up => buffer.move_up(),
+                    _ => buffer.move_down(),
+                };
+                move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+            }
+            KeyCode::Home => {
+                buffer.move_home();
+                move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+            }
+            KeyCode::End => {
+                buffer.move_end();
+                move_input_cursor(out, &buffer, layout, &mut cursor_row)?;
+            }
+
+            // Regular character input, Ctrl with an unbound letter typing nothing
+            KeyCode::Char(c) if !control => {
+                undo.push(buffer.clone());
+                buffer.insert(c);
+                redraw_input(out, &buffer, layout, &mut cursor_row)?;
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Redraw the whole input after the prompt and place the terminal cursor
+///
+/// `cursor_row` is the screen row of the input the terminal cursor is currently on and is
+/// updated to the new cursor row.
+fn redraw_input(
+    out: &mut impl Write,
+    buffer: &InputBuffer,
+    layout: ScreenLayout,
+    cursor_row: &mut usize,
+) -> io::Result<()> {
+    // Go back to the end of the prompt and clear everything after it
+    if *cursor_row > 0 {
+        queue!(out, MoveUp(*cursor_row as u16))?;
+    }
+    queue!(
+        out,
+        MoveToColumn(layout.prompt_width as u16),
+        Clear(ClearType::FromCursorDown)
+    )?;
+
+    for (row, line) in buffer.lines().iter().enumerate() {
+        if row > 0 {
+            write!(out, "\r\n")?;
+        }
+        write!(out, "{}", line)?;
+        // The terminal only wraps when the next character comes, so wrap now to have a
+        // row for the cursor
+        if layout.fills_row(row, line) {
+            write!(out, "\r\n")?;
+        }
+    }
+
+    *cursor_row = layout.end(buffer).0;
+    move_input_cursor(out, buffer, layout, cursor_row)
+}
+
+/// Move the terminal cursor to the buffer cursor without redrawing any text
+fn move_input_cursor(
+    out: &mut impl Write,
+    buffer: &InputBuffer,
+    layout: ScreenLayout,
+    cursor_row: &mut usize,
+) -> io::Result<()> {
+    let (row, column) = layout.cursor(buffer);
+
+    if row < *cursor_row {
+        queue!(out, MoveUp((*cursor_row - row) as u16))?;
+    } else if row > *cursor_row {
+        queue!(out, MoveDown((row - *cursor_row) as u16))?;
+    }
+    queue!(out, MoveToColumn(column as u16))?;
+    out.flush()?;
+
+    *cursor_row = row;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn ctrl(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    fn typed(text: &str) -> impl Iterator<Item = Event> + '_ {
+        text.chars().map(|c| key(KeyCode::Char(c)))
+    }
+
+    // Feed the events to the editor, starting from `initial`
+    fn run(initial: &str, events: impl IntoIterator<Item = Event>) -> io::Result<InputEvent> {
+        run_with_history(initial, events, &[])
+    }
+
+    fn run_with_history(
+        initial: &str,
+        events: impl IntoIterator<Item = Event>,
+        history: &[&str],
+    ) -> io::Result<InputEvent> {
+        let history: Vec<String> = history.iter().map(|entry| entry.to_string()).collect();
+        let mut events = ScriptedEvents(events.into_iter().collect());
+        let mut out = Vec::new();
+        read_input(
+            &mut events,
+            &mut out,
+            ScreenLayout::new(5, 80),
+            initial,
+            &history,
+            &KeyBindings::default(),
+        )
+    }
+
+    #[test]
+    fn test_submit_multiline_text() {
+        let events = typed("ls")
+            .chain([key(KeyCode::Enter)])
+            .chain(typed("pwd"))
+            .chain([ctrl('s')]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Submit("ls\npwd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_editing_at_the_cursor() {
+        let events = [key(KeyCode::Home)].into_iter().chain(typed("un")).chain([
+            key(KeyCode::End),
+            key(KeyCode::Backspace),
+            ctrl('s'),
+        ]);
+        assert_eq!(
+            run("tars", events).unwrap(),
+            InputEvent::Submit("untar".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shortcuts() {
+        assert_eq!(run("", [ctrl('a')]).unwrap(), InputEvent::OpenModelMenu);
+        assert_eq!(run("", [ctrl('h')]).unwrap(), InputEvent::OpenHelp);
+        assert_eq!(run("draft", [ctrl('c')]).unwrap(), InputEvent::Quit);
+        assert_eq!(
+            run("draft", [key(KeyCode::Esc)]).unwrap(),
+            InputEvent::Cancel("draft".to_string())
+        );
+    }
+
+    #[test]
+    fn test_alt_enter_submits() {
+        let events =
+            typed("ls").chain([Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Submit("ls".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clear_screen_keeps_the_input() {
+        let events = typed("git st").chain([ctrl('l')]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::ClearScreen("git st".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tab_completes_run_commands_only() {
+        let events = typed("/run git ").chain([key(KeyCode::Tab)]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Complete("/run git ".to_string())
+        );
+
+        // Elsewhere Tab types nothing
+        let events = typed("git ").chain([key(KeyCode::Tab), ctrl('s')]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Submit("git ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_configured_keys() {
+        let keys = KeyBindings {
+            submit: vec!["ctrl+d".parse().unwrap()],
+            newline: vec!["shift+enter".parse().unwrap()],
+            cancel: vec!["ctrl+q".parse().unwrap()],
+            ..KeyBindings::default()
+        };
+        let read = |events: Vec<Event>| {
+            let mut events = ScriptedEvents(events.into());
+            read_input(
+                &mut events,
+                &mut Vec::new(),
+                ScreenLayout::new(5, 80),
+                "",
+                &[],
+                &keys,
+            )
+        };
+
+        // The default keys no longer do anything
+        let events = typed("ls")
+            .chain([
+                ctrl('s'),
+                key(KeyCode::Enter),
+                key(KeyCode::Esc),
+                Event::Key(KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT)),
+            ])
+            .chain(typed("pwd"))
+            .chain([ctrl('d')])
+            .collect();
+        assert_eq!(
+            read(events).unwrap(),
+            InputEvent::Submit("ls\npwd".to_string())
+        );
+        assert_eq!(
+            read(typed("x").chain([ctrl('q')]).collect()).unwrap(),
+            InputEvent::Cancel("x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typed_shortcut_names_are_plain_text() {
+        let events = typed("ctrl+a").chain([ctrl('s')]);
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Submit("ctrl+a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_other_events_are_ignored() {
+        let events = [Event::FocusGained, Event::Resize(80, 24), ctrl('s')];
+        assert_eq!(
+            run("ok", events).unwrap(),
+            InputEvent::Submit("ok".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resize_redraws_from_the_rewrapped_cursor() {
+        // 30 characters after a 5 column prompt take one row at 80 columns, four at 10
+        let mut events = ScriptedEvents([Event::Resize(10, 24), ctrl('s')].into());
+        let mut out = Vec::new();
+        let input = "x".repeat(30);
+        let event = read_input(
+            &mut events,
+            &mut out,
+            ScreenLayout::new(5, 80),
+            &input,
+            &[],
+            &KeyBindings::default(),
+        )
+        .unwrap();
+        assert_eq!(event, InputEvent::Submit(input));
+
+        // The redraw after the resize goes up from the cursor's rewrapped row
+        let out = String::from_utf8(out).unwrap();
+        let (_, after_resize) = out.split_once("\x1b[3A").expect("No move to the first row");
+        assert_eq!(after_resize.matches('x').count(), 30);
+    }
+
+    #[test]
+    fn test_undo_restores_previous_edits() {
+        let events = typed("ab")
+            .chain([key(KeyCode::Enter), key(KeyCode::Backspace)])
+            .chain([ctrl('7'), ctrl('_'), ctrl('/'), ctrl('s')]);
+        // Undoing the backspace, the new line and the "b"
+        assert_eq!(
+            run("", events).unwrap(),
+            InputEvent::Submit("a".to_string())
+        );
+
+        // Nothing to undo in the initial text
+        let events = [ctrl('_'), ctrl('_'), ctrl('s')];
+        assert_eq!(
+            run("draft", events).unwrap(),
+            InputEvent::Submit("draft".to_string())
+        );
+    }
+
+    #[test]
+    fn test_undo_is_limited() {
+        let text = "x".repeat(UNDO_LIMIT + 10);
+        let events = typed(&text)
+            .chain(std::iter::repeat_n(ctrl('_'), UNDO_LIMIT + 10))
+            .chain([ctrl('s')]);
+        assert_eq!(run("", events).unwrap(), InputEvent::Submit("x".repeat(10)));
+    }
+
+    fn alt(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::ALT))
+    }
+
+    #[test]
+    fn test_kill_and_yank() {
+        // Move the last word to the front
+        let events = [ctrl('w'), key(KeyCode::Home), ctrl('y')]
+            .into_iter()
+            .chain(typed(" "))
+            .chain([key(KeyCode::End), key(KeyCode::Backspace), ctrl('s')]);
+        assert_eq!(
+            run("status git", events).unwrap(),
+            InputEvent::Submit("git status".to_string())
+        );
+
+        // Alt+Y goes back to earlier deletions, and Ctrl+_ undoes the whole yank
+        let events = [
+            alt(KeyCode::Backspace),
+            ctrl('u'),
+            ctrl('y'),
+            alt(KeyCode::Char('y')),
+            ctrl('s'),
+        ];
+        assert_eq!(
+            run("one two", events).unwrap(),
+            InputEvent::Submit("two".to_string())
+        );
+        let events = [ctrl('w'), ctrl('y'), ctrl('y'), ctrl('_'), ctrl('s')];
+        assert_eq!(
+            run("echo hi", events).unwrap(),
+            InputEvent::Submit("echo hi".to_string())
+        );
+    }
+
+    #[test]
+    fn test_kills_at_the_line_edges_do_nothing() {
+        let events = [
+            key(KeyCode::Home),
+            ctrl('u'),
+            ctrl('y'),
+            ctrl('_'),
+            ctrl('s'),
+        ];
+        assert_eq!(
+            run("ls\npwd", events).unwrap(),
+            InputEvent::Submit("ls\npwd".to_string())
+        );
+        // Alt+Y without a yank before it isn't typed
+        let events = [ctrl('k'), alt(KeyCode::Char('y')), ctrl('s')];
+        assert_eq!(
+            run("ls", events).unwrap(),
+            InputEvent::Submit("ls".to_string())
+        );
+    }
+
+    #[test]
+    fn test_editing_other_lines() {
+        // Fix a typo on the first line after typing the second
+        let events = [
+            key(KeyCode::Up),
+            key(KeyCode::Home),
+            key(KeyCode::Delete),
+            key(KeyCode::Char('g')),
+            key(KeyCode::Down),
+            key(KeyCode::End),
+        ]
+        .into_iter()
+        .chain(typed(" -a"))
+        .chain([ctrl('s')]);
+        assert_eq!(
+            run("frep TODO\nls", events).unwrap(),
+            InputEvent::Submit("grep TODO\nls -a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_full_rows_are_wrapped_before_moving_the_cursor() {
+        let mut events = ScriptedEvents(typed("abcde").chain([ctrl('s')]).collect());
+        let mut out = Vec::new();
+        // The prompt and the text fill the 10 columns exactly
+        read_input(
+            &mut events,
+            &mut out,
+            ScreenLayout::new(5, 10),
+            "",
+            &[],
+            &KeyBindings::default(),
+        )
+        .unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("abcde\r\n\x1b[1G"));
+    }
+
+    #[test]
+    fn test_paste_is_inserted_at_the_cursor() {
+        let events = [
+            key(KeyCode::Home),
+            Event::Paste("cd /tmp\r\nls\x03\r\n".to_string()),
+            ctrl('s'),
+        ];
+        // Line endings are normalized and the Ctrl+C byte doesn't quit
+        assert_eq!(
+            run("pwd", events).unwrap(),
+            InputEvent::Submit("cd /tmp\nls\npwd".to_string())
+        );
+
+        // The whole paste is undone at once
+        let events = [Event::Paste("a\nb".to_string()), ctrl('_'), ctrl('s')];
+        assert_eq!(
+            run("x", events).unwrap(),
+            InputEvent::Submit("x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_long_paste_is_shown_as_placeholder() {
+        let script: String = (1..=412).map(|i| format!("echo {}\n", i)).collect();
+        let mut events = ScriptedEvents(
+            [Event::Paste(script.clone())]
+                .into_iter()
+                .chain(typed("explain"))
+                .chain([ctrl('s')])
+                .collect(),
+        );
+        let mut out = Vec::new();
+        let input = read_input(
+            &mut events,
+            &mut out,
+            ScreenLayout::new(5, 80),
+            "",
+            &[],
+            &KeyBindings::default(),
+        )
+        .unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("(pasted 412 lines)"));
+        assert!(!output.contains("echo 200"));
+        assert_eq!(input, InputEvent::Submit(format!("{}explain", script)));
+    }
+
+    const HISTORY: &[&str] = &["git status", "ls -la\n| less", "git log"];
+
+    #[test]
+    fn test_up_and_down_browse_the_history() {
+        let events = [key(KeyCode::Up), key(KeyCode::Up), ctrl('s')];
+        assert_eq!(
+            run_with_history("", events, HISTORY).unwrap(),
+            InputEvent::Submit("ls -la\n| less".to_string())
+        );
+
+        // Coming back down past the latest prompt restores the draft
+        let events = [
+            key(KeyCode::Home),
+            key(KeyCode::Up),
+            key(KeyCode::Up),
+            key(KeyCode::Down),
+            key(KeyCode::Down),
+            ctrl('s'),
+        ];
+        assert_eq!(
+            run_with_history("draft", events, HISTORY).unwrap(),
+            InputEvent::Submit("draft".to_string())
+        );
+
+        // Up past the oldest prompt stays on it, and a recalled prompt can be edited
+        let events = std::iter::repeat_n(key(KeyCode::Up), 5)
+            .chain(typed(" -s"))
+            .chain([ctrl('s')]);
+        assert_eq!(
+            run_with_history("", events, HISTORY).unwrap(),
+            InputEvent::Submit("git status -s".to_string())
+        );
+    }
+
+    #[test]
+    fn test_up_in_a_draft_moves_the_cursor() {
+        // Not at the start of the buffer, Up and Down keep editing the draft
+        let events = [key(KeyCode::Up), key(KeyCode::Char('!')), ctrl('s')];
+        assert_eq!(
+            run_with_history("one\ntwo", events, HISTORY).unwrap(),
+            InputEvent::Submit("one!\ntwo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ctrl_r_searches_the_history() {
+        // "git" finds "git log", Ctrl+R again the older "git status"
+        let events = [ctrl('r')]
+            .into_iter()
+            .chain(typed("git"))
+            .chain([ctrl('r'), key(KeyCode::End)])
+            .chain(typed(" -s"))
+            .chain([ctrl('s')]);
+        assert_eq!(
+            run_with_history("", events, HISTORY).unwrap(),
+            InputEvent::Submit("git status -s".to_string())
+        );
+
+        // Ctrl+S submits the match, Esc gives the search up
+        let events = [ctrl('r')]
+            .into_iter()
+            .chain(typed("less"))
+            .chain([ctrl('s')]);
+        assert_eq!(
+            run_with_history("", events, HISTORY).unwrap(),
+            InputEvent::Submit("ls -la\n| less".to_string())
+        );
+        let events = [ctrl('r')]
+            .into_iter()
+            .chain(typed("git"))
+            .chain([key(KeyCode::Esc), ctrl('s')]);
+        assert_eq!(
+            run_with_history("draft", events, HISTORY).unwrap(),
+            InputEvent::Submit("draft".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ctrl_r_shows_the_search() {
+        let history = vec!["cargo test".to_string()];
+        let mut events = ScriptedEvents(
+            [ctrl('r')]
+                .into_iter()
+                .chain(typed("test"))
+                .chain(typed("x"))
+                .chain([key(KeyCode::Esc), ctrl('s')])
+                .collect(),
+        );
+        let mut out = Vec::new();
+        read_input(
+            &mut events,
+            &mut out,
+            ScreenLayout::new(5, 80),
+            "",
+            &history,
+            &KeyBindings::default(),
+        )
+        .unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("(reverse-i-search) `test': cargo test"));
+        assert!(output.contains("(failing reverse-i-search) `testx': "));
+    }
+
+    #[test]
+    fn test_ctrl_r_backspace_removes_a_grapheme() {
+        let events = [ctrl('r')]
+            .into_iter()
+            .chain(typed("café"))
+            .chain(typed("👍🏽"))
+            .chain([key(KeyCode::Backspace), ctrl('s')]);
+        assert_eq!(
+            run_with_history("", events, &["echo café", "echo 👍🏽"]).unwrap(),
+            InputEvent::Submit("echo café".to_string())
+        );
+    }
+
+    #[test]
+    fn test_event_source_errors_are_returned() {
+        assert!(run("", typed("abc")).is_err());
+    }
+}
@@ -0,0 +1,487 @@
+// Key bindings of the prompt editor, configurable in `[keys]` of `config.toml`
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// A key pressed together with modifiers, such as Ctrl+S or Alt+Enter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Creates a binding, which `matches` only compares Ctrl, Alt and Shift of
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+
+    /// Whether a key event is this binding
+    pub fn matches(&self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        normalize(code, modifiers) == normalize(self.code, self.modifiers)
+    }
+
+    /// Formats the binding in the short form of the status line, such as `<c-s>`
+    pub fn short_name(&self) -> String {
+        let mut name = String::from("<");
+        for (modifier, prefix) in [
+            (KeyModifiers::CONTROL, "c-"),
+            (KeyModifiers::ALT, "m-"),
+            (KeyModifiers::SHIFT, "s-"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                name.push_str(prefix);
+            }
+        }
+        match self.code {
+            KeyCode::Char(c) => name.push(c.to_ascii_lowercase()),
+            KeyCode::Enter => name.push_str("cr"),
+            _ => name.push_str(&key_name(self.code).to_lowercase()),
+        }
+        name.push('>');
+        name
+    }
+}
+
+// Only Ctrl, Alt and Shift tell bindings apart. Shift is left out for characters, whose
+// case already shows it, and Ctrl combinations ignore the case.
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> (KeyCode, KeyModifiers) {
+    let mut modifiers =
+        modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+    let code = match code {
+        KeyCode::Char(c) => {
+            modifiers.remove(KeyModifiers::SHIFT);
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                KeyCode::Char(c.to_ascii_lowercase())
+            } else {
+                KeyCode::Char(c)
+            }
+        }
+        _ => code,
+    };
+    (code, modifiers)
+}
+
+// Name of a key as written in the configuration and the help screen
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_uppercase().to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    /// Parses a binding such as `ctrl+s`, `Alt+Enter` or `f5`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let (key, modifier_names) = parts.split_last().unwrap_or((&"", &[]));
+
+        let mut modifiers = KeyModifiers::NONE;
+        for name in modifier_names {
+            modifiers |= match name.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "option" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier '{}' in '{}'", name, text)),
+            };
+        }
+
+        let lower = key.to_lowercase();
+        let code = match lower.as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            _ => match (
+                lower.strip_prefix('f').map(str::parse::<u8>),
+                key.chars().count(),
+            ) {
+                (Some(Ok(n)), _) if (1..=12).contains(&n) => KeyCode::F(n),
+                (_, 1) => KeyCode::Char(key.chars().next().unwrap_or_default()),
+                _ => return Err(format!("Unknown key '{}' in '{}'", key, text)),
+            },
+        };
+        let (code, modifiers) = normalize(code, modifiers);
+        Ok(Self { code, modifiers })
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    /// Formats the binding as in the help screen, such as `Ctrl+S`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "Ctrl+"),
+            (KeyModifiers::ALT, "Alt+"),
+            (KeyModifiers::SHIFT, "Shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}", name)?;
+            }
+        }
+        write!(f, "{}", key_name(self.code))
+    }
+}
+
+/// What a configurable key does at the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Submit,
+    Newline,
+    Cancel,
+    ModelMenu,
+    Help,
+    ClearScreen,
+}
+
+impl KeyAction {
+    /// Gets the name of the action in `[keys]`
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyAction::Submit => "submit",
+            KeyAction::Newline => "newline",
+            KeyAction::Cancel => "cancel",
+            KeyAction::ModelMenu => "model_menu",
+            KeyAction::Help => "help",
+            KeyAction::ClearScreen => "clear_screen",
+        }
+    }
+}
+
+// Keys of the editor that can't be bound, with what they do
+const RESERVED_KEYS: &[(KeyBinding, &str)] = &[
+    (ctrl('c'), "exits ShellAI"),
+    (ctrl('r'), "searches earlier prompts"),
+    (ctrl('w'), "deletes a word"),
+    (ctrl('u'), "deletes to the start of the line"),
+    (ctrl('k'), "deletes to the end of the line"),
+    (ctrl('y'), "inserts deleted text"),
+    (ctrl('_'), "undoes the last edit"),
+    (ctrl('/'), "undoes the last edit"),
+    (ctrl('7'), "undoes the last edit"),
+    (
+        KeyBinding::new(KeyCode::Char('y'), KeyModifiers::ALT),
+        "inserts older deleted text",
+    ),
+    (
+        KeyBinding::new(KeyCode::Backspace, KeyModifiers::ALT),
+        "deletes a word",
+    ),
+    (key(KeyCode::Backspace), "deletes a character"),
+    (key(KeyCode::Delete), "deletes a character"),
+    (key(KeyCode::Left), "moves the cursor"),
+    (key(KeyCode::Right), "moves the cursor"),
+    (key(KeyCode::Up), "moves the cursor"),
+    (key(KeyCode::Down), "moves the cursor"),
+    (key(KeyCode::Home), "moves the cursor"),
+    (key(KeyCode::End), "moves the cursor"),
+];
+
+const fn ctrl(c: char) -> KeyBinding {
+    KeyBinding::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+}
+
+const fn key(code: KeyCode) -> KeyBinding {
+    KeyBinding::new(code, KeyModifiers::NONE)
+}
+
+/// Keys of the configurable prompt actions, each with one or more bindings
+///
+/// Ctrl+C, history search and the editing keys keep fixed bindings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "KeyBindingNames")]
+pub struct KeyBindings {
+    /// Send the prompt, Ctrl+S or Alt+Enter by default, since Ctrl+S is taken by flow
+    /// control in some terminals
+    pub submit: Vec<KeyBinding>,
+    /// Add a line break, Enter by default
+    pub newline: Vec<KeyBinding>,
+    /// Drop the input, Esc by default
+    pub cancel: Vec<KeyBinding>,
+    /// Open the model menu, Ctrl+A by default
+    pub model_menu: Vec<KeyBinding>,
+    /// Show the help screen, Ctrl+H by default
+    pub help: Vec<KeyBinding>,
+    /// Clear the screen and redraw the prompt, Ctrl+L by default
+    pub clear_screen: Vec<KeyBinding>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            submit: vec![
+                ctrl('s'),
+                KeyBinding::new(KeyCode::Enter, KeyModifiers::ALT),
+            ],
+            newline: vec![key(KeyCode::Enter)],
+            cancel: vec![key(KeyCode::Esc)],
+            model_menu: vec![ctrl('a')],
+            help: vec![ctrl('h')],
+            clear_screen: vec![ctrl('l')],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Gets the bindings of an action
+    pub fn get(&self, action: KeyAction) -> &[KeyBinding] {
+        match action {
+            KeyAction::Submit => &self.submit,
+            KeyAction::Newline => &self.newline,
+            KeyAction::Cancel => &self.cancel,
+            KeyAction::ModelMenu => &self.model_menu,
+            KeyAction::Help => &self.help,
+            KeyAction::ClearScreen => &self.clear_screen,
+        }
+    }
+
+    /// Finds the action bound to a key event
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<KeyAction> {
+        ACTIONS.into_iter().find(|action| {
+            self.get(*action)
+                .iter()
+                .any(|binding| binding.matches(code, modifiers))
+        })
+    }
+
+    /// Formats the bindings of an action for the help screen, such as `Ctrl+S/Alt+Enter`
+    pub fn describe(&self, action: KeyAction) -> String {
+        let names: Vec<String> = self.get(action).iter().map(ToString::to_string).collect();
+        names.join("/")
+    }
+
+    /// Formats the first binding of an action for the status line, such as `<c-s>`
+    pub fn short_name(&self, action: KeyAction) -> String {
+        self.get(action)
+            .first()
+            .map(KeyBinding::short_name)
+            .unwrap_or_default()
+    }
+
+    /// Checks that every action has a key, that no key does two things and that no key
+    /// is needed for typing or editing
+    ///
+    /// # Returns
+    ///
+    /// An error describing the first conflict found
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen: Vec<(KeyBinding, KeyAction)> = Vec::new();
+        for action in ACTIONS {
+            let bindings = self.get(action);
+            if bindings.is_empty() {
+                return Err(format!("'{}' needs at least one key", action.name()));
+            }
+            for binding in bindings {
+                if let KeyCode::Char(c) = binding.code {
+                    if !binding
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                    {
+                        return Err(format!(
+                            "'{}' can't be bound to '{}', it is needed for typing",
+                            action.name(),
+                            c
+                        ));
+                    }
+                }
+                if let Some((_, what)) = RESERVED_KEYS
+                    .iter()
+                    .find(|(reserved, _)| reserved.matches(binding.code, binding.modifiers))
+                {
+                    return Err(format!(
+                        "'{}' can't be bound to {}, which {}",
+                        action.name(),
+                        binding,
+                        what
+                    ));
+                }
+                if let Some((_, other)) = seen.iter().find(|(seen, _)| seen == binding) {
+                    return Err(format!(
+                        "{} is bound to both '{}' and '{}'",
+                        binding,
+                        other.name(),
+                        action.name()
+                    ));
+                }
+                seen.push((*binding, action));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Every configurable action, in the order conflicts are reported
+const ACTIONS: [KeyAction; 6] = [
+    KeyAction::Submit,
+    KeyAction::Newline,
+    KeyAction::Cancel,
+    KeyAction::ModelMenu,
+    KeyAction::Help,
+    KeyAction::ClearScreen,
+];
+
+// `[keys]` as written in `config.toml`, where actions not given keep their default keys
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeyBindingNames {
+    submit: Option<KeyNames>,
+    newline: Option<KeyNames>,
+    cancel: Option<KeyNames>,
+    model_menu: Option<KeyNames>,
+    help: Option<KeyNames>,
+    clear_screen: Option<KeyNames>,
+}
+
+// One key name, or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum KeyNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyNames {
+    fn parse(self) -> Result<Vec<KeyBinding>, String> {
+        let names = match self {
+            KeyNames::One(name) => vec![name],
+            KeyNames::Many(names) => names,
+        };
+        names.iter().map(|name| name.parse()).collect()
+    }
+}
+
+impl TryFrom<KeyBindingNames> for KeyBindings {
+    type Error = String;
+
+    fn try_from(names: KeyBindingNames) -> Result<Self, Self::Error> {
+        let mut bindings = KeyBindings::default();
+        for (names, keys) in [
+            (names.submit, &mut bindings.submit),
+            (names.newline, &mut bindings.newline),
+            (names.cancel, &mut bindings.cancel),
+            (names.model_menu, &mut bindings.model_menu),
+            (names.help, &mut bindings.help),
+            (names.clear_screen, &mut bindings.clear_screen),
+        ] {
+            if let Some(names) = names {
+                *keys = names.parse()?;
+            }
+        }
+        bindings.validate()?;
+        Ok(bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_bindings() {
+        let binding: KeyBinding = "ctrl+s".parse().unwrap();
+        assert_eq!(binding, ctrl('s'));
+        assert_eq!(binding.to_string(), "Ctrl+S");
+        assert_eq!(binding.short_name(), "<c-s>");
+
+        let binding: KeyBinding = "Alt + Enter".parse().unwrap();
+        assert_eq!(binding.to_string(), "Alt+Enter");
+        assert_eq!(binding.short_name(), "<m-cr>");
+        assert_eq!("F5".parse::<KeyBinding>().unwrap().to_string(), "F5");
+        assert_eq!(
+            "ctrl+space".parse::<KeyBinding>().unwrap().to_string(),
+            "Ctrl+Space"
+        );
+
+        assert!("hyper+s".parse::<KeyBinding>().is_err());
+        assert!("ctrl+".parse::<KeyBinding>().is_err());
+        assert!("ctrl+enterr".parse::<KeyBinding>().is_err());
+    }
+
+    #[test]
+    fn test_matching_ignores_case_and_other_modifiers() {
+        let binding = ctrl('s');
+        assert!(binding.matches(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert!(binding.matches(
+            KeyCode::Char('S'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        ));
+        assert!(!binding.matches(KeyCode::Char('s'), KeyModifiers::NONE));
+        assert!(!binding.matches(
+            KeyCode::Char('s'),
+            KeyModifiers::CONTROL | KeyModifiers::ALT
+        ));
+    }
+
+    #[test]
+    fn test_default_bindings() {
+        let keys = KeyBindings::default();
+        assert_eq!(keys.validate(), Ok(()));
+        assert_eq!(
+            keys.action(KeyCode::Char('s'), KeyModifiers::CONTROL),
+            Some(KeyAction::Submit)
+        );
+        assert_eq!(
+            keys.action(KeyCode::Enter, KeyModifiers::ALT),
+            Some(KeyAction::Submit)
+        );
+        assert_eq!(
+            keys.action(KeyCode::Enter, KeyModifiers::NONE),
+            Some(KeyAction::Newline)
+        );
+        assert_eq!(keys.action(KeyCode::Char('s'), KeyModifiers::NONE), None);
+        assert_eq!(keys.describe(KeyAction::Submit), "Ctrl+S/Alt+Enter");
+        assert_eq!(keys.short_name(KeyAction::Help), "<c-h>");
+    }
+
+    #[test]
+    fn test_conflicts_are_rejected() {
+        let parse = |names: KeyBindingNames| KeyBindings::try_from(names);
+        let names = |submit: &str| KeyBindingNames {
+            submit: Some(KeyNames::One(submit.to_string())),
+            ..KeyBindingNames::default()
+        };
+
+        let keys = parse(names("ctrl+d")).unwrap();
+        assert_eq!(keys.submit, vec![ctrl('d')]);
+        assert_eq!(keys.newline, KeyBindings::default().newline);
+
+        assert_eq!(
+            parse(names("ctrl+a")).unwrap_err(),
+            "Ctrl+A is bound to both 'submit' and 'model_menu'"
+        );
+        assert_eq!(
+            parse(names("ctrl+r")).unwrap_err(),
+            "'submit' can't be bound to Ctrl+R, which searches earlier prompts"
+        );
+        assert_eq!(
+            parse(names("x")).unwrap_err(),
+            "'submit' can't be bound to 'x', it is needed for typing"
+        );
+        assert_eq!(
+            parse(KeyBindingNames {
+                help: Some(KeyNames::Many(Vec::new())),
+                ..KeyBindingNames::default()
+            })
+            .unwrap_err(),
+            "'help' needs at least one key"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Markdown of model responses drawn with terminal styles
+
+use crate::ui::accessible;
+use crate::ui::highlight::highlight_rows;
+use crate::ui::theme::Themed;
+use crate::utils::render::render_ai_response;
+use colored::*;
+use regex::Regex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+
+// Matches **bold** text, capturing the text
+static BOLD_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*([^*]+)\*\*").unwrap());
+
+// Whether responses are rendered, see `configure_markdown`
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns Markdown rendering of responses on or off for the rest of the process
+///
+/// It is turned off with `--plain` and when stdout isn't a terminal, so piped output keeps
+/// the Markdown as the model wrote it.
+pub fn configure_markdown(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Gets a response ready to print, rendered with `render_markdown` unless rendering is off
+///
+/// # Arguments
+///
+/// * `response` - The response as the model wrote it, which isn't changed, so its code
+///   blocks can still be extracted from it
+/// * `width` - Width of the code block frames, in columns
+pub fn render_response(response: &str, width: usize) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        render_markdown(response, width)
+    } else {
+        response.to_string()
+    }
+}
+
+/// Renders the Markdown of a response with terminal styles
+///
+/// Headings are bright and bold, the first level underlined too, lists get their markers
+/// and indentation from `render_ai_response`, `inline code` is highlighted and **bold**
+/// text is bold. Fenced code blocks are drawn in a frame labelled with their language,
+/// including a block the response ended in.
+///
+/// # Arguments
+///
+/// * `response` - The response as the model wrote it
+/// * `width` - Width of the code block frames, in columns
+///
+/// # Returns
+///
+/// The rendered text, with the line breaks of the response
+pub fn render_markdown(response: &str, width: usize) -> String {
+    let listed = render_ai_response(response);
+    let mut lines: Vec<String> = Vec::new();
+    let mut code: Option<(String, Vec<&str>)> = None;
+
+    for line in listed.lines() {
+        let trimmed = line.trim_start();
+        // Inside a fenced code block everything is code until the closing fence
+        if let Some((language, code_lines)) = &mut code {
+            if trimmed.starts_with("```") {
+                lines.push(render_code_box(language, code_lines, width));
+                code = None;
+            } else {
+                code_lines.push(line);
+            }
+            continue;
+        }
+        if let Some(language) = trimmed.strip_prefix("```") {
+            code = Some((language.trim().to_string(), Vec::new()));
+            continue;
+        }
+        lines.push(render_heading(trimmed).unwrap_or_else(|| render_inline(line)));
+    }
+    if let Some((language, code_lines)) = code {
+        lines.push(render_code_box(&language, &code_lines, width));
+    }
+    lines.join("\n")
+}
+
+// Render a `#` to `######` heading line, None if the line isn't one
+fn render_heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    let text = render_inline(text.trim());
+    Some(match level {
+        1 => text.emphasis().bold().underline().to_string(),
+        2 => text.emphasis().bold().to_string(),
+        3..=6 => text.bold().to_string(),
+        _ => return None,
+    })
+}
+
+// Render the `code` spans and **bold** text of a line, leaving an unclosed backtick as it is
+fn render_inline(line: &str) -> String {
+    let parts: Vec<&str> = line.split('`').collect();
+    let closed = if parts.len().is_multiple_of(2) {
+        parts.len() - 1
+    } else {
+        parts.len()
+    };
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if i >= closed {
+                format!("`{}", render_bold(part))
+            } else if i % 2 == 1 {
+                part.warning().to_string()
+            } else {
+                render_bold(part)
+            }
+        })
+        .collect()
+}
+
+// Render the **bold** text of a part of a line outside code spans
+fn render_bold(text: &str) -> String {
+    BOLD_REGEX
+        .replace_all(text, |captures: &regex::Captures| {
+            captures[1].bold().to_string()
+        })
+        .into_owned()
+}
+
+// Draw a code block in a frame as wide as `width`, its language in the top border and its
+// lines highlighted for that language, wrapping the lines too long for the frame
+fn render_code_box(language: &str, lines: &[&str], width: usize) -> String {
+    let symbols = accessible::symbols();
+    let [top_left, top_right, bottom_left, bottom_right] = symbols.corners;
+    let inner = width.saturating_sub(2);
+    let top = if language.is_empty() {
+        symbols.horizontal.repeat(inner)
+    } else {
+        let label = format!("{} {} ", symbols.horizontal, language);
+        let rest = inner.saturating_sub(label.chars().count());
+        format!("{}{}", label, symbols.horizontal.repeat(rest))
+    };
+
+    let mut framed = vec![format!("{}{}{}", top_left, top, top_right)
+        .muted()
+        .to_string()];
+    for line in lines {
+        for row in highlight_rows(language, line, inner) {
+            framed.push(format!("{} {}", symbols.vertical.muted(), row));
+        }
+    }
+    framed.push(
+        format!(
+            "{}{}{}",
+            bottom_left,
+            symbols.horizontal.repeat(inner),
+            bottom_right
+        )
+        .muted()
+        .to_string(),
+    );
+    framed.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Remove the color codes, to compare the text alone
+    fn plain(text: &str) -> String {
+        Regex::new(r"\x1b\[[0-9;]*m")
+            .unwrap()
+            .replace_all(text, "")
+            .to_string()
+    }
+
+    #[test]
+    fn test_headings_and_inline_styles() {
+        let rendered = render_markdown("# Setup\nRun `make` with **care**.\n#hashtag", 20);
+        assert_eq!(plain(&rendered), "Setup\nRun make with care.\n#hashtag");
+
+        assert_eq!(
+            render_heading("## Usage").map(|line| plain(&line)),
+            Some("Usage".to_string())
+        );
+        assert_eq!(render_heading("####### Too deep"), None);
+        assert_eq!(render_heading("#!/bin/bash"), None);
+
+        // A backtick without its pair is kept
+        assert_eq!(plain(&render_inline("it's `ls")), "it's `ls");
+    }
+
+    #[test]
+    fn test_code_blocks_are_framed() {
+        let response = "List them:\n```bash\nls -la\n# **not bold**\n```\nDone.";
+        let rendered = plain(&render_markdown(response, 16));
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "List them:");
+        assert!(lines[1].contains(" bash "));
+        assert_eq!(lines[1].chars().count(), 16);
+        assert!(lines[2].ends_with(" ls -la"));
+        // Markdown inside code is left alone
+        assert!(lines[3].ends_with(" # **not bold**"));
+        assert_eq!(lines[4].chars().count(), 16);
+        assert_eq!(lines[5], "Done.");
+    }
+
+    #[test]
+    fn test_unterminated_block_is_framed() {
+        let rendered = plain(&render_markdown("```\nmake install", 20));
+        assert_eq!(rendered.lines().count(), 3);
+        assert!(rendered.lines().nth(1).unwrap().ends_with(" make install"));
+    }
+
+    #[test]
+    fn test_long_code_lines_are_wrapped_in_the_frame() {
+        let rendered = plain(&render_markdown("```sh\necho 0123456789\n```", 10));
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with(" echo 012"));
+        assert!(lines[2].ends_with(" 3456789"));
+    }
+
+    #[test]
+    fn test_lists_are_rendered() {
+        let rendered = plain(&render_markdown("Steps:\n1. `cd app`\n2. Build", 20));
+        assert!(rendered.contains("cd app"));
+        assert!(!rendered.contains('`'));
+    }
+}
@@ -0,0 +1,40 @@
+// Terminal user interface modules for ShellAI
+
+// Export the screen reader friendly output module
+pub mod accessible;
+
+// Export the slash command module
+pub mod commands;
+
+// Export the color output control module
+pub mod color;
+
+// Export the dropdown list module
+pub mod dropdown;
+
+// Export the input buffer editing module
+pub mod editor;
+
+// Export the code block syntax highlighting module
+pub mod highlight;
+
+// Export the multiline prompt input module
+pub mod input;
+
+// Export the configurable key bindings module
+pub mod keys;
+
+// Export the terminal Markdown rendering module
+pub mod markdown;
+
+// Export the terminal mode handling module
+pub mod terminal;
+
+// Export the spinner animation module
+pub mod spinner;
+
+// Export the output color theme module
+pub mod theme;
+
+// Export the tmux pane title module
+pub mod tmux;
@@ -0,0 +1,215 @@
+// Animated progress indicator for ShellAI
+
+use crate::ui::accessible;
+use crate::ui::terminal::terminal_width;
+use crate::ui::theme::Themed;
+use crossterm::{
+    cursor::{RestorePosition, SavePosition},
+    queue,
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Braille frames of the spinner animation
+pub const FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+// Time between two frames
+const FRAME_INTERVAL: Duration = Duration::from_millis(80);
+
+// Columns kept for the elapsed time after the message, a space and up to "99m 59s"
+const ELAPSED_WIDTH: usize = 8;
+
+// Whether spinners are drawn at all, see `configure_spinner`
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turns spinners on or off for the rest of the process
+///
+/// They are turned off with `--no-spinner` and when stdout isn't a terminal, where the
+/// frames would end up in the output. A disabled spinner prints nothing at all.
+pub fn configure_spinner(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Formats the time spent waiting, as shown after the spinner message
+///
+/// # Returns
+///
+/// Whole seconds such as `12s`, with minutes from one minute on, such as `2m 05s`
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+/// Gets the frame shown on a given tick, cycling through `FRAMES`
+pub fn frame_at(tick: usize) -> &'static str {
+    FRAMES[tick % FRAMES.len()]
+}
+
+/// Shortens a spinner message to fit on one row of a terminal with the given width
+///
+/// The frame and a space come first, and the last column stays free, so the line never
+/// wraps and clearing the current row removes all of it.
+pub fn fit_message(message: &str, columns: usize) -> String {
+    let room = columns.saturating_sub(3);
+    if message.chars().count() <= room {
+        return message.to_string();
+    }
+    let ellipsis = accessible::symbols().ellipsis;
+    if room <= ellipsis.chars().count() {
+        return message.chars().take(room).collect();
+    }
+    let kept = room - ellipsis.chars().count();
+    message.chars().take(kept).collect::<String>() + ellipsis
+}
+
+/// A spinner animated on a background task while a request runs
+///
+/// Call `stop` to tear it down and clear its line before printing anything else, such as
+/// the first piece of a streamed answer. Dropping a running spinner, for example on an
+/// early return, stops and clears it as well. In accessible mode only the message is
+/// printed, as screen readers would read every frame.
+pub struct Spinner {
+    running: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+    /// Whether anything was printed that has to be cleared
+    visible: bool,
+}
+
+impl Spinner {
+    /// Starts animating a spinner followed by `message` and the time elapsed since, from
+    /// the current cursor position
+    ///
+    /// The message is shortened to fit the terminal width, see `fit_message`.
+    pub fn start(message: &str) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Self {
+                running,
+                task: None,
+                visible: false,
+            };
+        }
+
+        let message = fit_message(message, terminal_width().saturating_sub(ELAPSED_WIDTH));
+        let mut stdout = io::stdout();
+        let _ = queue!(stdout, SavePosition);
+        if accessible::is_accessible() {
+            print!("{}", message);
+            let _ = stdout.flush();
+            return Self {
+                running,
+                task: None,
+                visible: true,
+            };
+        }
+
+        let task_running = Arc::clone(&running);
+        let started = Instant::now();
+
+        let task = tokio::spawn(async move {
+            let mut tick = 0;
+            while task_running.load(Ordering::Relaxed) {
+                let mut stdout = io::stdout();
+                let _ = queue!(stdout, RestorePosition);
+                print!(
+                    "{} {} {}",
+                    frame_at(tick).accent(),
+                    message.warning(),
+                    format_elapsed(started.elapsed()).muted()
+                );
+                let _ = queue!(stdout, Clear(ClearType::UntilNewLine));
+                let _ = stdout.flush();
+                tick += 1;
+                tokio::time::sleep(FRAME_INTERVAL).await;
+            }
+        });
+
+        Self {
+            running,
+            task: Some(task),
+            visible: true,
+        }
+    }
+
+    /// Stops the animation and clears its line
+    pub async fn stop(mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(task) = self.task.take() {
+            // Wait for the last frame so it can't be drawn over later output
+            let _ = task.await;
+        }
+        self.clear();
+    }
+
+    // Go back to where the spinner started and clear what it printed
+    fn clear(&self) {
+        if !self.visible {
+            return;
+        }
+        let mut stdout = io::stdout();
+        let _ = queue!(stdout, RestorePosition, Clear(ClearType::UntilNewLine));
+        let _ = stdout.flush();
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        // `stop` already cleared the line
+        if self.running.swap(false, Ordering::Relaxed) {
+            if let Some(task) = self.task.take() {
+                task.abort();
+            }
+            self.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_cycle() {
+        assert_eq!(frame_at(0), FRAMES[0]);
+        assert_eq!(frame_at(1), FRAMES[1]);
+        assert_eq!(frame_at(FRAMES.len() - 1), FRAMES[FRAMES.len() - 1]);
+        assert_eq!(frame_at(FRAMES.len()), FRAMES[0]);
+        assert_eq!(frame_at(FRAMES.len() * 3 + 2), FRAMES[2]);
+    }
+
+    #[test]
+    fn test_fit_message() {
+        assert_eq!(fit_message("AI is thinking...", 80), "AI is thinking...");
+        // Exactly filling the row would leave the cursor past it, so one column stays free
+        assert_eq!(fit_message("AI is thinking...", 20), "AI is thinking...");
+        assert_eq!(fit_message("AI is thinking...", 19).chars().count(), 16);
+        assert!(fit_message("AI is thinking...", 19).starts_with("AI is thinkin"));
+        assert_eq!(fit_message("AI is thinking...", 4), "A");
+        assert_eq!(fit_message("AI is thinking...", 2), "");
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(400)), "0s");
+        assert_eq!(format_elapsed(Duration::from_millis(12_900)), "12s");
+        assert_eq!(format_elapsed(Duration::from_secs(60)), "1m 00s");
+        assert_eq!(format_elapsed(Duration::from_secs(125)), "2m 05s");
+        assert!(format_elapsed(Duration::from_secs(99 * 60 + 59)).len() < ELAPSED_WIDTH);
+    }
+
+    #[tokio::test]
+    async fn test_stop_ends_the_animation() {
+        let spinner = Spinner::start("Working...");
+        let running = Arc::clone(&spinner.running);
+        spinner.stop().await;
+        assert!(!running.load(Ordering::Relaxed));
+    }
+}
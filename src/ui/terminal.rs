@@ -0,0 +1,186 @@
+// Terminal mode handling for ShellAI
+
+use crate::ui::accessible::symbols;
+use crate::ui::tmux;
+use crossterm::{
+    execute,
+    terminal::{self, Clear, ClearType, LeaveAlternateScreen},
+};
+use std::error::Error;
+use std::io::{self, Write};
+
+/// A terminal that can be switched in and out of raw mode
+pub trait RawModeTerminal {
+    fn enable_raw_mode(&self) -> io::Result<()>;
+    fn disable_raw_mode(&self) -> io::Result<()>;
+}
+
+/// The real terminal, driven through crossterm
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrosstermTerminal;
+
+impl RawModeTerminal for CrosstermTerminal {
+    fn enable_raw_mode(&self) -> io::Result<()> {
+        terminal::enable_raw_mode()
+    }
+
+    fn disable_raw_mode(&self) -> io::Result<()> {
+        terminal::disable_raw_mode()
+    }
+}
+
+/// Keeps the terminal in raw mode for as long as it is alive
+///
+/// Raw mode is disabled when the guard is dropped, including on early returns and `?` errors.
+/// `std::process::exit` skips destructors, so drop the guard before calling it.
+#[derive(Debug)]
+pub struct RawModeGuard<T: RawModeTerminal = CrosstermTerminal> {
+    terminal: T,
+}
+
+impl RawModeGuard {
+    /// Enables raw mode on the real terminal
+    pub fn enable() -> io::Result<Self> {
+        Self::with_terminal(CrosstermTerminal)
+    }
+}
+
+impl<T: RawModeTerminal> RawModeGuard<T> {
+    /// Enables raw mode on the given terminal
+    pub fn with_terminal(terminal: T) -> io::Result<Self> {
+        terminal.enable_raw_mode()?;
+        Ok(Self { terminal })
+    }
+}
+
+impl<T: RawModeTerminal> Drop for RawModeGuard<T> {
+    fn drop(&mut self) {
+        // Nothing useful can be done if this fails while dropping
+        let _ = self.terminal.disable_raw_mode();
+    }
+}
+
+/// Narrowest rules and boxes are drawn, so they stay recognizable on tiny terminals
+pub const MIN_RULE_WIDTH: usize = 20;
+
+// Columns assumed when the terminal size can't be read, as when the output is piped
+const FALLBACK_WIDTH: u16 = 80;
+
+/// Gets the number of columns of the terminal
+///
+/// The size is read on each call, so output printed after a resize follows the new width.
+pub fn terminal_width() -> usize {
+    terminal::size().map_or(FALLBACK_WIDTH, |(width, _)| width) as usize
+}
+
+/// Gets the width of rules and boxes for a terminal with the given number of columns
+pub fn rule_width_for(columns: usize) -> usize {
+    columns.max(MIN_RULE_WIDTH)
+}
+
+/// Gets the width of rules and boxes for the current terminal
+pub fn rule_width() -> usize {
+    rule_width_for(terminal_width())
+}
+
+/// Draws a horizontal rule across the terminal
+pub fn horizontal_rule() -> String {
+    symbols().horizontal.repeat(rule_width())
+}
+
+/// Puts the terminal back into a usable state
+///
+/// Executed scripts that crash, or a panic while raw mode is on, can leave the terminal
+/// without echo or stuck in raw mode. Errors are ignored since this runs on the way out.
+pub fn restore_terminal() {
+    let mut stdout = io::stdout();
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout, LeaveAlternateScreen, Clear(ClearType::All));
+    let _ = stdout.flush();
+}
+
+/// Restores the terminal on Ctrl+C signals and panics before exiting
+pub fn install_terminal_recovery() -> Result<(), Box<dyn Error>> {
+    ctrlc::set_handler(|| {
+        restore_terminal();
+        tmux::restore_pane_title();
+        std::process::exit(130);
+    })?;
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        tmux::restore_pane_title();
+        default_hook(info);
+    }));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    // Records whether raw mode is on instead of touching the real terminal
+    #[derive(Default)]
+    struct MockTerminal {
+        raw: Cell<bool>,
+        fail_enable: bool,
+    }
+
+    impl RawModeTerminal for &MockTerminal {
+        fn enable_raw_mode(&self) -> io::Result<()> {
+            if self.fail_enable {
+                return Err(io::Error::other("not a terminal"));
+            }
+            self.raw.set(true);
+            Ok(())
+        }
+
+        fn disable_raw_mode(&self) -> io::Result<()> {
+            self.raw.set(false);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dropping_guard_disables_raw_mode() {
+        let terminal = MockTerminal::default();
+
+        let guard = RawModeGuard::with_terminal(&terminal).expect("Failed to enable raw mode");
+        assert!(terminal.raw.get());
+
+        drop(guard);
+        assert!(!terminal.raw.get());
+    }
+
+    #[test]
+    fn test_guard_disables_raw_mode_on_early_return() {
+        fn read_until_error(terminal: &MockTerminal) -> io::Result<()> {
+            let _raw_mode = RawModeGuard::with_terminal(terminal)?;
+            Err(io::Error::other("read failed"))
+        }
+
+        let terminal = MockTerminal::default();
+        assert!(read_until_error(&terminal).is_err());
+        assert!(!terminal.raw.get());
+    }
+
+    #[test]
+    fn test_rule_width_follows_the_terminal() {
+        assert_eq!(rule_width_for(120), 120);
+        assert_eq!(rule_width_for(MIN_RULE_WIDTH), MIN_RULE_WIDTH);
+        assert_eq!(rule_width_for(8), MIN_RULE_WIDTH);
+    }
+
+    #[test]
+    fn test_failed_enable_returns_error() {
+        let terminal = MockTerminal {
+            fail_enable: true,
+            ..MockTerminal::default()
+        };
+        assert!(RawModeGuard::with_terminal(&terminal).is_err());
+        assert!(!terminal.raw.get());
+    }
+}
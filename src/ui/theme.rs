@@ -0,0 +1,244 @@
+// Colors of ShellAI's output, chosen by role so they can suit light and dark terminals
+
+use colored::{Color, ColoredString, Colorize};
+use serde::Deserialize;
+use std::sync::RwLock;
+
+/// The color of each kind of output (`[theme]` in `config.toml`)
+///
+/// The dark preset, the default, keeps the bright colors ShellAI always used. The light
+/// preset uses the darker colors, which stay readable on a light background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ThemeNames")]
+pub struct Theme {
+    /// The `You` label of the prompt
+    pub prompt: Color,
+    /// The `AI` label of answers and other labels of the model's text
+    pub ai_label: Color,
+    /// Questions to the user and notices
+    pub warning: Color,
+    /// Errors and failures
+    pub error: Color,
+    /// Successful outcomes
+    pub success: Color,
+    /// Secondary details such as notes and metadata
+    pub muted: Color,
+    /// Keys, names, paths and identifiers
+    pub accent: Color,
+    /// Diagrams, profiles and other extra information
+    pub info: Color,
+    /// Text that stands out from its surroundings, such as list bullets
+    pub emphasis: Color,
+    /// The border of the box around code about to run
+    pub code_border: Color,
+    /// Code without a highlighting color of its own
+    pub code: Color,
+}
+
+impl Theme {
+    /// Bright colors for terminals with a dark background
+    pub const DARK: Theme = Theme {
+        prompt: Color::BrightGreen,
+        ai_label: Color::BrightBlue,
+        warning: Color::BrightYellow,
+        error: Color::BrightRed,
+        success: Color::BrightGreen,
+        muted: Color::BrightBlack,
+        accent: Color::BrightCyan,
+        info: Color::BrightMagenta,
+        emphasis: Color::BrightWhite,
+        code_border: Color::BrightRed,
+        code: Color::BrightWhite,
+    };
+
+    /// Darker colors for terminals with a light background
+    pub const LIGHT: Theme = Theme {
+        prompt: Color::Green,
+        ai_label: Color::Blue,
+        warning: Color::Yellow,
+        error: Color::Red,
+        success: Color::Green,
+        muted: Color::BrightBlack,
+        accent: Color::Cyan,
+        info: Color::Magenta,
+        emphasis: Color::Black,
+        code_border: Color::Red,
+        code: Color::Black,
+    };
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DARK
+    }
+}
+
+/// The presets a theme starts from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    /// `Theme::DARK`
+    #[default]
+    Dark,
+    /// `Theme::LIGHT`
+    Light,
+}
+
+// The theme used by `theme()`, see `configure_theme`
+static THEME: RwLock<Theme> = RwLock::new(Theme::DARK);
+
+/// Sets the theme used for the rest of the process
+///
+/// Colors can still be turned off altogether with `--no-color` or `NO_COLOR`, see
+/// `configure_colors`.
+pub fn configure_theme(theme: Theme) {
+    *THEME.write().unwrap_or_else(|e| e.into_inner()) = theme;
+}
+
+/// Gets the theme set with `configure_theme`, the dark preset until then
+pub fn theme() -> Theme {
+    *THEME.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Colors text by its role in the output, with the colors of the current theme
+pub trait Themed: Colorize + Sized {
+    /// Colors a prompt label such as `You`
+    fn prompt_label(self) -> ColoredString {
+        self.color(theme().prompt)
+    }
+
+    /// Colors a label of the model's text such as `AI`
+    fn ai_label(self) -> ColoredString {
+        self.color(theme().ai_label)
+    }
+
+    /// Colors a question or a notice
+    fn warning(self) -> ColoredString {
+        self.color(theme().warning)
+    }
+
+    /// Colors an error
+    fn error(self) -> ColoredString {
+        self.color(theme().error)
+    }
+
+    /// Colors a successful outcome
+    fn success(self) -> ColoredString {
+        self.color(theme().success)
+    }
+
+    /// Colors a secondary detail
+    fn muted(self) -> ColoredString {
+        self.color(theme().muted)
+    }
+
+    /// Colors a key, name, path or identifier
+    fn accent(self) -> ColoredString {
+        self.color(theme().accent)
+    }
+
+    /// Colors extra information
+    fn info(self) -> ColoredString {
+        self.color(theme().info)
+    }
+
+    /// Colors text that stands out from its surroundings
+    fn emphasis(self) -> ColoredString {
+        self.color(theme().emphasis)
+    }
+
+    /// Colors the border of a code box
+    fn code_border(self) -> ColoredString {
+        self.color(theme().code_border)
+    }
+}
+
+impl<T: Colorize + Sized> Themed for T {}
+
+// `[theme]` as written in `config.toml`, where colors not given keep those of the preset
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeNames {
+    preset: ThemePreset,
+    prompt: Option<String>,
+    ai_label: Option<String>,
+    warning: Option<String>,
+    error: Option<String>,
+    success: Option<String>,
+    muted: Option<String>,
+    accent: Option<String>,
+    info: Option<String>,
+    emphasis: Option<String>,
+    code_border: Option<String>,
+    code: Option<String>,
+}
+
+impl TryFrom<ThemeNames> for Theme {
+    type Error = String;
+
+    fn try_from(names: ThemeNames) -> Result<Self, Self::Error> {
+        let mut theme = match names.preset {
+            ThemePreset::Dark => Theme::DARK,
+            ThemePreset::Light => Theme::LIGHT,
+        };
+        for (name, color) in [
+            (names.prompt, &mut theme.prompt),
+            (names.ai_label, &mut theme.ai_label),
+            (names.warning, &mut theme.warning),
+            (names.error, &mut theme.error),
+            (names.success, &mut theme.success),
+            (names.muted, &mut theme.muted),
+            (names.accent, &mut theme.accent),
+            (names.info, &mut theme.info),
+            (names.emphasis, &mut theme.emphasis),
+            (names.code_border, &mut theme.code_border),
+            (names.code, &mut theme.code),
+        ] {
+            if let Some(name) = name {
+                *color = parse_color(&name)?;
+            }
+        }
+        Ok(theme)
+    }
+}
+
+// Parse a color name such as "red", "bright cyan" or "bright_cyan"
+fn parse_color(name: &str) -> Result<Color, String> {
+    name.replace('_', " ").parse().map_err(|_| {
+        format!(
+            "Unknown color \"{}\", expected one of black, red, green, yellow, blue, magenta, \
+             cyan or white, optionally prefixed with \"bright \"",
+            name
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Theme, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(parse("").unwrap(), Theme::DARK);
+        assert_eq!(parse("preset = \"dark\"").unwrap(), Theme::DARK);
+        assert_eq!(parse("preset = \"light\"").unwrap(), Theme::LIGHT);
+        assert!(parse("preset = \"solarized\"").is_err());
+    }
+
+    #[test]
+    fn test_colors_override_the_preset() {
+        let theme =
+            parse("preset = \"light\"\nwarning = \"magenta\"\naccent = \"Bright_Blue\"").unwrap();
+        assert_eq!(theme.warning, Color::Magenta);
+        assert_eq!(theme.accent, Color::BrightBlue);
+        assert_eq!(theme.error, Theme::LIGHT.error);
+
+        let error = parse("code = \"grey\"").unwrap_err().to_string();
+        assert!(error.contains("Unknown color \"grey\""));
+        assert!(parse("background = \"black\"").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+// tmux pane title showing ShellAI and the current model
+
+use std::env;
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::OnceLock;
+
+// Title of the pane before ShellAI changed it, put back on the way out
+static PREVIOUS_TITLE: OnceLock<String> = OnceLock::new();
+
+/// Whether ShellAI runs inside tmux, as told by `$TMUX`
+pub fn in_tmux() -> bool {
+    env::var_os("TMUX").is_some_and(|value| !value.is_empty())
+}
+
+/// Gets the pane title shown while ShellAI runs, such as `ShellAI [gpt-4o]`
+pub fn pane_title(model: &str) -> String {
+    format!("ShellAI [{}]", model)
+}
+
+/// Builds the escape sequence setting the pane title, as `printf '\033]2;title\033\\'` does
+///
+/// Control characters are left out of the title so they can't end the sequence early.
+pub fn title_sequence(title: &str) -> String {
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    format!("\x1b]2;{}\x1b\\", title)
+}
+
+/// Shows the current model in the tmux pane title, and puts the previous title back when
+/// dropped
+#[derive(Debug)]
+pub struct PaneTitle {
+    /// Model in the title, to only update it when the model changes
+    model: String,
+}
+
+impl PaneTitle {
+    /// Shows the model in the pane title
+    ///
+    /// # Returns
+    ///
+    /// The title to update as the model changes, or None outside tmux
+    pub fn start(model: &str) -> Option<Self> {
+        if !in_tmux() {
+            return None;
+        }
+        if let Some(previous) = read_pane_title() {
+            let _ = PREVIOUS_TITLE.set(previous);
+        }
+        write_title(&pane_title(model));
+        Some(Self {
+            model: model.to_string(),
+        })
+    }
+
+    /// Shows another model in the pane title, if it changed
+    pub fn update(&mut self, model: &str) {
+        if self.model != model {
+            write_title(&pane_title(model));
+            self.model = model.to_string();
+        }
+    }
+}
+
+impl Drop for PaneTitle {
+    fn drop(&mut self) {
+        restore_pane_title();
+    }
+}
+
+/// Puts back the pane title ShellAI found at startup, if it changed it
+///
+/// Also called when exiting on Ctrl+C, which skips destructors.
+pub fn restore_pane_title() {
+    if let Some(previous) = PREVIOUS_TITLE.get() {
+        write_title(previous);
+    }
+}
+
+// Ask tmux for the title of the pane ShellAI runs in
+fn read_pane_title() -> Option<String> {
+    let mut command = Command::new("tmux");
+    command.arg("display-message").arg("-p");
+    if let Ok(pane) = env::var("TMUX_PANE") {
+        command.arg("-t").arg(pane);
+    }
+    let output = command.arg("#{pane_title}").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches('\n')
+            .to_string(),
+    )
+}
+
+// Failing to set the title only leaves the old one, so errors are ignored
+fn write_title(title: &str) {
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "{}", title_sequence(title));
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_sequence() {
+        assert_eq!(pane_title("gpt-4o"), "ShellAI [gpt-4o]");
+        assert_eq!(
+            title_sequence("ShellAI [gpt-4o]"),
+            "\x1b]2;ShellAI [gpt-4o]\x1b\\"
+        );
+        assert_eq!(title_sequence("a\x1b\\b\x07"), "\x1b]2;a\\b\x1b\\");
+    }
+}
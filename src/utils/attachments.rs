@@ -0,0 +1,286 @@
+// File contents attached to prompts with @path references
+
+use crate::utils::execute::truncate_output;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes checked for a null byte to tell binary files from text
+const BINARY_CHECK_BYTES: usize = 8_000;
+
+// Punctuation ending a sentence rather than a path, as in "look at @main.rs."
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', '"', '\''];
+
+/// A file read for a prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// The path as written after the `@`
+    pub path: String,
+    /// The text of the file, cut to the size limit
+    pub content: String,
+    /// Whether the file was longer than the size limit
+    pub truncated: bool,
+}
+
+/// Why a referenced file couldn't be attached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachmentError {
+    /// Nothing exists at the path
+    NotFound(String),
+    /// The file looks binary
+    Binary(String),
+    /// The path isn't a regular file or couldn't be read
+    Unreadable { path: String, reason: String },
+}
+
+impl fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentError::NotFound(path) => write!(f, "@{}: file not found", path),
+            AttachmentError::Binary(path) => write!(f, "@{}: binary file", path),
+            AttachmentError::Unreadable { path, reason } => write!(f, "@{}: {}", path, reason),
+        }
+    }
+}
+
+/// What to do with a prompt whose references couldn't all be attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingFileChoice {
+    /// Send the prompt with the files that could be attached
+    Send,
+    /// Go back to editing the prompt
+    Edit,
+    /// Drop the prompt
+    Cancel,
+}
+
+impl MissingFileChoice {
+    /// Parses the answer to the missing file question, anything unrecognised cancels
+    pub fn parse(input: &str) -> Self {
+        match input.trim().to_lowercase().as_str() {
+            "s" | "send" => MissingFileChoice::Send,
+            "e" | "edit" => MissingFileChoice::Edit,
+            _ => MissingFileChoice::Cancel,
+        }
+    }
+}
+
+/// Finds the `@path` references in a prompt, each listed once
+///
+/// A reference is a word starting with `@`, so addresses such as `me@example.com` are left
+/// alone. Punctuation after the path is not part of it.
+pub fn file_references(input: &str) -> Vec<&str> {
+    let mut references = Vec::new();
+
+    for word in input.split_whitespace() {
+        let Some(path) = word.strip_prefix('@') else {
+            continue;
+        };
+        let path = path.trim_end_matches(TRAILING_PUNCTUATION);
+        if !path.is_empty() && !references.contains(&path) {
+            references.push(path);
+        }
+    }
+
+    references
+}
+
+/// Reads a referenced file
+///
+/// # Arguments
+///
+/// * `root` - The directory relative paths are resolved from
+/// * `path` - The path as written after the `@`
+/// * `max_bytes` - Largest number of bytes attached, the rest of the file is left out
+///
+/// # Returns
+///
+/// The attachment, or why the file can't be attached
+pub fn read_attachment(
+    root: &Path,
+    path: &str,
+    max_bytes: usize,
+) -> Result<Attachment, AttachmentError> {
+    let full_path = root.join(path);
+    let unreadable = |reason: String| AttachmentError::Unreadable {
+        path: path.to_string(),
+        reason,
+    };
+
+    let metadata = match fs::metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AttachmentError::NotFound(path.to_string()))
+        }
+        Err(e) => return Err(unreadable(e.to_string())),
+    };
+    if !metadata.is_file() {
+        return Err(unreadable("not a regular file".to_string()));
+    }
+
+    let mut bytes = Vec::new();
+    fs::File::open(&full_path)
+        .and_then(|file| {
+            file.take((max_bytes as u64).saturating_add(1))
+                .read_to_end(&mut bytes)
+        })
+        .map_err(|e| unreadable(e.to_string()))?;
+
+    if bytes.iter().take(BINARY_CHECK_BYTES).any(|&byte| byte == 0) {
+        return Err(AttachmentError::Binary(path.to_string()));
+    }
+
+    let truncated = bytes.len() > max_bytes;
+    bytes.truncate(max_bytes);
+    Ok(Attachment {
+        path: path.to_string(),
+        content: String::from_utf8_lossy(&bytes).into_owned(),
+        truncated,
+    })
+}
+
+/// Appends the files referenced in a prompt to it, each in a fenced block labelled with its path
+///
+/// # Arguments
+///
+/// * `root` - The directory relative paths are resolved from
+/// * `input` - The prompt as typed
+/// * `max_bytes` - Largest number of bytes attached per file
+///
+/// # Returns
+///
+/// The prompt to send, with the files that could be read, and the references that couldn't
+pub fn attach_files(root: &Path, input: &str, max_bytes: usize) -> (String, Vec<AttachmentError>) {
+    let mut prompt = input.to_string();
+    let mut errors = Vec::new();
+
+    for path in file_references(input) {
+        match read_attachment(root, path, max_bytes) {
+            Ok(attachment) => {
+                let note = if attachment.truncated {
+                    format!("\n[truncated after {} bytes]", max_bytes)
+                } else {
+                    String::new()
+                };
+                prompt.push_str(&format!(
+                    "\n\n{}:\n```\n{}\n```{}",
+                    attachment.path,
+                    attachment.content.trim_end(),
+                    note
+                ));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (prompt, errors)
+}
+
+/// Adds text piped on stdin below a question, as context for it
+///
+/// Input longer than `max_bytes` keeps its beginning and end, with a note of how much was
+/// left out in between, since logs usually end with the error.
+///
+/// # Arguments
+///
+/// * `question` - The question given on the command line
+/// * `input` - The text read from stdin
+/// * `max_bytes` - Largest number of bytes of the input sent
+///
+/// # Returns
+///
+/// The prompt to send, which is the question alone if the input is blank
+pub fn with_piped_input(question: &str, input: &str, max_bytes: usize) -> String {
+    if input.trim().is_empty() {
+        return question.to_string();
+    }
+
+    format!(
+        "{}\n\nInput piped from stdin:\n```\n{}\n```",
+        question,
+        truncate_output(input, max_bytes).trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_references() {
+        assert_eq!(
+            file_references("why does @src/main.rs fail? see @Cargo.toml, @src/main.rs"),
+            vec!["src/main.rs", "Cargo.toml"]
+        );
+        assert!(file_references("mail me@example.com about @ it").is_empty());
+    }
+
+    #[test]
+    fn test_attach_files() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        fs::write(temp_dir.path().join("notes.txt"), "first line\n").expect("Failed to write");
+        fs::write(temp_dir.path().join("long.txt"), "0123456789").expect("Failed to write");
+
+        let (prompt, errors) = attach_files(temp_dir.path(), "summarize @notes.txt", 100);
+        assert!(errors.is_empty());
+        assert_eq!(
+            prompt,
+            "summarize @notes.txt\n\nnotes.txt:\n```\nfirst line\n```"
+        );
+
+        let (prompt, _) = attach_files(temp_dir.path(), "@long.txt", 4);
+        assert!(prompt.ends_with("```\n0123\n```\n[truncated after 4 bytes]"));
+
+        // No limit at all doesn't overflow
+        let (prompt, _) = attach_files(temp_dir.path(), "@long.txt", usize::MAX);
+        assert!(prompt.ends_with("```\n0123456789\n```"));
+    }
+
+    #[test]
+    fn test_with_piped_input() {
+        assert_eq!(
+            with_piped_input("why is this failing?", "error: boom\n", 100),
+            "why is this failing?\n\nInput piped from stdin:\n```\nerror: boom\n```"
+        );
+        assert_eq!(with_piped_input("hello", " \n", 100), "hello");
+
+        let prompt = with_piped_input("why?", "0123456789", 4);
+        assert!(prompt.contains("01\n[... 6 bytes truncated ...]\n89"));
+    }
+
+    #[test]
+    fn test_attach_files_reports_problems() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        fs::write(
+            temp_dir.path().join("image.png"),
+            b"\x89PNG\r\n\x1a\n\0\0\0",
+        )
+        .expect("Failed to write");
+        fs::create_dir(temp_dir.path().join("src")).expect("Failed to create directory");
+
+        let (prompt, errors) = attach_files(temp_dir.path(), "@missing.rs @image.png @src", 100);
+        assert_eq!(prompt, "@missing.rs @image.png @src");
+        assert_eq!(
+            errors,
+            vec![
+                AttachmentError::NotFound("missing.rs".to_string()),
+                AttachmentError::Binary("image.png".to_string()),
+                AttachmentError::Unreadable {
+                    path: "src".to_string(),
+                    reason: "not a regular file".to_string()
+                },
+            ]
+        );
+        assert_eq!(errors[0].to_string(), "@missing.rs: file not found");
+    }
+
+    #[test]
+    fn test_parse_missing_file_choice() {
+        assert_eq!(MissingFileChoice::parse("s"), MissingFileChoice::Send);
+        assert_eq!(MissingFileChoice::parse(" Edit\n"), MissingFileChoice::Edit);
+        assert_eq!(MissingFileChoice::parse("c"), MissingFileChoice::Cancel);
+        assert_eq!(MissingFileChoice::parse(""), MissingFileChoice::Cancel);
+    }
+}
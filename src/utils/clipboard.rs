@@ -0,0 +1,83 @@
+// Clipboard watching for ShellAI
+
+/// Something the current clipboard text can be read from
+pub trait ClipboardSource {
+    /// Reads the clipboard text, or `None` if it holds no text or can't be read
+    fn read_text(&mut self) -> Option<String>;
+}
+
+impl ClipboardSource for arboard::Clipboard {
+    fn read_text(&mut self) -> Option<String> {
+        self.get_text().ok()
+    }
+}
+
+/// Reports text newly copied to the clipboard
+///
+/// Whatever is on the clipboard when the watcher starts counts as already seen, so only
+/// text copied afterwards is reported.
+pub struct ClipboardWatcher<C: ClipboardSource> {
+    source: C,
+    last_seen: Option<String>,
+}
+
+impl<C: ClipboardSource> ClipboardWatcher<C> {
+    /// Starts watching a clipboard
+    pub fn new(mut source: C) -> Self {
+        let last_seen = source.read_text();
+        Self { source, last_seen }
+    }
+
+    /// Checks the clipboard once
+    ///
+    /// # Returns
+    ///
+    /// The clipboard text if it changed since the last check and isn't blank
+    pub fn poll(&mut self) -> Option<String> {
+        let text = self.source.read_text()?;
+        if self.last_seen.as_ref() == Some(&text) {
+            return None;
+        }
+
+        self.last_seen = Some(text.clone());
+        (!text.trim().is_empty()).then_some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Returns a scripted sequence of clipboard contents
+    struct ScriptedClipboard(VecDeque<Option<&'static str>>);
+
+    impl ClipboardSource for ScriptedClipboard {
+        fn read_text(&mut self) -> Option<String> {
+            self.0.pop_front().flatten().map(str::to_string)
+        }
+    }
+
+    #[test]
+    fn test_reports_only_new_text() {
+        let clipboard = ScriptedClipboard(VecDeque::from([
+            Some("copied before starting"),
+            Some("copied before starting"),
+            Some("stack trace"),
+            Some("stack trace"),
+            None,
+            Some("   "),
+            Some("stack trace"),
+        ]));
+        let mut watcher = ClipboardWatcher::new(clipboard);
+
+        assert_eq!(watcher.poll(), None);
+        assert_eq!(watcher.poll(), Some("stack trace".to_string()));
+        assert_eq!(watcher.poll(), None);
+        // Unreadable clipboards are skipped without forgetting the last text
+        assert_eq!(watcher.poll(), None);
+        // Blank text isn't reported, but copying the old text again afterwards is
+        assert_eq!(watcher.poll(), None);
+        assert_eq!(watcher.poll(), Some("stack trace".to_string()));
+    }
+}
@@ -1,9 +1,457 @@
 // Directory utility functions for ShellAI
 
+use crate::ui::accessible::{self, Symbols};
+use chrono::{DateTime, Local};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+
+/// Options controlling how a directory is scanned for the system prompt
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Maximum depth of the tree view (0 means only the top level)
+    pub max_depth: usize,
+    /// Whether to include hidden files and directories
+    pub show_hidden: bool,
+    /// Whether to leave out entries ignored by git (`respect_gitignore` in `config.toml`)
+    pub respect_gitignore: bool,
+    /// Globs a file must match to be shown (all files if empty)
+    pub include_globs: Vec<String>,
+    /// Globs of files and directories to leave out, winning over `include_globs`
+    pub exclude_globs: Vec<String>,
+    /// Whether the tree shows file sizes, directory subtotals and a total
+    pub show_sizes: bool,
+    /// List the entries changed most often in the git history of the last this many days
+    /// first in the tree (sorted by name if unset)
+    pub change_frequency_days: Option<u32>,
+    /// How the tree is drawn
+    pub tree_style: TreeStyle,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            show_hidden: false,
+            respect_gitignore: false,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            show_sizes: false,
+            change_frequency_days: None,
+            tree_style: TreeStyle::default(),
+        }
+    }
+}
+
+/// Include and exclude globs deciding which entries a directory scan shows
+///
+/// Globs are matched against paths relative to the scanned directory, with `/` as the
+/// separator. A glob without a `/` matches the entry's name at any depth, so `*.rs`
+/// matches `src/main.rs`, while `src/*.rs` only matches files directly inside `src`.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    gitignore: bool,
+}
+
+impl PathFilter {
+    /// Compiles the include and exclude globs
+    ///
+    /// # Arguments
+    ///
+    /// * `include` - Globs a file must match to be kept (all files are kept if empty)
+    /// * `exclude` - Globs of files and directories to drop, winning over `include`
+    ///
+    /// # Returns
+    ///
+    /// The filter, or the error of the first invalid glob
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        Ok(Self {
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+            gitignore: false,
+        })
+    }
+
+    /// Builds the filter of the globs in scan options, leaving out what git ignores if
+    /// `respect_gitignore` is set
+    pub fn from_options(options: &ScanOptions) -> Result<Self, globset::Error> {
+        Ok(Self { gitignore: options.respect_gitignore, ..Self::new(&options.include_globs, &options.exclude_globs)? })
+    }
+
+    /// Builds the filter of the globs in scan options, also excluding the globs of the
+    /// `.shellai-ignore` file of the scanned directory
+    pub fn for_directory(root: &Path, options: &ScanOptions) -> Result<Self, globset::Error> {
+        let mut exclude = options.exclude_globs.clone();
+        exclude.extend(read_shellai_ignore(root));
+        Ok(Self { gitignore: options.respect_gitignore, ..Self::new(&options.include_globs, &exclude)? })
+    }
+
+    /// Finds the entries of a directory that git ignores, if the filter leaves them out
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding the entries
+    /// * `names` - Names of the entries
+    ///
+    /// # Returns
+    ///
+    /// The names to leave out, empty if the filter keeps ignored entries
+    pub fn ignored_in(&self, dir: &Path, names: &[PathBuf]) -> HashSet<PathBuf> {
+        if !self.gitignore {
+            return HashSet::new();
+        }
+        gitignored_paths(dir, names)
+    }
+
+    /// Checks whether an entry should be shown
+    ///
+    /// # Arguments
+    ///
+    /// * `relative` - Path of the entry relative to the scanned directory
+    /// * `is_dir` - Whether the entry is a directory (includes only apply to files)
+    ///
+    /// # Returns
+    ///
+    /// False if the entry matches an exclude glob, or is a file matching no include glob
+    pub fn allows(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative)) {
+            return false;
+        }
+        is_dir || self.include.as_ref().is_none_or(|include| include.is_match(relative))
+    }
+
+    /// Whether the filter only keeps files matching include globs
+    fn has_includes(&self) -> bool {
+        self.include.is_some()
+    }
+}
+
+// Compile globs into a set, or None if there are none
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>, globset::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // Globs without a separator match the entry's name in any directory
+        let pattern = if pattern.contains('/') {
+            pattern.trim_start_matches("./").to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        builder.add(GlobBuilder::new(&pattern).literal_separator(true).build()?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// How the current directory is described in the system prompt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextFormat {
+    /// Indented tree of the directory structure
+    #[default]
+    Tree,
+    /// `ls -la`-style listing of the top level
+    Ls,
+    /// Both the listing and the tree
+    Combined,
+    /// Only the path, without scanning the directory
+    None,
+}
+
+/// How the directory tree is drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeStyle {
+    /// Entries indented by two spaces per level and marked as files or directories
+    #[default]
+    Indented,
+    /// Entries joined by connectors such as `├──` and `└──`, like `tree(1)`
+    Lines,
+}
+
+/// A file or directory found by `scan_directory_tree`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DirNode {
+    /// File name of the entry
+    pub name: String,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// Entries of a directory, directories first and then files, each sorted by name
+    pub children: Vec<DirNode>,
+    /// Whether the directory is deeper than the scan went, so its children weren't read
+    pub truncated: bool,
+    /// Size of a file in bytes, or the total of the files scanned below a directory
+    pub size: u64,
+    /// Where a symbolic link points, if the entry is one (links are never followed)
+    pub link_target: Option<String>,
+}
+
+// Units of `format_size` above bytes
+const SIZE_UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+
+/// Formats a number of bytes for people, such as `512 B`, `2.3 KB` or `1.5 GB`
+///
+/// # Arguments
+///
+/// * `bytes` - The size to format
+///
+/// # Returns
+///
+/// The size in the largest unit keeping it at 1 or more, with one decimal above bytes
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Move up a unit before rounding would show 1024.0
+    while value >= 1023.95 && unit + 1 < SIZE_UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, SIZE_UNITS[unit])
+}
+
+/// Scans a directory into a tree of its entries
+///
+/// Hidden files and directories are skipped.
+///
+/// # Arguments
+///
+/// * `path` - The path to scan
+/// * `max_depth` - Maximum depth to scan (0 means only the top level)
+///
+/// # Returns
+///
+/// The node of `path`, with the entries found below it
+pub fn scan_directory_tree(path: &Path, max_depth: usize) -> Result<DirNode, Box<dyn Error>> {
+    scan_directory_tree_filtered(path, max_depth, &PathFilter::default())
+}
+
+/// Scans a directory into a tree of the entries a filter allows
+///
+/// Hidden files and directories are skipped. Excluded directories are left out with
+/// everything below them, and when the filter has include globs, directories left
+/// without any matching file are left out too.
+///
+/// # Arguments
+///
+/// * `path` - The path to scan
+/// * `max_depth` - Maximum depth to scan (0 means only the top level)
+/// * `filter` - Globs deciding which entries are kept
+///
+/// # Returns
+///
+/// The node of `path`, with the entries found below it
+pub fn scan_directory_tree_filtered(path: &Path, max_depth: usize, filter: &PathFilter) -> Result<DirNode, Box<dyn Error>> {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+
+    let mut node = DirNode {
+        name,
+        is_dir: path.is_dir(),
+        children: Vec::new(),
+        truncated: false,
+        size: 0,
+        link_target: None,
+    };
+    if node.is_dir {
+        node.children = scan_children(path, Path::new(""), max_depth, 0, filter)?;
+        node.size = node.children.iter().map(|child| child.size).sum();
+    } else {
+        node.size = fs::metadata(path)?.len();
+    }
+
+    Ok(node)
+}
+
+// Read the entries of a directory at the given depth, recursing into subdirectories.
+// `relative` is the path of the directory relative to the scanned one, matched by the filter.
+fn scan_children(path: &Path, relative: &Path, max_depth: usize, depth: usize, filter: &PathFilter) -> Result<Vec<DirNode>, Box<dyn Error>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+
+    // Skip hidden files and directories
+    let entries: Vec<fs::DirEntry> = fs::read_dir(path)?
+        .filter(|entry| entry.as_ref().map_or(true, |entry| !entry.file_name().to_string_lossy().starts_with('.')))
+        .collect::<Result<_, _>>()?;
+    let names: Vec<PathBuf> = entries.iter().map(|entry| PathBuf::from(entry.file_name())).collect();
+    let ignored = filter.ignored_in(path, &names);
+
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if ignored.contains(Path::new(&entry.file_name())) {
+            continue;
+        }
+
+        // Links are listed with their target instead of being followed, so a link to a
+        // parent directory can't make the scan loop forever
+        let file_type = entry.file_type()?;
+        let link_target = file_type.is_symlink().then(|| {
+            fs::read_link(&path)
+                .map(|target| target.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "?".to_string())
+        });
+
+        let relative = relative.join(&file_name);
+        let is_dir = file_type.is_dir();
+        if !filter.allows(&relative, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            let (children, truncated) = if depth < max_depth {
+                (scan_children(&path, &relative, max_depth, depth + 1, filter)?, false)
+            } else {
+                (Vec::new(), true)
+            };
+            // With include globs, a fully scanned directory without matches has nothing to show
+            if filter.has_includes() && !truncated && children.is_empty() {
+                continue;
+            }
+            let size = children.iter().map(|child| child.size).sum();
+            dirs.push(DirNode { name: file_name, is_dir: true, children, truncated, size, link_target: None });
+        } else {
+            // Links take no space of their own worth counting, and an unreadable entry counts as empty
+            let size = match link_target {
+                Some(_) => 0,
+                None => entry.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            };
+            files.push(DirNode { name: file_name, is_dir: false, children: Vec::new(), truncated: false, size, link_target });
+        }
+    }
+
+    // Sort directories and files for consistent output
+    dirs.sort_by(|a, b| a.name.cmp(&b.name));
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    dirs.extend(files);
+
+    Ok(dirs)
+}
+
+/// Renders the entries of a tree as indented lines, as shown in the system prompt
+///
+/// # Arguments
+///
+/// * `nodes` - The entries to render
+/// * `depth` - Indentation level of the entries
+///
+/// # Returns
+///
+/// One line per entry, with `...` under directories whose children weren't scanned
+pub fn render_tree(nodes: &[DirNode], depth: usize) -> String {
+    render_nodes(nodes, depth, false, accessible::symbols())
+}
+
+/// Renders the entries of a tree like `render_tree`, with the size of each file and the
+/// subtotal of each fully scanned directory
+///
+/// # Arguments
+///
+/// * `nodes` - The entries to render
+/// * `depth` - Indentation level of the entries
+///
+/// # Returns
+///
+/// One line per entry, such as `📄 notes.txt (2.3 KB)`
+pub fn render_tree_with_sizes(nodes: &[DirNode], depth: usize) -> String {
+    render_nodes(nodes, depth, true, accessible::symbols())
+}
+
+/// Renders the entries of a tree with connectors like `tree(1)`, in ASCII in accessible
+/// mode
+///
+/// # Arguments
+///
+/// * `nodes` - The entries to render
+/// * `sizes` - Whether to show the size of each file and the subtotal of each directory
+///
+/// # Returns
+///
+/// One line per entry, such as `│   └── main.rs`, with `...` under directories whose
+/// children weren't scanned
+pub fn render_tree_with_lines(nodes: &[DirNode], sizes: bool) -> String {
+    render_lines(nodes, "", sizes, accessible::symbols())
+}
+
+// The size shown after an entry, if sizes are asked for
+fn size_label(node: &DirNode, sizes: bool) -> String {
+    // The subtotal of a truncated directory would leave out what wasn't scanned
+    if sizes && !(node.is_dir && node.truncated) {
+        format!(" ({})", format_size(node.size))
+    } else {
+        String::new()
+    }
+}
+
+// Render entries with the given markers, with sizes if asked for
+fn render_nodes(nodes: &[DirNode], depth: usize, sizes: bool, symbols: &Symbols) -> String {
+    let indent = "  ".repeat(depth);
+    let mut result = String::new();
+
+    for node in nodes {
+        let size = size_label(node, sizes);
+
+        if let Some(target) = &node.link_target {
+            result.push_str(&format!("{}{} {} -> {}\n", indent, symbols.link, node.name, target));
+        } else if node.is_dir {
+            result.push_str(&format!("{}{} {}/{}\n", indent, symbols.dir, node.name, size));
+            if node.truncated {
+                result.push_str(&format!("{}  ...\n", indent));
+            } else {
+                result.push_str(&render_nodes(&node.children, depth + 1, sizes, symbols));
+            }
+        } else {
+            result.push_str(&format!("{}{} {}{}\n", indent, symbols.file, node.name, size));
+        }
+    }
+
+    result
+}
+
+// Render entries with connectors after `prefix`, which continues the lines of the parents
+fn render_lines(nodes: &[DirNode], prefix: &str, sizes: bool, symbols: &Symbols) -> String {
+    let line = symbols.horizontal.repeat(2);
+    let mut result = String::new();
+
+    for (index, node) in nodes.iter().enumerate() {
+        let is_last = index + 1 == nodes.len();
+        let connector = if is_last { symbols.corners[2] } else { symbols.junctions[2] };
+        let size = size_label(node, sizes);
+
+        if let Some(target) = &node.link_target {
+            result.push_str(&format!("{}{}{} {} -> {}\n", prefix, connector, line, node.name, target));
+        } else if node.is_dir {
+            result.push_str(&format!("{}{}{} {}/{}\n", prefix, connector, line, node.name, size));
+            // The parent's line goes on past its children unless it was the last entry
+            let child_prefix = if is_last { format!("{}    ", prefix) } else { format!("{}{}   ", prefix, symbols.vertical) };
+            if node.truncated {
+                result.push_str(&format!("{}{}{} ...\n", child_prefix, symbols.corners[2], line));
+            } else {
+                result.push_str(&render_lines(&node.children, &child_prefix, sizes, symbols));
+            }
+        } else {
+            result.push_str(&format!("{}{}{} {}{}\n", prefix, connector, line, node.name, size));
+        }
+    }
+
+    result
+}
 
 /// Scans a directory and builds a tree structure representation
 ///
@@ -21,54 +469,445 @@ pub fn scan_directory(path: &Path, max_depth: usize, current_depth: usize) -> Re
         return Ok("...".to_string());
     }
 
-    let mut result = String::new();
-    
-    if path.is_dir() {
-        let entries = fs::read_dir(path)?;
-        let mut dirs = Vec::new();
-        let mut files = Vec::new();
-        
-        for entry in entries {
-            let entry = entry?;
-            let path = entry.path();
-            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-            
-            // Skip hidden files and directories
-            if file_name.starts_with('.') {
-                continue;
-            }
-            
-            if path.is_dir() {
-                dirs.push(file_name);
-            } else {
-                files.push(file_name);
+    let tree = scan_directory_tree(path, max_depth - current_depth)?;
+    Ok(render_tree(&tree.children, current_depth))
+}
+
+/// Scans a directory into the tree shown in the system prompt
+///
+/// # Arguments
+///
+/// * `path` - The path to scan
+/// * `options` - Scan options (`max_depth`, `show_sizes`, `tree_style` and the include and
+///   exclude globs apply to the tree)
+///
+/// # Returns
+///
+/// A string representation of the directory tree, or an error if a glob is invalid
+pub fn scan_directory_with_options(path: &Path, options: &ScanOptions) -> Result<String, Box<dyn Error>> {
+    let filter = PathFilter::for_directory(path, options)?;
+    let mut tree = scan_directory_tree_filtered(path, options.max_depth, &filter)?;
+    // Outside a repository, or if git can't be run, the tree keeps its order by name
+    if let Some(days) = options.change_frequency_days {
+        if let Ok(frequencies) = file_change_frequency(path, days) {
+            sort_by_change_frequency(&mut tree.children, Path::new(""), &directory_change_totals(&frequencies));
+        }
+    }
+    let rendered = match options.tree_style {
+        TreeStyle::Indented => render_nodes(&tree.children, 0, options.show_sizes, accessible::symbols()),
+        TreeStyle::Lines => render_tree_with_lines(&tree.children, options.show_sizes),
+    };
+    if !options.show_sizes {
+        return Ok(rendered);
+    }
+
+    Ok(format!("{}Total of the files shown: {}\n", rendered, format_size(tree.size)))
+}
+
+/// Counts how often each file changed in the recent git history
+///
+/// Runs `git log --name-only` from `root`, so only changes below it are counted.
+///
+/// # Arguments
+///
+/// * `root` - The directory to count changes in, inside a repository
+/// * `days` - How many days of history to look at
+///
+/// # Returns
+///
+/// The number of commits that touched each file, keyed by its path relative to `root`,
+/// or an error if `root` isn't in a repository or git couldn't be run
+pub fn file_change_frequency(root: &Path, days: u32) -> Result<HashMap<PathBuf, u32>, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["log", "--name-only", "--relative", "--format="])
+        .arg(format!("--since={}.days.ago", days))
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("git log failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+
+    let mut frequencies = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines().filter(|line| !line.is_empty()) {
+        *frequencies.entry(PathBuf::from(line)).or_insert(0) += 1;
+    }
+    Ok(frequencies)
+}
+
+// Add the changes of each file to every directory above it, so directories can be ranked too
+fn directory_change_totals(frequencies: &HashMap<PathBuf, u32>) -> HashMap<PathBuf, u32> {
+    let mut totals = frequencies.clone();
+    for (path, count) in frequencies {
+        for ancestor in path.ancestors().skip(1).filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+            *totals.entry(ancestor.to_path_buf()).or_insert(0) += count;
+        }
+    }
+    totals
+}
+
+// Order the entries of each directory by how often they changed, keeping directories before
+// files and falling back to the name
+fn sort_by_change_frequency(nodes: &mut [DirNode], relative: &Path, totals: &HashMap<PathBuf, u32>) {
+    let changes = |node: &DirNode| totals.get(&relative.join(&node.name)).copied().unwrap_or(0);
+    nodes.sort_by(|a, b| {
+        b.is_dir.cmp(&a.is_dir)
+            .then_with(|| changes(b).cmp(&changes(a)))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    for node in nodes.iter_mut().filter(|node| node.is_dir) {
+        sort_by_change_frequency(&mut node.children, &relative.join(&node.name), totals);
+    }
+}
+
+/// File of globs left out of the directory description, one per line like exclude globs
+pub const SHELLAI_IGNORE_FILE: &str = ".shellai-ignore";
+
+// Directories that usually hold many generated or downloaded files
+const HEAVY_DIRECTORIES: &[&str] = &[
+    "target", "node_modules", "dist", "build", "out", "vendor", "coverage", "__pycache__", ".venv", "venv", ".next", ".cache",
+];
+
+/// Reads the globs of the `.shellai-ignore` file of a directory
+///
+/// # Arguments
+///
+/// * `root` - The directory holding the file
+///
+/// # Returns
+///
+/// The globs, without blank lines and `#` comments, or none if the file can't be read
+pub fn read_shellai_ignore(root: &Path) -> Vec<String> {
+    fs::read_to_string(root.join(SHELLAI_IGNORE_FILE))
+        .map(|contents| {
+            contents.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Generates a `.shellai-ignore` template from the `.gitignore` of a directory
+///
+/// Only directory-level patterns are kept, those ending with `/` or naming directories that
+/// usually hold many files such as `target` or `node_modules`. Patterns for single files,
+/// such as `*.log` or `.env`, often name files worth describing to the model, so they are left
+/// for the user to add.
+///
+/// # Arguments
+///
+/// * `root` - The directory holding the `.gitignore`
+///
+/// # Returns
+///
+/// The contents of the template, or an error if `.gitignore` can't be read
+pub fn generate_shellai_ignore_from_gitignore(root: &Path) -> Result<String, Box<dyn Error>> {
+    let gitignore = fs::read_to_string(root.join(".gitignore"))
+        .map_err(|e| format!("Could not read .gitignore: {}", e))?;
+
+    let mut template = String::from(
+        "# Globs left out of the directory description sent to the model, one per line.\n\
+         # Generated from .gitignore; globs without a / match names at any depth.\n",
+    );
+    let mut globs: Vec<String> = Vec::new();
+    for line in gitignore.lines().map(str::trim) {
+        // Comments and negations can't be expressed as exclude globs
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let name = line.trim_start_matches('/').trim_end_matches('/');
+        let is_directory = line.ends_with('/') || HEAVY_DIRECTORIES.contains(&name);
+        if is_directory && !name.is_empty() && !globs.iter().any(|glob| glob == name) {
+            globs.push(name.to_string());
+        }
+    }
+    for glob in globs {
+        template.push_str(&glob);
+        template.push('\n');
+    }
+    Ok(template)
+}
+
+// The parts of `Cargo.lock` needed to find the direct dependencies
+#[derive(Debug, Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    // Only set for packages that don't belong to the project, such as those from crates.io
+    source: Option<String>,
+    // Written as "name", or "name version" when several versions are locked
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// Reads the versions of a Rust project's direct dependencies from its `Cargo.lock`
+///
+/// The direct dependencies are those of the project's own packages, every member of a
+/// workspace, as opposed to the dependencies they pull in.
+///
+/// # Arguments
+///
+/// * `root` - The directory holding `Cargo.lock`
+///
+/// # Returns
+///
+/// The name and locked version of each direct dependency in the order of the lock file,
+/// none if there is no `Cargo.lock`, or an error if it can't be read or parsed
+pub fn parse_cargo_lock_deps(root: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let path = root.join("Cargo.lock");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let lock: CargoLock = toml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| format!("Could not parse Cargo.lock: {}", e))?;
+
+    let local: Vec<&LockedPackage> = lock
+        .package
+        .iter()
+        .filter(|package| package.source.is_none())
+        .collect();
+    let mut deps: Vec<(String, String)> = Vec::new();
+    for dependency in local.iter().flat_map(|package| &package.dependencies) {
+        let mut parts = dependency.split_whitespace();
+        let Some(name) = parts.next() else {
+            continue;
+        };
+        if local.iter().any(|package| package.name == name) {
+            continue;
+        }
+        // Without a version in the entry, only one version of the package is locked
+        let version = match parts.next() {
+            Some(version) => version.to_string(),
+            None => match lock.package.iter().find(|package| package.name == name) {
+                Some(package) => package.version.clone(),
+                None => continue,
+            },
+        };
+        if !deps.iter().any(|(known, _)| known == name) {
+            deps.push((name.to_string(), version));
+        }
+    }
+    Ok(deps)
+}
+
+// Answers of `is_gitignored` and `gitignored_paths`, kept for the rest of the session
+static GITIGNORE_CACHE: OnceLock<Mutex<HashMap<(PathBuf, PathBuf), bool>>> = OnceLock::new();
+
+/// Checks whether git ignores a path
+///
+/// Runs `git check-ignore` from `root`, so the repository's `.gitignore` files, its
+/// `info/exclude` and the user's global excludes all apply. Results are cached for the
+/// session.
+///
+/// # Arguments
+///
+/// * `root` - The directory to run git from, inside the repository
+/// * `path` - The path to check
+///
+/// # Returns
+///
+/// True if git ignores the path, false if it doesn't, `root` isn't in a repository or git
+/// couldn't be run
+pub fn is_gitignored(root: &Path, path: &Path) -> bool {
+    gitignored_paths(root, &[path.to_path_buf()]).contains(path)
+}
+
+/// Checks which of several paths git ignores, with a single `git check-ignore` run
+///
+/// Works like `is_gitignored`, sharing its cache, so only paths not asked about before are
+/// passed to git.
+///
+/// # Arguments
+///
+/// * `root` - The directory to run git from, inside the repository
+/// * `paths` - The paths to check, relative to `root` or absolute
+///
+/// # Returns
+///
+/// The paths git ignores, empty if `root` isn't in a repository or git couldn't be run
+pub fn gitignored_paths(root: &Path, paths: &[PathBuf]) -> HashSet<PathBuf> {
+    let cache = GITIGNORE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut ignored = HashSet::new();
+    let mut unknown = Vec::new();
+    {
+        let cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        for path in paths {
+            match cache.get(&(root.to_path_buf(), path.clone())) {
+                Some(true) => {
+                    ignored.insert(path.clone());
+                }
+                Some(false) => {}
+                None => unknown.push(path.clone()),
             }
         }
-        
-        // Sort directories and files for consistent output
-        dirs.sort();
-        files.sort();
-        
-        // Add directories first
-        for dir in dirs {
-            let indent = "  ".repeat(current_depth);
-            result.push_str(&format!("{}📁 {}/\n", indent, dir));
-            
-            let subdir_path = path.join(&dir);
-            let subdir_content = scan_directory(&subdir_path, max_depth, current_depth + 1)?;
-            result.push_str(&subdir_content);
+    }
+    if unknown.is_empty() {
+        return ignored;
+    }
+
+    let found = check_ignore(root, &unknown).unwrap_or_default();
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    for path in unknown {
+        let is_ignored = found.contains(&path);
+        cache.insert((root.to_path_buf(), path.clone()), is_ignored);
+        if is_ignored {
+            ignored.insert(path);
         }
-        
-        // Then add files
-        for file in files {
-            let indent = "  ".repeat(current_depth);
-            result.push_str(&format!("{}📄 {}\n", indent, file));
+    }
+    ignored
+}
+
+// Run `git check-ignore --stdin` from `root` on the paths, returning those it reports as
+// ignored, or None if git couldn't be run or `root` isn't in a repository
+fn check_ignore(root: &Path, paths: &[PathBuf]) -> Option<HashSet<PathBuf>> {
+    let mut child = Command::new("git")
+        .args(["check-ignore", "--stdin", "-z"])
+        .current_dir(root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Paths are written from another thread so git can't block on a full output pipe
+    let mut input = Vec::new();
+    for path in paths {
+        input.extend_from_slice(path.to_string_lossy().as_bytes());
+        input.push(0);
+    }
+    let mut stdin = child.stdin.take()?;
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let mut output = Vec::new();
+    child.stdout.take()?.read_to_end(&mut output).ok()?;
+    let _ = writer.join();
+    // Exit code 0 means some paths are ignored, 1 none and 128 an error such as not being in a repository
+    if child.wait().ok()?.code()? > 1 {
+        return None;
+    }
+
+    Some(output
+        .split(|&byte| byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+        .collect())
+}
+
+/// Builds an `ls -la`-style listing of the entries directly inside a directory
+///
+/// # Arguments
+///
+/// * `path` - The directory to list
+/// * `options` - Scan options (`show_hidden`, `respect_gitignore` and the include and
+///   exclude globs apply to the listing)
+///
+/// # Returns
+///
+/// One line per entry with permissions, size, modification date and name
+pub fn ls_style_listing(path: &Path, options: &ScanOptions) -> Result<String, Box<dyn Error>> {
+    let filter = PathFilter::for_directory(path, options)?;
+    let mut entries = Vec::new();
+
+    let dir_entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+    let names: Vec<PathBuf> = dir_entries.iter().map(|entry| PathBuf::from(entry.file_name())).collect();
+    let ignored = filter.ignored_in(path, &names);
+
+    for entry in dir_entries {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if !options.show_hidden && file_name.starts_with('.') {
+            continue;
+        }
+
+        if ignored.contains(Path::new(&entry.file_name())) {
+            continue;
         }
+
+        if !filter.allows(Path::new(&file_name), entry.path().is_dir()) {
+            continue;
+        }
+
+        // Don't follow symlinks so they are listed as links, like ls does
+        let metadata = fs::symlink_metadata(entry.path())?;
+        let modified: DateTime<Local> = metadata.modified()?.into();
+
+        entries.push((
+            format_permissions(&metadata),
+            metadata.len(),
+            modified.format("%b %e %H:%M").to_string(),
+            file_name,
+        ));
     }
-    
+
+    entries.sort_by(|a, b| a.3.cmp(&b.3));
+
+    // Right-align sizes like ls does
+    let size_width = entries
+        .iter()
+        .map(|(_, size, _, _)| size.to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    let mut result = String::new();
+    for (permissions, size, modified, name) in entries {
+        result.push_str(&format!(
+            "{} {:>width$} {} {}\n",
+            permissions,
+            size,
+            modified,
+            name,
+            width = size_width
+        ));
+    }
+
     Ok(result)
 }
 
+/// Formats file metadata as an `ls`-style permission string such as `drwxr-xr-x`
+#[cfg(unix)]
+fn format_permissions(metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file_type = if metadata.file_type().is_symlink() {
+        'l'
+    } else if metadata.is_dir() {
+        'd'
+    } else {
+        '-'
+    };
+
+    let mode = metadata.permissions().mode();
+    let mut result = String::with_capacity(10);
+    result.push(file_type);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        result.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        result.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        result.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    result
+}
+
+/// Formats file metadata as an `ls`-style permission string such as `drw-rw-rw-`
+#[cfg(not(unix))]
+fn format_permissions(metadata: &fs::Metadata) -> String {
+    let file_type = if metadata.is_dir() { 'd' } else { '-' };
+    let mode = if metadata.permissions().readonly() {
+        "r--r--r--"
+    } else {
+        "rw-rw-rw-"
+    };
+    format!("{}{}", file_type, mode)
+}
+
 /// Gets the current working directory
 ///
 /// # Returns
@@ -84,11 +923,13 @@ pub fn get_current_directory() -> Result<PathBuf, Box<dyn Error>> {
 /// # Arguments
 ///
 /// * `base_prompt` - The base system prompt to enhance with directory information
+/// * `options` - Options controlling the directory scan
+/// * `format` - Whether to describe the directory as a tree, a listing, or both
 ///
 /// # Returns
 ///
 /// An enhanced system prompt with directory information
-pub fn build_directory_aware_prompt(base_prompt: &str) -> Result<String, Box<dyn Error>> {
+pub fn build_directory_aware_prompt(base_prompt: &str, options: &ScanOptions, format: ContextFormat) -> Result<String, Box<dyn Error>> {
     let current_dir = get_current_directory()?;
     let dir_name = current_dir.file_name()
         .map(|name| name.to_string_lossy().to_string())
@@ -96,13 +937,30 @@ pub fn build_directory_aware_prompt(base_prompt: &str) -> Result<String, Box<dyn
     
     let dir_path = current_dir.to_string_lossy().to_string();
     
-    // Scan the directory structure (limit depth to 2 to avoid overwhelming output)
-    let dir_tree = scan_directory(&current_dir, 2, 0)?;
+    // Describe the directory in the configured format
+    let dir_context = match format {
+        ContextFormat::Tree => format!(
+            "Directory structure:\n{}",
+            scan_directory_with_options(&current_dir, options)?
+        ),
+        ContextFormat::Ls => format!(
+            "Directory listing:\n{}",
+            ls_style_listing(&current_dir, options)?
+        ),
+        ContextFormat::Combined => format!(
+            "Directory listing:\n{}\nDirectory structure:\n{}",
+            ls_style_listing(&current_dir, options)?,
+            scan_directory_with_options(&current_dir, options)?
+        ),
+        // Without a scan there is no structure for the guidelines to point at
+        ContextFormat::None => {
+            return Ok(format!("Current working directory: {}\nDirectory name: {}\n\n{}", dir_path, dir_name, base_prompt));
+        }
+    };
     
     let prompt = format!(r#"Current working directory: {}
 Directory name: {}
 
-Directory structure:
 {}
 
 {}
@@ -110,7 +968,7 @@ Directory structure:
 Additional guidelines:
 - Be aware of the current directory structure shown above when suggesting commands.
 - When referencing files or directories, use the correct paths based on the current directory."#, 
-        dir_path, dir_name, dir_tree, base_prompt);
+        dir_path, dir_name, dir_context, base_prompt);
 
     Ok(prompt)
 }
@@ -174,11 +1032,472 @@ mod tests {
         assert!(!limited_result.contains("📄 file2.txt"));
     }
 
+    // Create a file node for tree assertions
+    fn file_node(name: &str) -> DirNode {
+        DirNode { name: name.to_string(), is_dir: false, children: Vec::new(), truncated: false, size: 0, link_target: None }
+    }
+
+    #[test]
+    fn test_scan_directory_tree() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+
+        fs::create_dir_all(temp_path.join("src/nested")).expect("Failed to create directories");
+        File::create(temp_path.join("src/nested/deep.rs")).expect("Failed to create file");
+        File::create(temp_path.join("src/main.rs")).expect("Failed to create file");
+        File::create(temp_path.join("README.md")).expect("Failed to create file");
+        File::create(temp_path.join(".env")).expect("Failed to create file");
+
+        let tree = scan_directory_tree(temp_path, 1).expect("Failed to scan directory");
+        assert!(tree.is_dir);
+        assert_eq!(
+            tree.children,
+            vec![
+                DirNode {
+                    name: "src".to_string(),
+                    is_dir: true,
+                    children: vec![
+                        DirNode { name: "nested".to_string(), is_dir: true, children: Vec::new(), truncated: true, size: 0, link_target: None },
+                        file_node("main.rs"),
+                    ],
+                    truncated: false,
+                    size: 0,
+                    link_target: None,
+                },
+                file_node("README.md"),
+            ]
+        );
+
+        // With depth 0 only the top level is read
+        let tree = scan_directory_tree(temp_path, 0).expect("Failed to scan directory");
+        assert_eq!(tree.children[0].children, Vec::new());
+        assert!(tree.children[0].truncated);
+
+        // Deep enough, nothing is truncated
+        let tree = scan_directory_tree(temp_path, 2).expect("Failed to scan directory");
+        assert_eq!(tree.children[0].children[0].children, vec![file_node("deep.rs")]);
+        assert!(!tree.children[0].children[0].truncated);
+
+        assert_eq!(
+            render_tree(&scan_directory_tree(temp_path, 1).expect("Failed to scan directory").children, 0),
+            "📁 src/\n  📁 nested/\n    ...\n  📄 main.rs\n📄 README.md\n"
+        );
+    }
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .status()
+            .expect("Failed to run git");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_file_change_frequency() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+        fs::create_dir(temp_path.join("src")).expect("Failed to create directory");
+        fs::create_dir(temp_path.join("docs")).expect("Failed to create directory");
+        run_git(temp_path, &["init", "-q"]);
+        for (i, files) in [vec!["docs/guide.md", "src/lib.rs", "a.txt"], vec!["src/lib.rs", "src/main.rs"], vec!["src/lib.rs"]].iter().enumerate() {
+            for file in files {
+                fs::write(temp_path.join(file), i.to_string()).expect("Failed to write file");
+            }
+            run_git(temp_path, &["add", "."]);
+            run_git(temp_path, &["commit", "-q", "-m", "change"]);
+        }
+
+        let frequencies = file_change_frequency(temp_path, 30).expect("Failed to count changes");
+        assert_eq!(frequencies.get(Path::new("src/lib.rs")), Some(&3));
+        assert_eq!(frequencies.get(Path::new("src/main.rs")), Some(&1));
+        assert_eq!(frequencies.get(Path::new("a.txt")), Some(&1));
+
+        // Paths are relative to the directory the changes are counted in
+        let frequencies = file_change_frequency(&temp_path.join("src"), 30).expect("Failed to count changes");
+        assert_eq!(frequencies.len(), 2);
+        assert_eq!(frequencies.get(Path::new("lib.rs")), Some(&3));
+
+        // src changed 4 times and docs once, so src comes first; lib.rs before main.rs
+        let options = ScanOptions { change_frequency_days: Some(30), ..ScanOptions::default() };
+        let tree = scan_directory_with_options(temp_path, &options).expect("Failed to scan directory");
+        assert!(tree.find("src/").unwrap() < tree.find("docs/").unwrap());
+        assert!(tree.find("lib.rs").unwrap() < tree.find("main.rs").unwrap());
+
+        let not_a_repo = tempdir().expect("Failed to create temp directory");
+        assert!(file_change_frequency(not_a_repo.path(), 30).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_does_not_follow_symlink_cycles() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+        fs::create_dir(temp_path.join("a")).expect("Failed to create directory");
+        File::create(temp_path.join("a/file.txt")).expect("Failed to create file");
+        // a/loop points back at the scanned directory
+        std::os::unix::fs::symlink("..", temp_path.join("a/loop")).expect("Failed to create symlink");
+
+        let tree = scan_directory_tree(temp_path, 50).expect("Failed to scan directory");
+        let link = &tree.children[0].children[1];
+        assert_eq!(link.name, "loop");
+        assert!(!link.is_dir);
+        assert!(link.children.is_empty());
+        assert_eq!(link.link_target.as_deref(), Some(".."));
+
+        assert_eq!(
+            render_tree(&tree.children, 0),
+            "📁 a/\n  📄 file.txt\n  🔗 loop -> ..\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tree_in_ascii() {
+        let nodes = vec![
+            DirNode { name: "src".to_string(), is_dir: true, children: vec![file_node("main.rs")], truncated: false, size: 0, link_target: None },
+            file_node("README.md"),
+        ];
+        assert_eq!(
+            render_nodes(&nodes, 0, false, &accessible::ASCII),
+            "[DIR] src/\n  [FILE] main.rs\n[FILE] README.md\n"
+        );
+    }
+
+    #[test]
+    fn test_render_tree_with_lines() {
+        let nodes = vec![
+            DirNode {
+                name: "src".to_string(),
+                is_dir: true,
+                children: vec![
+                    DirNode { name: "utils".to_string(), is_dir: true, children: vec![file_node("mod.rs")], truncated: false, size: 0, link_target: None },
+                    DirNode { name: "target".to_string(), is_dir: true, children: Vec::new(), truncated: true, size: 0, link_target: None },
+                    file_node("main.rs"),
+                ],
+                truncated: false,
+                size: 0,
+                link_target: None,
+            },
+            DirNode { name: "docs".to_string(), is_dir: true, children: vec![file_node("guide.md")], truncated: false, size: 0, link_target: None },
+            file_node("README.md"),
+        ];
+        assert_eq!(
+            render_lines(&nodes, "", false, &accessible::UNICODE),
+            "├── src/\n│   ├── utils/\n│   │   └── mod.rs\n│   ├── target/\n│   │   └── ...\n│   └── main.rs\n├── docs/\n│   └── guide.md\n└── README.md\n"
+        );
+        assert_eq!(render_lines(&nodes[1..], "", false, &accessible::ASCII), "+-- docs/\n|   +-- guide.md\n+-- README.md\n");
+    }
+
+    #[test]
+    fn test_scan_with_tree_style_lines() {
+        let temp_dir = project_tree();
+        let options = ScanOptions { max_depth: 1, tree_style: TreeStyle::Lines, exclude_globs: vec!["target".to_string(), "docs".to_string()], ..ScanOptions::default() };
+        let tree = scan_directory_with_options(temp_dir.path(), &options).expect("Failed to scan directory");
+        assert_eq!(tree, "├── src/\n│   ├── utils/\n│   │   └── ...\n│   └── main.rs\n└── Cargo.toml\n");
+    }
+
+    // Create a small source tree with build output and docs next to the code
+    fn project_tree() -> tempfile::TempDir {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+        for dir in ["src/utils", "target/debug", "docs"] {
+            fs::create_dir_all(temp_path.join(dir)).expect("Failed to create directories");
+        }
+        for file in ["src/main.rs", "src/utils/mod.rs", "src/utils/notes.md", "target/debug/build.rs", "docs/guide.md", "Cargo.toml"] {
+            File::create(temp_path.join(file)).expect("Failed to create file");
+        }
+        temp_dir
+    }
+
+    #[test]
+    fn test_scan_include_globs() {
+        let temp_dir = project_tree();
+        let options = ScanOptions { include_globs: vec!["*.rs".to_string()], ..ScanOptions::default() };
+
+        // Only Rust files survive, and directories without any are left out
+        assert_eq!(
+            scan_directory_with_options(temp_dir.path(), &options).expect("Failed to scan directory"),
+            "📁 src/\n  📁 utils/\n    📄 mod.rs\n  📄 main.rs\n📁 target/\n  📁 debug/\n    📄 build.rs\n"
+        );
+
+        // Globs with a separator match the path relative to the scanned directory
+        let options = ScanOptions { include_globs: vec!["src/*.rs".to_string()], ..ScanOptions::default() };
+        assert_eq!(
+            scan_directory_with_options(temp_dir.path(), &options).expect("Failed to scan directory"),
+            "📁 src/\n  📄 main.rs\n"
+        );
+    }
+
+    #[test]
+    fn test_scan_exclude_globs_win_over_includes() {
+        let temp_dir = project_tree();
+        let options = ScanOptions {
+            include_globs: vec!["*.rs".to_string(), "*.md".to_string()],
+            exclude_globs: vec!["target".to_string(), "notes.md".to_string()],
+            ..ScanOptions::default()
+        };
+
+        assert_eq!(
+            scan_directory_with_options(temp_dir.path(), &options).expect("Failed to scan directory"),
+            "📁 docs/\n  📄 guide.md\n📁 src/\n  📁 utils/\n    📄 mod.rs\n  📄 main.rs\n"
+        );
+
+        // The listing of the top level applies the same globs
+        let listing = ls_style_listing(temp_dir.path(), &options).expect("Failed to list directory");
+        let names: Vec<&str> = listing.lines().filter_map(|line| line.split_whitespace().last()).collect();
+        assert_eq!(names, vec!["docs", "src"]);
+    }
+
+    #[test]
+    fn test_invalid_glob_is_an_error() {
+        let options = ScanOptions { exclude_globs: vec!["[".to_string()], ..ScanOptions::default() };
+        assert!(PathFilter::from_options(&options).is_err());
+        assert!(scan_directory_with_options(Path::new("."), &options).is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(2355), "2.3 KB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 MB");
+        assert_eq!(format_size(1024 * 1024), "1.0 MB");
+        assert_eq!(format_size(1536 * 1024), "1.5 MB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+
+    #[test]
+    fn test_scan_shows_sizes_when_enabled() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+        fs::create_dir_all(temp_path.join("logs/old")).expect("Failed to create directories");
+        fs::write(temp_path.join("logs/app.log"), vec![b'a'; 2048]).expect("Failed to write");
+        fs::write(temp_path.join("logs/old/app.log"), vec![b'a'; 4096]).expect("Failed to write");
+        fs::write(temp_path.join("notes.txt"), "hello").expect("Failed to write");
+
+        let options = ScanOptions { max_depth: 0, ..ScanOptions::default() };
+        assert!(!scan_directory_with_options(temp_path, &options).expect("Failed to scan directory").contains(" B"));
+
+        // A truncated directory gets no subtotal, as its files weren't all counted
+        let options = ScanOptions { max_depth: 0, show_sizes: true, ..ScanOptions::default() };
+        assert_eq!(
+            scan_directory_with_options(temp_path, &options).expect("Failed to scan directory"),
+            "📁 logs/\n  ...\n📄 notes.txt (5 B)\nTotal of the files shown: 5 B\n"
+        );
+
+        let options = ScanOptions { show_sizes: true, ..ScanOptions::default() };
+        assert_eq!(
+            scan_directory_with_options(temp_path, &options).expect("Failed to scan directory"),
+            "📁 logs/ (6.0 KB)\n  📁 old/ (4.0 KB)\n    📄 app.log (4.0 KB)\n  📄 app.log (2.0 KB)\n📄 notes.txt (5 B)\nTotal of the files shown: 6.0 KB\n"
+        );
+    }
+
+    #[test]
+    fn test_ls_style_listing() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let temp_path = temp_dir.path();
+
+        fs::create_dir(temp_path.join("subdir")).expect("Failed to create subdir");
+        File::create(temp_path.join("file.txt")).and_then(|mut f| f.write_all(b"12345")).expect("Failed to create file");
+        File::create(temp_path.join(".hidden")).expect("Failed to create hidden file");
+
+        let result = ls_style_listing(temp_path, &ScanOptions::default()).expect("Failed to list directory");
+        let lines: Vec<&str> = result.lines().collect();
+
+        // Entries are sorted by name, hidden entries are skipped by default
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" file.txt"));
+        assert!(lines[0].starts_with('-'));
+        assert!(lines[0].contains(" 5 "));
+        assert!(lines[1].ends_with(" subdir"));
+        assert!(lines[1].starts_with('d'));
+
+        let options = ScanOptions { show_hidden: true, ..ScanOptions::default() };
+        let result = ls_style_listing(temp_path, &options).expect("Failed to list directory");
+        assert!(result.contains(".hidden"));
+    }
+
+    // Create a git repository that ignores log files
+    fn git_repo_with_ignored_logs() -> tempfile::TempDir {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(temp_dir.path())
+            .status()
+            .expect("Failed to run git");
+        assert!(status.success());
+        fs::write(temp_dir.path().join(".gitignore"), "*.log\n").expect("Failed to write .gitignore");
+        File::create(temp_dir.path().join("debug.log")).expect("Failed to create file");
+        File::create(temp_dir.path().join("main.rs")).expect("Failed to create file");
+        temp_dir
+    }
+
+    #[test]
+    fn test_parse_cargo_lock_deps() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        assert!(parse_cargo_lock_deps(temp_dir.path()).unwrap().is_empty());
+
+        fs::write(
+            temp_dir.path().join("Cargo.lock"),
+            r#"version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "core-lib",
+ "serde",
+ "tokio",
+]
+
+[[package]]
+name = "core-lib"
+version = "0.1.0"
+dependencies = [
+ "serde",
+ "syn 2.0.66",
+]
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "serde_derive",
+]
+
+[[package]]
+name = "serde_derive"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "syn"
+version = "1.0.109"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "syn"
+version = "2.0.66"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "tokio"
+version = "1.38.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#,
+        )
+        .expect("Failed to write Cargo.lock");
+
+        // Workspace members aren't dependencies, and neither are the dependencies of others
+        assert_eq!(
+            parse_cargo_lock_deps(temp_dir.path()).unwrap(),
+            [
+                ("serde".to_string(), "1.0.200".to_string()),
+                ("tokio".to_string(), "1.38.0".to_string()),
+                ("syn".to_string(), "2.0.66".to_string()),
+            ]
+        );
+
+        fs::write(
+            temp_dir.path().join("Cargo.lock"),
+            "[[package]]\nname = 1\n",
+        )
+        .unwrap();
+        assert!(parse_cargo_lock_deps(temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_generate_shellai_ignore_from_gitignore() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let root = temp_dir.path();
+        assert!(generate_shellai_ignore_from_gitignore(root).is_err());
+
+        fs::write(root.join(".gitignore"), "# Build output\n/target\nnode_modules/\n*.log\n.env\n!dist/keep\ndist/\n/coverage/\n*.egg-info/\ntarget/\n").expect("Failed to write .gitignore");
+        let template = generate_shellai_ignore_from_gitignore(root).expect("Failed to generate template");
+        let globs: Vec<&str> = template.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(globs, vec!["target", "node_modules", "dist", "coverage", "*.egg-info"]);
+
+        // Once written, the template hides the directories from the scan
+        fs::create_dir(root.join("node_modules")).expect("Failed to create directory");
+        File::create(root.join("node_modules/index.js")).expect("Failed to create file");
+        File::create(root.join("main.rs")).expect("Failed to create file");
+        fs::write(root.join(SHELLAI_IGNORE_FILE), &template).expect("Failed to write template");
+        assert_eq!(read_shellai_ignore(root).len(), 5);
+        let tree = scan_directory_with_options(root, &ScanOptions::default()).expect("Failed to scan directory");
+        assert!(tree.contains("main.rs"));
+        assert!(!tree.contains("node_modules"));
+    }
+
+    #[test]
+    fn test_is_gitignored() {
+        let temp_dir = git_repo_with_ignored_logs();
+        let root = temp_dir.path();
+
+        assert!(is_gitignored(root, &root.join("debug.log")));
+        assert!(!is_gitignored(root, &root.join("main.rs")));
+        // Asking again is answered from the cache
+        assert!(is_gitignored(root, &root.join("debug.log")));
+
+        // Outside a repository nothing is ignored
+        let plain_dir = tempdir().expect("Failed to create temp directory");
+        assert!(!is_gitignored(plain_dir.path(), &plain_dir.path().join("debug.log")));
+    }
+
+    #[test]
+    fn test_ls_style_listing_respects_gitignore() {
+        let temp_dir = git_repo_with_ignored_logs();
+
+        let result = ls_style_listing(temp_dir.path(), &ScanOptions::default()).expect("Failed to list directory");
+        assert!(result.contains("debug.log"));
+
+        let options = ScanOptions { respect_gitignore: true, ..ScanOptions::default() };
+        let result = ls_style_listing(temp_dir.path(), &options).expect("Failed to list directory");
+        assert!(!result.contains("debug.log"));
+        assert!(result.contains("main.rs"));
+    }
+
+    #[test]
+    fn test_tree_respects_gitignore() {
+        let temp_dir = git_repo_with_ignored_logs();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("logs")).expect("Failed to create directory");
+        File::create(root.join("logs").join("server.log")).expect("Failed to create file");
+        File::create(root.join("logs").join("README.md")).expect("Failed to create file");
+
+        let tree = scan_directory_with_options(root, &ScanOptions::default()).expect("Failed to scan directory");
+        assert!(tree.contains("debug.log"));
+        assert!(tree.contains("server.log"));
+
+        let options = ScanOptions { respect_gitignore: true, ..ScanOptions::default() };
+        let tree = scan_directory_with_options(root, &options).expect("Failed to scan directory");
+        assert!(!tree.contains("debug.log"));
+        assert!(!tree.contains("server.log"));
+        assert!(tree.contains("README.md"));
+        assert!(tree.contains("main.rs"));
+    }
+
+    #[test]
+    fn test_gitignored_paths() {
+        let temp_dir = git_repo_with_ignored_logs();
+        let root = temp_dir.path();
+
+        let paths = [PathBuf::from("debug.log"), PathBuf::from("main.rs"), PathBuf::from("other.log")];
+        let ignored = gitignored_paths(root, &paths);
+        assert_eq!(ignored, HashSet::from([PathBuf::from("debug.log"), PathBuf::from("other.log")]));
+
+        let plain_dir = tempdir().expect("Failed to create temp directory");
+        assert!(gitignored_paths(plain_dir.path(), &paths).is_empty());
+    }
+
     #[test]
     fn test_build_directory_aware_prompt() {
         // This is a basic test to ensure the function runs without errors
         let base_prompt = "This is a test prompt.";
-        let result = build_directory_aware_prompt(base_prompt);
+        let result = build_directory_aware_prompt(base_prompt, &ScanOptions::default(), ContextFormat::Tree);
         assert!(result.is_ok());
         
         let prompt = result.unwrap();
@@ -186,5 +1505,24 @@ mod tests {
         assert!(prompt.contains("Directory name:"));
         assert!(prompt.contains("Directory structure:"));
         assert!(prompt.contains(base_prompt));
+        assert!(!prompt.contains("Directory listing:"));
+    }
+
+    #[test]
+    fn test_build_directory_aware_prompt_combined() {
+        let prompt = build_directory_aware_prompt("Base.", &ScanOptions::default(), ContextFormat::Combined)
+            .expect("Failed to build prompt");
+        assert!(prompt.contains("Directory listing:"));
+        assert!(prompt.contains("Directory structure:"));
+    }
+
+    #[test]
+    fn test_build_directory_aware_prompt_without_scan() {
+        let prompt = build_directory_aware_prompt("Base.", &ScanOptions::default(), ContextFormat::None)
+            .expect("Failed to build prompt");
+        assert!(prompt.contains("Current working directory:"));
+        assert!(prompt.ends_with("Base."));
+        assert!(!prompt.contains("Directory structure:"));
+        assert!(!prompt.contains("Directory listing:"));
     }
 }
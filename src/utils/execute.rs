@@ -0,0 +1,646 @@
+// Execution of bash code blocks suggested by the model
+
+use crate::utils::risk::is_auto_approved;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What the user chose to do with a suggested code block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionChoice {
+    /// Run the code
+    Run,
+    /// Run the code and every block after it without asking, see `run_batch`
+    RunAll,
+    /// Ask the model to explain the code without running it
+    Explain,
+    /// Leave the code alone
+    Skip,
+}
+
+impl ExecutionChoice {
+    /// Parses the answer to the execution prompt, anything unrecognised skips the code
+    pub fn parse(input: &str) -> Self {
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" => ExecutionChoice::Run,
+            "a" | "all" => ExecutionChoice::RunAll,
+            "x" | "explain" => ExecutionChoice::Explain,
+            _ => ExecutionChoice::Skip,
+        }
+    }
+}
+
+/// Result of acting on an `ExecutionChoice`
+#[derive(Debug)]
+pub enum ExecutionOutcome {
+    /// The code ran with this output
+    Ran(Output),
+    /// The model explained the code, which didn't run
+    Explained(String),
+    /// Nothing was done
+    Skipped,
+}
+
+/// Runs code blocks, so tests can check what would be executed
+pub trait CommandRunner {
+    fn run(&self, code: &str) -> io::Result<Output>;
+}
+
+/// Runs code blocks with `bash -c`
+///
+/// The code inherits ShellAI's environment, with the runner's variables set on top of it,
+/// so they win over inherited variables of the same name. It runs in ShellAI's current
+/// directory unless the runner has a directory of its own.
+#[derive(Debug, Clone, Default)]
+pub struct BashRunner {
+    env: HashMap<String, String>,
+    current_dir: Option<PathBuf>,
+    auto_approve: Vec<String>,
+    sqlite_database: Option<PathBuf>,
+    execute_all: bool,
+    continue_on_error: bool,
+    runs: Arc<Mutex<Vec<CommandRun>>>,
+}
+
+/// How long code run by a `BashRunner` took, and how it exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandRun {
+    /// Time from starting bash to its exit
+    pub duration: Duration,
+    /// The exit code, None if a signal stopped the code
+    pub exit_code: Option<i32>,
+}
+
+impl BashRunner {
+    /// Creates a runner setting extra environment variables, such as `[env]` from `config.toml`
+    pub fn with_env(env: HashMap<String, String>) -> Self {
+        BashRunner {
+            env,
+            current_dir: None,
+            auto_approve: Vec::new(),
+            sqlite_database: None,
+            execute_all: false,
+            continue_on_error: false,
+            runs: Arc::default(),
+        }
+    }
+
+    /// Runs the code in `dir`, checked with `working_directory`, instead of the current directory
+    pub fn with_current_dir(mut self, dir: PathBuf) -> Self {
+        self.current_dir = Some(dir);
+        self
+    }
+
+    /// Lets code made only of commands starting with these prefixes, such as `ls` or
+    /// `git status`, run without asking, see `is_auto_approved`
+    pub fn with_auto_approve(mut self, prefixes: Vec<String>) -> Self {
+        self.auto_approve = prefixes;
+        self
+    }
+
+    /// Whether a code block may run without asking for confirmation
+    pub fn auto_approves(&self, code: &str) -> bool {
+        is_auto_approved(code, &self.auto_approve)
+    }
+
+    /// Runs SQLite queries on this database file instead of an in-memory database
+    pub fn with_sqlite_database(mut self, path: Option<PathBuf>) -> Self {
+        self.sqlite_database = path;
+        self
+    }
+
+    /// The database file SQLite queries run on, see `sql_command`
+    pub fn sqlite_database(&self) -> Option<&Path> {
+        self.sqlite_database.as_deref()
+    }
+
+    /// Runs all the bash blocks of a response in a row without asking, as with
+    /// `--execute-all`, unless one of them is dangerous
+    pub fn with_execute_all(mut self, execute_all: bool) -> Self {
+        self.execute_all = execute_all;
+        self
+    }
+
+    /// Whether all the bash blocks of a response run without asking
+    pub fn executes_all(&self) -> bool {
+        self.execute_all
+    }
+
+    /// Keeps running the blocks of a batch after one fails, as with `--continue-on-error`
+    pub fn with_continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Whether a batch keeps going after a block fails, see `run_batch`
+    pub fn continues_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    /// Gets how long the latest code run by this runner or its clones took, None if no
+    /// code ran since `take_runs`
+    pub fn last_run(&self) -> Option<CommandRun> {
+        let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.last().copied()
+    }
+
+    /// Takes the timings of the code run so far, oldest first, so they can be recorded in
+    /// the session
+    pub fn take_runs(&self) -> Vec<CommandRun> {
+        std::mem::take(&mut *self.runs.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl CommandRunner for BashRunner {
+    fn run(&self, code: &str) -> io::Result<Output> {
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(code).envs(&self.env);
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+
+        let started = Instant::now();
+        let output = command.output();
+        if let Ok(output) = &output {
+            let run = CommandRun {
+                duration: started.elapsed(),
+                exit_code: output.status.code(),
+            };
+            self.runs
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(run);
+        }
+        output
+    }
+}
+
+/// Checks a directory to run code blocks in, such as the one given with `--cwd`
+///
+/// # Arguments
+///
+/// * `path` - The directory, absolute or relative to the current directory
+///
+/// # Returns
+///
+/// The absolute path of the directory, or a message saying why it can't be used
+pub fn working_directory(path: &Path) -> Result<PathBuf, String> {
+    let dir = path
+        .canonicalize()
+        .map_err(|e| format!("{} doesn't exist: {}", path.display(), e))?;
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", path.display()));
+    }
+    Ok(dir)
+}
+
+/// Builds the prompt asking the model to explain a code block line by line
+pub fn build_explain_prompt(bash_code: &str) -> String {
+    format!(
+        "Explain this command line by line: what each part does, what it changes on my \
+         system and anything risky about it. Don't run it and don't suggest other commands.\n\n\
+         ```bash\n{}\n```",
+        bash_code
+    )
+}
+
+/// Shortens command output to at most `max_bytes`, keeping its beginning and end
+///
+/// The end of the output usually holds the error, so half of the limit goes to each side
+/// with a note of how much was left out in between.
+pub fn truncate_output(output: &str, max_bytes: usize) -> String {
+    if output.len() <= max_bytes {
+        return output.to_string();
+    }
+
+    // Cut on character boundaries so multi-byte characters stay intact
+    let mut head_end = max_bytes / 2;
+    while !output.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let mut tail_start = output.len() - (max_bytes - head_end);
+    while !output.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+
+    format!(
+        "{}\n[... {} bytes truncated ...]\n{}",
+        &output[..head_end],
+        tail_start - head_end,
+        &output[tail_start..]
+    )
+}
+
+/// Builds the message that sends the result of an executed code block back to the model
+///
+/// # Arguments
+///
+/// * `bash_code` - The code that was run
+/// * `output` - Its captured output and exit status
+/// * `max_bytes` - Limit applied to each of stdout and stderr
+///
+/// # Returns
+///
+/// A user message asking the model to look at the output
+pub fn build_output_follow_up(bash_code: &str, output: &Output, max_bytes: usize) -> String {
+    let status = match output.status.code() {
+        Some(code) => code.to_string(),
+        None => "terminated by a signal".to_string(),
+    };
+
+    let mut message = format!(
+        "I ran this command:\n```bash\n{}\n```\nExit status: {}\n\nHere's the output:\n",
+        bash_code, status
+    );
+    let streams = [("stdout", &output.stdout), ("stderr", &output.stderr)];
+    for (name, bytes) in streams {
+        if !bytes.is_empty() {
+            let text = truncate_output(&String::from_utf8_lossy(bytes), max_bytes);
+            message.push_str(&format!("{}:\n```\n{}\n```\n", name, text.trim_end()));
+        }
+    }
+    if output.stdout.is_empty() && output.stderr.is_empty() {
+        message.push_str("(no output)\n");
+    }
+    message.push_str("\nWhat does this tell us, and what should I do next?");
+    message
+}
+
+/// Most related commands suggested after a code block ran
+pub const MAX_RELATED_COMMANDS: usize = 3;
+
+/// Builds the prompt asking for commands the user might want to run after a code block
+pub fn build_related_commands_prompt(bash_code: &str) -> String {
+    format!(
+        "I just ran this successfully:\n```bash\n{}\n```\n\
+         What are 2-3 related commands I might want to run next? Answer with one command \
+         per line and nothing else: no numbering, explanations or code fences.",
+        bash_code
+    )
+}
+
+/// Extracts the suggested commands from the answer to `build_related_commands_prompt`
+///
+/// Bullets, numbering, backticks and code fences the model adds anyway are removed.
+pub fn parse_related_commands(response: &str) -> Vec<String> {
+    response
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let line = line
+                .strip_prefix("- ")
+                .or_else(|| line.strip_prefix("* "))
+                .unwrap_or(line);
+            // Numbering such as "1. " or "2) "
+            let line = match line.split_once(['.', ')']) {
+                Some((number, rest))
+                    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) =>
+                {
+                    rest.trim_start()
+                }
+                _ => line,
+            };
+            line.trim_matches('`').trim().to_string()
+        })
+        .filter(|line| !line.is_empty() && !line.starts_with("```") && line != "bash")
+        .take(MAX_RELATED_COMMANDS)
+        .collect()
+}
+
+/// Acts on the user's choice for a code block
+///
+/// # Arguments
+///
+/// * `choice` - What the user chose
+/// * `bash_code` - The code block
+/// * `runner` - Runs the code if the choice is `Run`
+/// * `explain` - Sends a prompt to the model if the choice is `Explain`
+///
+/// # Returns
+///
+/// The command output, the explanation, or `Skipped`
+pub async fn apply_choice<R, F, Fut>(
+    choice: ExecutionChoice,
+    bash_code: &str,
+    runner: &R,
+    explain: F,
+) -> Result<ExecutionOutcome, Box<dyn Error>>
+where
+    R: CommandRunner,
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<String, Box<dyn Error>>>,
+{
+    match choice {
+        ExecutionChoice::Run | ExecutionChoice::RunAll => {
+            Ok(ExecutionOutcome::Ran(runner.run(bash_code)?))
+        }
+        ExecutionChoice::Explain => Ok(ExecutionOutcome::Explained(
+            explain(build_explain_prompt(bash_code)).await?,
+        )),
+        ExecutionChoice::Skip => Ok(ExecutionOutcome::Skipped),
+    }
+}
+
+/// How a batch of code blocks run with `run_batch` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// Every block ran, including any that failed when errors don't stop the batch
+    Completed,
+    /// The block at this index failed, so the blocks after it didn't run
+    Aborted(usize),
+}
+
+/// Runs code blocks one after another, without asking
+///
+/// A block fails when it exits with a non-zero status or can't be started, which stops
+/// the batch unless `continue_on_error` is set.
+///
+/// # Arguments
+///
+/// * `blocks` - The code blocks, run in order
+/// * `runner` - Runs each block
+/// * `continue_on_error` - Whether to keep going after a block fails
+/// * `on_result` - Called with the index and the result of each block once it ran
+///
+/// # Returns
+///
+/// Whether every block ran or which one stopped the batch
+pub fn run_batch<R: CommandRunner>(
+    blocks: &[String],
+    runner: &R,
+    continue_on_error: bool,
+    mut on_result: impl FnMut(usize, &io::Result<Output>),
+) -> BatchOutcome {
+    for (i, code) in blocks.iter().enumerate() {
+        let result = runner.run(code);
+        on_result(i, &result);
+        let failed = !result.is_ok_and(|output| output.status.success());
+        if failed && !continue_on_error {
+            return BatchOutcome::Aborted(i);
+        }
+    }
+    BatchOutcome::Completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Records the code it is asked to run instead of running it
+    #[derive(Default)]
+    struct RecordingRunner {
+        runs: RefCell<Vec<String>>,
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&self, code: &str) -> io::Result<Output> {
+            self.runs.borrow_mut().push(code.to_string());
+            BashRunner::default().run("true")
+        }
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(ExecutionChoice::parse("y"), ExecutionChoice::Run);
+        assert_eq!(ExecutionChoice::parse(" Y\n"), ExecutionChoice::Run);
+        assert_eq!(ExecutionChoice::parse("a"), ExecutionChoice::RunAll);
+        assert_eq!(ExecutionChoice::parse("All"), ExecutionChoice::RunAll);
+        assert_eq!(ExecutionChoice::parse("x"), ExecutionChoice::Explain);
+        assert_eq!(ExecutionChoice::parse("n"), ExecutionChoice::Skip);
+        assert_eq!(ExecutionChoice::parse(""), ExecutionChoice::Skip);
+    }
+
+    #[test]
+    fn test_parse_related_commands() {
+        assert_eq!(
+            parse_related_commands("git log --oneline | head -20\ngit status\n"),
+            vec!["git log --oneline | head -20", "git status"]
+        );
+        assert_eq!(
+            parse_related_commands("1. `ls -la`\n2) du -sh .\n- df -h\n* free -m"),
+            vec!["ls -la", "du -sh .", "df -h"]
+        );
+        assert_eq!(
+            parse_related_commands("```bash\nps aux\n```"),
+            vec!["ps aux"]
+        );
+        assert!(parse_related_commands("  \n").is_empty());
+    }
+
+    #[test]
+    fn test_build_explain_prompt() {
+        let prompt = build_explain_prompt("rm -rf ./build");
+        assert!(prompt.contains("```bash\nrm -rf ./build\n```"));
+        assert!(prompt.contains("Don't run it"));
+    }
+
+    #[tokio::test]
+    async fn test_explain_does_not_run_code() {
+        let runner = RecordingRunner::default();
+        let sent_prompt = RefCell::new(String::new());
+
+        let outcome = apply_choice(
+            ExecutionChoice::Explain,
+            "rm -rf ./build",
+            &runner,
+            |prompt| {
+                *sent_prompt.borrow_mut() = prompt;
+                async { Ok("Deletes the build directory.".to_string()) }
+            },
+        )
+        .await
+        .expect("Explaining failed");
+
+        assert!(
+            matches!(outcome, ExecutionOutcome::Explained(text) if text == "Deletes the build directory.")
+        );
+        assert!(sent_prompt.borrow().contains("rm -rf ./build"));
+        assert!(runner.runs.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_executes_code() {
+        let runner = RecordingRunner::default();
+        let outcome = apply_choice(ExecutionChoice::Run, "ls", &runner, |_| async {
+            Err::<String, Box<dyn Error>>("should not explain".into())
+        })
+        .await
+        .expect("Running failed");
+
+        assert!(matches!(outcome, ExecutionOutcome::Ran(_)));
+        assert_eq!(*runner.runs.borrow(), vec!["ls"]);
+    }
+
+    #[test]
+    fn test_runner_sets_configured_variables() {
+        let runner = BashRunner::with_env(HashMap::from([
+            (
+                "SHELLAI_TEST_GREETING".to_string(),
+                "hello from config".to_string(),
+            ),
+            ("HOME".to_string(), "/tmp/shellai-home".to_string()),
+        ]));
+        let output = runner
+            .run("echo \"$SHELLAI_TEST_GREETING\"; echo \"$HOME\"; echo \"$PATH\"")
+            .expect("Failed to run bash");
+
+        // Configured variables win over inherited ones, which are still passed on
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "hello from config");
+        assert_eq!(lines[1], "/tmp/shellai-home");
+        assert!(!lines[2].is_empty());
+    }
+
+    #[test]
+    fn test_runner_uses_working_directory() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp directory");
+        let dir = working_directory(temp_dir.path()).expect("Directory should be accepted");
+
+        let output = BashRunner::default()
+            .with_current_dir(dir.clone())
+            .run("pwd -P")
+            .expect("Failed to run bash");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            dir.to_string_lossy()
+        );
+
+        let file = temp_dir.path().join("notes.txt");
+        std::fs::write(&file, "").expect("Failed to write file");
+        assert!(working_directory(&file)
+            .unwrap_err()
+            .ends_with("is not a directory"));
+        assert!(working_directory(&temp_dir.path().join("missing"))
+            .unwrap_err()
+            .contains("doesn't exist"));
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("short", 100), "short");
+
+        let output = format!("{}{}", "a".repeat(100), "b".repeat(100));
+        let truncated = truncate_output(&output, 20);
+        assert!(truncated.starts_with(&"a".repeat(10)));
+        assert!(truncated.ends_with(&"b".repeat(10)));
+        assert!(truncated.contains("[... 180 bytes truncated ...]"));
+
+        // Never splits a multi-byte character
+        let truncated = truncate_output(&"é".repeat(50), 11);
+        assert!(truncated.contains("bytes truncated"));
+    }
+
+    #[test]
+    fn test_build_output_follow_up() {
+        let output = BashRunner::default()
+            .run("echo hello; echo oops >&2; exit 3")
+            .expect("Failed to run bash");
+        let message = build_output_follow_up("./build.sh", &output, 1_000);
+
+        assert!(message.contains("```bash\n./build.sh\n```"));
+        assert!(message.contains("Exit status: 3"));
+        assert!(message.contains("stdout:\n```\nhello\n```"));
+        assert!(message.contains("stderr:\n```\noops\n```"));
+
+        let output = BashRunner::default()
+            .run("head -c 5000 /dev/zero | tr '\\0' x")
+            .expect("Failed to run bash");
+        let message = build_output_follow_up("make", &output, 100);
+        assert!(message.contains("bytes truncated"));
+        assert!(message.len() < 500);
+    }
+
+    #[test]
+    fn test_auto_approve_is_off_by_default() {
+        assert!(!BashRunner::default().auto_approves("ls"));
+
+        let runner = BashRunner::default().with_auto_approve(vec!["ls".to_string()]);
+        assert!(runner.auto_approves("ls -la"));
+        assert!(!runner.auto_approves("rm notes.txt"));
+    }
+
+    // Runs the code with bash, recording it first
+    #[derive(Default)]
+    struct BashRecordingRunner {
+        runs: RefCell<Vec<String>>,
+    }
+
+    impl CommandRunner for BashRecordingRunner {
+        fn run(&self, code: &str) -> io::Result<Output> {
+            self.runs.borrow_mut().push(code.to_string());
+            BashRunner::default().run(code)
+        }
+    }
+
+    fn blocks(codes: &[&str]) -> Vec<String> {
+        codes.iter().map(|code| code.to_string()).collect()
+    }
+
+    #[test]
+    fn test_batch_runs_blocks_in_order() {
+        let runner = BashRecordingRunner::default();
+        let mut results = Vec::new();
+        let outcome = run_batch(
+            &blocks(&["echo one", "echo two", "echo three"]),
+            &runner,
+            false,
+            |i, result| {
+                let output = result.as_ref().unwrap();
+                results.push((i, String::from_utf8_lossy(&output.stdout).to_string()));
+            },
+        );
+        assert_eq!(outcome, BatchOutcome::Completed);
+        assert_eq!(
+            results,
+            vec![
+                (0, "one\n".to_string()),
+                (1, "two\n".to_string()),
+                (2, "three\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_stops_at_first_failure() {
+        let runner = BashRecordingRunner::default();
+        let codes = blocks(&["true", "exit 3", "echo never"]);
+        let mut reported = Vec::new();
+        let outcome = run_batch(&codes, &runner, false, |i, _| reported.push(i));
+        assert_eq!(outcome, BatchOutcome::Aborted(1));
+        assert_eq!(reported, vec![0, 1]);
+        assert_eq!(*runner.runs.borrow(), codes[..2]);
+    }
+
+    #[test]
+    fn test_batch_continues_on_error() {
+        let runner = BashRecordingRunner::default();
+        let codes = blocks(&["false", "exit 2", "true"]);
+        let outcome = run_batch(&codes, &runner, true, |_, _| {});
+        assert_eq!(outcome, BatchOutcome::Completed);
+        assert_eq!(*runner.runs.borrow(), codes);
+    }
+
+    #[test]
+    fn test_runner_times_the_last_run() {
+        let runner = BashRunner::default();
+        assert_eq!(runner.last_run(), None);
+
+        runner.run("true").unwrap();
+        runner.run("exit 4").unwrap();
+        let run = runner.clone().last_run().expect("The run should be timed");
+        assert_eq!(run.exit_code, Some(4));
+        assert!(run.duration < Duration::from_secs(60));
+
+        let runs = runner.take_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].exit_code, Some(0));
+        assert_eq!(runner.last_run(), None);
+    }
+}
@@ -0,0 +1,223 @@
+// Git repository context for the system prompt
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Commit subjects included in the context
+const RECENT_COMMIT_COUNT: usize = 5;
+
+/// Lines of `git status --short` included before the rest is summarized
+const MAX_STATUS_LINES: usize = 30;
+
+/// What to collect about the repository around the working directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitContextOptions {
+    /// Largest staged diff to include, in bytes, or `None` to leave the diff out
+    pub staged_diff_bytes: Option<usize>,
+}
+
+/// Facts about the git repository the user is working in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitContext {
+    /// Current branch, or `None` on a detached HEAD
+    pub branch: Option<String>,
+    /// Output of `git status --short`
+    pub status: String,
+    /// Subjects of the latest commits, newest first
+    pub recent_commits: Vec<String>,
+    /// Staged changes, if requested and there are any
+    pub staged_diff: Option<String>,
+}
+
+// Run git in a directory, returning its output if it succeeded
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Collects the git context of a directory
+///
+/// # Arguments
+///
+/// * `dir` - The directory to describe, usually the working directory
+/// * `options` - What to include
+///
+/// # Returns
+///
+/// The context, or `None` if git isn't installed or `dir` isn't in a repository
+pub fn collect_git_context(dir: &Path, options: &GitContextOptions) -> Option<GitContext> {
+    if git(dir, &["rev-parse", "--is-inside-work-tree"])?.trim() != "true" {
+        return None;
+    }
+
+    let branch = git(dir, &["branch", "--show-current"])
+        .map(|branch| branch.trim().to_string())
+        .filter(|branch| !branch.is_empty());
+    let status = git(dir, &["status", "--short"]).unwrap_or_default();
+    // A repository without commits has no log
+    let recent_commits = git(
+        dir,
+        &["log", &format!("-{}", RECENT_COMMIT_COUNT), "--format=%s"],
+    )
+    .map(|log| log.lines().map(str::to_string).collect())
+    .unwrap_or_default();
+    let staged_diff = options.staged_diff_bytes.and_then(|max_bytes| {
+        git(dir, &["diff", "--cached"])
+            .filter(|diff| !diff.trim().is_empty())
+            .map(|diff| truncate_diff(&diff, max_bytes))
+    });
+
+    Some(GitContext {
+        branch,
+        status,
+        recent_commits,
+        staged_diff,
+    })
+}
+
+// Cut a diff to at most `max_bytes` on a character boundary
+fn truncate_diff(diff: &str, max_bytes: usize) -> String {
+    if diff.len() <= max_bytes {
+        return diff.to_string();
+    }
+
+    let mut end = max_bytes;
+    while !diff.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[diff truncated after {} bytes]", &diff[..end], end)
+}
+
+impl GitContext {
+    /// Describes the repository as a section of the system prompt
+    pub fn render(&self) -> String {
+        let mut result = format!(
+            "Git repository:\nBranch: {}\n",
+            self.branch.as_deref().unwrap_or("(detached HEAD)")
+        );
+
+        let status: Vec<&str> = self.status.lines().collect();
+        if status.is_empty() {
+            result.push_str("Working tree clean\n");
+        } else {
+            result.push_str("Status:\n");
+            for line in status.iter().take(MAX_STATUS_LINES) {
+                result.push_str(&format!("{}\n", line));
+            }
+            if status.len() > MAX_STATUS_LINES {
+                result.push_str(&format!(
+                    "... and {} more changed files\n",
+                    status.len() - MAX_STATUS_LINES
+                ));
+            }
+        }
+
+        if !self.recent_commits.is_empty() {
+            result.push_str("Recent commits:\n");
+            for subject in &self.recent_commits {
+                result.push_str(&format!("- {}\n", subject));
+            }
+        }
+
+        if let Some(diff) = &self.staged_diff {
+            result.push_str(&format!(
+                "Staged changes:\n```diff\n{}\n```\n",
+                diff.trim_end()
+            ));
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    // Run git in a test repository, failing the test if it fails
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .expect("Failed to run git");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_collect_git_context() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let dir = temp_dir.path();
+        run_git(dir, &["init", "-q", "-b", "main"]);
+        fs::write(dir.join("README.md"), "hello\n").expect("Failed to write");
+        run_git(dir, &["add", "README.md"]);
+        run_git(dir, &["commit", "-q", "-m", "Add a README"]);
+        fs::write(dir.join("README.md"), "hello world\n").expect("Failed to write");
+        run_git(dir, &["add", "README.md"]);
+        fs::write(dir.join("notes.txt"), "").expect("Failed to write");
+
+        let context = collect_git_context(dir, &GitContextOptions::default())
+            .expect("Should find the repository");
+        assert_eq!(context.branch.as_deref(), Some("main"));
+        assert_eq!(context.status, "M  README.md\n?? notes.txt\n");
+        assert_eq!(context.recent_commits, vec!["Add a README"]);
+        assert_eq!(context.staged_diff, None);
+
+        let options = GitContextOptions {
+            staged_diff_bytes: Some(10_000),
+        };
+        let context = collect_git_context(dir, &options).expect("Should find the repository");
+        assert!(context
+            .staged_diff
+            .is_some_and(|diff| diff.contains("+hello world")));
+    }
+
+    #[test]
+    fn test_no_context_outside_a_repository() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        assert_eq!(
+            collect_git_context(temp_dir.path(), &GitContextOptions::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let context = GitContext {
+            branch: Some("feature".to_string()),
+            status: " M src/main.rs\n".to_string(),
+            recent_commits: vec!["Fix the parser".to_string()],
+            staged_diff: Some("+added\n".to_string()),
+        };
+        assert_eq!(
+            context.render(),
+            "Git repository:\nBranch: feature\nStatus:\n M src/main.rs\n\
+             Recent commits:\n- Fix the parser\nStaged changes:\n```diff\n+added\n```\n"
+        );
+
+        let clean = GitContext::default();
+        assert_eq!(
+            clean.render(),
+            "Git repository:\nBranch: (detached HEAD)\nWorking tree clean\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate_diff() {
+        assert_eq!(truncate_diff("short", 100), "short");
+        assert_eq!(truncate_diff("ééé", 3), "é\n[diff truncated after 2 bytes]");
+    }
+}
@@ -0,0 +1,180 @@
+// Previews and renders of Mermaid diagrams suggested by the model
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+// Editor opened with the diagram by `live_editor_url`
+const MERMAID_LIVE_URL: &str = "https://mermaid.live/edit";
+
+/// What the user chose to do with a Mermaid diagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MermaidChoice {
+    /// Open the diagram in the Mermaid Live Editor
+    Preview,
+    /// Render the diagram to a PNG with `mmdc`
+    Render,
+    /// Leave the diagram alone
+    Skip,
+}
+
+impl MermaidChoice {
+    /// Parses the answer to the diagram prompt, anything unrecognised skips the diagram
+    pub fn parse(input: &str) -> Self {
+        match input.trim().to_lowercase().as_str() {
+            "p" | "preview" => MermaidChoice::Preview,
+            "r" | "render" => MermaidChoice::Render,
+            _ => MermaidChoice::Skip,
+        }
+    }
+}
+
+/// Builds the Mermaid Live Editor link showing a diagram
+///
+/// # Arguments
+///
+/// * `code` - The Mermaid source of the diagram
+///
+/// # Returns
+///
+/// A `https://mermaid.live/edit#base64:...` URL with the editor state encoded in it
+pub fn live_editor_url(code: &str) -> String {
+    let state = serde_json::json!({
+        "code": code,
+        "mermaid": "{\n  \"theme\": \"default\"\n}",
+    });
+    format!(
+        "{}#base64:{}",
+        MERMAID_LIVE_URL,
+        STANDARD.encode(state.to_string())
+    )
+}
+
+/// Opens a URL in the default browser
+pub fn open_in_browser(url: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+
+    let status = command
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "the browser couldn't be opened ({})",
+            status
+        )))
+    }
+}
+
+/// Checks whether the Mermaid CLI (`mmdc`) can be run
+pub fn mmdc_available() -> bool {
+    Command::new("mmdc")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Picks a file name for a rendered diagram that doesn't exist yet
+///
+/// # Arguments
+///
+/// * `dir` - The directory the diagram is saved in
+///
+/// # Returns
+///
+/// `diagram.png`, or `diagram-2.png`, `diagram-3.png` and so on if taken
+pub fn diagram_path(dir: &Path) -> PathBuf {
+    let mut path = dir.join("diagram.png");
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("diagram-{}.png", n));
+        n += 1;
+    }
+    path
+}
+
+/// Renders a diagram to a PNG with `mmdc`
+///
+/// # Arguments
+///
+/// * `code` - The Mermaid source of the diagram
+/// * `output` - Where to write the image
+///
+/// # Returns
+///
+/// The exit status of `mmdc`
+pub fn render_png(code: &str, output: &Path) -> io::Result<ExitStatus> {
+    // A new file of its own, which no other user can read or replace, removed once drawn
+    let mut input = tempfile::Builder::new()
+        .prefix("shellai-diagram-")
+        .suffix(".mmd")
+        .tempfile()?;
+    input.write_all(code.as_bytes())?;
+    input.flush()?;
+
+    Command::new("mmdc")
+        .arg("-i")
+        .arg(input.path())
+        .arg("-o")
+        .arg(output)
+        .stdout(Stdio::null())
+        .status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_mermaid_choice() {
+        assert_eq!(MermaidChoice::parse("p\n"), MermaidChoice::Preview);
+        assert_eq!(MermaidChoice::parse("Render"), MermaidChoice::Render);
+        assert_eq!(MermaidChoice::parse(""), MermaidChoice::Skip);
+        assert_eq!(MermaidChoice::parse("y"), MermaidChoice::Skip);
+    }
+
+    #[test]
+    fn test_live_editor_url() {
+        let url = live_editor_url("graph TD\n  A --> B");
+        let encoded = url
+            .strip_prefix("https://mermaid.live/edit#base64:")
+            .expect("URL should point at the live editor");
+
+        let state: serde_json::Value =
+            serde_json::from_slice(&STANDARD.decode(encoded).expect("State should be base64"))
+                .expect("State should be JSON");
+        assert_eq!(state["code"], "graph TD\n  A --> B");
+    }
+
+    #[test]
+    fn test_diagram_path_skips_existing_files() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        assert_eq!(
+            diagram_path(temp_dir.path()),
+            temp_dir.path().join("diagram.png")
+        );
+
+        std::fs::write(temp_dir.path().join("diagram.png"), "").expect("Failed to write");
+        std::fs::write(temp_dir.path().join("diagram-2.png"), "").expect("Failed to write");
+        assert_eq!(
+            diagram_path(temp_dir.path()),
+            temp_dir.path().join("diagram-3.png")
+        );
+    }
+}
@@ -1,4 +1,37 @@
 // Utility modules for ShellAI
 
 // Export the directory utilities module
-pub(crate) mod directory;
+pub mod directory;
+
+// Export the response rendering module
+pub mod render;
+
+// Export the streamed response parsing module
+pub mod stream;
+
+// Export the shell quoting module
+pub mod shell;
+
+// Export the code block execution module
+pub mod execute;
+
+// Export the clipboard watching module
+pub mod clipboard;
+
+// Export the git repository context module
+pub mod git;
+
+// Export the @file attachment module
+pub mod attachments;
+
+// Export the Mermaid diagram module
+pub mod mermaid;
+
+// Export the code block risk summary module
+pub mod risk;
+
+// Export the SQL query execution module
+pub mod sql;
+
+// Export the private file writing module
+pub mod files;
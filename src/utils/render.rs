@@ -0,0 +1,1254 @@
+// Rendering and parsing helpers for model responses
+
+use crate::agents::models::AIModel;
+use crate::ui::accessible::{self, Symbols};
+use crate::ui::theme::Themed;
+use crate::utils::sql::SqlDialect;
+use regex::Regex;
+use std::sync::LazyLock;
+use unicode_width::UnicodeWidthChar;
+
+// Matches fenced code blocks, capturing the language label and the content
+static CODE_BLOCK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"```(\w*)([\s\S]*?)```").unwrap());
+
+// Matches a heredoc operator, capturing the `-` that allows indented ends and the delimiter
+static HEREDOC_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^<<(-?)\s*['"]?([A-Za-z_][\w.-]*)['"]?"#).unwrap());
+
+// Language labels of the blocks treated as bash
+const BASH_LABELS: &[&str] = &["bash", "sh", ""];
+
+// Extract the trimmed contents of the fenced blocks whose label is accepted
+fn extract_blocks(response: &str, accepts: impl Fn(&str) -> bool) -> Vec<String> {
+    CODE_BLOCK_REGEX
+        .captures_iter(response)
+        .filter(|capture| accepts(&capture[1]))
+        .map(|capture| capture[2].trim().to_string())
+        .collect()
+}
+
+/// Extracts the bash code blocks from a model response
+///
+/// # Arguments
+///
+/// * `response` - The response text, possibly containing fenced code blocks
+///
+/// # Returns
+///
+/// The trimmed contents of each ```bash, ```sh or unlabelled block, in order, with the
+/// `$ ` prompts before the commands removed
+pub fn extract_bash_blocks(response: &str) -> Vec<String> {
+    extract_blocks(response, |label| BASH_LABELS.contains(&label))
+        .iter()
+        .map(|block| strip_prompts(block))
+        .collect()
+}
+
+/// Removes the `$ ` prompts that models sometimes write before commands
+///
+/// The block is only changed if every command in it starts with `$ `, so output shown
+/// after a prompt or a `$` that belongs to the code leaves it as it is. Lines inside
+/// heredocs and multi-line quotes and lines continuing a command after `\` are never
+/// touched. Lines starting with `# ` are kept too, since they can't be told apart from
+/// comments.
+///
+/// # Arguments
+///
+/// * `code` - The bash code of a block
+///
+/// # Returns
+///
+/// The code without the prompts, or unchanged
+pub fn strip_prompts(code: &str) -> String {
+    let lines: Vec<&str> = code.lines().collect();
+    let commands: Vec<usize> = command_starts(&lines)
+        .into_iter()
+        .enumerate()
+        .filter(|&(index, starts)| {
+            let line = lines[index].trim_start();
+            starts && !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let is_prompted = |line: &str| line == "$" || line.starts_with("$ ");
+    if commands.is_empty()
+        || !commands
+            .iter()
+            .all(|&index| is_prompted(lines[index].trim_start()))
+    {
+        return code.to_string();
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            if commands.contains(&index) {
+                line.trim_start()[1..].strip_prefix(' ').unwrap_or("")
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Whether each line starts a command, rather than continuing one or being part of a
+// heredoc or a quoted string
+fn command_starts(lines: &[&str]) -> Vec<bool> {
+    let mut starts = Vec::with_capacity(lines.len());
+    // Heredocs opened on the lines so far, as their delimiter and whether tabs may
+    // indent it, in the order their bodies come
+    let mut heredocs: Vec<(String, bool)> = Vec::new();
+    let mut quote = None;
+    let mut continued = false;
+
+    for line in lines {
+        if let Some((delimiter, indented)) = heredocs.first() {
+            let end = if *indented {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if end == delimiter {
+                heredocs.remove(0);
+            }
+            starts.push(false);
+            continue;
+        }
+        starts.push(quote.is_none() && !continued);
+        (quote, continued) = scan_line(line, quote, &mut heredocs);
+    }
+    starts
+}
+
+// Follow the quotes of a line, starting inside `quote` if a string is still open, and
+// record the heredocs it opens
+//
+// Returns the quote still open at the end of the line and whether the line ends with a
+// backslash continuing it.
+fn scan_line(
+    line: &str,
+    mut quote: Option<char>,
+    heredocs: &mut Vec<(String, bool)>,
+) -> (Option<char>, bool) {
+    let mut chars = line.char_indices().peekable();
+    let mut previous = ' ';
+    let mut continued = false;
+
+    while let Some((index, c)) = chars.next() {
+        continued = false;
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => continued = chars.next().is_none(),
+            (Some(_), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            // The rest of the line is a comment
+            (None, '#') if previous.is_whitespace() => break,
+            // A here-string, `<<<word`, opens no heredoc
+            (None, '<') if line[index..].starts_with("<<<") => {
+                chars.nth(1);
+            }
+            (None, '<') => {
+                if let Some(heredoc) = HEREDOC_REGEX.captures(&line[index..]) {
+                    heredocs.push((heredoc[2].to_string(), &heredoc[1] == "-"));
+                    let end = index + heredoc[0].len();
+                    while chars.next_if(|&(next, _)| next < end).is_some() {}
+                }
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    (quote, continued)
+}
+
+/// Finds the last code block of a model response, whatever its language
+///
+/// # Arguments
+///
+/// * `response` - The response text, possibly containing fenced code blocks
+///
+/// # Returns
+///
+/// The language label, "bash" for unlabelled blocks, and the trimmed contents of the block
+pub fn last_code_block(response: &str) -> Option<(String, String)> {
+    CODE_BLOCK_REGEX
+        .captures_iter(response)
+        .last()
+        .map(|capture| {
+            let language = match &capture[1] {
+                "" => "bash",
+                label => label,
+            };
+            (language.to_string(), capture[2].trim().to_string())
+        })
+}
+
+/// Extracts the Mermaid diagrams from a model response
+///
+/// # Arguments
+///
+/// * `response` - The response text, possibly containing fenced code blocks
+///
+/// # Returns
+///
+/// The trimmed contents of each ```mermaid block, in order
+pub fn extract_mermaid_blocks(response: &str) -> Vec<String> {
+    extract_blocks(response, |label| label == "mermaid")
+}
+
+/// Extracts the SQL queries from a model response
+///
+/// # Arguments
+///
+/// * `response` - The response text, possibly containing fenced code blocks
+///
+/// # Returns
+///
+/// The dialect and trimmed contents of each ```sql, ```sqlite or ```postgresql block (or
+/// another name of a PostgreSQL block, such as ```psql), in order
+pub fn extract_sql_blocks(response: &str) -> Vec<(SqlDialect, String)> {
+    CODE_BLOCK_REGEX
+        .captures_iter(response)
+        .filter_map(|capture| {
+            SqlDialect::from_label(&capture[1])
+                .map(|dialect| (dialect, capture[2].trim().to_string()))
+        })
+        .collect()
+}
+
+/// Formats rows of cells as a table with box-drawing borders
+///
+/// # Arguments
+///
+/// * `headers` - The column titles
+/// * `rows` - The cells of each row, in the same order as the headers
+///
+/// # Returns
+///
+/// The table with every column padded to its widest cell, one line per row
+pub fn format_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    format_table_with(headers, rows, accessible::symbols())
+}
+
+// Format a table drawn with the given characters
+fn format_table_with(headers: &[&str], rows: &[Vec<String>], symbols: &Symbols) -> String {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let border = |left: &str, middle: &str, right: &str| {
+        let segments: Vec<String> = widths
+            .iter()
+            .map(|width| symbols.horizontal.repeat(width + 2))
+            .collect();
+        format!("{}{}{}\n", left, segments.join(middle), right)
+    };
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(i, width)| format!(" {:<width$} ", cells.get(i).copied().unwrap_or("")))
+            .collect();
+        format!(
+            "{}{}{}\n",
+            symbols.vertical,
+            cells.join(symbols.vertical),
+            symbols.vertical
+        )
+    };
+
+    let [top_left, top_right, bottom_left, bottom_right] = symbols.corners;
+    let [top, bottom, left, right, middle] = symbols.junctions;
+    let mut table = border(top_left, top, top_right);
+    table.push_str(&line(headers.to_vec()));
+    table.push_str(&border(left, middle, right));
+    for row in rows {
+        table.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    table.push_str(&border(bottom_left, bottom, bottom_right));
+    table
+}
+
+/// Formats a context window size as a short token count such as `128K`
+fn format_context_window(tokens: Option<u32>) -> String {
+    match tokens {
+        Some(tokens) if tokens >= 1_000 => format!("{}K", (tokens as f64 / 1_000.0).round()),
+        Some(tokens) => tokens.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// Renders the models as a numbered table for the model picker
+///
+/// # Arguments
+///
+/// * `models` - The models to list, numbered from 1 in this order
+///
+/// # Returns
+///
+/// A table with the number, name, context window, cost (USD per million prompt/completion
+/// tokens) and notes of each model
+pub fn render_model_table(models: &[AIModel]) -> String {
+    let rows: Vec<Vec<String>> = models
+        .iter()
+        .enumerate()
+        .map(|(i, model)| {
+            let cost = model.pricing.map_or("-".to_string(), |pricing| {
+                format!("${:.2} / ${:.2}", pricing.prompt, pricing.completion)
+            });
+
+            let mut notes = vec![model.description.clone()];
+            if model.capabilities.reasoning {
+                notes.push("reasoning".to_string());
+            }
+            if !model.capabilities.supports_streaming {
+                notes.push("no streaming".to_string());
+            }
+
+            vec![
+                (i + 1).to_string(),
+                model.name.clone(),
+                format_context_window(model.context_window),
+                cost,
+                notes.join(", "),
+            ]
+        })
+        .collect();
+
+    format_table(
+        &[
+            "#",
+            "Name",
+            "Context Window",
+            "Cost (prompt/completion)",
+            "Notes",
+        ],
+        &rows,
+    )
+}
+
+// Matches a Markdown list item: its indentation, then a bullet with an optional checkbox, a
+// number or an arrow, and the text of the item
+static LIST_ITEM_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\s*)(?:[-*+]\s+(?:\[([ xX])\]\s+)?|(\d{1,9})[.)]\s+|(->|→)\s+)(\S.*)$").unwrap()
+});
+
+/// Markers put before the items of a rendered list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListStyle {
+    /// `•` in cyan
+    Bullet,
+    /// `1.`, `2.`... in yellow, counted separately at each level from the number of its
+    /// first item
+    Numbered,
+    /// `→` in blue
+    Arrow,
+    /// `✓` in green, for done tasks
+    Check,
+}
+
+// An item of a Markdown list
+struct ListItem<'a> {
+    indent: &'a str,
+    style: ListStyle,
+    // The number of a numbered item, as written
+    number: Option<usize>,
+    text: &'a str,
+}
+
+// Parse a line as a list item, styled after its own marker. Horizontal rules such as
+// `* * *` aren't items, and unchecked boxes stay in the text as `[ ]`.
+fn parse_list_item(line: &str) -> Option<ListItem<'_>> {
+    if line
+        .trim()
+        .chars()
+        .all(|c| matches!(c, '-' | '*' | '_' | ' '))
+    {
+        return None;
+    }
+    let captures = LIST_ITEM_REGEX.captures(line)?;
+    let mut text = captures.get(5).map_or("", |text| text.as_str());
+    let style = match (captures.get(2), captures.get(3), captures.get(4)) {
+        (_, Some(_), _) => ListStyle::Numbered,
+        (_, _, Some(_)) => ListStyle::Arrow,
+        (Some(checkbox), _, _) if checkbox.as_str() == " " => {
+            text = &line[checkbox.start() - 1..];
+            ListStyle::Bullet
+        }
+        (Some(_), _, _) => ListStyle::Check,
+        _ => ListStyle::Bullet,
+    };
+    Some(ListItem {
+        indent: captures.get(1).map_or("", |indent| indent.as_str()),
+        style,
+        number: captures
+            .get(3)
+            .and_then(|number| number.as_str().parse().ok()),
+        text,
+    })
+}
+
+// Whether an item outside a list starts one. A number other than 1 only does when the next
+// line is an item too, so a sentence such as `2024. was a year` stays text.
+fn starts_list(item: &ListItem, next: Option<&str>) -> bool {
+    item.number.is_none_or(|number| number == 1) || next.and_then(parse_list_item).is_some()
+}
+
+/// Renders list items with styled markers in place of their Markdown syntax
+///
+/// # Arguments
+///
+/// * `items` - The lines of the list, with or without Markdown markers such as `- `, `1. `
+///   or `- [x] `, indented for nested items
+/// * `style` - The markers of the outermost items; nested items keep the style of their
+///   own Markdown marker, and done tasks (`- [x] `) always get a check mark
+///
+/// # Returns
+///
+/// One line per item, keeping the indentation. ASCII markers are used in accessible mode.
+pub fn render_list(items: &[&str], style: ListStyle) -> String {
+    render_list_with(items, style, accessible::symbols())
+}
+
+// Render list items with the given characters
+fn render_list_with(items: &[&str], style: ListStyle, symbols: &Symbols) -> String {
+    let items: Vec<ListItem> = items
+        .iter()
+        .map(|line| {
+            parse_list_item(line).unwrap_or_else(|| {
+                let text = line.trim_start();
+                ListItem {
+                    indent: &line[..line.len() - text.len()],
+                    style,
+                    number: None,
+                    text,
+                }
+            })
+        })
+        .collect();
+    let outer = items
+        .iter()
+        .map(|item| item.indent.len())
+        .min()
+        .unwrap_or(0);
+
+    // Number of the latest item of each level of nesting open so far, by indentation,
+    // starting from the number of the first item of the level
+    let mut numbers: Vec<(usize, usize)> = Vec::new();
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| {
+            let indent = item.indent.len();
+            numbers.retain(|&(level, _)| level <= indent);
+            match numbers.last_mut() {
+                Some((level, number)) if *level == indent => *number += 1,
+                _ => numbers.push((indent, item.number.unwrap_or(1))),
+            }
+            let number = numbers.last().map_or(1, |&(_, number)| number);
+
+            let item_style = if indent > outer || item.style == ListStyle::Check {
+                item.style
+            } else {
+                style
+            };
+            let marker = match item_style {
+                ListStyle::Bullet => symbols.bullet.accent(),
+                ListStyle::Numbered => format!("{}.", number).warning(),
+                ListStyle::Arrow => symbols.arrow.ai_label(),
+                ListStyle::Check => symbols.check.success(),
+            };
+            format!("{}{} {}", item.indent, marker, item.text)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Renders a model response for the terminal
+///
+/// Runs of Markdown list items are drawn with `render_list`, in the style of the marker of
+/// their first outermost item that isn't a done task. A run starting with a number other
+/// than 1 needs a second item to be a list. Lines inside fenced code blocks are left as
+/// they are.
+pub fn render_ai_response(response: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut list: Vec<&str> = Vec::new();
+    let mut in_code = false;
+
+    let flush = |list: &mut Vec<&str>, lines: &mut Vec<String>| {
+        let items: Vec<ListItem> = list
+            .iter()
+            .filter_map(|line| parse_list_item(line))
+            .collect();
+        if let Some(outer) = items.iter().map(|item| item.indent.len()).min() {
+            let style = items
+                .iter()
+                .filter(|item| item.indent.len() == outer)
+                .map(|item| item.style)
+                .find(|&style| style != ListStyle::Check)
+                .unwrap_or(ListStyle::Check);
+            lines.push(render_list(list, style));
+        }
+        list.clear();
+    };
+
+    let mut response_lines = response.lines().peekable();
+    while let Some(line) = response_lines.next() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code
+            && parse_list_item(line).is_some_and(|item| {
+                !list.is_empty() || starts_list(&item, response_lines.peek().copied())
+            })
+        {
+            list.push(line);
+            continue;
+        }
+        flush(&mut list, &mut lines);
+        lines.push(line.to_string());
+    }
+    flush(&mut list, &mut lines);
+    lines.join("\n")
+}
+
+// Matches a terminal escape sequence, such as the color codes of rendered text
+static ESCAPE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap());
+
+// Split text into escape sequences and characters, with the columns each takes
+fn segments(text: &str) -> Vec<(&str, usize)> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for escape in ESCAPE_REGEX.find_iter(text) {
+        segments.extend(text[start..escape.start()].char_indices().map(|(i, c)| {
+            (
+                &text[start + i..start + i + c.len_utf8()],
+                c.width().unwrap_or(0),
+            )
+        }));
+        segments.push((escape.as_str(), 0));
+        start = escape.end();
+    }
+    segments.extend(text[start..].char_indices().map(|(i, c)| {
+        (
+            &text[start + i..start + i + c.len_utf8()],
+            c.width().unwrap_or(0),
+        )
+    }));
+    segments
+}
+
+// Wrap one line at spaces, continuing at its indentation. Words wider than a row are cut
+// between characters, never inside an escape sequence.
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    let indent = &line[..line.len() - line.trim_start_matches(' ').len()];
+    // Deep indentation would leave no room for the text
+    let indent = if indent.len() < width / 2 { indent } else { "" };
+
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut row_width = 0;
+    for (i, word) in line.split(' ').enumerate() {
+        let word = segments(word);
+        let word_width: usize = word.iter().map(|(_, width)| width).sum();
+        if i > 0 {
+            if row_width + 1 + word_width > width && row_width > indent.len() {
+                rows.push(std::mem::take(&mut row));
+                row.push_str(indent);
+                row_width = indent.len();
+            } else {
+                row.push(' ');
+                row_width += 1;
+            }
+        }
+        for (segment, segment_width) in word {
+            if row_width + segment_width > width && row_width > indent.len() {
+                rows.push(std::mem::take(&mut row));
+                row.push_str(indent);
+                row_width = indent.len();
+            }
+            row.push_str(segment);
+            row_width += segment_width;
+        }
+    }
+    rows.push(row);
+    rows
+}
+
+/// Wraps rendered text to fit the terminal
+///
+/// # Arguments
+///
+/// * `text` - The text, which may hold color escape sequences
+/// * `width` - The number of columns of the terminal
+///
+/// # Returns
+///
+/// The text with long lines broken at spaces, continuing at the indentation of the line.
+/// Escape sequences take no room and are never split, and lines inside fenced code blocks
+/// are left as they are so commands can be copied intact.
+pub fn wrap_text(text: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if is_fence(line) {
+            in_code = !in_code;
+            lines.push(line.to_string());
+        } else if in_code {
+            lines.push(line.to_string());
+        } else {
+            lines.extend(wrap_line(line, width));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Wraps text after a label such as `AI:` to fit the terminal, see `wrap_text`
+///
+/// # Arguments
+///
+/// * `label` - The label, which may hold color escape sequences
+/// * `text` - The text put after the label
+/// * `width` - The number of columns of the terminal
+///
+/// # Returns
+///
+/// The label and the text on the same line, unless the text starts with a code fence,
+/// which then starts the line after the label so the code block is still recognized
+pub fn wrap_labeled(label: &str, text: &str, width: usize) -> String {
+    if text.lines().next().is_some_and(is_fence) {
+        format!("{}\n{}", label, wrap_text(text, width))
+    } else {
+        wrap_text(&format!("{} {}", label, text), width)
+    }
+}
+
+// Whether a line, colored or not, opens or closes a fenced code block
+fn is_fence(line: &str) -> bool {
+    ESCAPE_REGEX
+        .replace_all(line, "")
+        .trim_start()
+        .starts_with("```")
+}
+
+// Matches **bold** text in an already escaped line
+static BOLD_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*\*(.+?)\*\*").unwrap());
+
+// Code block languages highlighted as shell code
+const SHELL_LANGUAGES: &[&str] = &["", "bash", "sh", "shell", "zsh", "console"];
+
+// Words highlighted as shell keywords
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "return", "export", "local", "sudo",
+];
+
+// Stylesheet embedded in every rendered page
+const HTML_STYLE: &str = r#"body { font-family: -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; max-width: 860px; margin: 2rem auto; padding: 0 1rem; line-height: 1.6; color: #24292f; background: #ffffff; }
+header { border-bottom: 1px solid #d0d7de; margin-bottom: 1.5rem; color: #57606a; font-size: 0.9rem; }
+code { font-family: "SFMono-Regular", Consolas, "Liberation Mono", Menlo, monospace; background: #f6f8fa; padding: 0.1em 0.3em; border-radius: 4px; }
+pre { background: #0d1117; color: #e6edf3; padding: 1rem; border-radius: 6px; overflow-x: auto; }
+pre code { background: none; padding: 0; }
+.tok-comment { color: #8b949e; font-style: italic; }
+.tok-string { color: #a5d6ff; }
+.tok-keyword { color: #ff7b72; }
+.tok-variable { color: #ffa657; }"#;
+
+/// Escapes the characters that have a meaning in HTML
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Render `code` spans and **bold** text of a line
+fn render_inline(line: &str) -> String {
+    line.split('`')
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 1 {
+                format!("<code>{}</code>", escape_html(part))
+            } else {
+                BOLD_REGEX
+                    .replace_all(&escape_html(part), "<strong>$1</strong>")
+                    .into_owned()
+            }
+        })
+        .collect()
+}
+
+// Wrap text in a span of a highlighting class
+fn token(class: &str, text: &str) -> String {
+    format!("<span class=\"tok-{}\">{}</span>", class, escape_html(text))
+}
+
+/// Highlights shell code as HTML, marking comments, strings, keywords and variables
+fn highlight_shell(code: &str) -> String {
+    let chars: Vec<char> = code.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let at_word_start = i == 0 || chars[i - 1].is_whitespace() || ";|&(".contains(chars[i - 1]);
+
+        if c == '#' && at_word_start {
+            // A comment runs to the end of the line
+            let end = chars[i..]
+                .iter()
+                .position(|&c| c == '\n')
+                .map_or(chars.len(), |n| i + n);
+            result.push_str(&token("comment", &chars[i..end].iter().collect::<String>()));
+            i = end;
+        } else if c == '"' || c == '\'' {
+            // A string runs to the matching quote, skipping escaped quotes in double quotes
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] != c {
+                if c == '"' && chars[end] == '\\' {
+                    end += 1;
+                }
+                end += 1;
+            }
+            let end = (end + 1).min(chars.len());
+            result.push_str(&token("string", &chars[i..end].iter().collect::<String>()));
+            i = end;
+        } else if c == '$'
+            && i + 1 < chars.len()
+            && (chars[i + 1] == '{' || chars[i + 1] == '_' || chars[i + 1].is_ascii_alphanumeric())
+        {
+            let end = if chars[i + 1] == '{' {
+                chars[i..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .map_or(chars.len(), |n| i + n + 1)
+            } else {
+                chars[i + 1..]
+                    .iter()
+                    .position(|c| !(c.is_ascii_alphanumeric() || *c == '_'))
+                    .map_or(chars.len(), |n| i + 1 + n)
+            };
+            result.push_str(&token(
+                "variable",
+                &chars[i..end].iter().collect::<String>(),
+            ));
+            i = end;
+        } else if c.is_ascii_alphabetic() && at_word_start {
+            let end = chars[i..]
+                .iter()
+                .position(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+                .map_or(chars.len(), |n| i + n);
+            let word: String = chars[i..end].iter().collect();
+            if SHELL_KEYWORDS.contains(&word.as_str()) {
+                result.push_str(&token("keyword", &word));
+            } else {
+                result.push_str(&escape_html(&word));
+            }
+            i = end;
+        } else {
+            result.push_str(&escape_html(&c.to_string()));
+            i += 1;
+        }
+    }
+
+    result
+}
+
+// Convert the Markdown of a response into HTML blocks
+fn render_markdown(response: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&str> = None;
+    let mut code: Option<(String, Vec<&str>)> = None;
+
+    let flush_paragraph = |html: &mut String, paragraph: &mut Vec<&str>| {
+        if !paragraph.is_empty() {
+            let lines: Vec<String> = paragraph.iter().map(|line| render_inline(line)).collect();
+            html.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+            paragraph.clear();
+        }
+    };
+    let close_list = |html: &mut String, list: &mut Option<&str>| {
+        if let Some(tag) = list.take() {
+            html.push_str(&format!("</{}>\n", tag));
+        }
+    };
+
+    for line in response.lines() {
+        let trimmed = line.trim_start();
+
+        // Inside a fenced code block everything is code until the closing fence
+        if let Some((language, lines)) = &mut code {
+            if trimmed.starts_with("```") {
+                let text = lines.join("\n");
+                let body = if SHELL_LANGUAGES.contains(&language.as_str()) {
+                    highlight_shell(&text)
+                } else {
+                    escape_html(&text)
+                };
+                let class = if language.is_empty() {
+                    "bash"
+                } else {
+                    language.as_str()
+                };
+                html.push_str(&format!(
+                    "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                    escape_html(class),
+                    body
+                ));
+                code = None;
+            } else {
+                lines.push(line);
+            }
+            continue;
+        }
+
+        if let Some(language) = trimmed.strip_prefix("```") {
+            flush_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+            code = Some((language.trim().to_lowercase(), Vec::new()));
+            continue;
+        }
+
+        let heading_level = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&heading_level) && trimmed[heading_level..].starts_with(' ') {
+            flush_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+            html.push_str(&format!(
+                "<h{level}>{}</h{level}>\n",
+                render_inline(trimmed[heading_level..].trim()),
+                level = heading_level
+            ));
+            continue;
+        }
+
+        let unordered = trimmed
+            .strip_prefix("- ")
+            .or_else(|| trimmed.strip_prefix("* "));
+        let ordered = trimmed
+            .split_once(". ")
+            .filter(|(number, _)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .map(|(_, item)| item);
+        if let Some((tag, item)) = unordered
+            .map(|item| ("ul", item))
+            .or(ordered.map(|item| ("ol", item)))
+        {
+            flush_paragraph(&mut html, &mut paragraph);
+            if list != Some(tag) {
+                close_list(&mut html, &mut list);
+                html.push_str(&format!("<{}>\n", tag));
+                list = Some(tag);
+            }
+            html.push_str(&format!("<li>{}</li>\n", render_inline(item)));
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            flush_paragraph(&mut html, &mut paragraph);
+            close_list(&mut html, &mut list);
+        } else {
+            close_list(&mut html, &mut list);
+            paragraph.push(line);
+        }
+    }
+
+    // An unterminated code block still shows its contents
+    if let Some((_, lines)) = code {
+        html.push_str(&format!(
+            "<pre><code>{}</code></pre>\n",
+            escape_html(&lines.join("\n"))
+        ));
+    }
+    flush_paragraph(&mut html, &mut paragraph);
+    close_list(&mut html, &mut list);
+
+    html
+}
+
+/// Renders a model response as a self-contained HTML page
+///
+/// Markdown headings, lists, paragraphs, inline code and fenced code blocks are converted,
+/// and shell code is highlighted with the embedded stylesheet. The page loads nothing else.
+///
+/// # Arguments
+///
+/// * `response` - The response text, in Markdown
+/// * `model` - The model that answered
+/// * `timestamp` - When the response was received, shown as is
+///
+/// # Returns
+///
+/// A complete HTML document
+pub fn render_as_html(response: &str, model: &str, timestamp: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>ShellAI response</title>\n<style>\n{}\n</style>\n</head>\n<body>\n\
+         <header><p>ShellAI response from <strong>{}</strong> at {}</p></header>\n\
+         <main>\n{}</main>\n</body>\n</html>\n",
+        HTML_STYLE,
+        escape_html(model),
+        escape_html(timestamp),
+        render_markdown(response)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_bash_blocks() {
+        let response =
+            "List files:\n```bash\nls -la\n```\nThen:\n```sh\npwd\n```\nOr:\n```\necho hi\n```";
+        assert_eq!(
+            extract_bash_blocks(response),
+            vec!["ls -la", "pwd", "echo hi"]
+        );
+    }
+
+    #[test]
+    fn test_strip_prompts() {
+        assert_eq!(
+            extract_bash_blocks("Run:\n```bash\n$ ls -la\n$ cd /tmp\n```"),
+            vec!["ls -la\ncd /tmp"]
+        );
+        // Comments stay, and `# ` isn't taken for a root prompt
+        assert_eq!(strip_prompts("# list files\n$ ls"), "# list files\nls");
+        assert_eq!(strip_prompts("# comment"), "# comment");
+        assert_eq!(strip_prompts("# apt update"), "# apt update");
+
+        // Blocks with a command without a prompt are left alone
+        assert_eq!(strip_prompts("$ ls\nnotes.txt"), "$ ls\nnotes.txt");
+        assert_eq!(strip_prompts("echo $HOME\n$ pwd"), "echo $HOME\n$ pwd");
+    }
+
+    #[test]
+    fn test_strip_prompts_keeps_heredocs_and_strings() {
+        assert_eq!(
+            strip_prompts("$ cat <<'EOF' > notes.txt\n$ not a prompt\nEOF\n$ wc -l notes.txt"),
+            "cat <<'EOF' > notes.txt\n$ not a prompt\nEOF\nwc -l notes.txt"
+        );
+        assert_eq!(
+            strip_prompts("$ cat <<-END\n\t$ 5\n\tEND\n$ echo \"it's\n$ quoted\""),
+            "cat <<-END\n\t$ 5\n\tEND\necho \"it's\n$ quoted\""
+        );
+        assert_eq!(
+            strip_prompts("$ docker run \\\n$ IMAGE # don't"),
+            "docker run \\\n$ IMAGE # don't"
+        );
+        // Here-strings have no body to skip
+        assert_eq!(
+            strip_prompts("$ grep -c a <<<EOF\n$ echo done\n$ cat <<< \"$x\""),
+            "grep -c a <<<EOF\necho done\ncat <<< \"$x\""
+        );
+    }
+
+    #[test]
+    fn test_extract_mermaid_blocks() {
+        let response = "Flow:\n```mermaid\ngraph TD\n  A --> B\n```\nRun:\n```bash\nls\n```\n```python\nprint(1)\n```";
+        assert_eq!(
+            extract_mermaid_blocks(response),
+            vec!["graph TD\n  A --> B"]
+        );
+        // Diagrams and other languages aren't offered for execution
+        assert_eq!(extract_bash_blocks(response), vec!["ls"]);
+    }
+
+    #[test]
+    fn test_extract_sql_blocks() {
+        let response = "Count them:\n```sql\nSELECT count(*) FROM users;\n```\n```sqlite\n.tables\n```\n```postgresql\n\\dt\n```\n```bash\nls\n```";
+        assert_eq!(
+            extract_sql_blocks(response),
+            vec![
+                (
+                    SqlDialect::Generic,
+                    "SELECT count(*) FROM users;".to_string()
+                ),
+                (SqlDialect::Sqlite, ".tables".to_string()),
+                (SqlDialect::Postgres, "\\dt".to_string()),
+            ]
+        );
+        // SQL isn't run as bash
+        assert_eq!(extract_bash_blocks(response), vec!["ls"]);
+    }
+
+    #[test]
+    fn test_last_code_block() {
+        let response = "Run:\n```bash\nls\n```\nOr in Python:\n```python\nprint(1)\n```\nDone.";
+        assert_eq!(
+            last_code_block(response),
+            Some(("python".to_string(), "print(1)".to_string()))
+        );
+        assert_eq!(
+            last_code_block("```\npwd\n```"),
+            Some(("bash".to_string(), "pwd".to_string()))
+        );
+        assert_eq!(last_code_block("No code here."), None);
+    }
+
+    #[test]
+    fn test_extract_bash_blocks_without_code() {
+        assert!(extract_bash_blocks("Just prose, no code.").is_empty());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let table = format_table(
+            &["#", "Name"],
+            &[
+                vec!["1".to_string(), "GPT-4".to_string()],
+                vec!["10".to_string(), "o1".to_string()],
+            ],
+        );
+        assert_eq!(
+            table,
+            "┌────┬───────┐\n\
+             │ #  │ Name  │\n\
+             ├────┼───────┤\n\
+             │ 1  │ GPT-4 │\n\
+             │ 10 │ o1    │\n\
+             └────┴───────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_format_table_in_ascii() {
+        let table = format_table_with(
+            &["#", "Name"],
+            &[vec!["1".to_string(), "o1".to_string()]],
+            &accessible::ASCII,
+        );
+        assert_eq!(
+            table,
+            "+---+------+\n\
+             | # | Name |\n\
+             +---+------+\n\
+             | 1 | o1   |\n\
+             +---+------+\n"
+        );
+    }
+
+    #[test]
+    fn test_render_model_table() {
+        let models = vec![
+            AIModel::new("GPT-4o", "gpt-4o", "Latest model"),
+            AIModel::new("o1", "o1", "Reasoning model"),
+            AIModel::custom("my-model"),
+        ];
+        let table = render_model_table(&models);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert!(lines[1].contains("Context Window"));
+        assert!(lines[1].contains("Cost (prompt/completion)"));
+        assert!(lines[3].contains("│ 1 "));
+        assert!(lines[3].contains("128K"));
+        assert!(lines[3].contains("$2.50 / $10.00"));
+        assert!(lines[4].contains("200K"));
+        assert!(lines[4].contains("Reasoning model, reasoning, no streaming"));
+        assert!(lines[5].contains("my-model"));
+        assert!(lines[5].contains(" - "));
+
+        // Every line of the table has the same width
+        let width = lines[0].chars().count();
+        assert!(lines.iter().all(|line| line.chars().count() == width));
+    }
+
+    #[test]
+    fn test_render_as_html_page() {
+        let html = render_as_html("Hello <world>", "gpt-4o", "2025-01-01 10:00:00");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<style>"));
+        assert!(html.contains("<strong>gpt-4o</strong> at 2025-01-01 10:00:00"));
+        assert!(html.contains("<p>Hello &lt;world&gt;</p>"));
+        // Self-contained: no external resources
+        assert!(!html.contains("<link"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_render_markdown_blocks() {
+        let response = "## Steps\nRun **this** with `ls`:\n\n- first\n- second\n\n1. one\n2. two";
+        assert_eq!(
+            render_markdown(response),
+            "<h2>Steps</h2>\n<p>Run <strong>this</strong> with <code>ls</code>:</p>\n\
+             <ul>\n<li>first</li>\n<li>second</li>\n</ul>\n\
+             <ol>\n<li>one</li>\n<li>two</li>\n</ol>\n"
+        );
+    }
+
+    #[test]
+    fn test_render_code_blocks() {
+        let response = "```bash\nif [ -f \"$HOME/.rc\" ]; then echo hi; fi # check\n```\n```rust\nlet a = \"<b>\";\n```";
+        let html = render_markdown(response);
+        assert!(html.contains("<pre><code class=\"language-bash\">"));
+        assert!(html.contains("<span class=\"tok-keyword\">if</span>"));
+        assert!(html.contains("<span class=\"tok-string\">&quot;$HOME/.rc&quot;</span>"));
+        assert!(html.contains("<span class=\"tok-comment\"># check</span>"));
+        assert!(html.contains("<span class=\"tok-keyword\">fi</span>"));
+        // Other languages are escaped but not highlighted
+        assert!(
+            html.contains("<code class=\"language-rust\">let a = &quot;&lt;b&gt;&quot;;</code>")
+        );
+    }
+
+    #[test]
+    fn test_highlight_shell_variables() {
+        assert_eq!(
+            highlight_shell("echo $PATH ${HOME}"),
+            "echo <span class=\"tok-variable\">$PATH</span> <span class=\"tok-variable\">${HOME}</span>"
+        );
+    }
+
+    // Remove the color codes of rendered text
+    fn plain(text: &str) -> String {
+        Regex::new(r"\x1b\[[0-9;]*m")
+            .unwrap()
+            .replace_all(text, "")
+            .to_string()
+    }
+
+    #[test]
+    fn test_render_list_styles() {
+        let items = ["- apt", "* dnf", "+ pacman"];
+        let render = |style| plain(&render_list_with(&items, style, &accessible::UNICODE));
+        assert_eq!(render(ListStyle::Bullet), "• apt\n• dnf\n• pacman");
+        assert_eq!(render(ListStyle::Numbered), "1. apt\n2. dnf\n3. pacman");
+
+        // Numbering goes on from the first item, as when a list is split by a paragraph
+        let items = ["3. apt", "4. dnf", "4. pacman"];
+        assert_eq!(
+            plain(&render_list_with(
+                &items,
+                ListStyle::Numbered,
+                &accessible::UNICODE
+            )),
+            "3. apt\n4. dnf\n5. pacman"
+        );
+        assert_eq!(render(ListStyle::Arrow), "→ apt\n→ dnf\n→ pacman");
+        assert_eq!(render(ListStyle::Check), "✓ apt\n✓ dnf\n✓ pacman");
+
+        // Items without Markdown markers are styled too, with ASCII markers when accessible
+        assert_eq!(
+            plain(&render_list_with(
+                &["one", "two"],
+                ListStyle::Arrow,
+                &accessible::ASCII
+            )),
+            "-> one\n-> two"
+        );
+    }
+
+    #[test]
+    fn test_render_nested_list() {
+        let items = [
+            "1. Install",
+            "   - apt install jq",
+            "   - brew install jq",
+            "2. Check",
+            "   1. jq --version",
+            "   2. which jq",
+            "3. Done",
+        ];
+        assert_eq!(
+            plain(&render_list_with(&items, ListStyle::Numbered, &accessible::UNICODE)),
+            "1. Install\n   • apt install jq\n   • brew install jq\n2. Check\n   1. jq --version\n   2. which jq\n3. Done"
+        );
+    }
+
+    #[test]
+    fn test_parse_list_item() {
+        let style = |line| parse_list_item(line).map(|item| (item.style, item.text));
+        assert_eq!(style("- ls"), Some((ListStyle::Bullet, "ls")));
+        assert_eq!(style("12) ls"), Some((ListStyle::Numbered, "ls")));
+        assert_eq!(style("-> ls"), Some((ListStyle::Arrow, "ls")));
+        assert_eq!(style("- [x] ls"), Some((ListStyle::Check, "ls")));
+        assert_eq!(style("- [ ] ls"), Some((ListStyle::Bullet, "[ ] ls")));
+        assert_eq!(style("**bold** text"), None);
+        assert_eq!(style("* * *"), None);
+        assert_eq!(style("-1 is the exit code"), None);
+    }
+
+    #[test]
+    fn test_render_ai_response_lists() {
+        let response = "Steps:\n1. Update\n2. Upgrade\n\nTasks:\n- [x] backup\n- [ ] deploy\n```bash\n- not a list\n```\n- end";
+        assert_eq!(
+            plain(&render_ai_response(response)),
+            "Steps:\n1. Update\n2. Upgrade\n\nTasks:\n✓ backup\n• [ ] deploy\n```bash\n- not a list\n```\n• end"
+        );
+        assert_eq!(render_ai_response("No lists here"), "No lists here");
+
+        // A number only starts a list in the context of one
+        assert_eq!(
+            plain(&render_ai_response("2024. was a year\nof changes")),
+            "2024. was a year\nof changes"
+        );
+        assert_eq!(
+            plain(&render_ai_response("Then:\n3. Reboot\n4. Check")),
+            "Then:\n3. Reboot\n4. Check"
+        );
+        assert_eq!(plain(&render_ai_response("1. Reboot")), "1. Reboot");
+    }
+
+    #[test]
+    fn test_wrap_text_at_spaces() {
+        assert_eq!(
+            wrap_text("the quick brown fox jumps", 10),
+            "the quick\nbrown fox\njumps"
+        );
+        // Continuation rows keep the indentation of the line
+        assert_eq!(
+            wrap_text("  - one two three", 10),
+            "  - one\n  two\n  three"
+        );
+        assert_eq!(wrap_text("abcdefghij", 4), "abcd\nefgh\nij");
+        assert_eq!(wrap_text("short\n\nlines", 10), "short\n\nlines");
+    }
+
+    #[test]
+    fn test_wrap_text_skips_escape_sequences_and_code() {
+        let text = "\x1b[94mAI\x1b[0m: hello there";
+        assert_eq!(wrap_text(text, 9), "\x1b[94mAI\x1b[0m: hello\nthere");
+
+        // A cut word keeps its escape sequences whole
+        let wrapped = wrap_text("\x1b[1mabcdef\x1b[0m", 3);
+        assert_eq!(wrapped, "\x1b[1mabc\ndef\x1b[0m");
+
+        let text = "Run:\n```bash\necho one two three four\n```";
+        assert_eq!(wrap_text(text, 10), text);
+    }
+
+    #[test]
+    fn test_wrap_labeled() {
+        assert_eq!(
+            wrap_labeled("\x1b[94mAI:\x1b[0m", "hello there", 9),
+            "\x1b[94mAI:\x1b[0m hello\nthere"
+        );
+
+        // A response starting with a code block keeps its fence at the start of a line
+        let code = "```bash\necho one two three four\n```\nthe quick brown fox";
+        assert_eq!(
+            wrap_labeled("AI:", code, 10),
+            "AI:\n```bash\necho one two three four\n```\nthe quick\nbrown fox"
+        );
+    }
+}
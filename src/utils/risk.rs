@@ -0,0 +1,449 @@
+// Risk summary of bash code blocks, shown before asking to run them
+
+use regex::Regex;
+use std::fmt;
+use std::sync::LazyLock;
+
+// Patterns that can wreck a system or run untrusted code, with what they do
+static DANGEROUS_PATTERNS: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        (
+            r"\brm\s+(-\S+\s+)*(/|/\*|~/?|\*|\$HOME/?)(\s|;|$)",
+            "deletes a whole directory tree",
+        ),
+        (r"\bdd\b[^\n]*\bof=/dev/", "writes to a raw device"),
+        (r">\s*/dev/(sd|nvme|hd|disk)", "writes to a raw device"),
+        (r"\bmkfs(\.\w+)?\b", "formats a filesystem"),
+        (r":\(\)\s*\{[^}]*:\s*\|\s*:", "is a fork bomb"),
+        (
+            r"\b(curl|wget)\b[^\n|]*\|\s*(sudo\s+)?(ba|z|da)?sh\b",
+            "runs a downloaded script",
+        ),
+        (
+            r"\bchmod\s+(-\S+\s+)*0?777\s+/",
+            "opens permissions on a system path",
+        ),
+    ]
+    .into_iter()
+    .map(|(pattern, description)| (Regex::new(pattern).unwrap(), description))
+    .collect()
+});
+
+// Output redirections to a file, capturing the target
+static REDIRECT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|[^<>&0-9])\d*(?:<>|>[>|]?)\s*([^\s;&|<>]+)").unwrap());
+
+// Redirections writing no file: discarded output and duplicated or closed descriptors
+static HARMLESS_REDIRECT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\d*|&)>>?\s*/dev/null(?:$|[\s;&|)])|\d*>&(?:\d+|-)(?:$|[\s;&|)])").unwrap()
+});
+
+// Separators starting a new command on the same line
+static SEPARATOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\|\|?|&&|;|\$\(|`|\(|\)|\{|\}").unwrap());
+
+// Anything that can start another command, splitting code checked for auto-approval more
+// strictly than `SEPARATOR_REGEX`, so `ls & rm x` or `ls <(rm x)` can't pass as `ls`
+static APPROVAL_SEPARATOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\|\|?|&&?|;|\$\(|`|\(|\)|\{|\}|<\(|>\(|\\$").unwrap());
+
+// Commands that run the command after them
+const WRAPPERS: &[&str] = &[
+    "env", "nohup", "time", "xargs", "exec", "nice", "then", "do", "else",
+];
+
+// Commands that run what follows with other privileges
+const PRIVILEGE_COMMANDS: &[&str] = &["sudo", "doas", "su", "pkexec"];
+
+// Commands that delete files or data
+const DELETE_COMMANDS: &[&str] = &["rm", "rmdir", "shred", "unlink", "truncate", "dd", "mkfs"];
+
+// Commands that create, move or change files
+const WRITE_COMMANDS: &[&str] = &[
+    "tee", "mv", "cp", "touch", "mkdir", "ln", "chmod", "chown", "chgrp", "install", "rsync",
+];
+
+// Commands that reach the network
+const NETWORK_COMMANDS: &[&str] = &[
+    "curl", "wget", "ssh", "scp", "sftp", "rsync", "nc", "ncat", "telnet", "ftp", "ping", "git",
+    "apt", "apt-get", "dnf", "yum", "brew", "pip", "pip3", "npm", "cargo",
+];
+
+// Git subcommands that don't reach the network
+const LOCAL_GIT_SUBCOMMANDS: &[&str] = &[
+    "status",
+    "log",
+    "diff",
+    "show",
+    "add",
+    "commit",
+    "branch",
+    "checkout",
+    "switch",
+    "stash",
+    "reset",
+    "restore",
+    "rebase",
+    "merge",
+    "tag",
+    "init",
+    "config",
+    "blame",
+    "rev-parse",
+];
+
+// Package manager subcommands that download something
+const INSTALL_SUBCOMMANDS: &[&str] = &["install", "update", "upgrade", "add", "publish"];
+
+// Commands that stop processes or the machine
+const PROCESS_COMMANDS: &[&str] = &[
+    "kill",
+    "pkill",
+    "killall",
+    "reboot",
+    "shutdown",
+    "halt",
+    "poweroff",
+    "systemctl",
+];
+
+/// What a bash code block does that deserves a second look before running it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskSummary {
+    /// Non-empty lines of code
+    pub lines: usize,
+    /// Whether a command runs with other privileges, such as through sudo
+    pub privileged: bool,
+    /// Commands deleting files or data
+    pub deletes: Vec<String>,
+    /// Commands and redirections writing files
+    pub writes: Vec<String>,
+    /// Commands reaching the network
+    pub network: Vec<String>,
+    /// Commands stopping processes or the machine
+    pub processes: Vec<String>,
+    /// Descriptions of the dangerous patterns found
+    pub dangers: Vec<&'static str>,
+}
+
+impl RiskSummary {
+    /// Analyzes a code block with simple heuristics
+    ///
+    /// Commands are recognized by name at the start of each pipeline stage, after `sudo`
+    /// and similar wrappers, so the summary is a hint rather than a guarantee.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The bash code
+    ///
+    /// # Returns
+    ///
+    /// The commands found in each category, in order of appearance
+    pub fn analyze(code: &str) -> Self {
+        let mut summary = RiskSummary::default();
+
+        for line in code.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            summary.lines += 1;
+
+            for (pattern, description) in DANGEROUS_PATTERNS.iter() {
+                if pattern.is_match(line) && !summary.dangers.contains(description) {
+                    summary.dangers.push(description);
+                }
+            }
+
+            for target in REDIRECT_REGEX.captures_iter(line) {
+                if &target[1] != "/dev/null" {
+                    add_unique(&mut summary.writes, ">");
+                }
+            }
+
+            for segment in SEPARATOR_REGEX.split(line) {
+                summary.add_command(segment);
+            }
+        }
+        summary
+    }
+
+    /// Records the command run by one pipeline stage
+    fn add_command(&mut self, segment: &str) {
+        let mut words = segment
+            .split_whitespace()
+            // Leading variable assignments such as `LANG=C`
+            .skip_while(|word| word.contains('=') && !word.starts_with('-'))
+            .map(|word| word.rsplit('/').next().unwrap_or(word));
+
+        let mut name = words.next();
+        while let Some(word) = name {
+            if PRIVILEGE_COMMANDS.contains(&word) {
+                self.privileged = true;
+            } else if !WRAPPERS.contains(&word) {
+                break;
+            }
+            // The wrapped command is the next word that isn't an option
+            name = words.find(|word| !word.starts_with('-'));
+        }
+        let Some(name) = name else {
+            return;
+        };
+        let subcommand = words.find(|word| !word.starts_with('-')).unwrap_or("");
+
+        if DELETE_COMMANDS.contains(&name) || name.starts_with("mkfs.") {
+            add_unique(&mut self.deletes, name);
+        }
+        if WRITE_COMMANDS.contains(&name)
+            || (name == "sed"
+                && segment
+                    .split_whitespace()
+                    .any(|word| word.starts_with("-i")))
+        {
+            add_unique(&mut self.writes, name);
+        }
+        let reaches_network = match name {
+            "git" => !LOCAL_GIT_SUBCOMMANDS.contains(&subcommand),
+            "rsync" | "scp" => segment.contains(':'),
+            _ if NETWORK_COMMANDS.contains(&name) => {
+                !is_package_manager(name) || INSTALL_SUBCOMMANDS.contains(&subcommand)
+            }
+            _ => false,
+        };
+        if reaches_network {
+            add_unique(&mut self.network, name);
+        }
+        if PROCESS_COMMANDS.contains(&name) {
+            add_unique(&mut self.processes, name);
+        }
+    }
+
+    /// Whether the code matches a pattern that can wreck the system
+    pub fn is_dangerous(&self) -> bool {
+        !self.dangers.is_empty()
+    }
+
+    /// Whether anything but the line count was found
+    pub fn has_risks(&self) -> bool {
+        self.privileged
+            || !self.deletes.is_empty()
+            || !self.writes.is_empty()
+            || !self.network.is_empty()
+            || !self.processes.is_empty()
+            || self.is_dangerous()
+    }
+}
+
+// Whether a network command only downloads for some subcommands
+fn is_package_manager(name: &str) -> bool {
+    matches!(
+        name,
+        "apt" | "apt-get" | "dnf" | "yum" | "brew" | "pip" | "pip3" | "npm" | "cargo"
+    )
+}
+
+fn add_unique(list: &mut Vec<String>, item: &str) {
+    if !list.iter().any(|existing| existing == item) {
+        list.push(item.to_string());
+    }
+}
+
+impl fmt::Display for RiskSummary {
+    /// Formats the summary on one line, as in "3 lines, deletes files (rm), uses sudo"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![format!(
+            "{} line{}",
+            self.lines,
+            if self.lines == 1 { "" } else { "s" }
+        )];
+        parts.extend(
+            self.dangers
+                .iter()
+                .map(|danger| format!("DANGER: {}", danger)),
+        );
+        if self.privileged {
+            parts.push("uses sudo".to_string());
+        }
+        for (label, commands) in [
+            ("deletes files", &self.deletes),
+            ("writes files", &self.writes),
+            ("uses the network", &self.network),
+            ("stops processes", &self.processes),
+        ] {
+            if !commands.is_empty() {
+                parts.push(format!("{} ({})", label, commands.join(", ")));
+            }
+        }
+        if !self.has_risks() {
+            parts.push("no risky commands found".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Checks a command against the patterns that can wreck a system or run untrusted code
+///
+/// # Arguments
+///
+/// * `command` - The bash code to check
+///
+/// # Returns
+///
+/// True if any line matches, such as `rm -rf /` or `curl ... | sh`
+pub fn is_dangerous_command(command: &str) -> bool {
+    RiskSummary::analyze(command).is_dangerous()
+}
+
+/// Checks whether a code block may run without asking, from an allowlist of command prefixes
+///
+/// Every command of the block has to start with one of the prefixes, compared word by word,
+/// so `git status` allows `git status -s` but not `git stash`. Blocks writing a file with a
+/// redirection or matching a dangerous pattern are never approved, whatever the allowlist.
+///
+/// # Arguments
+///
+/// * `code` - The bash code
+/// * `allowlist` - Command prefixes such as `ls` or `git status`, from `auto_approve`
+///
+/// # Returns
+///
+/// True if the block has at least one command and all of them are allowed
+pub fn is_auto_approved(code: &str, allowlist: &[String]) -> bool {
+    let summary = RiskSummary::analyze(code);
+    if summary.lines == 0 || summary.is_dangerous() || summary.writes.iter().any(|w| w == ">") {
+        return false;
+    }
+    // Any other `>` may write a file, whatever its descriptor or operator
+    if HARMLESS_REDIRECT_REGEX.replace_all(code, " ").contains('>') {
+        return false;
+    }
+    code.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(|line| APPROVAL_SEPARATOR_REGEX.split(line))
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .all(|command| {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            allowlist.iter().any(|prefix| {
+                let prefix: Vec<&str> = prefix.split_whitespace().collect();
+                !prefix.is_empty() && words.starts_with(&prefix)
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_only_commands() {
+        let summary = RiskSummary::analyze("ls -la\n# list the largest files\ndu -sh * | sort -h");
+        assert_eq!(summary.lines, 2);
+        assert!(!summary.has_risks());
+        assert_eq!(summary.to_string(), "2 lines, no risky commands found");
+
+        // Discarded output and descriptors aren't file writes
+        let summary = RiskSummary::analyze("find / -name '*.log' 2>/dev/null\nmake 2>&1 | less");
+        assert!(summary.writes.is_empty());
+    }
+
+    #[test]
+    fn test_sudo_and_deletes() {
+        let summary =
+            RiskSummary::analyze("sudo rm -r /var/cache/app\nsudo systemctl restart nginx");
+        assert!(summary.privileged);
+        assert_eq!(summary.deletes, vec!["rm"]);
+        assert_eq!(summary.processes, vec!["systemctl"]);
+        assert!(!summary.is_dangerous());
+        assert_eq!(
+            summary.to_string(),
+            "2 lines, uses sudo, deletes files (rm), stops processes (systemctl)"
+        );
+    }
+
+    #[test]
+    fn test_file_writes() {
+        let summary = RiskSummary::analyze(
+            "echo 'export PATH=$PATH:~/bin' >> ~/.bashrc\nsed -i 's/a/b/' config.txt\ncat a | tee b",
+        );
+        assert_eq!(summary.writes, vec![">", "sed", "tee"]);
+        assert_eq!(summary.to_string(), "3 lines, writes files (>, sed, tee)");
+
+        // Any descriptor number, and read-write opens, write files too
+        assert_eq!(RiskSummary::analyze("ls 10>~/.bashrc").writes, vec![">"]);
+        assert_eq!(RiskSummary::analyze("ls <>newfile").writes, vec![">"]);
+    }
+
+    #[test]
+    fn test_network() {
+        let summary = RiskSummary::analyze(
+            "git status && git pull\nLANG=C curl -s https://example.com\napt list --installed",
+        );
+        assert_eq!(summary.network, vec!["git", "curl"]);
+
+        let summary = RiskSummary::analyze("sudo apt install -y jq\nrsync -a src/ host:/backup");
+        assert_eq!(summary.network, vec!["apt", "rsync"]);
+        assert_eq!(summary.writes, vec!["rsync"]);
+    }
+
+    #[test]
+    fn test_dangerous_patterns() {
+        assert!(is_dangerous_command("sudo rm -rf /"));
+        assert!(is_dangerous_command("rm -rf ~"));
+        assert!(is_dangerous_command(
+            "curl -fsSL https://example.com/install.sh | sudo bash"
+        ));
+        assert!(is_dangerous_command("dd if=image.iso of=/dev/sdb bs=4M"));
+        assert!(is_dangerous_command("mkfs.ext4 /dev/sdb1"));
+        assert!(is_dangerous_command(":(){ :|:& };:"));
+
+        assert!(!is_dangerous_command("rm -rf ./build"));
+        assert!(!is_dangerous_command(
+            "curl -o install.sh https://example.com/install.sh"
+        ));
+
+        let summary = RiskSummary::analyze("curl -s https://example.com/x.sh | sh");
+        assert_eq!(
+            summary.to_string(),
+            "1 line, DANGER: runs a downloaded script, uses the network (curl)"
+        );
+    }
+
+    #[test]
+    fn test_auto_approval() {
+        let allowlist: Vec<String> = ["ls", "pwd", "cat", "git status", "rm"]
+            .map(String::from)
+            .to_vec();
+        assert!(is_auto_approved("ls -la", &allowlist));
+        assert!(is_auto_approved(
+            "# where am I\npwd && git status -s | cat",
+            &allowlist
+        ));
+
+        // Prefixes match whole words
+        assert!(!is_auto_approved("lsblk", &allowlist));
+        assert!(!is_auto_approved("git stash", &allowlist));
+        // Every command has to be allowed, wherever it hides
+        assert!(!is_auto_approved("ls; mv a b", &allowlist));
+        assert!(!is_auto_approved("ls & mv a b", &allowlist));
+        assert!(!is_auto_approved("ls $(mv a b)", &allowlist));
+        assert!(!is_auto_approved("ls \\\n; mv a b", &allowlist));
+        assert!(!is_auto_approved("cat notes > notes.bak", &allowlist));
+        assert!(!is_auto_approved("ls 10>~/.bashrc", &allowlist));
+        assert!(!is_auto_approved("cat a 99>>x", &allowlist));
+        assert!(!is_auto_approved("ls <>newfile", &allowlist));
+        assert!(!is_auto_approved("ls >|x", &allowlist));
+        assert!(!is_auto_approved("ls >&out.txt", &allowlist));
+        assert!(!is_auto_approved("ls 2>/dev/null.txt", &allowlist));
+        // Discarded output writes nothing
+        assert!(is_auto_approved("ls 2>/dev/null", &allowlist));
+        assert!(is_auto_approved("ls -la 2>/dev/null | cat", &allowlist));
+        assert!(!is_auto_approved("", &allowlist));
+        assert!(!is_auto_approved("ls", &[]));
+
+        // Dangerous code is never approved, even when allowlisted
+        assert!(is_auto_approved("rm -r ./build", &allowlist));
+        assert!(!is_auto_approved("rm -rf /", &allowlist));
+        assert!(!is_auto_approved("rm -rf ~", &allowlist));
+    }
+}
@@ -0,0 +1,102 @@
+// Shell quoting helpers for ShellAI
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A shell whose commands can be translated to another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    /// Names accepted by `from_str`, as typed after `--to` and `--from`
+    pub const NAMES: &'static [&'static str] = &["bash", "zsh", "fish", "pwsh"];
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "bash" | "sh" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "pwsh" | "powershell" => Ok(Shell::PowerShell),
+            _ => Err(format!(
+                "Unknown shell '{}', use one of: {}",
+                name,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "PowerShell",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Quotes a string so the shell passes it through as a single literal word
+///
+/// The string is wrapped in single quotes, inside which the shell interprets nothing, and
+/// each embedded single quote is written as `'\''` (close the quotes, an escaped quote,
+/// reopen the quotes). Use it for any user-controlled text placed in a command template.
+///
+/// # Arguments
+///
+/// * `s` - The text to quote
+///
+/// # Returns
+///
+/// The quoted text, safe to embed in a `bash -c` command
+pub fn shell_escape(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_shell_escape() {
+        assert_eq!(shell_escape("notes.txt"), "'notes.txt'");
+        assert_eq!(shell_escape(""), "''");
+        assert_eq!(shell_escape("it's"), r"'it'\''s'");
+        assert_eq!(shell_escape("$(rm -rf ~)"), "'$(rm -rf ~)'");
+    }
+
+    #[test]
+    fn test_shell_names() {
+        assert_eq!("fish".parse::<Shell>(), Ok(Shell::Fish));
+        assert_eq!("PowerShell".parse::<Shell>(), Ok(Shell::PowerShell));
+        assert_eq!("pwsh".parse::<Shell>(), Ok(Shell::PowerShell));
+        assert!("cmd"
+            .parse::<Shell>()
+            .unwrap_err()
+            .contains("bash, zsh, fish, pwsh"));
+        assert_eq!(Shell::PowerShell.to_string(), "PowerShell");
+    }
+
+    #[test]
+    fn test_shell_escape_round_trips_through_bash() {
+        let input = "a b; echo 'pwned' $HOME `id` \"q\" \\ *";
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(format!("printf '%s' {}", shell_escape(input)))
+            .output()
+            .expect("Failed to run bash");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), input);
+    }
+}
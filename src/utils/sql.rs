@@ -0,0 +1,199 @@
+// Running SQL query blocks with the sqlite3 or psql command-line clients
+
+use crate::utils::shell::shell_escape;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The SQL dialect of a code block, from its language label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    /// ```sql, which any client may run
+    Generic,
+    /// ```sqlite
+    Sqlite,
+    /// ```postgresql and its other names, such as ```psql
+    Postgres,
+}
+
+impl SqlDialect {
+    /// Gets the dialect of a code block label, None if the block isn't SQL
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.to_ascii_lowercase().as_str() {
+            "sql" => Some(SqlDialect::Generic),
+            "sqlite" | "sqlite3" => Some(SqlDialect::Sqlite),
+            "postgresql" | "postgres" | "psql" | "pgsql" | "plpgsql" => Some(SqlDialect::Postgres),
+            _ => None,
+        }
+    }
+}
+
+/// A command-line client that runs SQL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlClient {
+    /// `sqlite3`, on the configured database file or an in-memory one
+    Sqlite3,
+    /// `psql`, connecting with its usual `PG*` environment variables
+    Psql,
+}
+
+impl SqlClient {
+    /// Gets the program name of the client
+    pub fn program(self) -> &'static str {
+        match self {
+            SqlClient::Sqlite3 => "sqlite3",
+            SqlClient::Psql => "psql",
+        }
+    }
+}
+
+/// Picks the client to run a query of a dialect with
+///
+/// SQLite and PostgreSQL queries need their own client. Generic SQL goes to `sqlite3` if
+/// it is installed, or else to `psql`.
+///
+/// # Arguments
+///
+/// * `dialect` - The dialect of the query
+/// * `is_available` - Whether a program, such as `psql`, can be run
+///
+/// # Returns
+///
+/// The client, or None if the one the query needs isn't installed
+pub fn choose_client(
+    dialect: SqlDialect,
+    is_available: impl Fn(&str) -> bool,
+) -> Option<SqlClient> {
+    let candidates: &[SqlClient] = match dialect {
+        SqlDialect::Sqlite => &[SqlClient::Sqlite3],
+        SqlDialect::Postgres => &[SqlClient::Psql],
+        SqlDialect::Generic => &[SqlClient::Sqlite3, SqlClient::Psql],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|client| is_available(client.program()))
+}
+
+/// Checks whether a program can be run, by asking it for its version
+pub fn client_available(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Builds the bash code that runs a query with a client
+///
+/// The query is given on standard input through a quoted heredoc, so the shell changes
+/// nothing in it, and both clients stop at the first failing statement.
+///
+/// # Arguments
+///
+/// * `client` - The client to run the query with
+/// * `sql` - The query
+/// * `sqlite_database` - The database file `sqlite3` opens, an in-memory one if None
+///
+/// # Returns
+///
+/// The bash code, run like a bash block once confirmed
+pub fn sql_command(client: SqlClient, sql: &str, sqlite_database: Option<&Path>) -> String {
+    let command = match client {
+        SqlClient::Sqlite3 => match sqlite_database {
+            Some(path) => format!("sqlite3 -bail {}", shell_escape(&path.to_string_lossy())),
+            None => "sqlite3 -bail".to_string(),
+        },
+        SqlClient::Psql => "psql -v ON_ERROR_STOP=1".to_string(),
+    };
+
+    // The delimiter must not appear alone on a line of the query
+    let mut delimiter = "SHELLAI_SQL".to_string();
+    while sql.lines().any(|line| line.trim() == delimiter) {
+        delimiter.push('_');
+    }
+    format!(
+        "{} <<'{}'\n{}\n{}",
+        command,
+        delimiter,
+        sql.trim_end(),
+        delimiter
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_dialects() {
+        assert_eq!(SqlDialect::from_label("sql"), Some(SqlDialect::Generic));
+        assert_eq!(SqlDialect::from_label("SQLite"), Some(SqlDialect::Sqlite));
+        assert_eq!(
+            SqlDialect::from_label("postgresql"),
+            Some(SqlDialect::Postgres)
+        );
+        assert_eq!(SqlDialect::from_label("psql"), Some(SqlDialect::Postgres));
+        assert_eq!(SqlDialect::from_label("mysql"), None);
+        assert_eq!(SqlDialect::from_label("bash"), None);
+    }
+
+    #[test]
+    fn test_choose_client() {
+        let only = |name: &'static str| move |program: &str| program == name;
+        assert_eq!(
+            choose_client(SqlDialect::Generic, |_| true),
+            Some(SqlClient::Sqlite3)
+        );
+        assert_eq!(
+            choose_client(SqlDialect::Generic, only("psql")),
+            Some(SqlClient::Psql)
+        );
+        assert_eq!(
+            choose_client(SqlDialect::Postgres, |_| true),
+            Some(SqlClient::Psql)
+        );
+        assert_eq!(choose_client(SqlDialect::Postgres, only("sqlite3")), None);
+        assert_eq!(choose_client(SqlDialect::Sqlite, |_| false), None);
+    }
+
+    #[test]
+    fn test_sql_command() {
+        let database = PathBuf::from("my app.db");
+        assert_eq!(
+            sql_command(
+                SqlClient::Sqlite3,
+                "SELECT * FROM users;\n",
+                Some(&database)
+            ),
+            "sqlite3 -bail 'my app.db' <<'SHELLAI_SQL'\nSELECT * FROM users;\nSHELLAI_SQL"
+        );
+        assert_eq!(
+            sql_command(SqlClient::Psql, "SELECT '$HOME';", None),
+            "psql -v ON_ERROR_STOP=1 <<'SHELLAI_SQL'\nSELECT '$HOME';\nSHELLAI_SQL"
+        );
+
+        // A query containing the delimiter gets another one
+        let command = sql_command(SqlClient::Sqlite3, "SELECT 1;\nSHELLAI_SQL", None);
+        assert!(command.ends_with("\nSHELLAI_SQL_"));
+    }
+
+    #[test]
+    fn test_sql_command_runs_in_bash() {
+        if !client_available("sqlite3") {
+            return;
+        }
+        let command = sql_command(
+            SqlClient::Sqlite3,
+            "CREATE TABLE t (x);\nINSERT INTO t VALUES ('$x');\nSELECT x FROM t;",
+            None,
+        );
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(&command)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "$x\n");
+    }
+}
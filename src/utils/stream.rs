@@ -0,0 +1,270 @@
+// Splitting a streamed response into live prose and whole fenced code blocks
+
+/// A piece of a streamed response, ready to be printed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// Prose, printed as it arrives
+    Text(String),
+    /// A fenced code block, given only once its closing fence arrived or the response ended
+    CodeBlock {
+        /// The label after the opening fence, empty if there was none
+        language: String,
+        /// The lines between the fences, without the last line break
+        code: String,
+        /// False if the response ended before the closing fence
+        complete: bool,
+    },
+}
+
+/// Parses a response arriving in chunks, as streamed completions do
+///
+/// Fences are only recognized at the start of a line, like `render_ai_response` does, so a
+/// line is held back while it could still turn out to be one. Prose is passed on as soon as
+/// it can't, often before its line ends, while code blocks are buffered whole so their
+/// frame can be drawn around them.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownStream {
+    /// Received text not passed on yet
+    pending: String,
+    /// Whether part of the current prose line was already passed on
+    line_started: bool,
+    /// The language and lines of the code block being received
+    block: Option<(String, Vec<String>)>,
+}
+
+impl MarkdownStream {
+    /// Creates a parser expecting the start of a response
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next chunk of the response
+    ///
+    /// # Returns
+    ///
+    /// The prose and the code blocks completed by the chunk, in order
+    pub fn push(&mut self, chunk: &str) -> Vec<StreamEvent> {
+        self.pending.push_str(chunk);
+        let mut events = Vec::new();
+
+        loop {
+            if self.line_started {
+                // The rest of a prose line can't be a fence
+                match self.pending.find('\n') {
+                    Some(end) => {
+                        let text: String = self.pending.drain(..=end).collect();
+                        push_text(&mut events, &text);
+                        self.line_started = false;
+                    }
+                    None => {
+                        let text = std::mem::take(&mut self.pending);
+                        push_text(&mut events, &text);
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let Some(end) = self.pending.find('\n') else {
+                // Wait for more of a line that could be a fence
+                if self.block.is_none() && !could_be_fence(&self.pending) {
+                    let text = std::mem::take(&mut self.pending);
+                    push_text(&mut events, &text);
+                    self.line_started = true;
+                }
+                break;
+            };
+            let line: String = self.pending.drain(..=end).collect();
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+            match (&mut self.block, fence_label(line)) {
+                (Some(_), Some(_)) => {
+                    let (language, lines) = self.block.take().unwrap_or_default();
+                    events.push(StreamEvent::CodeBlock {
+                        language,
+                        code: lines.join("\n"),
+                        complete: true,
+                    });
+                }
+                (Some((_, lines)), None) => lines.push(line.to_string()),
+                (None, Some(language)) => self.block = Some((language, Vec::new())),
+                (None, None) => push_text(&mut events, &format!("{}\n", line)),
+            }
+        }
+        events
+    }
+
+    /// Ends the response, passing on what was held back
+    ///
+    /// A code block whose closing fence never came is given as it is, marked incomplete,
+    /// as happens when the answer is cut at the token limit.
+    ///
+    /// # Returns
+    ///
+    /// The remaining prose and code block, in order
+    pub fn finish(mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let rest = std::mem::take(&mut self.pending);
+        match self.block.take() {
+            Some((language, mut lines)) => {
+                let complete = fence_label(&rest).is_some();
+                if !complete && !rest.is_empty() {
+                    lines.push(rest);
+                }
+                events.push(StreamEvent::CodeBlock {
+                    language,
+                    code: lines.join("\n"),
+                    complete,
+                });
+            }
+            None if !rest.is_empty() => events.push(StreamEvent::Text(rest)),
+            None => {}
+        }
+        events
+    }
+}
+
+// Add prose to the events, joined to the prose just before it
+fn push_text(events: &mut Vec<StreamEvent>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match events.last_mut() {
+        Some(StreamEvent::Text(previous)) => previous.push_str(text),
+        _ => events.push(StreamEvent::Text(text.to_string())),
+    }
+}
+
+// Get the language label of a fence line, None if the line isn't a fence
+fn fence_label(line: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix("```")?;
+    Some(
+        rest.split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    )
+}
+
+// Whether the start of a line could still become a fence as more of it arrives
+fn could_be_fence(partial: &str) -> bool {
+    let partial = partial.trim_start();
+    partial.starts_with("```") || "```".starts_with(partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str =
+        "List the files:\n```bash\nls -la\necho `pwd`\n```\nThen check `git status`.\n";
+
+    // Feed the response in chunks of `size` characters and join the prose events that a single
+    // push would have given at once
+    fn stream_in_chunks(response: &str, size: usize) -> Vec<StreamEvent> {
+        let mut stream = MarkdownStream::new();
+        let mut events = Vec::new();
+        let chars: Vec<char> = response.chars().collect();
+        for chunk in chars.chunks(size) {
+            events.extend(stream.push(&chunk.iter().collect::<String>()));
+        }
+        events.extend(stream.finish());
+
+        let mut joined = Vec::new();
+        for event in events {
+            match event {
+                StreamEvent::Text(text) => push_text(&mut joined, &text),
+                block => joined.push(block),
+            }
+        }
+        joined
+    }
+
+    fn bash_block(code: &str, complete: bool) -> StreamEvent {
+        StreamEvent::CodeBlock {
+            language: "bash".to_string(),
+            code: code.to_string(),
+            complete,
+        }
+    }
+
+    #[test]
+    fn test_chunk_boundaries_dont_change_the_blocks() {
+        let expected = vec![
+            StreamEvent::Text("List the files:\n".to_string()),
+            bash_block("ls -la\necho `pwd`", true),
+            StreamEvent::Text("Then check `git status`.\n".to_string()),
+        ];
+        for size in 1..=RESPONSE.len() {
+            assert_eq!(
+                stream_in_chunks(RESPONSE, size),
+                expected,
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_prose_streams_before_its_line_ends() {
+        let mut stream = MarkdownStream::new();
+        assert_eq!(
+            stream.push("Hello"),
+            [StreamEvent::Text("Hello".to_string())]
+        );
+        assert_eq!(
+            stream.push(" there\n``"),
+            [StreamEvent::Text(" there\n".to_string())]
+        );
+        // Two backticks could be the start of a fence, the block waits for its end
+        assert!(stream.push("`sh\nls\n").is_empty());
+        assert_eq!(
+            stream.push("```\n"),
+            [StreamEvent::CodeBlock {
+                language: "sh".to_string(),
+                code: "ls".to_string(),
+                complete: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_response_ending_mid_fence() {
+        for size in 1..=8 {
+            assert_eq!(
+                stream_in_chunks("Run:\n```bash\nmake\nmake inst", size),
+                [
+                    StreamEvent::Text("Run:\n".to_string()),
+                    bash_block("make\nmake inst", false),
+                ]
+            );
+        }
+
+        // A closing fence without a line break still closes the block
+        assert_eq!(
+            stream_in_chunks("```bash\nmake\n```", 3),
+            [bash_block("make", true)]
+        );
+
+        // Text held back as a possible fence is prose after all
+        assert_eq!(
+            stream_in_chunks("Done\n``", 1),
+            [StreamEvent::Text("Done\n``".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_blocks_without_language_and_crlf() {
+        assert_eq!(
+            stream_in_chunks("```\r\npwd\r\n```\r\nok", 4),
+            [
+                StreamEvent::CodeBlock {
+                    language: String::new(),
+                    code: "pwd".to_string(),
+                    complete: true,
+                },
+                StreamEvent::Text("ok".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,425 @@
+// Integration tests for the non-interactive pipe mode
+
+use serde::Deserialize;
+use shellai::agents::openai::Usage;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use tempfile::tempdir;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Command running the shellai binary with `home` as its home directory, so the config,
+// state, history and cache of the user running the tests are neither read nor written
+fn shellai_command(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_shellai"));
+    command
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .env("XDG_DATA_HOME", home.join(".local/share"))
+        .env("XDG_STATE_HOME", home.join(".local/state"))
+        .env("XDG_CACHE_HOME", home.join(".cache"));
+    command
+}
+
+// Run the shellai binary against a mock API server, feeding `stdin` to it
+fn run_shellai(server: &MockServer, args: &[&str], stdin: &str) -> Output {
+    let home = tempdir().expect("Failed to create temp directory");
+    run_shellai_in(home.path(), server, args, stdin)
+}
+
+// Run the shellai binary against a mock API server with the given home directory
+fn run_shellai_in(home: &Path, server: &MockServer, args: &[&str], stdin: &str) -> Output {
+    // Run in an empty directory so the directory scan in the system prompt stays small
+    let work_dir = tempdir().expect("Failed to create temp directory");
+
+    let mut child = shellai_command(home)
+        .args(args)
+        .current_dir(work_dir.path())
+        .env("OPENAI_API_KEY", "test_key")
+        .env("OPENAI_BASE_URL", server.uri())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run shellai");
+
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(stdin.as_bytes())
+        .expect("Failed to write to stdin");
+
+    child
+        .wait_with_output()
+        .expect("Failed to wait for shellai")
+}
+
+// Shape of the object printed by --json
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct JsonOutput {
+    response: String,
+    bash_blocks: Vec<String>,
+    model: String,
+    usage: Option<Usage>,
+    system_fingerprint: Option<String>,
+}
+
+async fn mock_completion(server: &MockServer, content: &str) {
+    let body = serde_json::json!({
+        "model": "gpt-4-0613",
+        "choices": [{ "message": { "role": "assistant", "content": content } }],
+        "usage": { "prompt_tokens": 120, "completion_tokens": 30, "total_tokens": 150 }
+    });
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pipe_reads_prompt_from_stdin() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "Use `ls -la` to list files.").await;
+
+    let output = run_shellai(&server, &["--pipe"], "list files\n");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("Output should be UTF-8");
+    assert_eq!(stdout, "Use `ls -la` to list files.\n");
+    // No colors or terminal UI when stdout isn't a terminal
+    assert!(!stdout.contains('\x1b'));
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    assert_eq!(body["messages"][1]["content"], "list files");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prompt_flag() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "Hello!").await;
+
+    let output = run_shellai(&server, &["--prompt", "say hello"], "");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello!\n");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pipe_exits_non_zero_on_api_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("server error"))
+        .mount(&server)
+        .await;
+
+    let output = run_shellai(&server, &["--pipe"], "list files");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("server error"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pipe_without_prompt_is_a_usage_error() {
+    let server = MockServer::start().await;
+
+    let output = run_shellai(&server, &["--pipe"], "  \n");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_json_output() {
+    let server = MockServer::start().await;
+    let content =
+        "List them with:\n```bash\nls -la\n```\nand count them with:\n```sh\nls | wc -l\n```";
+    mock_completion(&server, content).await;
+
+    let output = run_shellai(&server, &["--pipe", "--json"], "list files");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("Output should be UTF-8");
+    assert!(!stdout.contains('\x1b'));
+    assert!(!stdout.contains("\\u001b"));
+
+    let parsed: JsonOutput = serde_json::from_str(&stdout).expect("Output should be JSON");
+    assert_eq!(
+        parsed,
+        JsonOutput {
+            response: content.to_string(),
+            bash_blocks: vec!["ls -la".to_string(), "ls | wc -l".to_string()],
+            model: "gpt-4-0613".to_string(),
+            usage: Some(Usage {
+                prompt_tokens: 120,
+                completion_tokens: 30,
+                total_tokens: 150,
+                completion_tokens_details: None,
+            }),
+            system_fingerprint: None,
+        }
+    );
+}
+
+#[test]
+fn test_json_requires_one_shot_mode() {
+    let home = tempdir().expect("Failed to create temp directory");
+    let output = shellai_command(home.path())
+        .arg("--json")
+        .env("OPENAI_API_KEY", "test_key")
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run shellai");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--prompt"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_system_prompt_file_replaces_guidelines() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "Bonjour").await;
+
+    let prompt_dir = tempdir().expect("Failed to create temp directory");
+    let prompt_path = prompt_dir.path().join("prompt.md");
+    std::fs::write(&prompt_path, "Always answer in French.").expect("Failed to write prompt");
+
+    let output = run_shellai(
+        &server,
+        &[
+            "--prompt",
+            "hello",
+            "--system-prompt",
+            prompt_path.to_str().expect("Path should be UTF-8"),
+        ],
+        "",
+    );
+    assert!(output.status.success());
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    let system_prompt = body["messages"][0]["content"]
+        .as_str()
+        .expect("System prompt should be a string");
+    assert!(system_prompt.contains("Always answer in French."));
+    assert!(system_prompt.contains("Current working directory:"));
+    assert!(!system_prompt.contains("You are ShellAI"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_seed_flag_is_sent_and_fingerprint_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "model": "gpt-4-0613",
+            "system_fingerprint": "fp_44709d6fcb",
+            "choices": [{ "message": { "role": "assistant", "content": "answer" } }]
+        })))
+        .mount(&server)
+        .await;
+
+    let output = run_shellai(&server, &["--prompt", "hi", "--json", "--seed", "42"], "");
+    assert!(output.status.success());
+
+    let json: JsonOutput = serde_json::from_slice(&output.stdout).expect("Output should be JSON");
+    assert_eq!(json.system_fingerprint.as_deref(), Some("fp_44709d6fcb"));
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    assert_eq!(body["seed"], 42);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_structured_output() {
+    let server = MockServer::start().await;
+    mock_completion(
+        &server,
+        r#"{"explanation": "Lists all files", "commands": ["ls -la"]}"#,
+    )
+    .await;
+
+    let output = run_shellai(&server, &["--prompt", "list files", "--structured"], "");
+
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output should be JSON");
+    assert_eq!(
+        json,
+        serde_json::json!({ "explanation": "Lists all files", "commands": ["ls -la"] })
+    );
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    assert_eq!(body["response_format"]["type"], "json_schema");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_structured_output_rejects_prose() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "Use `ls -la` to list files.").await;
+
+    let output = run_shellai(&server, &["--prompt", "list files", "--structured"], "");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("schema"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_html_output_format() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "List them with:\n```bash\nls -la\n```").await;
+
+    let output = run_shellai(
+        &server,
+        &["--prompt", "list files", "--output-format", "html"],
+        "",
+    );
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).expect("Output should be UTF-8");
+    assert!(stdout.starts_with("<!DOCTYPE html>"));
+    assert!(stdout.contains("<strong>gpt-4-0613</strong>"));
+    assert!(stdout.contains("<code class=\"language-bash\">ls -la</code>"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_question_with_piped_context() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "The config file is missing.").await;
+
+    let output = run_shellai(
+        &server,
+        &["why is this failing?"],
+        "error: config.toml not found\n",
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).expect("Output should be UTF-8");
+    assert_eq!(stdout, "The config file is missing.\n");
+    assert!(!stdout.contains('\x1b'));
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    assert_eq!(
+        body["messages"][1]["content"],
+        "why is this failing?\n\nInput piped from stdin:\n```\nerror: config.toml not found\n```"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_piped_stdin_without_question_is_a_usage_error() {
+    let server = MockServer::start().await;
+
+    let output = run_shellai(&server, &[], "error: config.toml not found\n");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage"));
+    assert!(server
+        .received_requests()
+        .await
+        .unwrap_or_default()
+        .is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_question_with_model_and_no_context() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "tar --exclude=node_modules -czf out.tgz dir").await;
+
+    let output = run_shellai(
+        &server,
+        &[
+            "how do I tar a directory?",
+            "--model",
+            "gpt-4o-mini",
+            "--no-context",
+            "--raw",
+        ],
+        "",
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "tar --exclude=node_modules -czf out.tgz dir\n"
+    );
+
+    let requests = server
+        .received_requests()
+        .await
+        .expect("Requests not recorded");
+    let body: serde_json::Value = requests[0].body_json().expect("Request should be JSON");
+    assert_eq!(body["model"], "gpt-4o-mini");
+    let system_prompt = body["messages"][0]["content"]
+        .as_str()
+        .expect("System prompt should be a string");
+    assert!(system_prompt.contains("Current working directory:"));
+    assert!(!system_prompt.contains("Directory structure:"));
+}
+
+#[test]
+fn test_exec_requires_one_shot_mode() {
+    let home = tempdir().expect("Failed to create temp directory");
+    let output = shellai_command(home.path())
+        .arg("--exec")
+        .env("OPENAI_API_KEY", "test_key")
+        .stdin(Stdio::null())
+        .output()
+        .expect("Failed to run shellai");
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_base_url_variable_overrides_config() {
+    let server = MockServer::start().await;
+    mock_completion(&server, "Use `ls`.").await;
+
+    // Nothing listens on the configured endpoint
+    let home = tempdir().expect("Failed to create temp directory");
+    let config_dir = home.path().join(".config").join("shellai");
+    fs::create_dir_all(&config_dir).expect("Failed to create config directory");
+    fs::write(
+        config_dir.join("config.toml"),
+        "base_url = \"http://127.0.0.1:9\"\n",
+    )
+    .expect("Failed to write config");
+
+    let output = run_shellai_in(home.path(), &server, &["--pipe"], "list files\n");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Use `ls`.\n");
+}